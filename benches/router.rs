@@ -0,0 +1,192 @@
+//! Requests per second through `Router::handle` and `FrozenRouter::handle`
+//! for a static and a param route, and through a 50-route app, with and
+//! without the lookup cache, for paths drawn from a Zipf distribution; and
+//! through a 100-route static app, with and without the static fast path.
+//!
+//! Run with `cargo bench --bench router`.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use expressjs::prelude::*;
+use std::hint::black_box;
+
+fn router() -> Router<()> {
+    let mut router = Router::<()>::default();
+    router.use_with("/", |_: &mut Request<()>, _: &mut Response| async {
+        next_res()
+    });
+    router.get("/health", async |_req: Request<()>, res: Response| {
+        res.send_text("ok")
+    });
+    router.get("/users/{id}", async |req: Request<()>, res: Response| {
+        let id = req.params().get("id").unwrap_or_default().to_owned();
+        res.send_text(id)
+    });
+    router
+}
+
+const URIS: [(&str, &str); 2] = [("static_route", "/health"), ("param_route", "/users/42")];
+
+fn bench_routes(c: &mut Criterion) {
+    let router = router();
+    bench_group(c, "router", |req| router.handle(req, Response::new()));
+
+    let frozen = self::router().freeze().unwrap();
+    bench_group(c, "frozen_router", |req| {
+        frozen.handle(req, Response::new())
+    });
+}
+
+fn bench_group<F, Fut>(c: &mut Criterion, group: &str, handle: F)
+where
+    F: Fn(Request<()>) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group(group);
+    group.throughput(Throughput::Elements(1));
+    for (name, uri) in URIS {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let req = Request::builder().uri(uri).body(()).unwrap();
+                black_box(runtime.block_on(handle(req)))
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Number of routes in the app of `bench_lookup_cache`.
+const ROUTE_COUNT: usize = 50;
+
+/// A 50-route API: a static and a param route per resource, each resource
+/// with its own middleware besides an app-wide one.
+fn api_router(lookup_cache: usize) -> FrozenRouter<()> {
+    let mut router = Router::<()>::default();
+    router.use_with("/api", |_: &mut Request<()>, _: &mut Response| async {
+        next_res()
+    });
+    for i in 0..ROUTE_COUNT / 2 {
+        router.use_with(
+            format!("/api/r{i}"),
+            |_: &mut Request<()>, _: &mut Response| async { next_res() },
+        );
+        router.get(
+            format!("/api/r{i}"),
+            async |_req: Request<()>, res: Response| res.send_text("list"),
+        );
+        router.get(
+            format!("/api/r{i}/{{id}}"),
+            async |req: Request<()>, res: Response| {
+                let id = req.params().get("id").unwrap_or_default().to_owned();
+                res.send_text(id)
+            },
+        );
+    }
+    router.lookup_cache(lookup_cache);
+    router.freeze().unwrap()
+}
+
+/// Paths over the routes of [`api_router`], the `k`-th most popular drawn with
+/// probability proportional to `1 / k` (Zipf, s = 1), from a fixed seed.
+fn zipf_paths(count: usize) -> Vec<String> {
+    let paths: Vec<String> = (0..ROUTE_COUNT)
+        .map(|k| match k % 2 {
+            0 => format!("/api/r{}", k / 2),
+            _ => format!("/api/r{}/{}", k / 2, 1000 + k),
+        })
+        .collect();
+    let mut cumulative = Vec::with_capacity(ROUTE_COUNT);
+    let mut total = 0.0;
+    for k in 1..=ROUTE_COUNT {
+        total += 1.0 / k as f64;
+        cumulative.push(total);
+    }
+
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    (0..count)
+        .map(|_| {
+            // xorshift64*: deterministic and dependency free.
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            let unit =
+                (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64;
+            let rank = cumulative.partition_point(|&c| c < unit * total);
+            paths[rank.min(ROUTE_COUNT - 1)].clone()
+        })
+        .collect()
+}
+
+fn bench_lookup_cache(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let paths = zipf_paths(4096);
+
+    let mut group = c.benchmark_group("zipf_50_routes");
+    group.throughput(Throughput::Elements(1));
+    for (name, capacity) in [("uncached", 0), ("lookup_cache", 1024)] {
+        let router = api_router(capacity);
+        let mut next = paths.iter().cycle();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let uri = next.next().unwrap();
+                let req = Request::builder().uri(uri.as_str()).body(()).unwrap();
+                black_box(runtime.block_on(router.handle(req, Response::new())))
+            })
+        });
+    }
+    group.finish();
+}
+
+/// A frozen app with 100 static routes. A never-requested param route makes
+/// the GET table dynamic, so lookups go through the matcher instead of the
+/// static fast path.
+fn static_router(with_param_route: bool) -> FrozenRouter<()> {
+    let mut router = Router::<()>::default();
+    for i in 0..100 {
+        router.get(
+            format!("/static/s{i}/page"),
+            async |_req: Request<()>, res: Response| res.send_text("page"),
+        );
+    }
+    if with_param_route {
+        router.get("/dynamic/{id}", async |_req: Request<()>, res: Response| {
+            res.send_text("dynamic")
+        });
+    }
+    router.freeze().unwrap()
+}
+
+fn bench_static_routes(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let paths: Vec<String> = (0..100).map(|i| format!("/static/s{i}/page")).collect();
+
+    let mut group = c.benchmark_group("static_100_routes");
+    group.throughput(Throughput::Elements(1));
+    for (name, with_param_route) in [("matcher", true), ("fast_path", false)] {
+        let router = static_router(with_param_route);
+        let mut next = paths.iter().cycle();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let uri = next.next().unwrap();
+                let req = Request::builder().uri(uri.as_str()).body(()).unwrap();
+                black_box(runtime.block_on(router.handle(req, Response::new())))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_routes,
+    bench_lookup_cache,
+    bench_static_routes
+);
+criterion_main!(benches);
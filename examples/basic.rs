@@ -117,19 +117,24 @@ async fn main() {
     });
 
     // /hello - X-Powered-By middleware then response
-    // app.use_with("/hello", async |_req: &mut Request, res: &mut Response| {
-    //     res.header("x-powered-by", HeaderValue::from_static("DevYatsu"));
-    //     stop_res()
-    // });
-
-    app.get("/hello", async |_req, res| {
-        res.body("Hello, world!")
-            .header(
+    app.use_with(
+        "/hello",
+        middleware_fn(|_req, res| {
+            Box::pin(async move {
+                res.header("x-powered-by", HeaderValue::from_static("DevYatsu"));
+                next_res()
+            })
+        }),
+    );
+
+    app.route("/hello")
+        .content_type("text/html")
+        .get(async |_req, res| {
+            res.body("Hello, world!").header(
                 header::CACHE_CONTROL,
                 HeaderValue::from_static("public, max-age=86400"),
             )
-            .header(header::CONTENT_TYPE, HeaderValue::from_static("text/html"))
-    });
+        });
 
     // Route builder pattern - multiple methods on the same path
     app.route("/api/v1/user")
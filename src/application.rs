@@ -1,12 +1,32 @@
-use crate::handler::{Handler, Request, Response};
-use crate::middleware::Middleware;
-use crate::router::{MethodKind, Route, Router};
-use crate::server::Server;
+use crate::config::{AppConfigFile, ConfigError};
+use crate::handler::extract::SharedState;
+use crate::handler::request::{
+    ConnectionInfo, Disconnect, IfMatch, JsonLimits, MissingContentType, QueryOptions, TrustProxy,
+};
+use crate::handler::response::{
+    CachePolicy, ErrorDetails, ErrorEnvelope, ErrorFormat, HeaderPolicy,
+};
+use crate::handler::{ExpressResponse, Handler, IntoResponse, Request, Response};
+use crate::middleware::{
+    BodySizeLimitMiddleware, CorsMiddleware, Middleware, RateLimitMiddleware, RequestSummary,
+    StaticServeMiddleware,
+};
+use crate::router::{
+    DuplicateRoutes, FreezeError, FrozenRouter, MethodKind, Phase, ResourceHandlers, Route,
+    RouteInfo, Router, SubApp,
+};
+use crate::server::{DrainState, Server, ServerOptions};
+use hyper::StatusCode;
 use hyper::body::Incoming;
+use hyper::header::{CONNECTION, HeaderValue};
 
+use std::future::Future;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 use tokio_rustls::rustls::ServerConfig;
+use tokio_util::sync::CancellationToken;
 
 /// The main application structure for `expressjs`.
 ///
@@ -17,12 +37,104 @@ use tokio_rustls::rustls::ServerConfig;
 /// [`App::listen_https`].
 pub struct App<B: Send + 'static = Incoming> {
     pub(crate) router: Router<B>,
+    settings: RequestSettings,
+    server: ServerOptions,
+    drain: DrainState,
+    shutdown_signal: Mutex<Option<ShutdownFuture>>,
+    shutdown_hooks: Mutex<Vec<ShutdownHook>>,
+}
+
+/// An [`App`] whose routes are validated and fixed, ready to serve.
+///
+/// Produced by [`App::freeze`], which [`App::listen`] calls implicitly. It
+/// has no registration methods, so routes, middleware and settings cannot
+/// change while requests are being served.
+pub struct FrozenApp<B: Send + 'static = Incoming> {
+    router: FrozenRouter<B>,
+    settings: RequestSettings,
+    server: ServerOptions,
+    drain: DrainState,
+    shutdown_signal: Mutex<Option<ShutdownFuture>>,
+    shutdown_hooks: Mutex<Vec<ShutdownHook>>,
+}
+
+type ShutdownFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type ShutdownHook = Box<dyn FnOnce() -> ShutdownFuture + Send>;
+
+/// App-wide settings inserted into every request's extensions, or applied
+/// to its response.
+#[derive(Default)]
+struct RequestSettings {
+    json_limits: Option<JsonLimits>,
+    error_format: Option<ErrorFormat>,
+    error_details: bool,
+    error_envelope: Option<ErrorEnvelope>,
+    trust_proxy: Option<TrustProxy>,
+    if_match: Option<IfMatch>,
+    missing_content_type: Option<MissingContentType>,
+    query_options: Option<QueryOptions>,
+    cache_policy: Option<CachePolicy>,
+    header_policy: Option<HeaderPolicy>,
+    state: SharedState,
+}
+
+impl RequestSettings {
+    /// Inserts the settings into `req` and `res`, and returns whether `req`
+    /// is HTTP/1.0.
+    fn apply<B>(&self, req: &mut Request<B>, res: &mut Response) -> bool {
+        if let Some(envelope) = &self.error_envelope {
+            res.extensions.insert(envelope.clone());
+        }
+        req.extensions_mut()
+            .insert(crate::handler::request::Locals::default());
+        if let Some(limits) = self.json_limits {
+            req.extensions_mut().insert(limits);
+        }
+        if let Some(format) = self.error_format {
+            req.extensions_mut().insert(format);
+        }
+        if self.error_details {
+            req.extensions_mut().insert(ErrorDetails);
+        }
+        if let Some(trust) = &self.trust_proxy {
+            req.extensions_mut().insert(trust.clone());
+        }
+        if let Some(policy) = self.if_match {
+            req.extensions_mut().insert(policy);
+        }
+        if let Some(policy) = self.missing_content_type {
+            req.extensions_mut().insert(policy);
+        }
+        if let Some(options) = self.query_options {
+            req.extensions_mut().insert(options);
+        }
+        if !self.state.0.is_empty() {
+            req.extensions_mut().insert(self.state.clone());
+        }
+        req.version() < hyper::Version::HTTP_11
+    }
+
+    /// Applies the response policies to `res`, the response to the request
+    /// summarized by `req`.
+    fn finish(&self, req: &RequestSummary, res: &mut Response) {
+        if let Some(policy) = &self.cache_policy {
+            policy.apply(res);
+        }
+        if let Some(policy) = &self.header_policy {
+            policy.apply(req.path(), res);
+        }
+    }
 }
 
 impl<B: Send + 'static> Default for App<B> {
     fn default() -> Self {
         Self {
             router: Router::default(),
+            settings: RequestSettings::default(),
+            server: ServerOptions::default(),
+            drain: DrainState::default(),
+            shutdown_signal: Mutex::new(None),
+            shutdown_hooks: Mutex::new(Vec::new()),
         }
     }
 }
@@ -30,11 +142,314 @@ impl<B: Send + 'static> Default for App<B> {
 impl<B: Send + 'static> App<B> {
     /// Handles an incoming request and returns a response.
     /// This method is typically called internally but is exposed for custom integrations.
-    pub async fn handle(&self, req: Request<B>, res: Response) -> Response {
-        let mut req = req;
-        req.extensions_mut()
-            .insert(crate::handler::request::Locals::default());
-        self.router.handle(req, res).await
+    pub async fn handle(&self, mut req: Request<B>, mut res: Response) -> Response {
+        let http10 = self.settings.apply(&mut req, &mut res);
+        let summary = RequestSummary::capture(&req);
+        let mut res = self.router.handle(req, res).await;
+        self.settings.finish(&summary, &mut res);
+        if http10 {
+            res.prepare_for_http10().await;
+        }
+        res
+    }
+
+    /// Validates the routes and fixes them for serving. See [`Router::freeze`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`FreezeError`] if the routes are inconsistent, e.g. the same
+    /// method and path are registered twice.
+    pub fn freeze(self) -> Result<FrozenApp<B>, FreezeError> {
+        Ok(FrozenApp {
+            router: self.router.freeze()?,
+            settings: self.settings,
+            server: self.server,
+            drain: self.drain,
+            shutdown_signal: self.shutdown_signal,
+            shutdown_hooks: self.shutdown_hooks,
+        })
+    }
+
+    /// Overrides the size and nesting limits enforced by [`RequestExt::json`](crate::prelude::RequestExt::json).
+    pub fn json_limits(&mut self, limits: JsonLimits) -> &mut Self {
+        self.settings.json_limits = Some(limits);
+        self
+    }
+
+    /// Sets how the bodies of [`ResponseError`](crate::prelude::ResponseError)
+    /// responses returned by handlers are formatted. Plain text by default.
+    pub fn error_format(&mut self, format: ErrorFormat) -> &mut Self {
+        self.settings.error_format = Some(format);
+        self
+    }
+
+    /// Sends the error chain of server errors to the client instead of only
+    /// the status reason, e.g. `handler panicked: index out of bounds`. For
+    /// development; off by default.
+    ///
+    /// Server errors no [`ErrorLogMiddleware`](crate::prelude::ErrorLogMiddleware)
+    /// logged are logged by the router either way, under the `express_rs::error`
+    /// target.
+    pub fn error_details(&mut self, enabled: bool) -> &mut Self {
+        self.settings.error_details = enabled;
+        self
+    }
+
+    /// Sets the JSON shape of the error bodies written by the built-in
+    /// middleware and the router, e.g. a `429` from [`RateLimitMiddleware`]
+    /// or a `404`. See [`ErrorEnvelope`].
+    pub fn error_envelope(&mut self, envelope: ErrorEnvelope) -> &mut Self {
+        self.settings.error_envelope = Some(envelope);
+        self
+    }
+
+    /// Sets how many middleware a single request may run through before a
+    /// warning is logged (debug builds only). See [`Router::middleware_warn_threshold`].
+    pub fn middleware_warn_threshold(&mut self, threshold: usize) -> &mut Self {
+        self.router.middleware_warn_threshold(threshold);
+        self
+    }
+
+    /// Caches the route lookups of up to `capacity` method and path pairs
+    /// once the app is frozen. See [`Router::lookup_cache`].
+    pub fn lookup_cache(&mut self, capacity: usize) -> &mut Self {
+        self.router.lookup_cache(capacity);
+        self
+    }
+
+    /// Answers requests using `methods` with `405 Method Not Allowed` instead
+    /// of running their routes. `TRACE` and `CONNECT` are denied by default. See [`Router::deny_methods`].
+    pub fn deny_methods(&mut self, methods: &[MethodKind]) -> &mut Self {
+        self.router.deny_methods(methods);
+        self
+    }
+
+    /// Routes `methods` again after a denial, e.g. `TRACE`. See [`Router::allow_methods`].
+    pub fn allow_methods(&mut self, methods: &[MethodKind]) -> &mut Self {
+        self.router.allow_methods(methods);
+        self
+    }
+
+    /// Sets how a second handler for a method and path already routed is
+    /// treated. See [`Router::duplicate_routes`].
+    pub fn duplicate_routes(&mut self, policy: DuplicateRoutes) -> &mut Self {
+        self.router.duplicate_routes(policy);
+        self
+    }
+
+    /// Sets which proxies are trusted to report the client address and protocol,
+    /// as used by [`RequestExt::client_ip`](crate::prelude::RequestExt::client_ip)
+    /// and [`RequestExt::protocol`](crate::prelude::RequestExt::protocol).
+    pub fn trust_proxy(&mut self, trust: TrustProxy) -> &mut Self {
+        self.settings.trust_proxy = Some(trust);
+        self
+    }
+
+    /// Sets how long a client may take to send a request line and headers
+    /// before the connection is dropped without a response, which stops
+    /// clients from holding connections open by trickling headers. It also
+    /// bounds how long a kept-alive connection may sit idle. `None` disables
+    /// it; 30 seconds by default.
+    pub fn header_read_timeout(&mut self, timeout: impl Into<Option<Duration>>) -> &mut Self {
+        self.server.header_read_timeout = timeout.into();
+        self
+    }
+
+    /// Keeps accepting connections for `delay` after the shutdown signal
+    /// fired, before the listener closes. During a rolling update this gives
+    /// the load balancer time to see the failing readiness probe of
+    /// [`health_checks`](App::health_checks) and stop sending new clients.
+    /// None by default.
+    pub fn pre_stop_delay(&mut self, delay: Duration) -> &mut Self {
+        self.server.pre_stop_delay = delay;
+        self
+    }
+
+    /// Registers `GET` liveness and readiness probes, e.g. for Kubernetes.
+    ///
+    /// Both answer `200` with `{"status": "ok"}`, until the shutdown signal
+    /// fires: readiness then answers `503` with `{"status": "draining"}`,
+    /// while liveness keeps answering `200` so in-flight requests can finish.
+    /// Every response sent while draining carries `Connection: close`.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// let mut app = express();
+    /// app.health_checks("/healthz", "/readyz")
+    ///     .pre_stop_delay(Duration::from_secs(5));
+    /// ```
+    pub fn health_checks(
+        &mut self,
+        liveness: impl AsRef<str>,
+        readiness: impl AsRef<str>,
+    ) -> &mut Self {
+        self.get(liveness, async |_req, res: Response| {
+            res.send_json(&serde_json::json!({ "status": "ok" }))
+        });
+        let drain = self.drain.clone();
+        self.get(readiness, move |_req, res: Response| {
+            let draining = drain.is_draining();
+            async move {
+                if draining {
+                    res.status(StatusCode::SERVICE_UNAVAILABLE)
+                        .send_json(&serde_json::json!({ "status": "draining" }))
+                } else {
+                    res.send_json(&serde_json::json!({ "status": "ok" }))
+                }
+            }
+        })
+    }
+
+    /// Whether the shutdown signal fired and the app is draining. See
+    /// [`health_checks`](App::health_checks).
+    pub fn is_draining(&self) -> bool {
+        self.drain.is_draining()
+    }
+
+    /// A handle on the draining state, for handlers to check it.
+    pub fn drain_state(&self) -> DrainState {
+        self.drain.clone()
+    }
+
+    /// Sets whether [`RequestExt::check_if_match`](crate::prelude::RequestExt::check_if_match)
+    /// rejects requests without an `If-Match` header. Optional by default.
+    pub fn if_match(&mut self, policy: IfMatch) -> &mut Self {
+        self.settings.if_match = Some(policy);
+        self
+    }
+
+    /// Sets the limits and duplicate-key handling of
+    /// [`RequestExt::query_nested`](crate::prelude::RequestExt::query_nested)
+    /// and [`RequestExt::query_as`](crate::prelude::RequestExt::query_as);
+    /// the length and parameter count limits also apply to the
+    /// [`Query`](crate::prelude::Query) extractor.
+    pub fn query_options(&mut self, options: QueryOptions) -> &mut Self {
+        self.settings.query_options = Some(options);
+        self
+    }
+
+    /// Sets default `Cache-Control` values by response status, applied to
+    /// responses that don't set the header themselves.
+    pub fn cache_policy(&mut self, policy: CachePolicy) -> &mut Self {
+        self.settings.cache_policy = Some(policy);
+        self
+    }
+
+    /// Sets headers every response must have or must not have, enforced
+    /// after handlers, middleware and the cache policy have run.
+    pub fn header_policy(&mut self, policy: HeaderPolicy) -> &mut Self {
+        self.settings.header_policy = Some(policy);
+        self
+    }
+
+    /// Sets whether [`RequestExt::expect_json`](crate::prelude::RequestExt::expect_json)
+    /// accepts requests without a `Content-Type`. Rejected by default.
+    pub fn missing_content_type(&mut self, policy: MissingContentType) -> &mut Self {
+        self.settings.missing_content_type = Some(policy);
+        self
+    }
+
+    /// Registers application state, available to extractor handlers through
+    /// [`State<S>`](crate::prelude::State). One value is kept per type; wrap
+    /// larger state in an `Arc` as it is cloned for each extraction.
+    pub fn state<S: Clone + Send + Sync + 'static>(&mut self, state: S) -> &mut Self {
+        Arc::make_mut(&mut self.settings.state.0).insert(state);
+        self
+    }
+
+    /// Applies a loaded [`AppConfigFile`] after [validating](AppConfigFile::validate) it.
+    ///
+    /// The server settings are applied first. The configured middleware are
+    /// then mounted in this order: CORS, so that even rejected requests carry
+    /// CORS headers; rate limiting, so that throttled clients cost nothing
+    /// more; the body size limit; and finally the static mounts in the order
+    /// listed. The first three run in [`Phase::PreRouting`] on every path,
+    /// while static mounts interleave with routes like any [`use_with`](App::use_with)
+    /// mount, so call this before registering routes to serve files first.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ConfigError`] naming the offending field, in which case
+    /// nothing is applied.
+    pub fn apply_config(&mut self, config: &AppConfigFile) -> Result<&mut Self, ConfigError>
+    where
+        B: Sync,
+    {
+        config.validate()?;
+
+        let server = &config.server;
+        self.trust_proxy(server.trust_proxy.clone())
+            .error_format(server.error_format)
+            .error_details(server.error_details)
+            .json_limits(server.json_limits());
+
+        if let Some(cors) = &config.cors {
+            self.use_with_phase("/", Phase::PreRouting, CorsMiddleware::from(cors.clone()));
+        }
+        if let Some(limit) = &config.rate_limit {
+            self.use_with_phase(
+                "/",
+                Phase::PreRouting,
+                RateLimitMiddleware::from(limit.clone()),
+            );
+        }
+        if let Some(limit) = &config.body_limit {
+            self.use_with_phase(
+                "/",
+                Phase::PreRouting,
+                BodySizeLimitMiddleware::from(limit.clone()),
+            );
+        }
+        for mount in &config.static_mounts {
+            self.use_with(
+                mount.mount_path(),
+                StaticServeMiddleware::from(mount.clone()),
+            );
+        }
+        Ok(self)
+    }
+
+    /// Replaces the signal that stops the server, Ctrl+C by default.
+    ///
+    /// Once `signal` resolves, [`App::listen`] stops accepting connections,
+    /// runs the [`on_shutdown`](App::on_shutdown) callbacks and returns.
+    pub fn shutdown_signal(
+        &mut self,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> &mut Self {
+        *self
+            .shutdown_signal
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Box::pin(signal));
+        self
+    }
+
+    /// Registers a callback awaited during graceful shutdown, after the server
+    /// stops accepting connections and before [`App::listen`] returns. Use it to
+    /// flush buffers, close pools or persist sessions.
+    ///
+    /// Callbacks run one after another, in registration order.
+    ///
+    /// ```rust,no_run
+    /// # use expressjs::prelude::*;
+    /// # async fn run() {
+    /// let mut app = express();
+    /// app.on_shutdown(|| async { println!("flushing sessions...") });
+    /// app.listen(3000, async |_| {}).await;
+    /// # }
+    /// ```
+    pub fn on_shutdown<F, Fut>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown_hooks
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(move || Box::pin(callback())));
+        self
     }
 
     /// Attaches a middleware to a specific path prefix.
@@ -43,6 +458,39 @@ impl<B: Send + 'static> App<B> {
         self
     }
 
+    /// Attaches a middleware to a specific path prefix in the given [`Phase`].
+    ///
+    /// Matching layers run sorted by phase, then by registration order, so a
+    /// [`Phase::PreRouting`] logger runs before every route even if registered
+    /// last, and a [`Phase::PostRouting`] fallback only runs when no route answered.
+    pub fn use_with_phase(
+        &mut self,
+        path: impl AsRef<str>,
+        phase: Phase,
+        middleware: impl Middleware<B>,
+    ) -> &mut Self {
+        self.router.use_with_phase(path, phase, middleware);
+        self
+    }
+
+    /// Attaches a middleware to a specific path prefix, running it only for the given methods.
+    ///
+    /// Useful for checks that only make sense on some verbs, e.g. a CSRF check
+    /// on mutating methods:
+    ///
+    /// ```rust,ignore
+    /// app.use_for(&[MethodKind::Post, MethodKind::Put], "/api", CsrfMiddleware);
+    /// ```
+    pub fn use_for(
+        &mut self,
+        methods: &[MethodKind],
+        path: impl AsRef<str>,
+        middleware: impl Middleware<B>,
+    ) -> &mut Self {
+        self.router.use_for(methods, path, middleware);
+        self
+    }
+
     /// Attaches a middleware to all paths.
     pub fn use_global(&mut self, middleware: impl Middleware<B>) -> &mut Self {
         self.router.use_with("/{*p}", middleware);
@@ -59,7 +507,8 @@ impl<B: Send + 'static> App<B> {
     pub fn not_found<F, Fut>(&mut self, handler: F) -> &mut Self
     where
         F: Fn(Request<B>, Response) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = Response> + Send + 'static,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: IntoResponse,
     {
         self.router.not_found(handler);
         self
@@ -69,17 +518,69 @@ impl<B: Send + 'static> App<B> {
     pub fn all<F, Fut>(&mut self, path: impl AsRef<str>, handler: F) -> &mut Self
     where
         F: Fn(Request<B>, Response) -> Fut + Send + Sync + Clone + 'static,
-        Fut: std::future::Future<Output = Response> + Send + 'static,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: IntoResponse,
     {
         self.router.all(path, handler);
         self
     }
 
+    /// Registers a development-only endpoint at `path` that answers every
+    /// method with what it received, like httpbin's `/anything`: the method,
+    /// path, query, headers and body as JSON. Bodies over 64 KiB (or the
+    /// request's [`BodyLimit`](crate::prelude::BodyLimit), if lower) get
+    /// `413 Payload Too Large`.
+    ///
+    /// Remove it before deploying: it reflects credentials such as the
+    /// `Authorization` and `Cookie` headers back to whoever calls it. A
+    /// warning is logged when it is registered.
+    pub fn debug_echo(&mut self, path: impl AsRef<str>) -> &mut Self
+    where
+        B: http_body_util::BodyExt + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+    {
+        log::warn!(
+            "debug echo endpoint registered at {}; remove it before deploying",
+            path.as_ref()
+        );
+        self.all(path, crate::handler::debug_echo::debug_echo)
+    }
+
+    /// Lists the registered routes. See [`Router::routes`].
+    pub fn routes(&self) -> impl Iterator<Item = RouteInfo<'_>> {
+        self.router.routes()
+    }
+
     /// Creates a route builder for the specified path, allowing chainable handler registrations.
     pub fn route(&mut self, path: impl AsRef<str>) -> Route<'_, B> {
         self.router.route_builder(path)
     }
 
+    /// Registers the routes of a REST resource under the collection path
+    /// `path`: `GET` and `POST` on `path`, `GET`, `PUT`, `PATCH` and
+    /// `DELETE` on `path/{id}`, for the actions given. See [`ResourceHandlers`].
+    pub fn resource(&mut self, path: impl AsRef<str>, handlers: ResourceHandlers<B>) -> &mut Self {
+        self.router.resource(path, handlers);
+        self
+    }
+
+    /// Scopes registrations under `prefix`: routes and middleware added
+    /// through the returned [`SubApp`] are registered on this app right
+    /// away, below the prefix.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    ///
+    /// let mut app = express();
+    /// let mut api = app.sub("/api");
+    /// api.use_with("/", CacheMiddleware::no_store());
+    /// api.get("/users", async |_req, res| res.send_text("users")); // GET /api/users
+    /// ```
+    pub fn sub(&mut self, prefix: impl AsRef<str>) -> SubApp<'_, B> {
+        self.router.sub(prefix)
+    }
+
     crate::define_methods!();
 
     fn add_route(
@@ -93,64 +594,189 @@ impl<B: Send + 'static> App<B> {
     }
 }
 
+impl<B: Send + 'static> FrozenApp<B> {
+    /// Handles an incoming request and returns a response, like [`App::handle`].
+    pub async fn handle(&self, mut req: Request<B>, mut res: Response) -> Response {
+        let http10 = self.settings.apply(&mut req, &mut res);
+        let summary = RequestSummary::capture(&req);
+        let mut res = self.router.handle(req, res).await;
+        self.settings.finish(&summary, &mut res);
+        if http10 {
+            res.prepare_for_http10().await;
+        }
+        res
+    }
+
+    /// Whether the shutdown signal fired and the app is draining.
+    pub fn is_draining(&self) -> bool {
+        self.drain.is_draining()
+    }
+
+    /// A handle on the draining state, for handlers to check it.
+    pub fn drain_state(&self) -> DrainState {
+        self.drain.clone()
+    }
+
+    /// Takes the shutdown signal (Ctrl+C if none was set) and the registered
+    /// callbacks. The signal is extended to start draining once it fires,
+    /// then wait out the pre-stop delay.
+    fn take_shutdown(&mut self) -> (ShutdownFuture, Vec<ShutdownHook>) {
+        let signal = self
+            .shutdown_signal
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .unwrap_or_else(|| Box::pin(Server::ctrl_c()));
+        let drain = self.drain.clone();
+        let delay = self.server.pre_stop_delay;
+        let signal: ShutdownFuture = Box::pin(async move {
+            signal.await;
+            drain.start();
+            if !delay.is_zero() {
+                log::info!("draining: closing the listener in {delay:?}");
+                tokio::time::sleep(delay).await;
+            }
+        });
+        let hooks = std::mem::take(
+            self.shutdown_hooks
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        (signal, hooks)
+    }
+}
+
 // listen only for Incoming
 impl App<Incoming> {
     /// Binds the HTTP server to the given port and invokes the callback once ready.
+    ///
+    /// Returns once the [shutdown signal](App::shutdown_signal) fires and the
+    /// [`on_shutdown`](App::on_shutdown) callbacks have completed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the routes cannot be [frozen](App::freeze); call `freeze`
+    /// and [`FrozenApp::listen`] to handle the error instead.
     pub async fn listen<T, Fut>(self, port: u16, callback: T)
+    where
+        Self: Sized + Send + Sync + 'static,
+        T: FnOnce(u16) -> Fut,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        match self.freeze() {
+            Ok(app) => app.listen(port, callback).await,
+            Err(e) => panic!("cannot serve the app: {e}"),
+        }
+    }
+
+    /// Binds the HTTPS server to the given port using a provided TLS configuration, and invokes the callback once ready.
+    ///
+    /// Shuts down like [`App::listen`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the routes cannot be [frozen](App::freeze).
+    pub async fn listen_https<T, Fut>(self, port: u16, tls_config: ServerConfig, callback: T)
+    where
+        Self: Sized + Send + Sync + 'static,
+        T: FnOnce(u16) -> Fut,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        match self.freeze() {
+            Ok(app) => app.listen_https(port, tls_config, callback).await,
+            Err(e) => panic!("cannot serve the app: {e}"),
+        }
+    }
+}
+
+impl FrozenApp<Incoming> {
+    /// Handles a request accepted by the server.
+    ///
+    /// If hyper drops this future because the client went away, the request's
+    /// [`Disconnect`] token is cancelled, waking [`RequestExt::on_disconnect`](crate::prelude::RequestExt::on_disconnect).
+    async fn serve(&self, mut req: Request<Incoming>) -> crate::server::ServerResponse {
+        let token = CancellationToken::new();
+        req.extensions_mut().insert(Disconnect(token.clone()));
+        let guard = token.drop_guard();
+        let mut response = self.handle(req, Response::new()).await;
+        guard.disarm();
+        if self.drain.is_draining() {
+            response
+                .headers
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+        response.into_hyper()
+    }
+
+    /// Binds the HTTP server to the given port and invokes the callback once ready.
+    ///
+    /// Returns once the [shutdown signal](App::shutdown_signal) fires and the
+    /// [`on_shutdown`](App::on_shutdown) callbacks have completed.
+    pub async fn listen<T, Fut>(mut self, port: u16, callback: T)
     where
         Self: Sized + Send + Sync + 'static,
         T: FnOnce(u16) -> Fut,
         Fut: std::future::Future<Output = ()> + Send + 'static,
     {
         let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+        let (shutdown, hooks) = self.take_shutdown();
+        let options = self.server;
         let app = Arc::new(self);
         callback(port).await;
 
-        let factory = move |addr: SocketAddr| {
+        let factory = move |conn: Arc<ConnectionInfo>| {
             let app = app.clone();
             hyper::service::service_fn(move |mut req| {
                 let app = app.clone();
                 use crate::handler::request::RequestMetadataInternal;
-                req.set_metadata(addr, false);
-                async move {
-                    let response = app.handle(req, Response::new()).await;
-                    Ok::<_, std::convert::Infallible>(response.into_hyper())
-                }
+                req.set_connection(Arc::clone(&conn));
+                async move { Ok::<_, std::convert::Infallible>(app.serve(req).await) }
             })
         };
 
-        if let Err(e) = Server::bind(addr, factory).await {
+        if let Err(e) = Server::bind(addr, options, factory, shutdown).await {
             eprintln!("server error: {}", e);
         }
+        run_shutdown_hooks(hooks).await;
     }
 
     /// Binds the HTTPS server to the given port using a provided TLS configuration, and invokes the callback once ready.
-    pub async fn listen_https<T, Fut>(self, port: u16, tls_config: ServerConfig, callback: T)
+    ///
+    /// Shuts down like [`FrozenApp::listen`].
+    pub async fn listen_https<T, Fut>(mut self, port: u16, tls_config: ServerConfig, callback: T)
     where
         Self: Sized + Send + Sync + 'static,
         T: FnOnce(u16) -> Fut,
         Fut: std::future::Future<Output = ()> + Send + 'static,
     {
         let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+        let (shutdown, hooks) = self.take_shutdown();
+        let options = self.server;
         let app = Arc::new(self);
         callback(port).await;
 
-        let factory = move |addr: SocketAddr| {
+        let factory = move |conn: Arc<ConnectionInfo>| {
             let app = app.clone();
             hyper::service::service_fn(move |mut req| {
                 let app = app.clone();
                 use crate::handler::request::RequestMetadataInternal;
-                req.set_metadata(addr, true);
-                async move {
-                    let response = app.handle(req, Response::new()).await;
-                    Ok::<_, std::convert::Infallible>(response.into_hyper())
-                }
+                req.set_connection(Arc::clone(&conn));
+                async move { Ok::<_, std::convert::Infallible>(app.serve(req).await) }
             })
         };
 
-        if let Err(e) = Server::bind_tls(addr, Arc::new(tls_config), factory).await {
+        if let Err(e) =
+            Server::bind_tls(addr, options, Arc::new(tls_config), factory, shutdown).await
+        {
             eprintln!("https server error: {}", e);
         }
+        run_shutdown_hooks(hooks).await;
+    }
+}
+
+async fn run_shutdown_hooks(hooks: Vec<ShutdownHook>) {
+    for hook in hooks {
+        hook().await;
     }
 }
 
@@ -159,3 +785,11 @@ impl<B: Send + 'static> std::fmt::Debug for App<B> {
         f.debug_struct("App").field("router", &self.router).finish()
     }
 }
+
+impl<B: Send + 'static> std::fmt::Debug for FrozenApp<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrozenApp")
+            .field("router", &self.router)
+            .finish()
+    }
+}
@@ -0,0 +1,339 @@
+//! Outbound HTTP client for calling other services from handlers.
+//!
+//! Enabled with the `client` feature. Get one bound to the current request
+//! with [`RequestExt::http_client`](crate::prelude::RequestExt::http_client):
+//! it forwards the request's `X-Request-Id`, `traceparent` and `tracestate`
+//! headers, never waits past the request's
+//! [`deadline`](crate::prelude::RequestExt::deadline), and reads response
+//! bodies up to the request's [`BodyLimit`].
+//!
+//! ```rust,no_run
+//! use expressjs::client::Http;
+//! use expressjs::prelude::*;
+//! use std::time::Duration;
+//!
+//! # async fn run() {
+//! let mut app = express();
+//! // Optional: share a configured client; a default one is used otherwise.
+//! app.state(Http::new().timeout(Duration::from_secs(5)));
+//! app.get("/profile", async |req, res| {
+//!     let upstream = req
+//!         .http_client()
+//!         .post("http://users.internal/lookup")
+//!         .json(&serde_json::json!({ "id": 42 }))
+//!         .send()
+//!         .await;
+//!     match upstream {
+//!         Ok(user) => res.status_code(user.status.as_u16()).body(user.body),
+//!         Err(e) => res.status_code(502).send_text(e.to_string()),
+//!     }
+//! });
+//! # }
+//! ```
+
+use crate::handler::request::BodyLimit;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use hyper::{Method, StatusCode, Uri};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Request headers forwarded to every outbound call made for a request.
+const PROPAGATED: [HeaderName; 3] = [
+    HeaderName::from_static("x-request-id"),
+    HeaderName::from_static("traceparent"),
+    HeaderName::from_static("tracestate"),
+];
+
+/// The client used by requests when none was registered as app state.
+static DEFAULT: Lazy<Http> = Lazy::new(Http::new);
+
+/// A pooled HTTP/1 client. Cloning is cheap and clones share connections.
+///
+/// Register one with [`App::state`](crate::prelude::App::state) to configure
+/// it; [`RequestExt::http_client`](crate::prelude::RequestExt::http_client)
+/// falls back to a process-wide default.
+#[derive(Clone)]
+pub struct Http {
+    client: Client<HttpConnector, Full<Bytes>>,
+    timeout: Option<Duration>,
+    /// Caps response bodies; falls back to the current request's [`BodyLimit`].
+    max_body_size: Option<usize>,
+    /// Headers forwarded from the current request.
+    propagated: HeaderMap,
+    /// The current request's deadline, which caps every call.
+    deadline: Option<Instant>,
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Http {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Http")
+            .field("timeout", &self.timeout)
+            .field("max_body_size", &self.max_body_size)
+            .field("propagated", &self.propagated)
+            .field("deadline", &self.deadline)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Http {
+    /// Creates a client with its own connection pool and no default timeout.
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            timeout: None,
+            max_body_size: None,
+            propagated: HeaderMap::new(),
+            deadline: None,
+        }
+    }
+
+    /// Sets the timeout applied to every call, unless overridden per call.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Caps how many bytes of a response body are read, overriding the
+    /// request's [`BodyLimit`]. Larger bodies fail with
+    /// [`ClientError::BodyTooLarge`].
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Returns the shared default client.
+    pub(crate) fn shared() -> &'static Http {
+        &DEFAULT
+    }
+
+    /// Binds a clone of this client to a request's propagated headers,
+    /// deadline and body limit.
+    pub(crate) fn bind(
+        &self,
+        headers: &HeaderMap,
+        deadline: Option<Instant>,
+        body_limit: Option<BodyLimit>,
+    ) -> Self {
+        let mut propagated = HeaderMap::new();
+        for name in PROPAGATED {
+            if let Some(value) = headers.get(&name) {
+                propagated.insert(name, value.clone());
+            }
+        }
+        Self {
+            client: self.client.clone(),
+            timeout: self.timeout,
+            max_body_size: self
+                .max_body_size
+                .or(body_limit.map(|BodyLimit(bytes)| bytes)),
+            propagated,
+            deadline,
+        }
+    }
+
+    /// Starts a `GET` request.
+    pub fn get(&self, url: impl AsRef<str>) -> ClientRequest {
+        self.request(Method::GET, url)
+    }
+
+    /// Starts a `POST` request.
+    pub fn post(&self, url: impl AsRef<str>) -> ClientRequest {
+        self.request(Method::POST, url)
+    }
+
+    /// Starts a request with any method.
+    pub fn request(&self, method: Method, url: impl AsRef<str>) -> ClientRequest {
+        let url = url.as_ref();
+        ClientRequest {
+            http: self.clone(),
+            method,
+            uri: url
+                .parse()
+                .map_err(|_| ClientError::InvalidUrl(url.to_owned())),
+            headers: self.propagated.clone(),
+            body: Bytes::new(),
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// A request being built by [`Http`]; send it with [`ClientRequest::send`].
+#[derive(Debug)]
+pub struct ClientRequest {
+    http: Http,
+    method: Method,
+    uri: Result<Uri, ClientError>,
+    headers: HeaderMap,
+    body: Bytes,
+    timeout: Option<Duration>,
+}
+
+impl ClientRequest {
+    /// Sets a header, replacing any propagated value of the same name.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets the body.
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Serializes `body` as the JSON body and sets `Content-Type`.
+    pub fn json<T: Serialize>(mut self, body: &T) -> Self {
+        match serde_json::to_vec(body) {
+            Ok(json) => {
+                self.body = json.into();
+                self.headers
+                    .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            }
+            Err(e) => self.uri = Err(ClientError::Json(e.to_string())),
+        }
+        self
+    }
+
+    /// Overrides the client's timeout for this call. The request deadline
+    /// still applies if it is sooner.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sends the request and collects the response body, up to the client's
+    /// [`max_body_size`](Http::max_body_size).
+    pub async fn send(self) -> Result<ClientResponse, ClientError> {
+        let (limit, by_deadline) = self.time_limit()?;
+        let max_bytes = self
+            .http
+            .max_body_size
+            .unwrap_or_else(|| BodyLimit::default().0);
+
+        let mut req = hyper::Request::builder()
+            .method(self.method)
+            .uri(self.uri?)
+            .body(Full::new(self.body))
+            .map_err(|e| ClientError::Request(e.to_string()))?;
+        *req.headers_mut() = self.headers;
+
+        let client = self.http.client;
+        let exchange = async move {
+            let res = client
+                .request(req)
+                .await
+                .map_err(|e| ClientError::Request(e.to_string()))?;
+            let (parts, body) = res.into_parts();
+            let declared_len = parts
+                .headers
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<usize>().ok());
+            if declared_len.is_some_and(|len| len > max_bytes) {
+                return Err(ClientError::BodyTooLarge(max_bytes));
+            }
+            let body = Limited::new(body, max_bytes)
+                .collect()
+                .await
+                .map_err(|e| match e.downcast_ref::<LengthLimitError>() {
+                    Some(_) => ClientError::BodyTooLarge(max_bytes),
+                    None => ClientError::Request(e.to_string()),
+                })?
+                .to_bytes();
+            Ok(ClientResponse {
+                status: parts.status,
+                headers: parts.headers,
+                body,
+            })
+        };
+
+        match limit {
+            None => exchange.await,
+            Some(limit) => {
+                tokio::time::timeout(limit, exchange)
+                    .await
+                    .unwrap_or(Err(if by_deadline {
+                        ClientError::DeadlineExceeded
+                    } else {
+                        ClientError::Timeout
+                    }))
+            }
+        }
+    }
+
+    /// The time the call may take, and whether the request deadline set it.
+    fn time_limit(&self) -> Result<(Option<Duration>, bool), ClientError> {
+        let remaining = match self.http.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Err(ClientError::DeadlineExceeded),
+            },
+            None => None,
+        };
+        Ok(match (self.timeout, remaining) {
+            (Some(timeout), Some(remaining)) if remaining < timeout => (Some(remaining), true),
+            (Some(timeout), _) => (Some(timeout), false),
+            (None, remaining) => (remaining, remaining.is_some()),
+        })
+    }
+}
+
+/// A response received by [`Http`], with its body fully read.
+#[derive(Debug, Clone)]
+pub struct ClientResponse {
+    /// The response status.
+    pub status: StatusCode,
+    /// The response headers.
+    pub headers: HeaderMap,
+    /// The response body.
+    pub body: Bytes,
+}
+
+impl ClientResponse {
+    /// Deserializes the body as JSON.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, ClientError> {
+        serde_json::from_slice(&self.body).map_err(|e| ClientError::Json(e.to_string()))
+    }
+
+    /// Returns the body as text, replacing invalid UTF-8.
+    pub fn text(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+}
+
+/// Errors returned by [`ClientRequest::send`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ClientError {
+    /// The URL could not be parsed.
+    #[error("invalid URL: {0}")]
+    InvalidUrl(String),
+    /// The body could not be serialized, or the response body deserialized.
+    #[error("JSON error: {0}")]
+    Json(String),
+    /// The call took longer than its timeout.
+    #[error("request timed out")]
+    Timeout,
+    /// The incoming request's deadline passed before the call completed.
+    #[error("request deadline exceeded")]
+    DeadlineExceeded,
+    /// The response body was larger than the limit, in bytes.
+    #[error("response body exceeds {0} bytes")]
+    BodyTooLarge(usize),
+    /// Connecting, sending or reading the response failed.
+    #[error("request failed: {0}")]
+    Request(String),
+}
@@ -0,0 +1,581 @@
+//! Declarative configuration of the built-in middleware and app settings.
+//!
+//! Every struct here implements [`Deserialize`], so a configuration file in
+//! any serde format (TOML, JSON, YAML, ...) can be loaded with that format's
+//! crate and applied with [`App::apply_config`](crate::prelude::App::apply_config):
+//!
+//! ```rust,no_run
+//! use expressjs::config::AppConfigFile;
+//! use expressjs::prelude::*;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let config: AppConfigFile = serde_json::from_str(&std::fs::read_to_string("app.json")?)?;
+//! let mut app = express();
+//! app.apply_config(&config)?;
+//! app.listen(config.server.port, |port| async move {
+//!     println!("listening on {port}");
+//! })
+//! .await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every field is optional except the `path` and `root` of static mounts,
+//! and unknown fields are rejected so typos don't go unnoticed. Values are
+//! checked with [`AppConfigFile::validate`] before anything is mounted.
+
+use crate::handler::request::{JsonLimits, TrustProxy};
+use crate::handler::response::ErrorFormat;
+use crate::middleware::{
+    BodySizeLimitMiddleware, CorsMiddleware, RateLimitMiddleware, StaticServeMiddleware,
+};
+use hyper::Method;
+use hyper::header::HeaderName;
+use serde::{Deserialize, Deserializer};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+/// A configuration value that cannot be used, naming the offending field.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ConfigError {
+    /// A field holds a value of the right type that is out of range or malformed.
+    #[error("invalid `{field}`: {reason}")]
+    Invalid {
+        /// The dotted path of the field, e.g. `rate_limit.window_secs`.
+        field: String,
+        /// Why the value was rejected.
+        reason: String,
+    },
+    /// An environment variable read by [`AppConfigFile::from_env`] could not
+    /// be deserialized into the field it names.
+    #[error("invalid environment variable `{var}`: {reason}")]
+    Env {
+        /// The name of the variable.
+        var: String,
+        /// Why the value was rejected.
+        reason: String,
+    },
+}
+
+impl ConfigError {
+    fn invalid(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        ConfigError::Invalid {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// The whole configuration of an app, as loaded from a file or the environment.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfigFile {
+    /// App-wide settings.
+    pub server: ServerConfig,
+    /// Mounts a [`CorsMiddleware`] when present.
+    pub cors: Option<CorsConfig>,
+    /// Mounts a [`RateLimitMiddleware`] when present.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Mounts a [`BodySizeLimitMiddleware`] when present.
+    pub body_limit: Option<BodyLimitConfig>,
+    /// Mounts one [`StaticServeMiddleware`] per entry, in order.
+    #[serde(rename = "static")]
+    pub static_mounts: Vec<StaticMountConfig>,
+}
+
+/// App-wide settings.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The port to pass to [`App::listen`](crate::prelude::App::listen). Not
+    /// applied by [`App::apply_config`](crate::prelude::App::apply_config),
+    /// which doesn't start the server. `3000` by default.
+    pub port: u16,
+    /// Which proxies are trusted: `false`, `true`, a number of hops or a list
+    /// of proxy addresses. See [`TrustProxy`].
+    pub trust_proxy: TrustProxy,
+    /// `"text"`, `"json"` or `"negotiate"`. See [`ErrorFormat`].
+    pub error_format: ErrorFormat,
+    /// Whether server error bodies carry the error chain; for development.
+    /// See [`App::error_details`](crate::prelude::App::error_details).
+    pub error_details: bool,
+    /// Maximum size in bytes of JSON request bodies.
+    pub json_max_bytes: usize,
+    /// Maximum nesting depth of JSON request bodies.
+    pub json_max_depth: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let json = JsonLimits::default();
+        Self {
+            port: 3000,
+            trust_proxy: TrustProxy::Disabled,
+            error_format: ErrorFormat::Text,
+            error_details: false,
+            json_max_bytes: json.max_bytes,
+            json_max_depth: json.max_depth,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The JSON limits described by this configuration.
+    pub fn json_limits(&self) -> JsonLimits {
+        JsonLimits {
+            max_bytes: self.json_max_bytes,
+            max_depth: self.json_max_depth,
+        }
+    }
+}
+
+/// Settings of a [`CorsMiddleware`]. List fields also accept a single
+/// comma-separated string, which suits environment variables.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Allowed origins; `"*"` allows any.
+    #[serde(deserialize_with = "string_or_list")]
+    pub origins: Vec<String>,
+    /// Methods allowed in preflight responses.
+    #[serde(deserialize_with = "string_or_list")]
+    pub methods: Vec<String>,
+    /// Headers allowed in preflight responses.
+    #[serde(deserialize_with = "string_or_list")]
+    pub headers: Vec<String>,
+    /// Whether to send `Access-Control-Allow-Credentials: true`.
+    pub allow_credentials: bool,
+    /// How long, in seconds, browsers may cache a preflight response.
+    pub max_age: Option<u32>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let cors = CorsMiddleware::default();
+        Self {
+            origins: Vec::new(),
+            methods: cors.allowed_methods.into_iter().collect(),
+            headers: Vec::new(),
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl From<CorsConfig> for CorsMiddleware {
+    fn from(config: CorsConfig) -> Self {
+        CorsMiddleware::new_inner(
+            config.origins.into_iter().collect(),
+            config.methods.into_iter().collect(),
+            config.headers.into_iter().collect(),
+            config.allow_credentials,
+            config.max_age,
+        )
+    }
+}
+
+/// Settings of a [`RateLimitMiddleware`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Requests a client may make per window. `60` by default.
+    pub requests_per_window: u32,
+    /// Length of the window in seconds. `60` by default.
+    pub window_secs: u64,
+    /// Response bytes a client may receive per window, unlimited by default.
+    pub bytes_per_window: Option<u64>,
+    /// Send `Retry-After` as an HTTP date rather than a number of seconds.
+    pub retry_after_http_date: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_window: 60,
+            window_secs: 60,
+            bytes_per_window: None,
+            retry_after_http_date: false,
+        }
+    }
+}
+
+impl From<RateLimitConfig> for RateLimitMiddleware {
+    fn from(config: RateLimitConfig) -> Self {
+        let mut limiter = RateLimitMiddleware::new(
+            config.requests_per_window,
+            Duration::from_secs(config.window_secs),
+        );
+        if let Some(bytes) = config.bytes_per_window {
+            limiter = limiter.bandwidth_limit(bytes);
+        }
+        if config.retry_after_http_date {
+            limiter = limiter.retry_after_http_date();
+        }
+        limiter
+    }
+}
+
+/// Settings of a [`BodySizeLimitMiddleware`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BodyLimitConfig {
+    /// Maximum body size in bytes. 10 MB by default.
+    pub max_bytes: usize,
+    /// Reject requests without a `Content-Length` header.
+    pub strict: bool,
+}
+
+impl Default for BodyLimitConfig {
+    fn default() -> Self {
+        let limit = BodySizeLimitMiddleware::default();
+        Self {
+            max_bytes: limit.max_size_bytes,
+            strict: limit.strict,
+        }
+    }
+}
+
+impl From<BodyLimitConfig> for BodySizeLimitMiddleware {
+    fn from(config: BodyLimitConfig) -> Self {
+        BodySizeLimitMiddleware {
+            max_size_bytes: config.max_bytes,
+            strict: config.strict,
+        }
+    }
+}
+
+/// A directory served by a [`StaticServeMiddleware`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticMountConfig {
+    /// The URL prefix the files are served under, e.g. `/assets`.
+    pub path: String,
+    /// The directory the files are read from.
+    pub root: String,
+    /// `Cache-Control: max-age` in seconds; `0` sends `no-cache` instead.
+    /// One hour by default.
+    #[serde(default = "default_static_max_age")]
+    pub max_age: u64,
+}
+
+fn default_static_max_age() -> u64 {
+    3600
+}
+
+impl StaticMountConfig {
+    /// The route pattern the middleware is mounted on.
+    pub(crate) fn mount_path(&self) -> String {
+        format!("{}/{{*p}}", self.path.trim_end_matches('/'))
+    }
+}
+
+impl From<StaticMountConfig> for StaticServeMiddleware {
+    fn from(config: StaticMountConfig) -> Self {
+        let serve = StaticServeMiddleware::new(config.root);
+        match config.max_age {
+            0 => serve.no_cache(),
+            secs => serve.max_age(secs),
+        }
+    }
+}
+
+impl AppConfigFile {
+    /// Checks the values that deserialization alone can't, such as zero
+    /// limits, malformed methods or header names and missing directories.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::Invalid`] naming the first offending field.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.json_max_bytes == 0 {
+            return Err(ConfigError::invalid(
+                "server.json_max_bytes",
+                "must be greater than zero",
+            ));
+        }
+        if self.server.json_max_depth == 0 {
+            return Err(ConfigError::invalid(
+                "server.json_max_depth",
+                "must be greater than zero",
+            ));
+        }
+
+        if let Some(cors) = &self.cors {
+            if cors.origins.is_empty() {
+                return Err(ConfigError::invalid(
+                    "cors.origins",
+                    "must list at least one origin, or \"*\" for any",
+                ));
+            }
+            for method in &cors.methods {
+                if Method::from_bytes(method.as_bytes()).is_err() {
+                    return Err(ConfigError::invalid(
+                        "cors.methods",
+                        format!("{method:?} is not an HTTP method"),
+                    ));
+                }
+            }
+            for header in &cors.headers {
+                if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                    return Err(ConfigError::invalid(
+                        "cors.headers",
+                        format!("{header:?} is not a header name"),
+                    ));
+                }
+            }
+        }
+
+        if let Some(limit) = &self.rate_limit {
+            if limit.requests_per_window == 0 {
+                return Err(ConfigError::invalid(
+                    "rate_limit.requests_per_window",
+                    "must be greater than zero",
+                ));
+            }
+            if limit.window_secs == 0 {
+                return Err(ConfigError::invalid(
+                    "rate_limit.window_secs",
+                    "must be greater than zero",
+                ));
+            }
+        }
+
+        if let Some(limit) = &self.body_limit
+            && limit.max_bytes == 0
+        {
+            return Err(ConfigError::invalid(
+                "body_limit.max_bytes",
+                "must be greater than zero",
+            ));
+        }
+
+        for (i, mount) in self.static_mounts.iter().enumerate() {
+            if !mount.path.starts_with('/') {
+                return Err(ConfigError::invalid(
+                    format!("static[{i}].path"),
+                    format!("{:?} must start with '/'", mount.path),
+                ));
+            }
+            if !Path::new(&mount.root).is_dir() {
+                return Err(ConfigError::invalid(
+                    format!("static[{i}].root"),
+                    format!("{:?} is not a directory", mount.root),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads a configuration from the environment variables starting with
+    /// `{prefix}_`, then [validates](Self::validate) it.
+    ///
+    /// The rest of a variable's name is the lowercased path of the field,
+    /// with sections separated by a double underscore: with the prefix `APP`,
+    /// `APP_SERVER__PORT=8080` sets `server.port` and
+    /// `APP_RATE_LIMIT__WINDOW_SECS=30` sets `rate_limit.window_secs`. Values
+    /// are read as JSON when they parse as such and as strings otherwise,
+    /// so `APP_CORS__ORIGINS=https://a.example,https://b.example` and
+    /// `APP_STATIC=[{"path":"/assets","root":"public"}]` both work.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::Env`] naming the first variable that doesn't
+    /// fit its field or names no field at all, or the error of [`validate`](Self::validate).
+    pub fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        Self::from_vars(prefix, std::env::vars())
+    }
+
+    fn from_vars(
+        prefix: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let prefix = format!("{prefix}_");
+        let mut vars: Vec<_> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(&prefix))
+            .collect();
+        vars.sort();
+
+        let mut merged = serde_json::Map::new();
+        for (var, raw) in vars {
+            let path: Vec<String> = var[prefix.len()..]
+                .split("__")
+                .map(str::to_ascii_lowercase)
+                .collect();
+            let value = serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw));
+
+            // Each variable is deserialized on its own first, so a bad value
+            // is reported against the variable that holds it.
+            let mut alone = serde_json::Map::new();
+            insert_path(&mut alone, &path, value.clone());
+            let env_error = |reason: String| ConfigError::Env {
+                var: var.clone(),
+                reason,
+            };
+            serde_json::from_value::<Self>(alone.into()).map_err(|e| env_error(e.to_string()))?;
+            if !insert_path(&mut merged, &path, value) {
+                return Err(env_error("conflicts with another variable".into()));
+            }
+        }
+
+        let config: Self = serde_json::from_value(merged.into()).map_err(|e| ConfigError::Env {
+            var: format!("{prefix}*"),
+            reason: e.to_string(),
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Sets `value` at `path` in `map`, creating intermediate objects. Returns
+/// `false` if the path runs through a value that isn't an object.
+fn insert_path(
+    map: &mut serde_json::Map<String, serde_json::Value>,
+    path: &[String],
+    value: serde_json::Value,
+) -> bool {
+    let (last, sections) = path.split_last().expect("split yields at least one part");
+    let mut map = map;
+    for section in sections {
+        let entry = map
+            .entry(section.clone())
+            .or_insert_with(|| serde_json::Value::Object(Default::default()));
+        match entry {
+            serde_json::Value::Object(inner) => map = inner,
+            _ => return false,
+        }
+    }
+    map.insert(last.clone(), value);
+    true
+}
+
+/// Accepts a list of strings, or a single string of comma-separated items.
+fn string_or_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::List(items) => items,
+        StringOrList::String(items) => items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect(),
+    })
+}
+
+impl<'de> Deserialize<'de> for TrustProxy {
+    /// Reads `false`, `true`, a number of trusted hops or a list of proxy addresses.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Enabled(bool),
+            Hops(usize),
+            Ips(Vec<IpAddr>),
+        }
+
+        Ok(
+            match Repr::deserialize(deserializer).map_err(|_| {
+                serde::de::Error::custom(
+                    "expected a boolean, a number of hops or a list of IP addresses",
+                )
+            })? {
+                Repr::Enabled(false) => TrustProxy::Disabled,
+                Repr::Enabled(true) => TrustProxy::All,
+                Repr::Hops(hops) => TrustProxy::Hops(hops),
+                Repr::Ips(ips) => TrustProxy::ips(ips),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_env_reads_prefixed_variables() {
+        let config = AppConfigFile::from_vars(
+            "APP",
+            vars(&[
+                ("APP_SERVER__PORT", "8080"),
+                ("APP_SERVER__TRUST_PROXY", "2"),
+                ("APP_SERVER__ERROR_FORMAT", "json"),
+                ("APP_SERVER__ERROR_DETAILS", "true"),
+                ("APP_CORS__ORIGINS", "https://a.example, https://b.example"),
+                ("APP_CORS__MAX_AGE", "600"),
+                ("APP_RATE_LIMIT__WINDOW_SECS", "30"),
+                ("APP_BODY_LIMIT__STRICT", "true"),
+                ("OTHER_SERVER__PORT", "1"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.server.trust_proxy, TrustProxy::Hops(2));
+        assert_eq!(config.server.error_format, ErrorFormat::Json);
+        assert!(config.server.error_details);
+        let cors = config.cors.unwrap();
+        assert_eq!(cors.origins, ["https://a.example", "https://b.example"]);
+        assert_eq!(cors.max_age, Some(600));
+        assert_eq!(config.rate_limit.unwrap().window_secs, 30);
+        assert!(config.body_limit.unwrap().strict);
+        assert!(config.static_mounts.is_empty());
+    }
+
+    #[test]
+    fn test_errors_name_the_field() {
+        let err =
+            AppConfigFile::from_vars("APP", vars(&[("APP_SERVER__PORT", "http")])).unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::Env { var, .. } if var == "APP_SERVER__PORT"),
+            "{err}"
+        );
+
+        let err = AppConfigFile::from_vars("APP", vars(&[("APP_SERVER__PROT", "80")])).unwrap_err();
+        assert!(err.to_string().contains("unknown field `prot`"), "{err}");
+
+        let err = AppConfigFile::from_vars("APP", vars(&[("APP_RATE_LIMIT__WINDOW_SECS", "0")]))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ConfigError::invalid("rate_limit.window_secs", "must be greater than zero")
+        );
+
+        let config: AppConfigFile = serde_json::from_str(
+            r#"{"cors": {"origins": "*", "methods": ["GET", "NOT A METHOD"]},
+                "static": [{"path": "/assets", "root": "/definitely/not/here"}]}"#,
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(
+            err.to_string().starts_with("invalid `cors.methods`"),
+            "{err}"
+        );
+
+        let config: AppConfigFile = serde_json::from_str(
+            r#"{"static": [{"path": "/assets", "root": "/definitely/not/here"}]}"#,
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(
+            err.to_string().starts_with("invalid `static[0].root`"),
+            "{err}"
+        );
+    }
+}
@@ -1,3 +1,7 @@
+pub(crate) mod catch_panic;
+pub(crate) mod debug_echo;
+/// Provides typed extractors for handler arguments.
+pub mod extract;
 /// Provides request parsing and extraction utilities.
 pub mod request;
 /// Provides response creation and formatting utilities.
@@ -6,7 +10,7 @@ pub mod response;
 use async_trait::async_trait;
 use hyper::body::Incoming;
 pub use request::Request;
-pub use response::{ExpressResponse, Response, ResponseError};
+pub use response::{ExpressResponse, IntoResponse, Response, ResponseError};
 
 /// Trait for async handler abstraction.
 #[async_trait]
@@ -15,15 +19,38 @@ pub trait Handler<B = Incoming>: Send + Sync + 'static {
     async fn call(&self, req: Request<B>, res: Response) -> Response;
 }
 
-/// Blanket impl for closures or functions that match the async signature.
+/// Blanket impl for closures or functions that match the async signature and
+/// return anything convertible with [`IntoResponse`].
 #[async_trait]
 impl<F, Fut, B> Handler<B> for F
 where
     F: Fn(Request<B>, Response) -> Fut + Send + Sync + 'static,
-    Fut: Future<Output = Response> + Send + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
     B: Send + 'static,
 {
-    async fn call(&self, req: Request<B>, res: Response) -> Response {
-        (self)(req, res).await
+    async fn call(&self, req: Request<B>, mut res: Response) -> Response {
+        // Only a response the middleware decorated needs keeping: if the
+        // handler builds a fresh one instead of returning `res`, it is laid
+        // over a copy of it.
+        let base = (res.status != hyper::StatusCode::OK || !res.headers.is_empty()).then(|| {
+            res.extensions.insert(Decorated);
+            (res.status, res.headers.clone())
+        });
+        let mut built = (self)(req, res).await.into_response();
+        match base {
+            Some(_) if built.extensions.remove::<Decorated>().is_some() => built,
+            Some((status, headers)) => {
+                let mut base = Response::new();
+                base.status = status;
+                base.headers = headers;
+                extract::merge_response(base, built)
+            }
+            None => built,
+        }
     }
 }
+
+/// Marks the response a handler was given, to tell it from one it built.
+#[derive(Clone)]
+struct Decorated;
@@ -0,0 +1,52 @@
+//! Turns handler panics into `500` responses instead of dropping the connection.
+
+use super::{IntoResponse, Response, ResponseError};
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
+use std::sync::{Arc, Once};
+
+/// Backtrace of a handler panic, attached to the `500` response's extensions
+/// when the backtrace hook is installed and `RUST_BACKTRACE` is enabled.
+#[derive(Debug, Clone)]
+pub(crate) struct PanicBacktrace(pub(crate) Arc<Backtrace>);
+
+thread_local! {
+    static LAST_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static HOOK: Once = Once::new();
+
+/// Installs (once) a panic hook that records a backtrace for the panicking
+/// thread before unwinding starts; the previous hook still runs afterwards.
+pub(crate) fn install_backtrace_hook() {
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let backtrace = Backtrace::capture();
+            if backtrace.status() == BacktraceStatus::Captured {
+                LAST_BACKTRACE.with(|last| *last.borrow_mut() = Some(backtrace));
+            }
+            previous(info);
+        }));
+    });
+}
+
+/// The message of a panic raised with a string, as `panic!` does.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        Some(s)
+    } else {
+        payload.downcast_ref::<String>().map(String::as_str)
+    }
+}
+
+/// Builds the response for a handler that panicked with `payload`.
+pub(crate) fn panic_response(payload: Box<dyn Any + Send>) -> Response {
+    let message = panic_message(&*payload).unwrap_or("handler panicked");
+    let mut res = ResponseError::HandlerPanicked(message.to_owned()).into_response();
+    if let Some(backtrace) = LAST_BACKTRACE.with(|last| last.borrow_mut().take()) {
+        res.extensions.insert(PanicBacktrace(Arc::new(backtrace)));
+    }
+    res
+}
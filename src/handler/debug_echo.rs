@@ -0,0 +1,60 @@
+//! The development echo endpoint registered by [`App::debug_echo`](crate::prelude::App::debug_echo).
+
+use super::request::{BodyLimit, RequestExt};
+use super::{ExpressResponse, Request, Response, ResponseError};
+use http_body_util::BodyExt;
+use serde_json::{Map, Value, json};
+
+/// The most body bytes the echo endpoint reads; larger bodies get `413`.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Answers with the request's method, path, query, headers and body as JSON.
+/// Repeated query keys and header names map to arrays of their values.
+pub(crate) async fn debug_echo<B>(
+    mut req: Request<B>,
+    res: Response,
+) -> Result<Response, ResponseError>
+where
+    B: BodyExt + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+{
+    let limit = req
+        .extensions()
+        .get::<BodyLimit>()
+        .map_or(MAX_BODY_BYTES, |limit| limit.0.min(MAX_BODY_BYTES));
+    req.extensions_mut().insert(BodyLimit(limit));
+
+    let mut query = Map::new();
+    for (key, value) in form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()) {
+        push(&mut query, key.into_owned(), value.into_owned());
+    }
+    let mut headers = Map::new();
+    for (name, value) in req.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        push(&mut headers, name.as_str().to_owned(), value);
+    }
+    let method = req.method().to_string();
+    let path = req.uri().path().to_owned();
+
+    let body = req.bytes().await?;
+    Ok(res.json(&json!({
+        "method": method,
+        "path": path,
+        "query": query,
+        "headers": headers,
+        "body": String::from_utf8_lossy(&body),
+        "body_size": body.len(),
+    })))
+}
+
+/// Adds `value` under `key`, turning the entry into an array on repeats.
+fn push(map: &mut Map<String, Value>, key: String, value: String) {
+    match map.get_mut(&key) {
+        Some(Value::Array(values)) => values.push(value.into()),
+        Some(first) => *first = Value::Array(vec![first.take(), value.into()]),
+        None => {
+            map.insert(key, value.into());
+        }
+    }
+}
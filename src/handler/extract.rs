@@ -0,0 +1,598 @@
+//! Typed extractors for handler arguments.
+//!
+//! Handlers registered with the `*_x` methods (e.g. [`App::get_x`](crate::prelude::App::get_x))
+//! take up to six arguments implementing [`FromRequest`], in any order, and
+//! return anything implementing [`IntoResponse`]. At most one argument may
+//! consume the body. Extraction failures short-circuit with the extractor's
+//! rejection, usually an [`ExtractRejection`].
+
+use super::request::{
+    MatchedPath, QueryOptions, RequestExt, RouteParams, check_limits, is_json_content_type,
+    read_json,
+};
+use super::response::{IntoResponse, Json, ResponseError};
+use super::{Handler, Request, Response};
+use async_trait::async_trait;
+use cookie::{Cookie, CookieJar};
+use http_body_util::BodyExt;
+use hyper::header::{CONTENT_TYPE, COOKIE, SET_COOKIE};
+use hyper::http::Extensions;
+use hyper::{HeaderMap, StatusCode};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use thiserror::Error;
+
+mod de;
+
+use de::{NestedDeserializer, PairsDeserializer};
+
+/// Extracts a value from the request head (URI, headers, extensions) without
+/// touching the body.
+///
+/// Every such type is also a [`FromRequest`] extractor.
+pub trait FromRequestParts: Sized {
+    /// The response produced when extraction fails.
+    type Rejection: IntoResponse;
+
+    /// Extracts `Self` from the request head.
+    fn from_request_parts(head: &Request<()>) -> Result<Self, Self::Rejection>;
+}
+
+/// Extracts a value from the request, possibly consuming its body.
+///
+/// `body` is `None` once an earlier argument has consumed it.
+#[async_trait]
+pub trait FromRequest<B>: Sized {
+    /// The response produced when extraction fails.
+    type Rejection: IntoResponse;
+
+    /// Extracts `Self` from the request head and body.
+    async fn from_request(
+        head: &Request<()>,
+        body: &mut Option<B>,
+    ) -> Result<Self, Self::Rejection>;
+}
+
+#[async_trait]
+impl<B, T> FromRequest<B> for T
+where
+    B: Send + 'static,
+    T: FromRequestParts,
+{
+    type Rejection = T::Rejection;
+
+    async fn from_request(
+        head: &Request<()>,
+        _body: &mut Option<B>,
+    ) -> Result<Self, Self::Rejection> {
+        T::from_request_parts(head)
+    }
+}
+
+/// Why an extractor could not produce its value.
+#[derive(Error, Debug)]
+pub enum ExtractRejection {
+    /// The route parameters don't deserialize into the requested type.
+    #[error("invalid path parameters: {0}")]
+    InvalidPath(String),
+    /// The query string doesn't deserialize into the requested type.
+    #[error("invalid query string: {0}")]
+    InvalidQuery(String),
+    /// The query string is longer than [`QueryOptions::max_length`](crate::prelude::QueryOptions::max_length).
+    #[error("query string is longer than {0} bytes")]
+    QueryTooLong(usize),
+    /// The body was not declared as JSON.
+    #[error("expected a request body with content type `application/json`")]
+    UnsupportedMediaType,
+    /// Another argument of the handler already consumed the body.
+    #[error("the request body was already extracted")]
+    BodyAlreadyExtracted,
+    /// Reading or parsing the body failed.
+    #[error(transparent)]
+    Body(#[from] ResponseError),
+    /// No state of the requested type was registered with [`App::state`](crate::prelude::App::state).
+    #[error("no application state of type `{0}`")]
+    MissingState(&'static str),
+}
+
+impl ExtractRejection {
+    /// Returns the HTTP status code this rejection responds with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ExtractRejection::InvalidPath(_) | ExtractRejection::InvalidQuery(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            ExtractRejection::QueryTooLong(_) => StatusCode::URI_TOO_LONG,
+            ExtractRejection::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ExtractRejection::Body(e) => e.status(),
+            ExtractRejection::BodyAlreadyExtracted | ExtractRejection::MissingState(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl IntoResponse for ExtractRejection {
+    fn into_response(self) -> Response {
+        match self {
+            ExtractRejection::Body(e) => e.into_response(),
+            rejection if rejection.status().is_server_error() => {
+                // A programming error in the handler, not the client's fault.
+                ResponseError::Internal(rejection.to_string()).into_response()
+            }
+            rejection => (rejection.status(), rejection.to_string()).into_response(),
+        }
+    }
+}
+
+/// Deserializes the matched route parameters, e.g. `Path<u32>` for
+/// `/users/{id}`, a tuple for several parameters, or a struct by name.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequestParts for Path<T> {
+    type Rejection = ExtractRejection;
+
+    fn from_request_parts(req: &Request<()>) -> Result<Self, Self::Rejection> {
+        deserialize_params(req.extensions()).map(Path)
+    }
+}
+
+/// Deserializes the route parameters found in `extensions`; shared by
+/// [`Path`] and [`RequestExt::params_as`].
+pub(crate) fn deserialize_params<T: DeserializeOwned>(
+    extensions: &Extensions,
+) -> Result<T, ExtractRejection> {
+    let pattern = extensions.get::<MatchedPath>().map(|p| &*p.0);
+    let params = extensions.get::<RouteParams>();
+
+    // Only the matched route's own parameters: mounted middleware may add more.
+    let pairs: Vec<(&str, &str)> = match (pattern, params) {
+        (Some(pattern), Some(params)) => param_names(pattern)
+            .filter_map(|name| Some((name, params.get(name)?)))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    T::deserialize(PairsDeserializer::new(&pairs))
+        .map_err(|e| ExtractRejection::InvalidPath(e.to_string()))
+}
+
+/// Deserializes a query string parsed by [`RequestExt::query_nested`].
+pub(crate) fn deserialize_nested_query<T: DeserializeOwned>(
+    query: &serde_json::Value,
+) -> Result<T, ExtractRejection> {
+    T::deserialize(NestedDeserializer(query))
+        .map_err(|e| ExtractRejection::InvalidQuery(e.to_string()))
+}
+
+/// Yields the parameter names of a route pattern in order, e.g. `id` and
+/// `rest` for `/users/{id}/{*rest}`.
+fn param_names(pattern: &str) -> impl Iterator<Item = &str> {
+    pattern.split('/').filter_map(|segment| {
+        let name = segment.strip_prefix('{')?.strip_suffix('}')?;
+        let name = name.strip_suffix('?').unwrap_or(name);
+        Some(name.strip_prefix('*').unwrap_or(name))
+    })
+}
+
+/// Deserializes the query string, e.g. into a struct with `Option` fields for
+/// optional parameters.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequestParts for Query<T> {
+    type Rejection = ExtractRejection;
+
+    fn from_request_parts(req: &Request<()>) -> Result<Self, Self::Rejection> {
+        let query = req.uri().query().unwrap_or("");
+        let options = req
+            .extensions()
+            .get::<QueryOptions>()
+            .copied()
+            .unwrap_or_default();
+        check_limits(query, &options)?;
+        let owned: Vec<_> = form_urlencoded::parse(query.as_bytes()).collect();
+        let pairs: Vec<(&str, &str)> = owned.iter().map(|(k, v)| (&**k, &**v)).collect();
+
+        T::deserialize(PairsDeserializer::new(&pairs))
+            .map(Query)
+            .map_err(|e| ExtractRejection::InvalidQuery(e.to_string()))
+    }
+}
+
+/// Application state registered with [`App::state`](crate::prelude::App::state),
+/// inserted into every request by `App::handle`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedState(pub(crate) Arc<Extensions>);
+
+/// A clone of the application state of type `S`, registered with
+/// [`App::state`](crate::prelude::App::state). Wrap state in an `Arc` to make
+/// the per-request clone cheap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct State<S>(pub S);
+
+impl<S: Clone + Send + Sync + 'static> FromRequestParts for State<S> {
+    type Rejection = ExtractRejection;
+
+    fn from_request_parts(req: &Request<()>) -> Result<Self, Self::Rejection> {
+        req.extensions()
+            .get::<SharedState>()
+            .and_then(|state| state.0.get::<S>())
+            .cloned()
+            .map(State)
+            .ok_or(ExtractRejection::MissingState(std::any::type_name::<S>()))
+    }
+}
+
+/// A copy of the request headers.
+#[derive(Debug, Clone, Default)]
+pub struct Headers(pub HeaderMap);
+
+impl FromRequestParts for Headers {
+    type Rejection = std::convert::Infallible;
+
+    fn from_request_parts(req: &Request<()>) -> Result<Self, Self::Rejection> {
+        Ok(Headers(req.headers().clone()))
+    }
+}
+
+/// The cookies sent with the request. Malformed pairs are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct Cookies(pub CookieJar);
+
+impl Cookies {
+    /// Returns the value of the cookie called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(Cookie::value)
+    }
+}
+
+impl FromRequestParts for Cookies {
+    type Rejection = std::convert::Infallible;
+
+    fn from_request_parts(req: &Request<()>) -> Result<Self, Self::Rejection> {
+        let mut jar = CookieJar::new();
+        for pair in req
+            .header_all(COOKIE)
+            .into_iter()
+            .flat_map(|header| header.split(';'))
+        {
+            if let Ok(cookie) = Cookie::parse(pair.trim().to_owned()) {
+                jar.add_original(cookie);
+            }
+        }
+        Ok(Cookies(jar))
+    }
+}
+
+/// Parses a JSON body like [`RequestExt::json`], so the app's
+/// [`JsonLimits`](crate::prelude::JsonLimits) apply. Bodies that aren't declared
+/// as `application/json` (or a `+json` type) are rejected with `415`.
+#[async_trait]
+impl<B, T> FromRequest<B> for Json<T>
+where
+    B: BodyExt + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+    T: DeserializeOwned,
+{
+    type Rejection = ExtractRejection;
+
+    async fn from_request(
+        head: &Request<()>,
+        body: &mut Option<B>,
+    ) -> Result<Self, Self::Rejection> {
+        if !is_json_content_type(head.header(CONTENT_TYPE)) {
+            return Err(ExtractRejection::UnsupportedMediaType);
+        }
+        let body = body.take().ok_or(ExtractRejection::BodyAlreadyExtracted)?;
+        Ok(Json(read_json(head, body).await?))
+    }
+}
+
+type BoxResponseFuture = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+/// An async function whose arguments are all extractors.
+///
+/// Implemented for functions of up to six arguments; `T` records the argument
+/// types so the implementations don't overlap.
+pub trait ExtractHandler<T, B>: Clone + Send + Sync + 'static {
+    /// Extracts the arguments from `req` and calls the function.
+    fn call(&self, req: Request<B>) -> BoxResponseFuture;
+}
+
+impl<F, Fut, B> ExtractHandler<(), B> for F
+where
+    F: Fn() -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+    B: Send + 'static,
+{
+    fn call(&self, _req: Request<B>) -> BoxResponseFuture {
+        let fut = (self)();
+        Box::pin(async move { fut.await.into_response() })
+    }
+}
+
+macro_rules! impl_extract_handler {
+    ($($arg:ident),+) => {
+        impl<F, Fut, B, $($arg),+> ExtractHandler<($($arg,)+), B> for F
+        where
+            F: Fn($($arg),+) -> Fut + Clone + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse,
+            B: Send + 'static,
+            $($arg: FromRequest<B> + Send + 'static,)+
+        {
+            #[allow(non_snake_case)]
+            fn call(&self, req: Request<B>) -> BoxResponseFuture {
+                let handler = self.clone();
+                Box::pin(async move {
+                    let (parts, body) = req.into_parts();
+                    let head = Request::from_parts(parts, ());
+                    let mut body = Some(body);
+                    $(
+                        let $arg = match $arg::from_request(&head, &mut body).await {
+                            Ok(value) => value,
+                            Err(rejection) => return rejection.into_response(),
+                        };
+                    )+
+                    handler($($arg),+).await.into_response()
+                })
+            }
+        }
+    };
+}
+
+impl_extract_handler!(T1);
+impl_extract_handler!(T1, T2);
+impl_extract_handler!(T1, T2, T3);
+impl_extract_handler!(T1, T2, T3, T4);
+impl_extract_handler!(T1, T2, T3, T4, T5);
+impl_extract_handler!(T1, T2, T3, T4, T5, T6);
+
+/// Adapts an [`ExtractHandler`] to the [`Handler`] trait the router stores.
+pub(crate) struct Extract<H, T> {
+    handler: H,
+    _args: PhantomData<fn() -> T>,
+}
+
+impl<H, T> Extract<H, T> {
+    pub(crate) fn new(handler: H) -> Self {
+        Self {
+            handler,
+            _args: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<H, T, B> Handler<B> for Extract<H, T>
+where
+    H: ExtractHandler<T, B>,
+    T: 'static,
+    B: Send + 'static,
+{
+    async fn call(&self, req: Request<B>, res: Response) -> Response {
+        merge_response(res, self.handler.call(req).await)
+    }
+}
+
+/// Lays the response an extractor handler built over `base`, the one the
+/// middleware before it decorated, as if the handler had built on `base`:
+/// its headers replace those of the same name (cookies add up), its body
+/// and error win, and a `200` keeps a status the middleware set.
+pub(crate) fn merge_response(mut base: Response, built: Response) -> Response {
+    let Response {
+        status,
+        headers,
+        body,
+        error,
+        extensions,
+    } = built;
+    if status != StatusCode::OK {
+        base.status = status;
+    }
+    let mut name = None;
+    for (next, value) in headers {
+        if let Some(next) = next {
+            if next != SET_COOKIE {
+                base.headers.remove(&next);
+            }
+            name = Some(next);
+        }
+        if let Some(name) = &name {
+            base.headers.append(name, value);
+        }
+    }
+    base.body = body;
+    base.error = error;
+    base.extensions.extend(extensions);
+    base
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::request::RequestMetadataInternal;
+    use crate::router::interner::INTERNER;
+
+    fn routed(pattern: &str, params: &[(&str, &str)], uri: &str) -> Request<()> {
+        let mut req = Request::builder().uri(uri).body(()).unwrap();
+        req.extensions_mut().insert(MatchedPath(pattern.into()));
+        req.set_params(
+            params
+                .iter()
+                .map(|(k, v)| (INTERNER.get_or_intern(k), Arc::from(*v)))
+                .collect(),
+        );
+        req
+    }
+
+    #[test]
+    fn test_param_names() {
+        let names: Vec<_> = param_names("/users/{id}/files/{*rest}").collect();
+        assert_eq!(names, ["id", "rest"]);
+        assert_eq!(param_names("/static").count(), 0);
+    }
+
+    #[test]
+    fn test_path_extractor() {
+        let req = routed(
+            "/users/{id}/posts/{slug}",
+            &[("p", "ignored"), ("id", "7"), ("slug", "hello")],
+            "/users/7/posts/hello",
+        );
+        let Path((id, slug)) = Path::<(u32, String)>::from_request_parts(&req).unwrap();
+        assert_eq!((id, slug.as_str()), (7, "hello"));
+
+        #[derive(Debug, serde::Deserialize)]
+        struct Params {
+            slug: String,
+            id: u64,
+        }
+        let Path(params) = Path::<Params>::from_request_parts(&req).unwrap();
+        assert_eq!((params.id, params.slug.as_str()), (7, "hello"));
+
+        // A single value needs exactly one parameter.
+        let err = Path::<u32>::from_request_parts(&req).unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        let req = routed("/users/{id}", &[("id", "abc")], "/users/abc");
+        let err = Path::<u32>::from_request_parts(&req).unwrap_err();
+        assert!(matches!(err, ExtractRejection::InvalidPath(_)));
+    }
+
+    #[test]
+    fn test_params_as_struct() {
+        #[derive(Debug, serde::Deserialize)]
+        struct PathParams {
+            id: u32,
+            slug: String,
+        }
+
+        let req = routed(
+            "/posts/{id}/{slug}",
+            &[("id", "42"), ("slug", "hello-world")],
+            "/posts/42/hello-world",
+        );
+        let params: PathParams = req.params_as().unwrap();
+        assert_eq!((params.id, params.slug.as_str()), (42, "hello-world"));
+
+        let req = routed(
+            "/posts/{id}/{slug}",
+            &[("id", "forty-two"), ("slug", "hello-world")],
+            "/posts/forty-two/hello-world",
+        );
+        let err = req.params_as::<PathParams>().unwrap_err();
+        assert!(matches!(err, ExtractRejection::InvalidPath(_)), "{err}");
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert!(err.to_string().contains("forty-two"), "{err}");
+
+        #[derive(Debug, serde::Deserialize)]
+        struct OptionalSlug {
+            id: u32,
+            slug: Option<String>,
+        }
+
+        let req = routed(
+            "/posts/{id}/{slug?}",
+            &[("id", "5"), ("slug", "my-title")],
+            "/posts/5/my-title",
+        );
+        let params: OptionalSlug = req.params_as().unwrap();
+        assert_eq!((params.id, params.slug.as_deref()), (5, Some("my-title")));
+
+        let req = routed("/posts/{id}/{slug?}", &[("id", "5")], "/posts/5");
+        let params: OptionalSlug = req.params_as().unwrap();
+        assert_eq!((params.id, params.slug), (5, None));
+    }
+
+    #[test]
+    fn test_query_extractor() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Search {
+            q: String,
+            page: Option<u32>,
+            exact: Option<bool>,
+        }
+
+        let req = routed("/search", &[], "/search?q=rust%20web&page=2");
+        let Query(search) = Query::<Search>::from_request_parts(&req).unwrap();
+        assert_eq!(search.q, "rust web");
+        assert_eq!(search.page, Some(2));
+        assert_eq!(search.exact, None);
+
+        let req = routed("/search", &[], "/search?q=x&page=two");
+        let err = Query::<Search>::from_request_parts(&req).unwrap_err();
+        assert!(matches!(err, ExtractRejection::InvalidQuery(_)));
+
+        let req = routed("/search", &[], "/search");
+        assert!(Query::<Search>::from_request_parts(&req).is_err());
+
+        // An excessive number of parameters is rejected before parsing.
+        let flood = vec!["page=1"; 2000].join("&");
+        let req = routed("/search", &[], &format!("/search?q=x&{flood}"));
+        let err = Query::<Search>::from_request_parts(&req).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid query string: more than 1000 parameters"
+        );
+
+        let mut req = routed("/search", &[], "/search?q=a-long-search");
+        req.extensions_mut().insert(QueryOptions {
+            max_length: 8,
+            ..QueryOptions::default()
+        });
+        let err = Query::<Search>::from_request_parts(&req).unwrap_err();
+        assert_eq!(err.status(), StatusCode::URI_TOO_LONG);
+    }
+
+    #[test]
+    fn test_headers_and_cookies_extractors() {
+        let mut req = routed("/", &[], "/");
+        req.headers_mut()
+            .insert(COOKIE, "theme=dark; session=abc".parse().unwrap());
+        req.headers_mut().append(COOKIE, "lang=fr".parse().unwrap());
+
+        let Ok(cookies) = Cookies::from_request_parts(&req);
+        assert_eq!(cookies.get("theme"), Some("dark"));
+        assert_eq!(cookies.get("session"), Some("abc"));
+        assert_eq!(cookies.get("lang"), Some("fr"));
+        assert_eq!(cookies.get("missing"), None);
+
+        let Ok(Headers(headers)) = Headers::from_request_parts(&req);
+        assert_eq!(headers.get_all(COOKIE).iter().count(), 2);
+    }
+
+    #[test]
+    fn test_state_extractor() {
+        let mut req = routed("/", &[], "/");
+        assert!(matches!(
+            State::<u8>::from_request_parts(&req),
+            Err(ExtractRejection::MissingState("u8"))
+        ));
+
+        let mut state = Extensions::new();
+        state.insert(42u8);
+        req.extensions_mut().insert(SharedState(Arc::new(state)));
+        let State(value) = State::<u8>::from_request_parts(&req).unwrap();
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn test_rejection_responses() {
+        let res = ExtractRejection::UnsupportedMediaType.into_response();
+        assert_eq!(res.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let res = ExtractRejection::Body(ResponseError::PayloadTooLarge(8)).into_response();
+        assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let res = ExtractRejection::MissingState("AppState").into_response();
+        assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -1,27 +1,104 @@
+use super::extract::{ExtractRejection, deserialize_nested_query, deserialize_params};
+use crate::middleware::{AuthenticatedUser, BodyDecoding, BodyDigest};
 use crate::router::interner::Symbol;
-use hyper::{Request as HRequest, body::Incoming};
+use bytes::Bytes;
+use hyper::header::AsHeaderName;
+use hyper::{Request as HRequest, Uri, Version, body::Incoming};
+use once_cell::sync::Lazy;
 use rustc_hash::FxHashMap;
 use smallvec::SmallVec;
-use std::net::SocketAddr;
+use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::SystemTime;
+use tokio_util::sync::CancellationToken;
+
+mod accept;
+mod charset;
+mod conditional;
+mod forwarded;
+mod nonce;
+mod query;
+mod timing;
+mod trust_proxy;
+
+pub(crate) use accept::quality_values;
+use charset::Charset;
+pub use conditional::IfMatch;
+pub(crate) use conditional::{if_range, strong_etag};
+pub use nonce::Nonce;
+pub(crate) use nonce::random_bytes;
+pub(crate) use query::check_limits;
+pub use query::{DuplicateKeys, QueryOptions};
+pub use timing::{ServerTimings, TimingMark};
+pub use trust_proxy::TrustProxy;
+pub(crate) use trust_proxy::{client_ip, forwarded_ips, protocol};
 
 /// Aliased request type for the framework.
 pub type Request<B = Incoming> = HRequest<B>;
 
-/// Wraps the client's socket address for injection into request extensions.
-#[derive(Debug, Clone, Copy)]
-pub struct ClientAddr(
-    /// The actual socket address of the client
-    pub SocketAddr,
-);
+/// The connection a request arrived on, captured when the server accepts it
+/// and shared by every request on that connection. Read it with
+/// [`RequestExt::connection_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The address of the peer, which is the client unless a proxy sits in
+    /// between; see [`RequestExt::client_ip`].
+    pub remote_addr: SocketAddr,
+    /// The local address the connection was accepted on, which tells
+    /// listeners apart.
+    pub local_addr: SocketAddr,
+    /// The TLS session details, or `None` for a plain-text connection.
+    pub tls: Option<TlsInfo>,
+}
 
-/// Wraps TLS connection status for injection into request extensions.
-#[derive(Debug, Clone, Copy)]
+/// What was negotiated during a connection's TLS handshake.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct TlsInfo {
-    /// Indicates whether the request connection is secure via TLS.
-    pub is_secure: bool,
+    /// The ALPN protocol agreed with the client, e.g. `b"http/1.1"`.
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The server name (SNI) the client asked for.
+    pub server_name: Option<String>,
 }
 
+#[cfg(test)]
+impl ConnectionInfo {
+    /// A plain-text connection from `peer`, for tests that only care about it.
+    pub(crate) fn peer(peer: &str) -> Arc<Self> {
+        Arc::new(Self {
+            remote_addr: peer.parse().unwrap(),
+            local_addr: ([127, 0, 0, 1], 3000).into(),
+            tls: None,
+        })
+    }
+}
+
+/// The [`ConnectionInfo`] in `extensions`, if the request came from the server.
+pub(crate) fn connection_info(extensions: &hyper::http::Extensions) -> Option<&ConnectionInfo> {
+    extensions
+        .get::<Arc<ConnectionInfo>>()
+        .map(|info| info.as_ref())
+}
+
+/// The route pattern (e.g. `/users/{id}`) that matched the request.
+///
+/// Inserted by the router before any middleware runs, so it is available to
+/// middleware as well as handlers.
+#[derive(Debug, Clone)]
+pub struct MatchedPath(pub Arc<str>);
+
+/// The URI a request arrived with, kept by middleware that rewrites it such
+/// as [`PathRewriteMiddleware`](crate::prelude::PathRewriteMiddleware).
+#[derive(Debug, Clone)]
+pub(crate) struct OriginalUri(pub(crate) Uri);
+
+/// Cancelled when the client goes away before the response is ready.
+///
+/// Inserted by the server for each request; see [`RequestExt::on_disconnect`].
+#[derive(Debug, Clone)]
+pub(crate) struct Disconnect(pub(crate) CancellationToken);
+
 /// Request-scoped state storage.
 ///
 /// Uses a plain `HashMap` (not `Arc<DashMap>`) because `Locals` is only ever
@@ -29,26 +106,260 @@ pub struct TlsInfo {
 #[derive(Debug, Clone, Default)]
 pub struct Locals(pub FxHashMap<String, serde_json::Value>);
 
+/// Limits applied when parsing a request body as JSON with [`RequestExt::json`].
+///
+/// Oversized bodies are rejected with `413 Payload Too Large` and bodies that
+/// nest deeper than `max_depth` with `400 Bad Request`, both before `serde_json`
+/// sees a single byte. Configure them app-wide with [`App::json_limits`](crate::prelude::App::json_limits).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonLimits {
+    /// Maximum accepted body size in bytes.
+    pub max_bytes: usize,
+    /// Maximum nesting depth of arrays and objects.
+    pub max_depth: usize,
+}
+
+impl Default for JsonLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1024 * 1024, // 1 MB
+            max_depth: 64,
+        }
+    }
+}
+
+/// Caps how many bytes [`RequestExt::bytes`] and [`RequestExt::json`] read
+/// from a request body.
+///
+/// A body whose `Content-Length` exceeds the cap is rejected with
+/// `413 Payload Too Large` before a single byte is read; a body without one
+/// (e.g. chunked) is rejected as soon as it grows past the cap. Inserted as a
+/// request extension, typically by [`BodySizeLimitMiddleware`](crate::prelude::BodySizeLimitMiddleware).
+/// The stricter of this and [`JsonLimits::max_bytes`] applies to JSON bodies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimit(pub usize);
+
+impl Default for BodyLimit {
+    fn default() -> Self {
+        BodyLimit(10 * 1024 * 1024) // 10 MB
+    }
+}
+
+/// Whether [`RequestExt::expect_json`] accepts requests without a
+/// `Content-Type` header. Configure it app-wide with
+/// [`App::missing_content_type`](crate::prelude::App::missing_content_type).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingContentType {
+    /// Requests without `Content-Type` are answered with
+    /// `415 Unsupported Media Type`, like any other non-JSON type.
+    #[default]
+    Reject,
+    /// Requests without `Content-Type` pass, for clients that omit it; the
+    /// body is still parsed as JSON.
+    Accept,
+}
+
+/// The instant by which the response to a request should be ready.
+///
+/// Inserted as a request extension by middleware that enforces a time budget,
+/// e.g. one derived from an upstream timeout header. Read it with
+/// [`RequestExt::deadline`]; outbound calls made with
+/// [`RequestExt::http_client`] never wait past it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(pub std::time::Instant);
+
 use async_trait::async_trait;
-use http_body_util::BodyExt;
+use http_body_util::{BodyExt, LengthLimitError, Limited};
 
 /// Extension trait for [`Request`] to provide Express.js-like properties.
 #[async_trait]
 pub trait RequestExt<B = Incoming> {
     /// Returns the parsed route parameters.
     fn params(&self) -> &RouteParams;
+    /// Deserializes the matched route's parameters into `T`, converting each
+    /// to its field's type, like the [`Path`](crate::prelude::Path) extractor.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct PostPath {
+    ///     id: u32,
+    ///     slug: String,
+    /// }
+    ///
+    /// async fn show(req: Request, res: Response) -> Result<Response, ExtractRejection> {
+    ///     let PostPath { id, slug } = req.params_as()?;
+    ///     Ok(res.send_text(format!("post {id}: {slug}")))
+    /// }
+    ///
+    /// let mut app = App::default();
+    /// app.get("/posts/{id}/{slug}", show);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ExtractRejection::InvalidPath`](crate::prelude::ExtractRejection::InvalidPath),
+    /// which responds with `400 Bad Request`, if a parameter is missing or
+    /// doesn't convert.
+    fn params_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, ExtractRejection>;
     /// Returns the requested path.
     fn path(&self) -> &str;
+    /// Returns the requested path with percent-encoded bytes decoded, for
+    /// display or logging. Invalid UTF-8 is replaced with `U+FFFD`.
+    ///
+    /// `%2F` and `%25` stay encoded so the result can't be confused with a
+    /// path that has more segments: `/a%2Fb` decodes to `/a%2Fb`, not `/a/b`.
+    /// Routing always uses the raw [`path`](RequestExt::path).
+    fn path_decoded(&self) -> Cow<'_, str>;
+    /// Returns the route pattern that matched this request, if any.
+    fn matched_path(&self) -> Option<&str>;
     /// Returns the requested query parameter.
     fn query(&self, key: &str) -> Option<String>;
+    /// Parses the query string with bracket syntax into an object of
+    /// strings, arrays and objects: `tags[]=a&tags[]=b&filter[status]=open`
+    /// gives `{"tags": ["a", "b"], "filter": {"status": "open"}}`. The
+    /// [`QueryOptions`] set with [`App::query_options`](crate::prelude::App::query_options)
+    /// apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ExtractRejection::InvalidQuery`](crate::prelude::ExtractRejection::InvalidQuery),
+    /// which responds with `400 Bad Request`, if a key nests deeper than
+    /// [`QueryOptions::max_depth`] or there are more than
+    /// [`QueryOptions::max_params`] parameters, and
+    /// [`ExtractRejection::QueryTooLong`](crate::prelude::ExtractRejection::QueryTooLong)
+    /// if the query string is longer than [`QueryOptions::max_length`].
+    fn query_nested(&self) -> Result<serde_json::Value, ExtractRejection>;
+    /// Deserializes the query string parsed by [`query_nested`](RequestExt::query_nested)
+    /// into `T`, which may contain `Vec`s and nested structs. Values convert
+    /// to their field's type, and a single `tag=a` fills a `Vec` too.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Filter {
+    ///     status: String,
+    /// }
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Search {
+    ///     tags: Vec<String>,
+    ///     filter: Option<Filter>,
+    ///     page: Option<u32>,
+    /// }
+    ///
+    /// // GET /search?tags[]=rust&tags[]=web&filter[status]=open&page=2
+    /// async fn search(req: Request, res: Response) -> Result<Response, ExtractRejection> {
+    ///     let search: Search = req.query_as()?;
+    ///     Ok(res.send_text(format!("{} tags", search.tags.len())))
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ExtractRejection::InvalidQuery`](crate::prelude::ExtractRejection::InvalidQuery)
+    /// if the query is rejected or doesn't deserialize.
+    fn query_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, ExtractRejection>;
+    /// Returns the query string as sent, without the leading `?`.
+    fn raw_query(&self) -> Option<&str>;
+    /// Returns the path and query the request arrived with, like Express's
+    /// `req.originalUrl`: the same as the URI's unless a middleware such as
+    /// [`PathRewriteMiddleware`](crate::prelude::PathRewriteMiddleware)
+    /// rewrote it.
+    fn original_url(&self) -> &str;
     /// Returns the specified HTTP header value.
     fn get_header(&self, key: &str) -> Option<&str>;
+    /// Returns the first value of the `name` header.
+    ///
+    /// Returns `None` if the header is absent or its value is not valid UTF-8.
+    fn header<K: AsHeaderName>(&self, name: K) -> Option<&str>;
+    /// Parses the first value of the `name` header with [`FromStr`], ignoring
+    /// surrounding whitespace.
+    ///
+    /// Returns `None` if the header is absent, not valid UTF-8 or fails to parse.
+    fn header_parsed<T: FromStr, K: AsHeaderName>(&self, name: K) -> Option<T>;
+    /// Returns every value of the `name` header in order, skipping values that
+    /// are not valid UTF-8.
+    fn header_all<K: AsHeaderName>(&self, name: K) -> Vec<&str>;
+    /// Splits the `Authorization` header into its scheme and credentials,
+    /// e.g. `("Bearer", "abc123")`, without interpreting the credentials.
+    ///
+    /// Compare the scheme case-insensitively: `bearer` and `Bearer` are the
+    /// same scheme. Returns `None` if the header is absent, not valid UTF-8, or
+    /// lacks either part.
+    fn authorization(&self) -> Option<(&str, &str)>;
+    /// When the token of the user authenticated by
+    /// [`AuthMiddleware`](crate::prelude::AuthMiddleware) expires, if known,
+    /// e.g. to prompt a re-login in time.
+    fn auth_expires_at(&self) -> Option<SystemTime>;
     /// Returns the requested host name from the headers.
     fn host_name(&self) -> Option<&str>;
     /// Returns the remote socket address.
     fn ip(&self) -> Option<SocketAddr>;
+    /// Returns the details of the connection the request arrived on:
+    /// both socket addresses and, over HTTPS, the TLS session. `None` for
+    /// requests that didn't come through the server, e.g. in tests.
+    fn connection_info(&self) -> Option<&ConnectionInfo>;
+    /// Returns the client address, read from `Forwarded` or `X-Forwarded-For`
+    /// when the peer is a trusted proxy (see [`TrustProxy`]) and the socket
+    /// address otherwise.
+    fn client_ip(&self) -> Option<IpAddr>;
+    /// Returns the trusted forwarded addresses, client first.
+    ///
+    /// Empty unless the peer is a trusted proxy.
+    fn forwarded_ips(&self) -> Vec<IpAddr>;
+    /// Returns `"https"` or `"http"`, honouring `Forwarded` or
+    /// `X-Forwarded-Proto` from a trusted proxy.
+    fn protocol(&self) -> &'static str;
+    /// Returns the HTTP version the request was received over, e.g. to only
+    /// send HTTP/2-specific hints to clients that can use them.
+    ///
+    /// Same as the inherent [`hyper::Request::version`], but also usable
+    /// through the trait in code that is generic over request types.
+    fn version(&self) -> Version;
+    /// Returns a future that resolves if the client disconnects before the
+    /// response is ready.
+    ///
+    /// The handler itself is dropped at its current `.await` when that
+    /// happens, so this is for work it hands off: a spawned task or a
+    /// blocking loop can `select!` on it (or poll it) to stop computing for
+    /// nobody. The future is `'static` and can be moved into such tasks.
+    /// Requests not served by [`App::listen`](crate::prelude::App::listen),
+    /// e.g. in tests calling [`App::handle`](crate::prelude::App::handle),
+    /// never resolve it.
+    fn on_disconnect(&self) -> impl Future<Output = ()> + Send + 'static;
+    /// Returns the request's [`Deadline`], if middleware set one.
+    fn deadline(&self) -> Option<std::time::Instant>;
+    /// Returns the request's CSP [`Nonce`], generating it on first use, for
+    /// `<script nonce="...">` tags. `None` unless middleware such as
+    /// [`SecurityHeaders::script_src_nonce`](crate::prelude::SecurityHeaders::script_src_nonce)
+    /// put one in the `Content-Security-Policy`.
+    fn csp_nonce(&self) -> Option<&str>;
+    /// Starts timing an operation reported in `Server-Timing` as `name`,
+    /// e.g. `db` or `cache`: the duration is recorded when the returned
+    /// [`TimingMark`] is dropped, and marks of the same name add up. Nothing
+    /// is recorded unless [`ServerTimingMiddleware`](crate::prelude::ServerTimingMiddleware)
+    /// runs for the request.
+    fn time_mark(&self, name: &str) -> TimingMark;
+    /// Returns an HTTP client for calling other services on behalf of this
+    /// request: the [`Http`](crate::client::Http) registered with
+    /// [`App::state`](crate::prelude::App::state), or a shared default.
+    ///
+    /// Calls forward the request's `X-Request-Id`, `traceparent` and
+    /// `tracestate` headers and are cut short at its [`deadline`](RequestExt::deadline).
+    /// Response bodies are read up to its [`BodyLimit`].
+    #[cfg(feature = "client")]
+    fn http_client(&self) -> crate::client::Http;
     /// Returns true if the request was an XMLHttpRequest.
     fn xhr(&self) -> bool;
+    /// Returns true if this is a WebSocket opening handshake: a `GET` with
+    /// `Connection: Upgrade`, `Upgrade: websocket`, `Sec-WebSocket-Version: 13`
+    /// and a `Sec-WebSocket-Key`.
+    ///
+    /// Middleware that rewrites or limits bodies should let these through untouched.
+    fn is_websocket_upgrade(&self) -> bool;
     /// Checks if the request's Content-Type matches the given string.
     fn is(&self, content_type_to_match: &str) -> bool;
     /// Returns true if the request prefers a JSON response based on the Accept header.
@@ -59,36 +370,143 @@ pub trait RequestExt<B = Incoming> {
     fn locals(&self) -> &Locals;
     /// Returns a mutable reference to the request-scoped locals.
     fn locals_mut(&mut self) -> &mut Locals;
-    /// Parses the request body as JSON.
+    /// Checks the `If-Match` header against the strong ETag of the current
+    /// representation, e.g. one computed with [`Response::json_etag`](crate::prelude::Response::json_etag),
+    /// before a write.
+    ///
+    /// On mismatch, returns [`ResponseError::PreconditionFailed`](crate::handler::ResponseError::PreconditionFailed),
+    /// which responds `412 Precondition Failed`. Without `If-Match` the check
+    /// passes, unless [`IfMatch::Required`] is set, in which case
+    /// [`ResponseError::PreconditionRequired`](crate::handler::ResponseError::PreconditionRequired)
+    /// (`428 Precondition Required`) is returned.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    ///
+    /// # #[derive(Serialize)] struct Doc;
+    /// # fn load() -> Doc { Doc }
+    /// async fn update(req: Request, res: Response) -> Result<Response, ResponseError> {
+    ///     let current = load();
+    ///     req.check_if_match(&Response::json_etag(&current)?)?;
+    ///     // ... apply the update ...
+    ///     Ok(res.json_with_etag(&current))
+    /// }
+    /// ```
+    fn check_if_match(&self, current_etag: &str) -> Result<(), crate::handler::ResponseError>;
+    /// Checks that the request declares a JSON body (`application/json` or a
+    /// `+json` type) without reading it, so a JSON-only endpoint can answer
+    /// a wrong type with `415` and keep `400` for bodies that don't parse.
+    ///
+    /// Returns [`ResponseError::UnsupportedMediaType`](crate::handler::ResponseError::UnsupportedMediaType),
+    /// which responds `415 Unsupported Media Type`, for other types and,
+    /// unless [`MissingContentType::Accept`] is set, for requests without a
+    /// `Content-Type`.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    ///
+    /// async fn create(req: Request, res: Response) -> Result<Response, ResponseError> {
+    ///     req.expect_json()?;
+    ///     let item: serde_json::Value = req.json().await?;
+    ///     Ok(res.status_code(201).json(&item))
+    /// }
+    /// ```
+    fn expect_json(&self) -> Result<(), crate::handler::ResponseError>;
+    /// Collects the request body, enforcing the request's [`BodyLimit`] (or
+    /// the default one) against both `Content-Length` and the bytes received.
+    async fn bytes(self) -> Result<Bytes, crate::handler::ResponseError>
+    where
+        B: BodyExt + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display;
+    /// Returns the request body as an [`AsyncRead`](tokio::io::AsyncRead),
+    /// for parsers and decoders that consume a reader, under the same limit
+    /// as [`RequestExt::bytes`].
+    ///
+    /// A body declared too large is rejected up front. One that grows past
+    /// the limit fails the read with an [`std::io::Error`] wrapping
+    /// [`ResponseError::PayloadTooLarge`](crate::handler::ResponseError::PayloadTooLarge).
+    /// A body the [`CompressionMiddleware`](crate::prelude::CompressionMiddleware)
+    /// has to decompress is read in full first.
+    fn body_reader(
+        self,
+    ) -> Result<impl tokio::io::AsyncRead + Send + Unpin + 'static, crate::handler::ResponseError>
+    where
+        B: BodyExt + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display;
+    /// Parses the request body as JSON, enforcing the configured [`JsonLimits`].
+    ///
+    /// Bodies declared with a `charset` other than UTF-8 on `Content-Type`
+    /// (ISO-8859-1 or UTF-16) are transcoded first; other charsets are
+    /// rejected with `415 Unsupported Media Type`.
     async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T, crate::handler::ResponseError>
     where
         B: BodyExt + Send + Unpin + 'static,
         B::Data: Send,
         B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display;
+    /// Like [`RequestExt::json`], but returns `default` when the body is empty,
+    /// e.g. for `PATCH` endpoints where the body is optional.
+    ///
+    /// A non-empty body must be declared as JSON on `Content-Type`, or
+    /// [`ResponseError::UnsupportedMediaType`](crate::handler::ResponseError::UnsupportedMediaType)
+    /// is returned; invalid JSON is an error as with [`RequestExt::json`].
+    async fn json_or<T>(self, default: T) -> Result<T, crate::handler::ResponseError>
+    where
+        T: serde::de::DeserializeOwned + Send,
+        B: BodyExt + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display;
 }
 
 /// Internal trait used to attach request metadata during server processing.
 pub(crate) trait RequestMetadataInternal {
     fn set_params(&mut self, params: SmallVec<[(Symbol, Arc<str>); 4]>);
-    fn set_metadata(&mut self, addr: SocketAddr, is_tls: bool);
+    fn set_connection(&mut self, info: Arc<ConnectionInfo>);
 }
 
 #[async_trait]
 impl<B> RequestExt<B> for Request<B> {
     fn params(&self) -> &RouteParams {
-        self.extensions()
-            .get::<RouteParams>()
-            .expect("Route parameters must be set before accessing them")
+        // The router only inserts parameters for routes that have some.
+        static EMPTY: Lazy<RouteParams> = Lazy::new(RouteParams::default);
+        self.extensions().get::<RouteParams>().unwrap_or(&EMPTY)
+    }
+
+    fn params_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, ExtractRejection> {
+        deserialize_params(self.extensions())
     }
 
     fn path(&self) -> &str {
         self.uri().path()
     }
 
+    fn path_decoded(&self) -> Cow<'_, str> {
+        decode_path(self.uri().path())
+    }
+
+    fn matched_path(&self) -> Option<&str> {
+        self.extensions()
+            .get::<MatchedPath>()
+            .map(|matched| matched.0.as_ref())
+    }
+
+    fn raw_query(&self) -> Option<&str> {
+        self.uri().query()
+    }
+
+    fn original_url(&self) -> &str {
+        let uri = self
+            .extensions()
+            .get::<OriginalUri>()
+            .map_or(self.uri(), |original| &original.0);
+        uri.path_and_query().map_or("/", |pq| pq.as_str())
+    }
+
     fn query(&self, key: &str) -> Option<String> {
         // Lazy-initialise the parsed query cache on first call.
         // We can't store a mutable reference here, so we parse on every
-        // miss — but the extension is inserted on set_metadata, so we
+        // miss — but the extension is inserted on set_connection, so we
         // re-parse at most once per request.
         self.uri().query().and_then(|q| {
             form_urlencoded::parse(q.as_bytes())
@@ -97,18 +515,124 @@ impl<B> RequestExt<B> for Request<B> {
         })
     }
 
+    fn query_nested(&self) -> Result<serde_json::Value, ExtractRejection> {
+        let options = self
+            .extensions()
+            .get::<QueryOptions>()
+            .copied()
+            .unwrap_or_default();
+        let query = self.uri().query().unwrap_or("");
+        query::check_limits(query, &options)?;
+        query::parse_nested(query, &options).map_err(ExtractRejection::InvalidQuery)
+    }
+
+    fn query_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, ExtractRejection> {
+        deserialize_nested_query(&self.query_nested()?)
+    }
+
     fn get_header(&self, key: &str) -> Option<&str> {
-        self.headers().get(key).and_then(|v| v.to_str().ok())
+        self.header(key)
     }
 
-    fn host_name(&self) -> Option<&str> {
+    fn header<K: AsHeaderName>(&self, name: K) -> Option<&str> {
+        self.headers().get(name).and_then(|v| v.to_str().ok())
+    }
+
+    fn header_parsed<T: FromStr, K: AsHeaderName>(&self, name: K) -> Option<T> {
+        self.header(name).and_then(|v| v.trim().parse().ok())
+    }
+
+    fn header_all<K: AsHeaderName>(&self, name: K) -> Vec<&str> {
         self.headers()
-            .get(hyper::header::HOST)
-            .and_then(|v| v.to_str().ok())
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect()
+    }
+
+    fn authorization(&self) -> Option<(&str, &str)> {
+        let value = self.header(hyper::header::AUTHORIZATION)?.trim();
+        let (scheme, credentials) = value.split_once([' ', '\t'])?;
+        let credentials = credentials.trim_start();
+        let is_token = scheme
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+        (is_token && !credentials.is_empty()).then_some((scheme, credentials))
+    }
+
+    fn auth_expires_at(&self) -> Option<SystemTime> {
+        self.extensions()
+            .get::<AuthenticatedUser>()
+            .and_then(|user| user.expires_at)
+    }
+
+    fn host_name(&self) -> Option<&str> {
+        self.header(hyper::header::HOST)
     }
 
     fn ip(&self) -> Option<SocketAddr> {
-        self.extensions().get::<ClientAddr>().map(|addr| addr.0)
+        self.connection_info().map(|info| info.remote_addr)
+    }
+
+    fn connection_info(&self) -> Option<&ConnectionInfo> {
+        connection_info(self.extensions())
+    }
+
+    fn client_ip(&self) -> Option<IpAddr> {
+        client_ip(self.headers(), self.extensions())
+    }
+
+    fn forwarded_ips(&self) -> Vec<IpAddr> {
+        forwarded_ips(self.headers(), self.extensions())
+    }
+
+    fn protocol(&self) -> &'static str {
+        protocol(self.headers(), self.extensions())
+    }
+
+    fn version(&self) -> Version {
+        HRequest::version(self)
+    }
+
+    fn on_disconnect(&self) -> impl Future<Output = ()> + Send + 'static {
+        let token = self
+            .extensions()
+            .get::<Disconnect>()
+            .map(|disconnect| disconnect.0.clone());
+        async move {
+            match token {
+                Some(token) => token.cancelled_owned().await,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn deadline(&self) -> Option<std::time::Instant> {
+        self.extensions()
+            .get::<Deadline>()
+            .map(|deadline| deadline.0)
+    }
+
+    fn csp_nonce(&self) -> Option<&str> {
+        self.extensions().get::<Nonce>().map(Nonce::get)
+    }
+
+    fn time_mark(&self, name: &str) -> TimingMark {
+        TimingMark::new(name, self.extensions().get::<ServerTimings>().cloned())
+    }
+
+    #[cfg(feature = "client")]
+    fn http_client(&self) -> crate::client::Http {
+        use crate::client::Http;
+        let registered = self
+            .extensions()
+            .get::<crate::handler::extract::SharedState>()
+            .and_then(|state| state.0.get::<Http>());
+        registered.unwrap_or_else(|| Http::shared()).bind(
+            self.headers(),
+            self.deadline(),
+            self.extensions().get::<BodyLimit>().copied(),
+        )
     }
 
     fn xhr(&self) -> bool {
@@ -117,6 +641,23 @@ impl<B> RequestExt<B> for Request<B> {
             .unwrap_or(false)
     }
 
+    fn is_websocket_upgrade(&self) -> bool {
+        use hyper::header::{CONNECTION, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE};
+
+        let has_token = |name, token: &str| {
+            self.header_all(name)
+                .iter()
+                .flat_map(|v| v.split(','))
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
+        };
+
+        self.method() == hyper::Method::GET
+            && has_token(CONNECTION, "upgrade")
+            && has_token(UPGRADE, "websocket")
+            && self.header(SEC_WEBSOCKET_VERSION).map(str::trim) == Some("13")
+            && self.headers().contains_key(SEC_WEBSOCKET_KEY)
+    }
+
     fn is(&self, content_type_to_match: &str) -> bool {
         let content_type = self.get_header("Content-Type").unwrap_or("");
         // All MIME type comparisons are ASCII — use the allocation-free variant.
@@ -142,10 +683,8 @@ impl<B> RequestExt<B> for Request<B> {
     }
 
     fn secure(&self) -> bool {
-        self.extensions()
-            .get::<TlsInfo>()
-            .map(|info| info.is_secure)
-            .unwrap_or(false)
+        self.connection_info()
+            .is_some_and(|info| info.tls.is_some())
     }
 
     fn locals(&self) -> &Locals {
@@ -160,26 +699,355 @@ impl<B> RequestExt<B> for Request<B> {
             .expect("Locals must be initialized in App::handle")
     }
 
+    fn check_if_match(&self, current_etag: &str) -> Result<(), crate::handler::ResponseError> {
+        use crate::handler::ResponseError;
+
+        let values = self.header_all(hyper::header::IF_MATCH);
+        if values.is_empty() {
+            return match self.extensions().get::<IfMatch>() {
+                Some(IfMatch::Required) => Err(ResponseError::PreconditionRequired),
+                _ => Ok(()),
+            };
+        }
+        if conditional::if_match(values, current_etag) {
+            Ok(())
+        } else {
+            Err(ResponseError::PreconditionFailed)
+        }
+    }
+
+    fn expect_json(&self) -> Result<(), crate::handler::ResponseError> {
+        let content_type = self.header(hyper::header::CONTENT_TYPE);
+        if is_json_content_type(content_type) {
+            return Ok(());
+        }
+        let missing = self.extensions().get::<MissingContentType>();
+        if content_type.is_none() && missing == Some(&MissingContentType::Accept) {
+            return Ok(());
+        }
+        Err(crate::handler::ResponseError::UnsupportedMediaType(
+            content_type.unwrap_or("none").to_owned(),
+        ))
+    }
+
+    async fn bytes(self) -> Result<Bytes, crate::handler::ResponseError>
+    where
+        B: BodyExt + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+    {
+        let (parts, body) = self.into_parts();
+        let head = Request::from_parts(parts, ());
+        let BodyLimit(max_bytes) = head
+            .extensions()
+            .get::<BodyLimit>()
+            .copied()
+            .unwrap_or_default();
+        reject_declared_oversize(&head, max_bytes)?;
+        read_body(&head, body, max_bytes).await
+    }
+
+    fn body_reader(
+        self,
+    ) -> Result<impl tokio::io::AsyncRead + Send + Unpin + 'static, crate::handler::ResponseError>
+    where
+        B: BodyExt + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+    {
+        use crate::handler::ResponseError;
+        use crate::middleware::DigestCheck;
+        use bytes::Buf;
+        use futures_util::stream::{self, BoxStream, StreamExt};
+        use std::io;
+        use std::task::Poll;
+
+        let (parts, body) = self.into_parts();
+        let head = Request::from_parts(parts, ());
+        let BodyLimit(max_bytes) = head
+            .extensions()
+            .get::<BodyLimit>()
+            .copied()
+            .unwrap_or_default();
+        reject_declared_oversize(&head, max_bytes)?;
+
+        if head.extensions().get::<BodyDecoding>().is_some() {
+            // The decoders work on whole bodies.
+            let chunks: BoxStream<'static, io::Result<Bytes>> = stream::once(async move {
+                read_body(&head, body, max_bytes)
+                    .await
+                    .map_err(io::Error::other)
+            })
+            .boxed();
+            return Ok(tokio_util::io::StreamReader::new(chunks));
+        }
+
+        let mut chunks = Limited::new(body, max_bytes)
+            .into_data_stream()
+            .map(move |chunk| match chunk {
+                Ok(mut data) => Ok(data.copy_to_bytes(data.remaining())),
+                Err(e) if e.downcast_ref::<LengthLimitError>().is_some() => {
+                    Err(io::Error::other(ResponseError::PayloadTooLarge(max_bytes)))
+                }
+                Err(e) => Err(io::Error::other(e)),
+            })
+            .boxed();
+        // A digest is checked once the last chunk has been read.
+        let mut check = head.extensions().get::<BodyDigest>().map(BodyDigest::check);
+        let chunks: BoxStream<'static, io::Result<Bytes>> =
+            stream::poll_fn(move |cx| match chunks.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(bytes))) => {
+                    if let Some(check) = &mut check {
+                        check.update(&bytes);
+                    }
+                    Poll::Ready(Some(Ok(bytes)))
+                }
+                Poll::Ready(None) => match check.take().map(DigestCheck::finish) {
+                    Some(Err(e)) => Poll::Ready(Some(Err(io::Error::other(e)))),
+                    _ => Poll::Ready(None),
+                },
+                poll => poll,
+            })
+            .boxed();
+        Ok(tokio_util::io::StreamReader::new(chunks))
+    }
+
     async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T, crate::handler::ResponseError>
     where
         B: BodyExt + Send + Unpin + 'static,
         B::Data: Send,
         B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
     {
-        let body = self.into_body();
-        let bytes = body
-            .collect()
-            .await
-            .map_err(|e| crate::handler::ResponseError::BodyReadError(e.to_string()))?
-            .to_bytes();
+        let (parts, body) = self.into_parts();
+        read_json(&Request::from_parts(parts, ()), body).await
+    }
+
+    async fn json_or<T>(self, default: T) -> Result<T, crate::handler::ResponseError>
+    where
+        T: serde::de::DeserializeOwned + Send,
+        B: BodyExt + Send + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+    {
+        let (parts, body) = self.into_parts();
+        let head = Request::from_parts(parts, ());
+        let limits = json_limits(&head)?;
+        let bytes = read_body(&head, body, limits.max_bytes).await?;
+        if bytes.is_empty() {
+            return Ok(default);
+        }
+
+        let content_type = head.header(hyper::header::CONTENT_TYPE);
+        if !is_json_content_type(content_type) {
+            return Err(crate::handler::ResponseError::UnsupportedMediaType(
+                content_type.unwrap_or("none").to_owned(),
+            ));
+        }
+        let charset = Charset::from_content_type(content_type)?;
+        decode_json(charset.decode(bytes)?, limits)
+    }
+}
+
+/// Percent-decodes `path`, keeping `%2F` and `%25` encoded so segment
+/// boundaries survive. Borrows when there is nothing to decode.
+fn decode_path(path: &str) -> Cow<'_, str> {
+    if !path.contains('%') {
+        return Cow::Borrowed(path);
+    }
+
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(&[hi, lo]) = bytes.get(i + 1..i + 3)
+            && let (Some(hi), Some(lo)) = (hex(hi), hex(lo))
+        {
+            let decoded = hi << 4 | lo;
+            if decoded == b'/' || decoded == b'%' {
+                out.extend_from_slice(&bytes[i..i + 3]);
+            } else {
+                out.push(decoded);
+            }
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// Whether a `Content-Type` value declares JSON: `application/json` or a
+/// `+json` structured syntax suffix, with any parameters.
+pub(crate) fn is_json_content_type(content_type: Option<&str>) -> bool {
+    content_type.is_some_and(|ct| {
+        let mime = ct.split(';').next().unwrap_or("").trim();
+        mime.eq_ignore_ascii_case("application/json")
+            || mime.to_ascii_lowercase().ends_with("+json")
+    })
+}
+
+/// Reads `body` as JSON under the [`JsonLimits`] and charset declared in `head`.
+///
+/// Shared by [`RequestExt::json`] and the `Json` extractor, which splits the
+/// request head from its body.
+pub(crate) async fn read_json<T, B>(
+    head: &Request<()>,
+    body: B,
+) -> Result<T, crate::handler::ResponseError>
+where
+    T: serde::de::DeserializeOwned,
+    B: BodyExt + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+{
+    let limits = json_limits(head)?;
+    let charset = Charset::from_content_type(head.header(hyper::header::CONTENT_TYPE))?;
+    let bytes = read_body(head, body, limits.max_bytes).await?;
+    decode_json(charset.decode(bytes)?, limits)
+}
+
+/// Returns the [`JsonLimits`] in effect for `head`, tightened by its
+/// [`BodyLimit`], rejecting up front a body whose declared `Content-Length`
+/// already exceeds them.
+fn json_limits(head: &Request<()>) -> Result<JsonLimits, crate::handler::ResponseError> {
+    let mut limits = head
+        .extensions()
+        .get::<JsonLimits>()
+        .copied()
+        .unwrap_or_default();
+    if let Some(BodyLimit(max_bytes)) = head.extensions().get::<BodyLimit>() {
+        limits.max_bytes = limits.max_bytes.min(*max_bytes);
+    }
+
+    reject_declared_oversize(head, limits.max_bytes)?;
+    Ok(limits)
+}
+
+/// Fast rejection: don't read a single byte of a body declared too large.
+fn reject_declared_oversize(
+    head: &Request<()>,
+    max_bytes: usize,
+) -> Result<(), crate::handler::ResponseError> {
+    let declared_len = head
+        .get_header("Content-Length")
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared_len.is_some_and(|len| len > max_bytes) {
+        return Err(crate::handler::ResponseError::PayloadTooLarge(max_bytes));
+    }
+    Ok(())
+}
+
+/// Collects `body` under `max_bytes`, checks it against the [`BodyDigest`]
+/// in `head` if any, and decodes its content coding.
+async fn read_body<B>(
+    head: &Request<()>,
+    body: B,
+    max_bytes: usize,
+) -> Result<Bytes, crate::handler::ResponseError>
+where
+    B: BodyExt + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+{
+    let bytes = read_limited(body, max_bytes).await?;
+    if let Some(digest) = head.extensions().get::<BodyDigest>() {
+        digest.verify(&bytes)?;
+    }
+    decode_content(head, bytes, max_bytes)
+}
+
+/// Collects `body`, failing as soon as it grows past `max_bytes`.
+async fn read_limited<B>(body: B, max_bytes: usize) -> Result<Bytes, crate::handler::ResponseError>
+where
+    B: BodyExt + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+{
+    use crate::handler::ResponseError;
+
+    Ok(Limited::new(body, max_bytes)
+        .collect()
+        .await
+        .map_err(|e| {
+            if e.downcast_ref::<LengthLimitError>().is_some() {
+                ResponseError::PayloadTooLarge(max_bytes)
+            } else {
+                ResponseError::BodyReadError(e.to_string())
+            }
+        })?
+        .to_bytes())
+}
+
+/// Decompresses a body whose `Content-Encoding` the
+/// [`CompressionMiddleware`](crate::prelude::CompressionMiddleware) accepted,
+/// holding the decoded body to `max_bytes` as well.
+fn decode_content(
+    head: &Request<()>,
+    bytes: Bytes,
+    max_bytes: usize,
+) -> Result<Bytes, crate::handler::ResponseError> {
+    match head.extensions().get::<BodyDecoding>() {
+        Some(decoding) => decoding.decode(&bytes, max_bytes),
+        None => Ok(bytes),
+    }
+}
+
+/// Parses UTF-8 `bytes` as JSON, enforcing `limits.max_depth`.
+fn decode_json<T: serde::de::DeserializeOwned>(
+    bytes: Bytes,
+    limits: JsonLimits,
+) -> Result<T, crate::handler::ResponseError> {
+    use crate::handler::ResponseError;
 
-        serde_json::from_slice(&bytes)
-            .map_err(crate::handler::ResponseError::JsonSerializationError)
+    if exceeds_json_depth(&bytes, limits.max_depth) {
+        return Err(ResponseError::JsonTooDeep(limits.max_depth));
     }
+
+    serde_json::from_slice(&bytes).map_err(ResponseError::JsonSerializationError)
+}
+
+/// Scans raw JSON and reports whether arrays/objects nest deeper than `max_depth`.
+///
+/// Runs in a single pass without recursion, so it is safe to call on hostile
+/// input before handing it to `serde_json`.
+fn exceeds_json_depth(bytes: &[u8], max_depth: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for &b in bytes {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if b == b'\\' {
+                escaped = true;
+            } else if b == b'"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match b {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    false
 }
 
 /// Parsed route parameters from the request URI.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct RouteParams(
     /// Internal representation of the route parameters.
     SmallVec<[(Symbol, Arc<str>); 4]>,
@@ -204,8 +1072,529 @@ impl<B> RequestMetadataInternal for Request<B> {
         self.extensions_mut().insert(RouteParams(params));
     }
 
-    fn set_metadata(&mut self, addr: SocketAddr, is_tls: bool) {
-        self.extensions_mut().insert(ClientAddr(addr));
-        self.extensions_mut().insert(TlsInfo { is_secure: is_tls });
+    fn set_connection(&mut self, info: Arc<ConnectionInfo>) {
+        self.extensions_mut().insert(info);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::ResponseError;
+    use bytes::Bytes;
+    use http_body_util::Full;
+
+    fn json_request(body: impl Into<Bytes>) -> Request<Full<Bytes>> {
+        Request::builder()
+            .uri("/")
+            .header("Content-Type", "application/json")
+            .body(Full::new(body.into()))
+            .unwrap()
+    }
+
+    fn header_request() -> Request<()> {
+        Request::builder()
+            .header("X-Count", " 42 ")
+            .header("Accept", "text/html")
+            .header("Accept", "application/json")
+            .header("X-Binary", &b"caf\xe9"[..])
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_header_present_and_absent() {
+        let req = header_request();
+        assert_eq!(req.header("x-count"), Some(" 42 "));
+        assert_eq!(req.header(hyper::header::ACCEPT), Some("text/html"));
+        assert_eq!(req.header("x-missing"), None);
+        assert_eq!(req.header_all("x-missing"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_header_parsed() {
+        let req = header_request();
+        assert_eq!(req.header_parsed::<u32, _>("x-count"), Some(42));
+        assert_eq!(req.header_parsed::<u32, _>("accept"), None);
+        assert_eq!(req.header_parsed::<u32, _>("x-missing"), None);
+    }
+
+    #[test]
+    fn test_header_non_utf8() {
+        let req = header_request();
+        assert!(req.headers().contains_key("x-binary"));
+        assert_eq!(req.header("x-binary"), None);
+        assert_eq!(req.header_parsed::<String, _>("x-binary"), None);
+        assert_eq!(req.header_all("x-binary"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_header_all_multi_value() {
+        let req = header_request();
+        assert_eq!(req.header_all("accept"), ["text/html", "application/json"]);
+    }
+
+    #[test]
+    fn test_authorization_schemes() {
+        let auth = |value: &str| {
+            Request::builder()
+                .header("Authorization", value)
+                .body(())
+                .unwrap()
+        };
+        let req = auth("Bearer abc.def.ghi");
+        assert_eq!(req.authorization(), Some(("Bearer", "abc.def.ghi")));
+        let req = auth("Basic YWxpY2U6c2VjcmV0");
+        assert_eq!(req.authorization(), Some(("Basic", "YWxpY2U6c2VjcmV0")));
+        let req = auth("Signature keyId=\"k1\", algorithm=\"hmac-sha256\", signature=\"x==\"");
+        assert_eq!(
+            req.authorization(),
+            Some((
+                "Signature",
+                "keyId=\"k1\", algorithm=\"hmac-sha256\", signature=\"x==\""
+            ))
+        );
+
+        for malformed in ["Bearer", "Bearer   ", "", "Be@rer token", "  token"] {
+            assert_eq!(auth(malformed).authorization(), None, "{malformed:?}");
+        }
+        assert_eq!(header_request().authorization(), None);
+    }
+
+    fn websocket_request() -> hyper::http::request::Builder {
+        Request::builder()
+            .uri("/ws")
+            .header("Connection", "keep-alive, Upgrade")
+            .header("Upgrade", "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+    }
+
+    #[test]
+    fn test_is_websocket_upgrade() {
+        assert!(websocket_request().body(()).unwrap().is_websocket_upgrade());
+
+        let normal = Request::builder().uri("/ws").body(()).unwrap();
+        assert!(!normal.is_websocket_upgrade());
+
+        let post = websocket_request().method("POST").body(()).unwrap();
+        assert!(!post.is_websocket_upgrade());
+
+        let mut no_key = websocket_request().body(()).unwrap();
+        no_key.headers_mut().remove("Sec-WebSocket-Key");
+        assert!(!no_key.is_websocket_upgrade());
+
+        let mut h2c = websocket_request().body(()).unwrap();
+        h2c.headers_mut()
+            .insert("Upgrade", hyper::header::HeaderValue::from_static("h2c"));
+        assert!(!h2c.is_websocket_upgrade());
+    }
+
+    #[test]
+    fn test_version() {
+        fn version_of<R: RequestExt<()>>(req: &R) -> Version {
+            req.version()
+        }
+
+        let req = Request::builder().body(()).unwrap();
+        assert_eq!(version_of(&req), Version::HTTP_11);
+
+        for version in [Version::HTTP_10, Version::HTTP_2, Version::HTTP_3] {
+            let req = Request::builder().version(version).body(()).unwrap();
+            assert_eq!(version_of(&req), version);
+            assert_eq!(RequestExt::version(&req), req.version());
+        }
+    }
+
+    fn proxied_request(peer: &str, trust: Option<TrustProxy>) -> Request<()> {
+        let mut req = Request::builder()
+            .header("X-Forwarded-For", "203.0.113.7, 10.0.0.2")
+            .header("X-Forwarded-For", "10.0.0.1")
+            .header("X-Forwarded-Proto", "https")
+            .body(())
+            .unwrap();
+        req.set_connection(ConnectionInfo::peer(peer));
+        if let Some(trust) = trust {
+            req.extensions_mut().insert(trust);
+        }
+        req
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_client_ip_ignores_forwarding_headers_by_default() {
+        let req = proxied_request("198.51.100.1:4000", None);
+        assert_eq!(req.client_ip(), Some(ip("198.51.100.1")));
+        assert!(req.forwarded_ips().is_empty());
+        assert_eq!(req.protocol(), "http");
+    }
+
+    #[test]
+    fn test_client_ip_with_trusted_proxies() {
+        let req = proxied_request("10.0.0.9:4000", Some(TrustProxy::All));
+        assert_eq!(req.client_ip(), Some(ip("203.0.113.7")));
+        assert_eq!(
+            req.forwarded_ips(),
+            [ip("203.0.113.7"), ip("10.0.0.2"), ip("10.0.0.1")]
+        );
+        assert_eq!(req.protocol(), "https");
+
+        let req = proxied_request("10.0.0.9:4000", Some(TrustProxy::Hops(2)));
+        assert_eq!(req.client_ip(), Some(ip("10.0.0.2")));
+        assert_eq!(req.forwarded_ips(), [ip("10.0.0.2"), ip("10.0.0.1")]);
+
+        let trusted = TrustProxy::ips([ip("10.0.0.9"), ip("10.0.0.1")]);
+        let req = proxied_request("10.0.0.9:4000", Some(trusted.clone()));
+        assert_eq!(req.client_ip(), Some(ip("10.0.0.2")));
+
+        // A spoofing client talking to the app directly is not a trusted proxy.
+        let req = proxied_request("198.51.100.1:4000", Some(trusted));
+        assert_eq!(req.client_ip(), Some(ip("198.51.100.1")));
+        assert_eq!(req.protocol(), "http");
+    }
+
+    #[test]
+    fn test_client_ip_from_forwarded_header() {
+        let forwarded = |peer: &str, trust, values: &[&str]| {
+            let mut req = Request::builder();
+            for value in values {
+                req = req.header("Forwarded", *value);
+            }
+            // `Forwarded` takes precedence over the legacy headers.
+            let mut req = req
+                .header("X-Forwarded-For", "192.0.2.99")
+                .header("X-Forwarded-Proto", "http")
+                .body(())
+                .unwrap();
+            req.set_connection(ConnectionInfo::peer(peer));
+            req.extensions_mut().insert::<TrustProxy>(trust);
+            req
+        };
+
+        let req = forwarded(
+            "10.0.0.9:4000",
+            TrustProxy::All,
+            &[
+                r#"for="[2001:db8:cafe::17]:4711";proto=https"#,
+                "for=10.0.0.2;proto=http, for=10.0.0.1",
+            ],
+        );
+        assert_eq!(req.client_ip(), Some(ip("2001:db8:cafe::17")));
+        assert_eq!(
+            req.forwarded_ips(),
+            [ip("2001:db8:cafe::17"), ip("10.0.0.2"), ip("10.0.0.1")]
+        );
+        assert_eq!(req.protocol(), "https");
+
+        let req = forwarded(
+            "10.0.0.9:4000",
+            TrustProxy::Hops(2),
+            &["for=203.0.113.7, for=10.0.0.2;proto=https, for=10.0.0.1"],
+        );
+        assert_eq!(req.client_ip(), Some(ip("10.0.0.2")));
+
+        // An obfuscated hop hides everything before it.
+        let req = forwarded(
+            "10.0.0.9:4000",
+            TrustProxy::All,
+            &["for=203.0.113.7, for=_hidden, for=10.0.0.1"],
+        );
+        assert_eq!(req.client_ip(), Some(ip("10.0.0.1")));
+
+        // Untrusted peers cannot spoof through the standard header either.
+        let req = forwarded(
+            "198.51.100.1:4000",
+            TrustProxy::ips([ip("10.0.0.9")]),
+            &["for=203.0.113.7;proto=https"],
+        );
+        assert_eq!(req.client_ip(), Some(ip("198.51.100.1")));
+        assert_eq!(req.protocol(), "http");
+    }
+
+    #[tokio::test]
+    async fn test_json_parses_within_limits() {
+        let value: serde_json::Value = json_request(r#"{"a":[1,{"b":"]]]"}]}"#)
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(value["a"][1]["b"], "]]]");
+    }
+
+    #[tokio::test]
+    async fn test_json_rejects_deep_nesting() {
+        let nested = format!("{}{}", "[".repeat(10_000), "]".repeat(10_000));
+        let err = json_request(nested)
+            .json::<serde_json::Value>()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ResponseError::JsonTooDeep(64)));
+        assert_eq!(err.status(), hyper::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_json_rejects_oversized_body() {
+        let mut req = json_request(format!("\"{}\"", "x".repeat(64)));
+        req.extensions_mut().insert(JsonLimits {
+            max_bytes: 16,
+            ..JsonLimits::default()
+        });
+        let err = req.json::<String>().await.unwrap_err();
+        assert!(matches!(err, ResponseError::PayloadTooLarge(16)));
+        assert_eq!(err.status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_json_rejects_declared_oversized_body() {
+        let mut req = json_request("{}");
+        req.headers_mut()
+            .insert("Content-Length", "999999999".parse().unwrap());
+        let err = req.json::<serde_json::Value>().await.unwrap_err();
+        assert!(matches!(err, ResponseError::PayloadTooLarge(_)));
+    }
+
+    fn json_request_with_charset(charset: &str, body: Vec<u8>) -> Request<Full<Bytes>> {
+        Request::builder()
+            .uri("/")
+            .header(
+                "Content-Type",
+                format!("application/json; charset={charset}"),
+            )
+            .body(Full::new(Bytes::from(body)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_json_transcodes_declared_charset() {
+        // "café" with é as the single Latin-1 byte 0xE9, invalid as UTF-8.
+        let req = json_request_with_charset("ISO-8859-1", b"{\"name\":\"caf\xe9\"}".to_vec());
+        let value = req.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(value["name"], "café");
+
+        let utf16: Vec<u8> = "{\"name\":\"café ☕\"}"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let req = json_request_with_charset("\"utf-16le\"", utf16);
+        let value = req.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(value["name"], "café ☕");
+
+        // Plain UTF-16 honours a little-endian BOM.
+        let mut utf16 = vec![0xFF, 0xFE];
+        utf16.extend("[1,2]".encode_utf16().flat_map(u16::to_le_bytes));
+        let req = json_request_with_charset("UTF-16", utf16);
+        assert_eq!(req.json::<Vec<u8>>().await.unwrap(), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_json_rejects_unsupported_charset() {
+        let req = json_request_with_charset("shift_jis", b"{}".to_vec());
+        let err = req.json::<serde_json::Value>().await.unwrap_err();
+        assert!(matches!(&err, ResponseError::UnsupportedCharset(c) if c == "shift_jis"));
+        assert_eq!(err.status(), hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Patch {
+        name: Option<String>,
+    }
+
+    #[tokio::test]
+    async fn test_json_or_parses_present_body() {
+        let patch = json_request(r#"{"name":"Ada"}"#)
+            .json_or(Patch { name: None })
+            .await
+            .unwrap();
+        assert_eq!(patch.name.as_deref(), Some("Ada"));
+    }
+
+    #[tokio::test]
+    async fn test_json_or_returns_default_for_empty_body() {
+        let default = || Patch {
+            name: Some("default".into()),
+        };
+        let patch = json_request("").json_or(default()).await.unwrap();
+        assert_eq!(patch, default());
+
+        // No content type is needed when there is no body.
+        let req = Request::builder()
+            .uri("/")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert_eq!(req.json_or(default()).await.unwrap(), default());
+    }
+
+    #[test]
+    fn test_expect_json_checks_the_content_type() {
+        let request = |content_type: Option<&str>| {
+            let mut req = Request::builder();
+            if let Some(content_type) = content_type {
+                req = req.header("Content-Type", content_type);
+            }
+            req.body(()).unwrap()
+        };
+        for ok in [
+            "application/json",
+            "application/merge-patch+json; charset=utf-8",
+        ] {
+            assert!(request(Some(ok)).expect_json().is_ok(), "{ok}");
+        }
+
+        let err = request(Some("text/plain")).expect_json().unwrap_err();
+        assert!(matches!(&err, ResponseError::UnsupportedMediaType(ct) if ct == "text/plain"));
+        assert_eq!(err.status(), hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let err = request(None).expect_json().unwrap_err();
+        assert_eq!(err.status(), hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let mut req = request(None);
+        req.extensions_mut().insert(MissingContentType::Accept);
+        assert!(req.expect_json().is_ok());
+        // Accepting a missing type doesn't let a wrong one through.
+        let mut req = request(Some("text/plain"));
+        req.extensions_mut().insert(MissingContentType::Accept);
+        assert!(req.expect_json().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_json_or_rejects_invalid_body() {
+        let err = json_request("{not json")
+            .json_or(Patch { name: None })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ResponseError::JsonSerializationError(_)));
+
+        let req = Request::builder()
+            .uri("/")
+            .header("Content-Type", "text/plain")
+            .body(Full::new(Bytes::from_static(br#"{"name":"Ada"}"#)))
+            .unwrap();
+        let err = req.json_or(Patch { name: None }).await.unwrap_err();
+        assert!(matches!(&err, ResponseError::UnsupportedMediaType(ct) if ct == "text/plain"));
+        assert_eq!(err.status(), hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    fn path_request(uri: &str) -> Request<()> {
+        Request::builder().uri(uri).body(()).unwrap()
+    }
+
+    #[test]
+    fn test_path_decoded() {
+        let req = path_request("/files/caf%C3%A9%20menu.txt?q=a%20b");
+        assert_eq!(req.path_decoded(), "/files/café menu.txt");
+        assert_eq!(req.raw_query(), Some("q=a%20b"));
+
+        let req = path_request("/plain/path");
+        assert!(matches!(req.path_decoded(), Cow::Borrowed("/plain/path")));
+        assert_eq!(req.raw_query(), None);
+    }
+
+    #[test]
+    fn test_path_decoded_keeps_segment_boundaries() {
+        // An encoded slash must not turn into a new segment, and `%25` must
+        // stay encoded so `%252F` can't decode into the same text as `%2F`.
+        assert_eq!(path_request("/a%2Fb").path_decoded(), "/a%2Fb");
+        assert_eq!(path_request("/a%2fb").path_decoded(), "/a%2fb");
+        assert_eq!(path_request("/a%252Fb").path_decoded(), "/a%252Fb");
+        // Malformed escapes and invalid UTF-8 are passed through or replaced.
+        assert_eq!(path_request("/100%zz").path_decoded(), "/100%zz");
+        assert_eq!(path_request("/bad%FF").path_decoded(), "/bad\u{FFFD}");
+    }
+
+    /// A body of `chunks` chunks of `size` bytes, without a known length.
+    fn chunked_body(
+        chunks: usize,
+        size: usize,
+    ) -> http_body_util::StreamBody<
+        impl futures_util::Stream<Item = Result<hyper::body::Frame<Bytes>, std::io::Error>>,
+    > {
+        let chunk = Bytes::from(vec![b'x'; size]);
+        http_body_util::StreamBody::new(futures_util::stream::iter(
+            (0..chunks).map(move |_| Ok(hyper::body::Frame::data(chunk.clone()))),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_bytes_rejects_declared_length_without_reading() {
+        let body = http_body_util::StreamBody::new(futures_util::stream::poll_fn(
+            |_| -> std::task::Poll<Option<Result<hyper::body::Frame<Bytes>, std::io::Error>>> {
+                panic!("the body must not be read")
+            },
+        ));
+        let mut req = Request::builder()
+            .uri("/")
+            .header("Content-Length", "2048")
+            .body(body)
+            .unwrap();
+        req.extensions_mut().insert(BodyLimit(1024));
+
+        let err = req.bytes().await.unwrap_err();
+        assert!(matches!(err, ResponseError::PayloadTooLarge(1024)));
+        assert_eq!(err.status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_bytes_enforces_limit_on_chunked_body() {
+        let mut req = Request::builder()
+            .uri("/")
+            .body(chunked_body(8, 256))
+            .unwrap();
+        req.extensions_mut().insert(BodyLimit(1024));
+        let err = req.bytes().await.unwrap_err();
+        assert!(matches!(err, ResponseError::PayloadTooLarge(1024)));
+
+        let mut req = Request::builder()
+            .uri("/")
+            .body(chunked_body(4, 256))
+            .unwrap();
+        req.extensions_mut().insert(BodyLimit(1024));
+        assert_eq!(req.bytes().await.unwrap().len(), 1024);
+    }
+
+    #[tokio::test]
+    async fn test_body_reader_streams_the_body() {
+        use tokio::io::AsyncReadExt;
+
+        let chunks = ["first line\n", "second ", "line\n"]
+            .map(|c| Ok::<_, std::io::Error>(hyper::body::Frame::data(Bytes::from(c))));
+        let body = http_body_util::StreamBody::new(futures_util::stream::iter(chunks));
+        let req = Request::builder().uri("/").body(body).unwrap();
+        let mut text = String::new();
+        req.body_reader()
+            .unwrap()
+            .read_to_string(&mut text)
+            .await
+            .unwrap();
+        assert_eq!(text, "first line\nsecond line\n");
+
+        let mut req = Request::builder()
+            .uri("/")
+            .body(chunked_body(8, 256))
+            .unwrap();
+        req.extensions_mut().insert(BodyLimit(1024));
+        let mut reader = req.body_reader().unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        let inner = err.get_ref().unwrap().downcast_ref::<ResponseError>();
+        assert!(matches!(inner, Some(ResponseError::PayloadTooLarge(1024))));
+
+        let mut req = Request::builder()
+            .uri("/")
+            .header("Content-Length", "2048")
+            .body(chunked_body(8, 256))
+            .unwrap();
+        req.extensions_mut().insert(BodyLimit(1024));
+        assert!(matches!(
+            req.body_reader().err(),
+            Some(ResponseError::PayloadTooLarge(1024))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_json_honours_stricter_body_limit() {
+        let mut req = json_request(format!("\"{}\"", "x".repeat(64)));
+        req.extensions_mut().insert(BodyLimit(16));
+        let err = req.json::<String>().await.unwrap_err();
+        assert!(matches!(err, ResponseError::PayloadTooLarge(16)));
     }
 }
@@ -0,0 +1,38 @@
+//! Quality values of `Accept`-style headers, shared by content and encoding
+//! negotiation.
+
+/// Splits an `Accept`-style header (`Accept`, `Accept-Encoding`, ...) into
+/// its non-empty items, trimmed but in their original case, each with its
+/// `q` parameter: 1 when missing or unparsable.
+pub(crate) fn quality_values(header: &str) -> impl Iterator<Item = (&str, f32)> {
+    header.split(',').filter_map(|item| {
+        let mut parts = item.split(';');
+        let value = parts.next()?.trim();
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        (!value.is_empty()).then_some((value, q))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quality_values() {
+        let values: Vec<_> =
+            quality_values("Text/HTML, application/json;q=0.5 , ,br;level=1; q=0, gzip;q=x")
+                .collect();
+        assert_eq!(
+            values,
+            [
+                ("Text/HTML", 1.0),
+                ("application/json", 0.5),
+                ("br", 0.0),
+                ("gzip", 1.0),
+            ]
+        );
+    }
+}
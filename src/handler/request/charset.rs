@@ -0,0 +1,78 @@
+use crate::handler::ResponseError;
+use bytes::Bytes;
+
+/// A request body charset that [`RequestExt::json`](super::RequestExt::json)
+/// can transcode to UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Charset {
+    /// UTF-8, or a subset of it such as US-ASCII. Needs no transcoding.
+    Utf8,
+    /// ISO-8859-1: every byte maps to the code point of the same value.
+    Latin1,
+    /// UTF-16 little-endian.
+    Utf16Le,
+    /// UTF-16 big-endian.
+    Utf16Be,
+    /// UTF-16 with the byte order taken from a BOM, big-endian without one.
+    Utf16,
+}
+
+impl Charset {
+    /// Reads the `charset` parameter of a `Content-Type` value.
+    ///
+    /// A missing parameter means UTF-8. Unknown charsets are rejected with
+    /// [`ResponseError::UnsupportedCharset`].
+    pub(crate) fn from_content_type(content_type: Option<&str>) -> Result<Self, ResponseError> {
+        let Some(label) = content_type.and_then(charset_param) else {
+            return Ok(Charset::Utf8);
+        };
+
+        match label.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "us-ascii" | "ascii" => Ok(Charset::Utf8),
+            "iso-8859-1" | "iso8859-1" | "latin1" | "l1" => Ok(Charset::Latin1),
+            "utf-16le" => Ok(Charset::Utf16Le),
+            "utf-16be" => Ok(Charset::Utf16Be),
+            "utf-16" => Ok(Charset::Utf16),
+            _ => Err(ResponseError::UnsupportedCharset(label.to_owned())),
+        }
+    }
+
+    /// Transcodes `bytes` to UTF-8. UTF-8 input is returned untouched and left
+    /// for `serde_json` to validate.
+    pub(crate) fn decode(self, bytes: Bytes) -> Result<Bytes, ResponseError> {
+        let text = match self {
+            Charset::Utf8 => return Ok(bytes),
+            Charset::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+            Charset::Utf16Le => decode_utf16(&bytes, u16::from_le_bytes)?,
+            Charset::Utf16Be => decode_utf16(&bytes, u16::from_be_bytes)?,
+            Charset::Utf16 => match bytes.as_ref() {
+                [0xFF, 0xFE, rest @ ..] => decode_utf16(rest, u16::from_le_bytes)?,
+                [0xFE, 0xFF, rest @ ..] => decode_utf16(rest, u16::from_be_bytes)?,
+                rest => decode_utf16(rest, u16::from_be_bytes)?,
+            },
+        };
+        Ok(Bytes::from(text))
+    }
+}
+
+/// Extracts the (unquoted) `charset` parameter from a `Content-Type` value.
+fn charset_param(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Result<String, ResponseError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(ResponseError::BodyReadError(
+            "UTF-16 body has an odd number of bytes".to_owned(),
+        ));
+    }
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| ResponseError::BodyReadError(e.to_string()))
+}
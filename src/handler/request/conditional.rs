@@ -0,0 +1,125 @@
+//! Entity tags and the `If-Match` and `If-Range` preconditions (RFC 9110
+//! §8.8.3, §13.1.1, §13.1.5).
+
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+/// Whether [`RequestExt::check_if_match`](crate::prelude::RequestExt::check_if_match)
+/// accepts requests without an `If-Match` header. Configure it app-wide with
+/// [`App::if_match`](crate::prelude::App::if_match).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IfMatch {
+    /// Requests without `If-Match` pass, so clients may skip the check.
+    #[default]
+    Optional,
+    /// Requests without `If-Match` are answered with `428 Precondition Required`,
+    /// so no client can overwrite changes it hasn't seen.
+    Required,
+}
+
+/// A strong entity tag for `bytes`: the first 128 bits of their SHA-256, quoted.
+pub(crate) fn strong_etag(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    let mut etag = String::with_capacity(34);
+    etag.push('"');
+    for byte in &digest[..16] {
+        let _ = write!(etag, "{byte:02x}");
+    }
+    etag.push('"');
+    etag
+}
+
+/// Whether an `If-Match` header made of `values` is satisfied by the
+/// representation tagged `current`, using the strong comparison: weak tags
+/// never match. `*` matches any current representation.
+pub(crate) fn if_match<'a>(values: impl IntoIterator<Item = &'a str>, current: &str) -> bool {
+    if current.starts_with("W/") {
+        return false;
+    }
+    values.into_iter().any(|value| {
+        value.trim() == "*" || entity_tags(value).any(|(weak, tag)| !weak && tag == current)
+    })
+}
+
+/// Whether an `If-Range` header `value` is satisfied by the representation
+/// with the given validators, i.e. whether a `Range` may be honoured. An
+/// entity tag uses the strong comparison; a date must equal `Last-Modified`
+/// exactly.
+pub(crate) fn if_range(value: &str, etag: Option<&str>, last_modified: Option<&str>) -> bool {
+    let value = value.trim();
+    if value.starts_with('"') || value.starts_with("W/") {
+        return etag.is_some_and(|etag| {
+            !etag.starts_with("W/") && !value.starts_with("W/") && etag == value
+        });
+    }
+    match (
+        httpdate::parse_http_date(value),
+        last_modified.map(httpdate::parse_http_date),
+    ) {
+        (Ok(date), Some(Ok(last_modified))) => date == last_modified,
+        _ => false,
+    }
+}
+
+/// Splits a list of entity tags into `(weak, "opaque-tag")` pairs. Commas may
+/// appear inside the quotes, so the list is scanned rather than split; the
+/// scan stops at the first malformed tag.
+fn entity_tags(list: &str) -> impl Iterator<Item = (bool, &str)> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        let (weak, tail) = match rest.strip_prefix("W/") {
+            Some(tail) => (true, tail),
+            None => (false, rest),
+        };
+        let end = tail.strip_prefix('"')?.find('"')? + 2;
+        let tag = &tail[..end];
+        rest = &tail[end..];
+        Some((weak, tag))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_if_match_uses_strong_comparison() {
+        let current = "\"v2\"";
+        assert!(if_match(["\"v2\""], current));
+        assert!(if_match(["\"v1\", \"v2\""], current));
+        assert!(if_match(["\"v1\"", "\"v2\""], current));
+        assert!(if_match(["*"], current));
+        assert!(!if_match(["\"v1\""], current));
+        assert!(!if_match(["W/\"v2\""], current));
+        assert!(!if_match(["\"v2\""], "W/\"v2\""));
+        assert!(!if_match(["v2"], current));
+
+        // Commas inside a tag don't split it.
+        assert!(if_match(["\"a,b\""], "\"a,b\""));
+        assert!(!if_match(["\"a,b\""], "\"b\""));
+    }
+
+    #[test]
+    fn test_if_range_matches_validators_exactly() {
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert!(if_range("\"v2\"", Some("\"v2\""), None));
+        assert!(!if_range("\"v1\"", Some("\"v2\""), None));
+        assert!(!if_range("W/\"v2\"", Some("W/\"v2\""), None));
+        assert!(!if_range("\"v2\"", None, Some(date)));
+
+        assert!(if_range(date, Some("\"v2\""), Some(date)));
+        assert!(!if_range("Wed, 21 Oct 2015 07:28:01 GMT", None, Some(date)));
+        assert!(!if_range(date, None, None));
+        assert!(!if_range("yesterday", None, Some(date)));
+    }
+
+    #[test]
+    fn test_strong_etag_is_stable_and_quoted() {
+        let etag = strong_etag(b"{\"id\":1}");
+        assert_eq!(etag.len(), 34);
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert_eq!(etag, strong_etag(b"{\"id\":1}"));
+        assert_ne!(etag, strong_etag(b"{\"id\":2}"));
+    }
+}
@@ -0,0 +1,147 @@
+//! Parsing of the standard `Forwarded` header (RFC 7239).
+
+use std::borrow::Cow;
+use std::net::{IpAddr, Ipv6Addr};
+
+/// The parameters of one proxy hop of a `Forwarded` header that the app
+/// uses. Others, such as `by` and `host`, are parsed past and dropped.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Element<'a> {
+    /// The `for` parameter: the node the proxy received the request from.
+    pub(crate) for_node: Option<Cow<'a, str>>,
+    /// The `proto` parameter, e.g. `https`.
+    pub(crate) proto: Option<Cow<'a, str>>,
+}
+
+impl Element<'_> {
+    /// The address in the `for` parameter, or `None` for `unknown`,
+    /// obfuscated identifiers (`_hidden`) and anything malformed.
+    pub(crate) fn for_ip(&self) -> Option<IpAddr> {
+        parse_node(self.for_node.as_deref()?)
+    }
+}
+
+/// Parses the elements of one or more `Forwarded` header values, nearest
+/// the client first. Unknown parameters are skipped.
+pub(crate) fn parse<'a>(values: impl IntoIterator<Item = &'a str>) -> Vec<Element<'a>> {
+    values
+        .into_iter()
+        .flat_map(|value| split_unquoted(value, ','))
+        .map(|element| {
+            let mut parsed = Element::default();
+            for pair in split_unquoted(element, ';') {
+                let Some((name, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                let value = unquote(value.trim());
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "for" => parsed.for_node = Some(value),
+                    "proto" => parsed.proto = Some(value),
+                    _ => {}
+                }
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Splits `s` on `sep`, ignoring separators inside quoted strings.
+fn split_unquoted(s: &str, sep: char) -> impl Iterator<Item = &str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts.into_iter().map(str::trim).filter(|p| !p.is_empty())
+}
+
+/// Removes the quotes and escapes of a quoted string; tokens are returned as is.
+fn unquote(value: &str) -> Cow<'_, str> {
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return Cow::Borrowed(value);
+    };
+    if !inner.contains('\\') {
+        return Cow::Borrowed(inner);
+    }
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        out.push(if c == '\\' {
+            chars.next().unwrap_or('\\')
+        } else {
+            c
+        });
+    }
+    Cow::Owned(out)
+}
+
+/// Parses a node: `192.0.2.1`, `192.0.2.1:8080`, `[2001:db8::1]` or
+/// `[2001:db8::1]:8080`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, port) = rest.split_once(']')?;
+        if !(port.is_empty() || port.starts_with(':')) {
+            return None;
+        }
+        return ip.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    let ip = match node.split_once(':') {
+        Some((ip, _port)) => ip,
+        None => node,
+    };
+    ip.parse().ok().filter(IpAddr::is_ipv4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_forwarded_elements() {
+        let elements = parse([
+            r#"for=192.0.2.60;proto=HTTPS;by=203.0.113.43;host="example.com:8443""#,
+            r#"For="[2001:db8:cafe::17]:4711", for=unknown"#,
+            r#"for="198.51.100.17:80";host="a\"b,c""#,
+        ]);
+        assert_eq!(elements.len(), 4);
+
+        assert_eq!(elements[0].for_ip(), Some(ip("192.0.2.60")));
+        assert_eq!(elements[0].proto.as_deref(), Some("HTTPS"));
+
+        assert_eq!(elements[1].for_ip(), Some(ip("2001:db8:cafe::17")));
+        assert_eq!(elements[1].proto, None);
+        assert_eq!(elements[2].for_node.as_deref(), Some("unknown"));
+        assert_eq!(elements[2].for_ip(), None);
+
+        // Separators and escaped quotes inside quoted strings.
+        assert_eq!(elements[3].for_ip(), Some(ip("198.51.100.17")));
+        let escaped = parse([r#"proto="ht\"tp""#]);
+        assert_eq!(escaped[0].proto.as_deref(), Some(r#"ht"tp"#));
+    }
+
+    #[test]
+    fn test_parse_forwarded_nodes() {
+        assert_eq!(parse_node("[::1]"), Some(ip("::1")));
+        assert_eq!(parse_node("[2001:db8::1]:8080"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("10.0.0.1:_port"), Some(ip("10.0.0.1")));
+        // IPv6 must be bracketed, and obfuscated identifiers carry no address.
+        assert_eq!(parse_node("2001:db8::1"), None);
+        assert_eq!(parse_node("[2001:db8::1]x"), None);
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_node("unknown"), None);
+    }
+}
@@ -0,0 +1,52 @@
+//! Per-request nonces for a nonce-based `Content-Security-Policy`.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use once_cell::sync::Lazy;
+use std::sync::{Arc, OnceLock};
+use tokio_rustls::rustls::crypto::{SecureRandom, aws_lc_rs};
+
+static RANDOM: Lazy<&'static dyn SecureRandom> =
+    Lazy::new(|| aws_lc_rs::default_provider().secure_random);
+
+/// `N` bytes from the system's cryptographically secure generator.
+pub(crate) fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    RANDOM
+        .fill(&mut bytes)
+        .expect("the system random number generator failed");
+    bytes
+}
+
+/// A request's CSP nonce: 128 random bits, base64-encoded, generated on
+/// first use and then the same for the whole request.
+///
+/// [`SecurityHeaders::script_src_nonce`](crate::prelude::SecurityHeaders::script_src_nonce)
+/// inserts one into the request, where handlers read it with
+/// [`RequestExt::csp_nonce`](crate::prelude::RequestExt::csp_nonce) to put it
+/// in their `<script nonce="...">` tags, and adds it to the
+/// `Content-Security-Policy` header. Clones share the same value.
+#[derive(Debug, Clone, Default)]
+pub struct Nonce(Arc<OnceLock<String>>);
+
+impl Nonce {
+    /// The nonce, generated on the first call.
+    pub fn get(&self) -> &str {
+        self.0.get_or_init(|| STANDARD.encode(random_bytes::<16>()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nonce_is_stable_per_request_and_unique() {
+        let nonce = Nonce::default();
+        let value = nonce.get().to_owned();
+        assert_eq!(value.len(), 24);
+        assert_eq!(STANDARD.decode(&value).unwrap().len(), 16);
+        assert_eq!(nonce.clone().get(), value);
+        assert_ne!(Nonce::default().get(), value);
+    }
+}
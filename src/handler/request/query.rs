@@ -0,0 +1,227 @@
+//! Bracket-syntax query strings, e.g. `tags[]=a&tags[]=b&filter[status]=open`.
+
+use crate::handler::extract::ExtractRejection;
+use serde_json::{Map, Value};
+
+/// How [`RequestExt::query_nested`](crate::prelude::RequestExt::query_nested)
+/// and [`RequestExt::query_as`](crate::prelude::RequestExt::query_as) parse
+/// the query string. Configure it app-wide with
+/// [`App::query_options`](crate::prelude::App::query_options).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryOptions {
+    /// Maximum number of bracketed segments in a key: `a[b][c]` has two.
+    /// Deeper keys reject the whole query string.
+    pub max_depth: usize,
+    /// What a key given more than once without `[]` resolves to.
+    pub duplicates: DuplicateKeys,
+    /// Maximum number of `&`-separated parameters. More reject the query
+    /// string with `400 Bad Request`.
+    pub max_params: usize,
+    /// Maximum length of the query string in bytes, as sent. Longer ones
+    /// are rejected with `414 URI Too Long`.
+    pub max_length: usize,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 5,
+            duplicates: DuplicateKeys::Collect,
+            max_params: 1000,
+            max_length: 16 * 1024,
+        }
+    }
+}
+
+/// What a repeated key such as `tag=a&tag=b` resolves to. Keys ending in
+/// `[]` always collect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// The values collect into an array: `["a", "b"]`.
+    #[default]
+    Collect,
+    /// The last value replaces the earlier ones: `"b"`.
+    LastWins,
+}
+
+/// Rejects `query` if it exceeds the length or parameter count limits, before
+/// anything is decoded.
+pub(crate) fn check_limits(query: &str, options: &QueryOptions) -> Result<(), ExtractRejection> {
+    if query.len() > options.max_length {
+        return Err(ExtractRejection::QueryTooLong(options.max_length));
+    }
+    let mut params = query.split('&').filter(|param| !param.is_empty());
+    if params.nth(options.max_params).is_some() {
+        return Err(ExtractRejection::InvalidQuery(format!(
+            "more than {} parameters",
+            options.max_params
+        )));
+    }
+    Ok(())
+}
+
+/// Parses `query` into an object whose leaves are strings, arrays and
+/// objects, or returns why it was rejected.
+pub(crate) fn parse_nested(query: &str, options: &QueryOptions) -> Result<Value, String> {
+    let mut root = Value::Object(Map::new());
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let segments = split_key(&key);
+        if segments.len() > options.max_depth + 1 {
+            return Err(format!(
+                "key `{key}` nests deeper than {} levels",
+                options.max_depth
+            ));
+        }
+        insert(&mut root, &segments, value.into_owned(), options.duplicates);
+    }
+    Ok(root)
+}
+
+/// Splits `a[b][]` into `["a", "b", ""]`. A key that isn't well-formed
+/// bracket syntax is kept whole.
+fn split_key(key: &str) -> Vec<&str> {
+    let Some(open) = key.find('[').filter(|&open| open > 0) else {
+        return vec![key];
+    };
+    let mut segments = vec![&key[..open]];
+    let mut rest = &key[open..];
+    while !rest.is_empty() {
+        let Some(segment) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) else {
+            return vec![key];
+        };
+        segments.push(segment.0);
+        rest = segment.1;
+    }
+    segments
+}
+
+/// Inserts `value` at the path `segments` below `node`. Where the shape of
+/// an earlier key conflicts, e.g. `a=1&a[b]=2`, the later key wins.
+fn insert(node: &mut Value, segments: &[&str], value: String, duplicates: DuplicateKeys) {
+    let (segment, rest) = segments.split_first().expect("keys have a segment");
+
+    if segment.is_empty() {
+        if !node.is_array() {
+            *node = Value::Array(Vec::new());
+        }
+        let Value::Array(items) = node else {
+            unreachable!()
+        };
+        if rest.is_empty() {
+            items.push(Value::String(value));
+        } else {
+            let mut child = Value::Object(Map::new());
+            insert(&mut child, rest, value, duplicates);
+            items.push(child);
+        }
+        return;
+    }
+
+    if !node.is_object() {
+        *node = Value::Object(Map::new());
+    }
+    let Value::Object(map) = node else {
+        unreachable!()
+    };
+    if !rest.is_empty() {
+        let child = map
+            .entry(*segment)
+            .or_insert_with(|| Value::Object(Map::new()));
+        insert(child, rest, value, duplicates);
+        return;
+    }
+    match (map.get_mut(*segment), duplicates) {
+        (Some(Value::Array(items)), DuplicateKeys::Collect) => items.push(Value::String(value)),
+        (Some(first @ Value::String(_)), DuplicateKeys::Collect) => {
+            *first = Value::Array(vec![first.take(), Value::String(value)]);
+        }
+        _ => {
+            map.insert((*segment).to_owned(), Value::String(value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(query: &str) -> Value {
+        parse_nested(query, &QueryOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_arrays_and_nested_maps() {
+        assert_eq!(
+            parse("tags[]=a&tags[]=b&filter[status]=open&filter[owner][id]=7&page=2"),
+            json!({
+                "tags": ["a", "b"],
+                "filter": { "status": "open", "owner": { "id": "7" } },
+                "page": "2",
+            })
+        );
+        assert_eq!(
+            parse("items[][name]=x&items[][name]=y"),
+            json!({ "items": [{ "name": "x" }, { "name": "y" }] })
+        );
+        // Percent-encoded brackets, and keys that aren't bracket syntax.
+        assert_eq!(
+            parse("a%5Bb%5D=1&[c]=2&d[e=3&f]=4"),
+            json!({ "a": { "b": "1" }, "[c]": "2", "d[e": "3", "f]": "4" })
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        assert_eq!(
+            parse("tag=a&tag=b&tag=c"),
+            json!({ "tag": ["a", "b", "c"] })
+        );
+
+        let last_wins = QueryOptions {
+            duplicates: DuplicateKeys::LastWins,
+            ..QueryOptions::default()
+        };
+        let parsed = parse_nested("tag=a&tag=b&f[x]=1&f[x]=2&all[]=1&all[]=2", &last_wins);
+        assert_eq!(
+            parsed.unwrap(),
+            json!({ "tag": "b", "f": { "x": "2" }, "all": ["1", "2"] })
+        );
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let options = QueryOptions {
+            max_depth: 2,
+            ..QueryOptions::default()
+        };
+        assert!(parse_nested("a[b][c]=1", &options).is_ok());
+        let err = parse_nested("ok=1&a[b][c][d]=1", &options).unwrap_err();
+        assert_eq!(err, "key `a[b][c][d]` nests deeper than 2 levels");
+    }
+
+    #[test]
+    fn test_param_count_and_length_limits() {
+        let options = QueryOptions {
+            max_params: 3,
+            max_length: 32,
+            ..QueryOptions::default()
+        };
+        // Empty pieces between separators don't count.
+        assert!(check_limits("a=1&&b=2&c&", &options).is_ok());
+        let err = check_limits("a=1&b=2&c=3&d=4", &options).unwrap_err();
+        assert_eq!(err.status(), hyper::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            err.to_string(),
+            "invalid query string: more than 3 parameters"
+        );
+
+        let err = check_limits(&"a".repeat(33), &options).unwrap_err();
+        assert_eq!(err.status(), hyper::StatusCode::URI_TOO_LONG);
+
+        // Thousands of parameters are rejected by default.
+        let flood = vec!["x"; 5000].join("&");
+        let err = check_limits(&flood, &QueryOptions::default()).unwrap_err();
+        assert_eq!(err.status(), hyper::StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,108 @@
+//! Handler-recorded durations reported in `Server-Timing`.
+
+use log::warn;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The durations recorded for a request, reported in its `Server-Timing`
+/// response header.
+///
+/// [`ServerTimingMiddleware`](crate::prelude::ServerTimingMiddleware) inserts
+/// one into every request, where handlers record into it with
+/// [`RequestExt::time_mark`](crate::prelude::RequestExt::time_mark). Clones
+/// share the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct ServerTimings(Arc<Mutex<Vec<(String, Duration)>>>);
+
+impl ServerTimings {
+    /// Adds `duration` to the entry `name`, creating it at the end if this
+    /// is its first record. Names must be HTTP tokens, e.g. `db` or
+    /// `cache-lookup`; others are dropped with a warning.
+    pub fn record(&self, name: &str, duration: Duration) {
+        if name.is_empty() || !name.bytes().all(is_tchar) {
+            warn!("ignoring Server-Timing entry with invalid name {name:?}");
+            return;
+        }
+        let mut entries = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match entries.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, total)) => *total += duration,
+            None => entries.push((name.to_owned(), duration)),
+        }
+    }
+
+    /// The `Server-Timing` header value, with durations in milliseconds, or
+    /// `None` if nothing was recorded.
+    pub(crate) fn header_value(&self) -> Option<String> {
+        let entries = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.is_empty() {
+            return None;
+        }
+        let value = entries
+            .iter()
+            .map(|(name, duration)| format!("{name};dur={:.1}", duration.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Some(value)
+    }
+}
+
+/// Records the time from its creation to its drop under a name, returned by
+/// [`RequestExt::time_mark`](crate::prelude::RequestExt::time_mark).
+///
+/// Keep it in a named binding (`let _db = ...`): `let _ = ...` drops it, and
+/// records, immediately.
+#[derive(Debug)]
+#[must_use = "the mark records when dropped, so it must be held for the timed work"]
+pub struct TimingMark {
+    name: String,
+    start: Instant,
+    timings: Option<ServerTimings>,
+}
+
+impl TimingMark {
+    pub(crate) fn new(name: impl Into<String>, timings: Option<ServerTimings>) -> Self {
+        Self {
+            name: name.into(),
+            start: Instant::now(),
+            timings,
+        }
+    }
+
+    /// Records now instead of at the end of the scope.
+    pub fn stop(self) {}
+}
+
+impl Drop for TimingMark {
+    fn drop(&mut self) {
+        if let Some(timings) = &self.timings {
+            timings.record(&self.name, self.start.elapsed());
+        }
+    }
+}
+
+/// Whether `b` may appear in an HTTP token (RFC 9110, section 5.6.2).
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marks_accumulate_by_name() {
+        let timings = ServerTimings::default();
+        assert_eq!(timings.header_value(), None);
+
+        timings.record("db", Duration::from_micros(1500));
+        timings.record("cache", Duration::from_millis(2));
+        timings.record("db", Duration::from_millis(3));
+        timings.record("not a token", Duration::from_millis(1));
+        assert_eq!(timings.header_value().unwrap(), "db;dur=4.5, cache;dur=2.0");
+
+        // Without a middleware, marks record nowhere.
+        TimingMark::new("db", None).stop();
+        TimingMark::new("render", Some(timings.clone())).stop();
+        assert!(timings.header_value().unwrap().contains(", render;dur="));
+    }
+}
@@ -0,0 +1,138 @@
+use super::connection_info;
+use super::forwarded;
+use hyper::HeaderMap;
+use hyper::http::Extensions;
+use smallvec::SmallVec;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Which proxies in front of the app are trusted to report the client address
+/// and protocol through the standard `Forwarded` header (RFC 7239) or, when
+/// it is absent, `X-Forwarded-For` / `X-Forwarded-Proto`.
+///
+/// Without trust, those headers are ignored: any client can send them, so
+/// believing them would let callers spoof their address. Configure it
+/// app-wide with [`App::trust_proxy`](crate::prelude::App::trust_proxy).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TrustProxy {
+    /// Ignore forwarding headers and use the socket peer address.
+    #[default]
+    Disabled,
+    /// Trust every hop; the left-most forwarded address is the client.
+    All,
+    /// Trust the given number of proxies, counting from the socket peer.
+    Hops(usize),
+    /// Trust only proxies with one of these addresses.
+    Ips(Arc<[IpAddr]>),
+}
+
+impl TrustProxy {
+    /// Trust only proxies with one of the given addresses.
+    pub fn ips(ips: impl IntoIterator<Item = IpAddr>) -> Self {
+        TrustProxy::Ips(ips.into_iter().collect())
+    }
+
+    /// Whether `addr`, found `hop` steps away from the app, may forward for others.
+    fn trusts(&self, addr: IpAddr, hop: usize) -> bool {
+        match self {
+            TrustProxy::Disabled => false,
+            TrustProxy::All => true,
+            TrustProxy::Hops(n) => hop < *n,
+            TrustProxy::Ips(ips) => ips.contains(&addr),
+        }
+    }
+}
+
+/// The socket peer followed by every trusted forwarded address, nearest first.
+/// The last entry is the resolved client.
+fn trusted_chain(headers: &HeaderMap, extensions: &Extensions) -> SmallVec<[IpAddr; 4]> {
+    let mut chain = SmallVec::new();
+    let Some(peer) = connection_info(extensions).map(|info| info.remote_addr.ip()) else {
+        return chain;
+    };
+    chain.push(peer);
+
+    let trust = extensions
+        .get::<TrustProxy>()
+        .unwrap_or(&TrustProxy::Disabled);
+    if !trust.trusts(peer, 0) {
+        return chain;
+    }
+
+    // Each proxy appends the address it received the request from, so walk
+    // the list right to left.
+    let forwarded: SmallVec<[Option<IpAddr>; 4]> = if headers.contains_key("forwarded") {
+        forwarded::parse(header_values(headers, "forwarded"))
+            .iter()
+            .map(forwarded::Element::for_ip)
+            .collect()
+    } else {
+        header_values(headers, "x-forwarded-for")
+            .flat_map(|v| v.split(','))
+            .map(|entry| entry.trim().parse().ok())
+            .collect()
+    };
+
+    for entry in forwarded.into_iter().rev() {
+        // `unknown` and obfuscated nodes end what can be traced.
+        let Some(addr) = entry else {
+            break;
+        };
+        chain.push(addr);
+        if !trust.trusts(addr, chain.len() - 1) {
+            break;
+        }
+    }
+    chain
+}
+
+/// Every value of the header `name` that is valid text.
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers.get_all(name).iter().filter_map(|v| v.to_str().ok())
+}
+
+/// The client address, honouring the configured [`TrustProxy`].
+pub(crate) fn client_ip(headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+    trusted_chain(headers, extensions).last().copied()
+}
+
+/// The trusted forwarded addresses, client first, excluding the socket peer.
+pub(crate) fn forwarded_ips(headers: &HeaderMap, extensions: &Extensions) -> Vec<IpAddr> {
+    trusted_chain(headers, extensions)
+        .into_iter()
+        .skip(1)
+        .rev()
+        .collect()
+}
+
+/// `"https"` or `"http"`, honouring the client-facing `proto` of `Forwarded`,
+/// or else `X-Forwarded-Proto`, from a trusted peer.
+pub(crate) fn protocol(headers: &HeaderMap, extensions: &Extensions) -> &'static str {
+    if connection_info(extensions).is_some_and(|info| info.tls.is_some()) {
+        return "https";
+    }
+
+    let trust = extensions
+        .get::<TrustProxy>()
+        .unwrap_or(&TrustProxy::Disabled);
+    let peer_trusted =
+        connection_info(extensions).is_some_and(|info| trust.trusts(info.remote_addr.ip(), 0));
+    let is_https = |proto: &str| proto.trim().eq_ignore_ascii_case("https");
+    let forwarded_https = if headers.contains_key("forwarded") {
+        forwarded::parse(header_values(headers, "forwarded"))
+            .iter()
+            .find_map(|element| element.proto.as_deref().map(is_https))
+            .unwrap_or(false)
+    } else {
+        header_values(headers, "x-forwarded-proto")
+            .next()
+            .and_then(|v| v.split(',').next())
+            .is_some_and(is_https)
+    };
+
+    if peer_trusted && forwarded_https {
+        "https"
+    } else {
+        "http"
+    }
+}
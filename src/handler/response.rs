@@ -1,12 +1,20 @@
+use crate::handler::Request;
+use crate::handler::request::strong_etag;
 use bytes::Bytes;
 use cookie::Cookie;
 use futures_util::StreamExt;
 use http_body_util::StreamBody;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Full};
+use hyper::HeaderMap;
 use hyper::StatusCode;
 use hyper::body::Frame;
-use hyper::header::{CONTENT_TYPE, HeaderValue, IntoHeaderName, LOCATION, SET_COOKIE};
+use hyper::header::{
+    ACCEPT, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    HeaderName, HeaderValue, IntoHeaderName, LAST_MODIFIED, LINK, LOCATION, RETRY_AFTER,
+    SET_COOKIE, TRANSFER_ENCODING, VARY,
+};
+use log::warn;
 use once_cell::sync::Lazy;
 use quick_cache::sync::Cache;
 use serde::Serialize;
@@ -14,10 +22,38 @@ use std::borrow::Cow;
 use std::io;
 use std::path::Path;
 use std::pin::Pin;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::fs::File;
 use tokio_util::io::ReaderStream;
 
+mod api_error;
+mod cache_policy;
+mod channel;
+mod error_envelope;
+mod header_policy;
+mod into_response;
+mod multipart;
+mod negotiate;
+mod pagination;
+mod range;
+
+use negotiate::Format;
+use range::ByteRange;
+
+pub use api_error::ApiError;
+pub use cache_policy::CachePolicy;
+pub use channel::BodyWriter;
+pub(crate) use error_envelope::error_code;
+pub use error_envelope::{ErrorEnvelope, ErrorInfo};
+pub use header_policy::HeaderPolicy;
+pub(crate) use into_response::{
+    DefaultErrorBody, ErrorDetails, json_errors, verbose_errors, write_error_body,
+};
+pub use into_response::{ErrorFormat, IntoResponse, Json};
+pub use multipart::Part;
+pub use pagination::PageMeta;
+
 /// Represents an error that occurs during response building or handling.
 #[derive(Error, Debug)]
 pub enum ResponseError {
@@ -27,6 +63,9 @@ pub enum ResponseError {
     /// Error serializing JSON body.
     #[error("JSON serialization error: {0}")]
     JsonSerializationError(#[from] serde_json::Error),
+    /// Error serializing a MessagePack or CBOR body.
+    #[error("serialization error: {0}")]
+    SerializationError(String),
     /// Error due to an invalid HTTP header value.
     #[error("invalid header value: {0}")]
     InvalidHeaderValue(#[from] hyper::header::InvalidHeaderValue),
@@ -39,6 +78,63 @@ pub enum ResponseError {
     /// Error reading request body.
     #[error("body read error: {0}")]
     BodyReadError(String),
+    /// The request body exceeded the configured size limit (in bytes).
+    #[error("payload too large: limit is {0} bytes")]
+    PayloadTooLarge(usize),
+    /// The JSON request body nested deeper than the configured limit.
+    #[error("JSON nesting exceeds maximum depth of {0}")]
+    JsonTooDeep(usize),
+    /// The request body declared a charset that cannot be decoded.
+    #[error("unsupported charset: {0}")]
+    UnsupportedCharset(String),
+    /// The request body's `Content-Type` is not the expected one.
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+    /// The handler panicked while producing the response.
+    #[error("handler panicked: {0}")]
+    HandlerPanicked(String),
+    /// A blocking body producer panicked or was cancelled.
+    #[error("blocking task failed: {0}")]
+    BlockingTaskFailed(String),
+    /// The request's `If-Match` names none of the current representation's tags.
+    #[error("precondition failed: the resource has changed")]
+    PreconditionFailed,
+    /// The request lacks an `If-Match` header the server requires.
+    #[error("precondition required: the request must be conditional on If-Match")]
+    PreconditionRequired,
+    /// The request body doesn't match the digest the client sent for it.
+    #[error("the request body does not match its {0} digest")]
+    DigestMismatch(&'static str),
+    /// An [`ApiError`] answering a client error, kept on the response.
+    #[error(transparent)]
+    Api(ApiError),
+    /// A server-side failure described by a message, e.g. from
+    /// [`ApiError::internal`]; only logged, never sent to the client.
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl ResponseError {
+    /// Returns the HTTP status code that best describes this error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ResponseError::BodyReadError(_)
+            | ResponseError::JsonTooDeep(_)
+            | ResponseError::JsonSerializationError(_) => StatusCode::BAD_REQUEST,
+            ResponseError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ResponseError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ResponseError::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            ResponseError::DigestMismatch(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ResponseError::UnsupportedCharset(_) | ResponseError::UnsupportedMediaType(_) => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            ResponseError::Api(e) => e.status(),
+            ResponseError::FileOpenError(e) if e.kind() == io::ErrorKind::NotFound => {
+                StatusCode::NOT_FOUND
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 /// The response structure used to construct HTTP responses.
@@ -52,6 +148,9 @@ pub struct Response {
     pub body: ResponseBody,
     /// Any error that occurred while processing the response.
     pub error: Option<ResponseError>,
+    /// Request-scoped data attached to the response, e.g. by middleware that
+    /// needs it again in [`Middleware::finish`](crate::prelude::Middleware::finish).
+    pub extensions: hyper::http::Extensions,
 }
 
 /// The body of an HTTP response.
@@ -68,8 +167,13 @@ pub enum ResponseBody {
     Stream(
         Pin<Box<dyn futures_util::Stream<Item = Result<Frame<Bytes>, io::Error>> + Send + Sync>>,
     ),
+    /// A body produced on demand by a closure; see [`Response::lazy_body`].
+    Lazy(LazyBody),
 }
 
+/// The closure behind [`ResponseBody::Lazy`].
+pub type LazyBody = Box<dyn FnOnce() -> Bytes + Send + Sync>;
+
 impl std::fmt::Debug for ResponseBody {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -77,6 +181,7 @@ impl std::fmt::Debug for ResponseBody {
             ResponseBody::Full(bytes) => f.debug_tuple("Full").field(bytes).finish(),
             ResponseBody::Buffered(chunks) => f.debug_tuple("Buffered").field(chunks).finish(),
             ResponseBody::Stream(_) => write!(f, "Stream(...)"),
+            ResponseBody::Lazy(_) => write!(f, "Lazy(...)"),
         }
     }
 }
@@ -85,6 +190,17 @@ impl ResponseBody {
     pub fn is_empty(&self) -> bool {
         matches!(self, ResponseBody::Empty)
     }
+
+    /// Returns the body length in bytes, or `None` for streaming and
+    /// not yet produced lazy bodies.
+    pub fn content_length(&self) -> Option<u64> {
+        match self {
+            ResponseBody::Empty => Some(0),
+            ResponseBody::Full(bytes) => Some(bytes.len() as u64),
+            ResponseBody::Buffered(chunks) => Some(chunks.iter().map(|c| c.len() as u64).sum()),
+            ResponseBody::Stream(_) | ResponseBody::Lazy(_) => None,
+        }
+    }
 }
 
 impl Default for Response {
@@ -96,6 +212,18 @@ impl Default for Response {
 /// Shorthand type for the hyper service response type.
 pub type ServerResponse = hyper::Response<BoxBody<Bytes, std::convert::Infallible>>;
 
+/// Builds the session cookies set by [`ExpressResponse::signed_cookie`].
+///
+/// Implemented by the cookie config of [`AuthMiddleware`](crate::prelude::AuthMiddleware),
+/// which signs with its `signing_keys`.
+pub trait CookieSigner {
+    /// Builds the cookie `name=value` with its attributes, signed when
+    /// [`signs`](Self::signs) is true.
+    fn session_cookie(&self, name: &str, value: &str) -> Cookie<'static>;
+    /// Whether [`session_cookie`](Self::session_cookie) signs its cookies.
+    fn signs(&self) -> bool;
+}
+
 /// Trait providing Express-like response builder methods.
 pub trait ExpressResponse: Sized {
     /// Sets the HTTP status code.
@@ -109,8 +237,12 @@ pub trait ExpressResponse: Sized {
         V: Into<HeaderValue>;
     /// Sets the `Content-Type` header.
     fn content_type<T: AsRef<str>>(self, mime_type: T) -> Self;
-    /// Sets the `Location` header.
+    /// Sets the `Location` header, percent-encoding characters a URL can't hold.
     fn location<T: AsRef<str>>(self, url: T) -> Self;
+    /// Marks the response as a download named after the last component of
+    /// `filename`, setting `Content-Disposition` and, from the extension,
+    /// `Content-Type`.
+    fn attachment<T: AsRef<str>>(self, filename: T) -> Self;
     /// Sets the response body entirely.
     fn body<T: Into<Bytes>>(self, data: T) -> Self;
     /// Appends data to the response body.
@@ -125,6 +257,14 @@ pub trait ExpressResponse: Sized {
     fn redirect<T: AsRef<str>>(self, url: T) -> Self;
     /// Sets a cookie.
     fn cookie(self, cookie: Cookie<'_>) -> Self;
+    /// Sets a session cookie built and signed by `signer`, e.g. an
+    /// [`AuthMiddleware`](crate::prelude::AuthMiddleware)'s `config()`, so
+    /// it is named and scoped the way the middleware reads it back. A value
+    /// changed by the client then fails verification and the request is
+    /// treated as unauthenticated.
+    ///
+    /// Without signing keys the cookie is sent unsigned, with a warning.
+    fn signed_cookie(self, name: &str, value: &str, signer: &impl CookieSigner) -> Self;
     /// Sets status to 200 OK.
     fn ok(self) -> Self;
     /// Sends a JSON response (alias for `send_json`).
@@ -144,6 +284,7 @@ impl Response {
             headers: hyper::HeaderMap::new(),
             body: ResponseBody::Empty,
             error: None,
+            extensions: hyper::http::Extensions::new(),
         }
     }
 
@@ -168,6 +309,139 @@ impl Response {
             .send_text("Internal Server Error")
     }
 
+    /// Creates an HTTP 429 Too Many Requests response with a `Retry-After` header.
+    ///
+    /// Use [`respond_too_many_requests`](Self::respond_too_many_requests) for a JSON body.
+    pub fn too_many_requests(retry_after: Duration) -> Self {
+        let mut res = Self::new();
+        res.respond_too_many_requests(retry_after, false);
+        res
+    }
+
+    /// Sets a body that `produce` builds only when the response is serialized.
+    ///
+    /// The closure runs at most once, inside [`into_hyper`](Self::into_hyper),
+    /// after every handler and middleware has finished. If the body is replaced
+    /// before then, e.g. by a middleware that stops the chain, or the status
+    /// forbids a body, it never runs. It runs synchronously on the connection's
+    /// task, so it should not block, and it must be `Send + Sync` because the
+    /// response may move between threads before it is sent.
+    ///
+    /// Middleware that rewrites the body as a whole, such as compression,
+    /// produces it early, when its [`finish`](crate::prelude::Middleware::finish) runs.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    ///
+    /// # fn render_report() -> String { String::new() }
+    /// let res = Response::new()
+    ///     .content_type("text/csv")
+    ///     .lazy_body(|| render_report());
+    /// ```
+    pub fn lazy_body<F, T>(mut self, produce: F) -> Self
+    where
+        F: FnOnce() -> T + Send + Sync + 'static,
+        T: Into<Bytes>,
+    {
+        self.body = ResponseBody::Lazy(Box::new(move || produce().into()));
+        self
+    }
+
+    /// Sends `data` as JSON with a strong `ETag` computed from the serialized
+    /// bytes, so clients can make later writes conditional on it with `If-Match`.
+    /// See [`RequestExt::check_if_match`](crate::prelude::RequestExt::check_if_match).
+    pub fn json_with_etag<T: Serialize>(mut self, data: &T) -> Self {
+        match serde_json::to_vec(data) {
+            Ok(json) => {
+                try_insert_header(&mut self.headers, ETAG, strong_etag(&json));
+                self.content_type("application/json").body(json)
+            }
+            Err(e) => {
+                self.error = Some(ResponseError::JsonSerializationError(e));
+                self
+            }
+        }
+    }
+
+    /// Sends `data` in the format the request's `Accept` header prefers:
+    /// JSON (`application/json`), MessagePack (`application/msgpack`) or
+    /// CBOR (`application/cbor`). Without a preference for one of them, e.g.
+    /// for `*/*` or no `Accept` at all, JSON is sent. `Vary: Accept` tells
+    /// caches the body depends on it.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    ///
+    /// let mut app = express();
+    /// app.get("/user", async |req, res| {
+    ///     res.body_negotiated(&req, &serde_json::json!({ "name": "ada" }))
+    /// });
+    /// ```
+    pub fn body_negotiated<B, T: Serialize>(mut self, req: &Request<B>, data: &T) -> Self {
+        let accept = req.headers().get(ACCEPT).and_then(|v| v.to_str().ok());
+        let format = Format::negotiate(accept);
+        vary_by(&mut self.headers, "Accept");
+        match format.serialize(data) {
+            Ok(bytes) => self.content_type(format.content_type()).body(bytes),
+            Err(e) => {
+                self.error = Some(ResponseError::SerializationError(e));
+                self
+            }
+        }
+    }
+
+    /// Sends one page of a collection as JSON, with its pagination metadata:
+    /// `{"data": [...], "page", "per_page", "total", "out_of_range", "links":
+    /// {"first", "prev", "next", "last"}}`, where `prev` and `next` are `null`
+    /// on the first and last pages. The same links are added to the `Link`
+    /// header. `out_of_range` is true for a page past the last one; see
+    /// [`PageMeta::out_of_range`].
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    ///
+    /// let mut app = express();
+    /// app.get("/todos", async |req, res| {
+    ///     let todos = ["write docs", "ship"];
+    ///     let meta = PageMeta::new(1, 20, 2).base_url(req.original_url());
+    ///     res.paginated(&todos, &meta)
+    /// });
+    /// ```
+    pub fn paginated<T: Serialize>(mut self, items: &[T], meta: &PageMeta) -> Self {
+        let data = match serde_json::to_value(items) {
+            Ok(data) => data,
+            Err(e) => {
+                self.error = Some(ResponseError::JsonSerializationError(e));
+                return self;
+            }
+        };
+        if let Some(link) = header_value(&LINK, meta.link_header()) {
+            self.headers.append(LINK, link);
+        }
+        self.send_json(&meta.body(data))
+    }
+
+    /// The `ETag` [`json_with_etag`](Self::json_with_etag) sends for `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ResponseError::JsonSerializationError`] if `data` can't be
+    /// serialized.
+    pub fn json_etag<T: Serialize>(data: &T) -> Result<String, ResponseError> {
+        Ok(strong_etag(&serde_json::to_vec(data)?))
+    }
+
+    /// Adds `Accept-Encoding` to the `Vary` header unless it is already
+    /// covered, telling caches that the body depends on the encodings the
+    /// client accepts.
+    ///
+    /// [`CompressionMiddleware`](crate::prelude::CompressionMiddleware) does
+    /// this for every response of a compressible type, compressed or not.
+    pub fn vary_by_accept_encoding(mut self) -> Self {
+        vary_by_accept_encoding(&mut self.headers);
+        self
+    }
+
     /// Gets the current HTTP status of the response.
     #[inline]
     pub fn get_status(&self) -> StatusCode {
@@ -175,42 +449,101 @@ impl Response {
     }
 
     /// Converts this `Response` builder into a standard hyper response.
-    pub fn into_hyper(self) -> ServerResponse {
-        let body: BoxBody<Bytes, std::convert::Infallible> = match self.body {
-            ResponseBody::Empty => Full::new(Bytes::new()).map_err(|n| match n {}).boxed(),
-            ResponseBody::Full(bytes) => Full::new(bytes).map_err(|n| match n {}).boxed(),
-            ResponseBody::Buffered(mut chunks) => {
-                let len = chunks.len();
-                if len == 0 {
-                    Full::new(Bytes::new()).map_err(|n| match n {}).boxed()
-                } else if len == 1 {
-                    Full::new(chunks.pop().unwrap())
-                        .map_err(|n| match n {})
-                        .boxed()
-                } else {
+    ///
+    /// Bodies set on a status that forbids one (`1xx`, `204 No Content`,
+    /// `304 Not Modified`) are dropped, with a warning in debug builds. Their
+    /// `Content-Length` goes too, except on a `304`, where it's set to the
+    /// length of the dropped body when that is known.
+    ///
+    /// For a non-empty body held in memory (not a stream), `Content-Length`
+    /// is always set from the bytes actually sent, so a value set by hand that
+    /// no longer matches the body is replaced. Streams keep a manual value, as
+    /// do empty bodies, so a `HEAD` response can describe the `GET` one.
+    pub fn into_hyper(mut self) -> ServerResponse {
+        self.strip_forbidden_body();
+
+        let bytes = match self.body {
+            ResponseBody::Empty => None,
+            ResponseBody::Full(bytes) => Some(bytes),
+            ResponseBody::Buffered(mut chunks) => match chunks.len() {
+                0 => None,
+                1 => chunks.pop(),
+                _ => {
                     let total_capacity = chunks.iter().map(|c| c.len()).sum();
                     let mut ret = bytes::BytesMut::with_capacity(total_capacity);
                     for chunk in chunks {
                         ret.extend_from_slice(&chunk);
                     }
-                    Full::new(ret.freeze()).map_err(|n| match n {}).boxed()
+                    Some(ret.freeze())
                 }
+            },
+            ResponseBody::Lazy(produce) => Some(produce()),
+            ResponseBody::Stream(stream) => {
+                let body = StreamBody::new(stream)
+                    .map_err(|_e| unreachable!("Stream error in Infallible response"))
+                    .boxed();
+                return Self::build_hyper(self.status, self.headers, body);
             }
-            ResponseBody::Stream(stream) => StreamBody::new(stream)
-                .map_err(|_e| unreachable!("Stream error in Infallible response"))
-                .boxed(),
         };
 
-        let mut builder = hyper::Response::builder().status(self.status);
+        let bytes = bytes.unwrap_or_default();
+        if !bytes.is_empty() {
+            self.headers
+                .insert(CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+        }
+        let body = Full::new(bytes).map_err(|n| match n {}).boxed();
+        Self::build_hyper(self.status, self.headers, body)
+    }
 
-        if let Some(headers) = builder.headers_mut() {
-            *headers = self.headers;
+    fn build_hyper(
+        status: StatusCode,
+        headers: hyper::HeaderMap,
+        body: BoxBody<Bytes, std::convert::Infallible>,
+    ) -> ServerResponse {
+        let mut builder = hyper::Response::builder().status(status);
+
+        if let Some(slot) = builder.headers_mut() {
+            *slot = headers;
         }
 
         builder.body(body).unwrap()
     }
 
-    /// Populate `self` with an error status, content-type and body.
+    /// Drops the body if the status forbids one. `304` keeps its headers since
+    /// they describe the cached representation.
+    fn strip_forbidden_body(&mut self) {
+        let status = self.status;
+        if !(status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED)
+        {
+            return;
+        }
+
+        let dropped = self.body.content_length();
+        if dropped != Some(0) {
+            if cfg!(debug_assertions) {
+                warn!("response body set on a {status} response; it will not be sent");
+            }
+            self.body = ResponseBody::Empty;
+        }
+        match dropped {
+            // A `304` describes the representation it stands in for: an empty
+            // body keeps a manual value, a dropped one is measured.
+            Some(0) if status == StatusCode::NOT_MODIFIED => {}
+            Some(len) if status == StatusCode::NOT_MODIFIED => {
+                self.headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+            }
+            _ => {
+                self.headers.remove(CONTENT_LENGTH);
+            }
+        }
+    }
+
+    /// Populate `self` with an error status, content-type and body:
+    /// `message` as text, or `json_body` as is when `json`. See
+    /// [`respond_enveloped_error`](Self::respond_enveloped_error) to follow
+    /// the app's [`ErrorEnvelope`] instead.
     pub fn respond_error(
         &mut self,
         status: u16,
@@ -218,15 +551,28 @@ impl Response {
         json_body: serde_json::Value,
         json: bool,
     ) -> &mut Self {
-        if let Ok(s) = StatusCode::from_u16(status) {
-            self.status = s;
+        self.set_error_status(status);
+        if json {
+            self.send_json(&json_body)
         } else {
-            self.error = Some(ResponseError::InvalidStatusCode(status));
-            self.status = StatusCode::INTERNAL_SERVER_ERROR;
+            self.send_text(message.to_owned())
         }
+    }
 
+    /// Populate `self` with an error status and body: `message` as text, or
+    /// when `json`, `message` and `details` (`Value::Null` for none) in the
+    /// app's [`ErrorEnvelope`], as the built-in middleware and the router do.
+    pub fn respond_enveloped_error(
+        &mut self,
+        status: u16,
+        message: &str,
+        details: serde_json::Value,
+        json: bool,
+    ) -> &mut Self {
+        self.set_error_status(status);
         if json {
-            self.send_json(&json_body)
+            let body = self.error_envelope_body(message, &details);
+            self.send_json(&body)
         } else {
             // If it's a simple error response, we can often avoid the String allocation if it's static
             // but since we get &str, we have to clone unless we want to change API to Cow
@@ -234,6 +580,51 @@ impl Response {
         }
     }
 
+    fn set_error_status(&mut self, status: u16) {
+        if let Ok(s) = StatusCode::from_u16(status) {
+            self.status = s;
+        } else {
+            self.error = Some(ResponseError::InvalidStatusCode(status));
+            self.status = StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+
+    /// The JSON error body for `message` and `details` at the current
+    /// status, in the app's [`ErrorEnvelope`] or the default one.
+    pub(crate) fn error_envelope_body(
+        &self,
+        message: &str,
+        details: &serde_json::Value,
+    ) -> serde_json::Value {
+        let code = error_code(self.status);
+        let error = ErrorInfo {
+            status: self.status,
+            code: &code,
+            message,
+            details,
+        };
+        match self.extensions.get::<ErrorEnvelope>() {
+            Some(envelope) => envelope.render(&error),
+            None => ErrorEnvelope::new().render(&error),
+        }
+    }
+
+    /// Populate `self` with a 429 response, a `Retry-After` header (in whole
+    /// seconds, rounded up) and the same error body as `RateLimitMiddleware`.
+    pub fn respond_too_many_requests(&mut self, retry_after: Duration, json: bool) -> &mut Self {
+        let secs = retry_after
+            .as_secs()
+            .saturating_add(u64::from(retry_after.subsec_nanos() > 0));
+        self.headers.insert(RETRY_AFTER, HeaderValue::from(secs));
+
+        self.respond_enveloped_error(
+            429,
+            "Rate limit exceeded",
+            serde_json::json!({ "retry_after": secs }),
+            json,
+        )
+    }
+
     async fn file<T: AsRef<str>>(mut self, path: T) -> Self {
         let path_str = path.as_ref();
 
@@ -281,10 +672,11 @@ impl Response {
             let mut s = String::with_capacity(mime.len() + 16);
             s.push_str(mime);
             s.push_str("; charset=utf-8");
-            mime_to_header_value(&s).unwrap()
+            mime_to_header_value(&s)
         } else {
-            mime_to_header_value(mime).unwrap()
-        };
+            mime_to_header_value(mime)
+        }
+        .unwrap_or_else(|| HeaderValue::from_static("application/octet-stream"));
 
         if metadata.len() < 1024 * 1024 {
             let bytes = tokio::fs::read(path_str).await?;
@@ -299,6 +691,229 @@ impl Response {
     pub async fn send_file<T: AsRef<str>>(self, path: T) -> Self {
         self.file(path).await
     }
+
+    /// Runs a blocking body producer on tokio's blocking thread pool and sets
+    /// its output as the body.
+    ///
+    /// Use this for synchronous work such as `std::fs` reads or CPU-heavy
+    /// serialization, which would otherwise stall every other request served
+    /// by the same executor thread. If `f` panics, the response becomes a
+    /// `500` with [`ResponseError::BlockingTaskFailed`] set.
+    ///
+    /// ```rust,no_run
+    /// # use expressjs::prelude::*;
+    /// # async fn handler(_req: Request, res: Response) -> Response {
+    /// res.body_blocking(|| std::fs::read("report.csv").unwrap_or_default())
+    ///     .await
+    /// # }
+    /// ```
+    pub async fn body_blocking<F, T>(mut self, f: F) -> Self
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Into<Bytes> + Send + 'static,
+    {
+        match tokio::task::spawn_blocking(f).await {
+            Ok(body) => self.body = ResponseBody::Full(body.into()),
+            Err(e) => {
+                let error = ResponseError::BlockingTaskFailed(e.to_string());
+                self.status = error.status();
+                self.error = Some(error);
+            }
+        }
+        self
+    }
+
+    /// Sends the part of `bytes` selected by a `Range` request header.
+    ///
+    /// A single satisfiable range answers `206 Partial Content` with a
+    /// `Content-Range`. Several ranges answer `206` with a
+    /// `multipart/byteranges` body, one part per range, each labelled with
+    /// the response's `Content-Type` (set it first) and its `Content-Range`.
+    /// A header with no satisfiable range answers `416` with an empty body; a
+    /// missing or malformed one sends the whole buffer with the current status.
+    /// `Accept-Ranges: bytes` is always set. For content that can change,
+    /// pass the request's `If-Range` to [`send_bytes_ranged_if`](Self::send_bytes_ranged_if).
+    ///
+    /// ```rust,no_run
+    /// # use expressjs::prelude::*;
+    /// # async fn handler(req: Request, res: Response) -> Response {
+    /// let range = req.headers().get("range").and_then(|v| v.to_str().ok());
+    /// res.send_bytes_ranged(&b"0123456789"[..], range)
+    /// # }
+    /// ```
+    pub fn send_bytes_ranged(self, bytes: impl Into<Bytes>, range: Option<&str>) -> Self {
+        self.send_bytes_ranged_if(bytes, range, None)
+    }
+
+    /// Like [`send_bytes_ranged`](Self::send_bytes_ranged), but honours the
+    /// `Range` only if the request's `If-Range` still matches the response's
+    /// `ETag` or `Last-Modified` (set them first). A client resuming a
+    /// download of content that has changed since then gets the whole new
+    /// content with the current status instead of a piece of it spliced
+    /// onto the old one.
+    ///
+    /// ```rust,no_run
+    /// # use expressjs::prelude::*;
+    /// # async fn handler(req: Request, res: Response) -> Response {
+    /// let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+    /// res.header("ETag", hyper::header::HeaderValue::from_static("\"v2\""))
+    ///     .send_bytes_ranged_if(&b"0123456789"[..], header("range"), header("if-range"))
+    /// # }
+    /// ```
+    pub fn send_bytes_ranged_if(
+        mut self,
+        bytes: impl Into<Bytes>,
+        range: Option<&str>,
+        if_range: Option<&str>,
+    ) -> Self {
+        let unchanged = if_range.is_none_or(|value| {
+            let validator = |name| self.headers.get(name).and_then(|v| v.to_str().ok());
+            crate::handler::request::if_range(value, validator(ETAG), validator(LAST_MODIFIED))
+        });
+        let range = range.filter(|_| unchanged);
+        let bytes = bytes.into();
+        let len = bytes.len() as u64;
+        self.headers
+            .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+        match ByteRange::resolve(range, len) {
+            ByteRange::Full => self.body = ResponseBody::Full(bytes),
+            ByteRange::Partial(r) => {
+                let value = format!("bytes {}-{}/{len}", r.start, r.end - 1);
+                self.status = StatusCode::PARTIAL_CONTENT;
+                try_insert_header(&mut self.headers, CONTENT_RANGE, value);
+                self.body = ResponseBody::Full(bytes.slice(r.start as usize..r.end as usize));
+            }
+            ByteRange::Multi(ranges) => {
+                let content_type = self
+                    .headers
+                    .get(CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("application/octet-stream");
+                let (boundary, chunks) = range::multipart(&bytes, &ranges, content_type);
+                let value = format!("multipart/byteranges; boundary={boundary}");
+                self.status = StatusCode::PARTIAL_CONTENT;
+                try_insert_header(&mut self.headers, CONTENT_TYPE, value);
+                self.body = ResponseBody::Buffered(chunks);
+            }
+            ByteRange::Unsatisfiable => {
+                let value = format!("bytes */{len}");
+                self.status = StatusCode::RANGE_NOT_SATISFIABLE;
+                try_insert_header(&mut self.headers, CONTENT_RANGE, value);
+                self.body = ResponseBody::Empty;
+            }
+        }
+        self
+    }
+
+    /// Streams `parts` as a `multipart/mixed` body, e.g. a batch of
+    /// resources, each part with its own headers. Sets `Content-Type` with
+    /// a random boundary; the parts are written as the stream yields them.
+    ///
+    /// An error from the stream aborts the response mid-body.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    /// use futures_util::stream;
+    ///
+    /// let parts = [
+    ///     Part::new(r#"{"id":1}"#).content_type("application/json"),
+    ///     Part::new("hello").content_type("text/plain"),
+    /// ];
+    /// let res = Response::new().multipart(stream::iter(parts.map(Ok)));
+    /// ```
+    pub fn multipart<S>(mut self, parts: S) -> Self
+    where
+        S: futures_util::Stream<Item = Result<Part, io::Error>> + Send + Sync + 'static,
+    {
+        let boundary = multipart::boundary();
+        let value = format!("multipart/mixed; boundary={boundary}");
+        try_insert_header(&mut self.headers, CONTENT_TYPE, value);
+        self.body = ResponseBody::Stream(Box::pin(multipart::frames(parts, boundary)));
+        self
+    }
+
+    /// Creates a response whose body is written chunk by chunk through the
+    /// returned [`BodyWriter`], e.g. for long-poll or server-push endpoints.
+    /// Return the response from the handler and keep writing from a task.
+    ///
+    /// At most `capacity` chunks (at least one) wait to be sent; past that,
+    /// writes wait for the client to read. The body ends when the writer is
+    /// dropped.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    ///
+    /// async fn ticks(_req: Request, _res: Response) -> Response {
+    ///     let (writer, res) = Response::channel(16);
+    ///     tokio::spawn(async move {
+    ///         for i in 0..10 {
+    ///             if writer.write(format!("tick {i}\n")).await.is_err() {
+    ///                 break; // the client disconnected
+    ///             }
+    ///         }
+    ///     });
+    ///     res.content_type("text/plain")
+    /// }
+    /// ```
+    pub fn channel(capacity: usize) -> (BodyWriter, Self) {
+        let (writer, body) = channel::channel(capacity);
+        let mut res = Self::new();
+        res.body = ResponseBody::Stream(Box::pin(body));
+        (writer, res)
+    }
+}
+
+/// Largest streaming body buffered for an HTTP/1.0 client; longer streams are
+/// delimited by closing the connection instead.
+pub(crate) const HTTP10_BUFFER_LIMIT: usize = 8 * 1024 * 1024;
+
+impl Response {
+    /// Prepares the response for an HTTP/1.0 client, which doesn't understand
+    /// `Transfer-Encoding: chunked`.
+    ///
+    /// Streaming bodies up to [`HTTP10_BUFFER_LIMIT`] are buffered so the client
+    /// gets a `Content-Length` (and the connection may be kept alive); the rest
+    /// keep streaming and hyper closes the connection to mark their end.
+    pub(crate) async fn prepare_for_http10(&mut self) {
+        self.headers.remove(TRANSFER_ENCODING);
+
+        let mut stream = match std::mem::take(&mut self.body) {
+            ResponseBody::Stream(stream) => stream,
+            body => {
+                self.body = body;
+                return;
+            }
+        };
+
+        let mut chunks = Vec::new();
+        let mut len = 0;
+        while len <= HTTP10_BUFFER_LIMIT {
+            match stream.next().await {
+                None => {
+                    self.body = ResponseBody::Buffered(chunks);
+                    return;
+                }
+                // HTTP/1.0 has no trailers: only data frames are kept.
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data() {
+                        len += data.len();
+                        chunks.push(data);
+                    }
+                }
+                Some(Err(e)) => {
+                    let replay: Vec<_> = chunks.into_iter().map(|c| Ok(Frame::data(c))).collect();
+                    let replay = futures_util::stream::iter(replay)
+                        .chain(futures_util::stream::iter([Err(e)]));
+                    self.body = ResponseBody::Stream(Box::pin(replay.chain(stream)));
+                    return;
+                }
+            }
+        }
+
+        let replay = futures_util::stream::iter(chunks.into_iter().map(|c| Ok(Frame::data(c))));
+        self.body = ResponseBody::Stream(Box::pin(replay.chain(stream)));
+    }
 }
 
 macro_rules! impl_express_response {
@@ -335,8 +950,15 @@ macro_rules! impl_express_response {
             #[inline]
             #[allow(unused_mut)]
             fn content_type<T: AsRef<str>>(mut self, mime_type: T) -> Self {
-                if let Some(val) = mime_to_header_value(mime_type.as_ref()) {
-                    self.headers.insert(CONTENT_TYPE, val);
+                let mime = mime_type.as_ref();
+                match mime_to_header_value(mime) {
+                    Some(val) => {
+                        self.headers.insert(CONTENT_TYPE, val);
+                    }
+                    // Only reached for invalid values, to report them.
+                    None => {
+                        try_insert_header(&mut self.headers, CONTENT_TYPE, mime);
+                    }
                 }
                 self
             }
@@ -344,8 +966,25 @@ macro_rules! impl_express_response {
             #[inline]
             #[allow(unused_mut)]
             fn location<T: AsRef<str>>(mut self, url: T) -> Self {
-                self.headers
-                    .insert(LOCATION, sanitize_header_value(url.as_ref()));
+                try_insert_header(&mut self.headers, LOCATION, &*encode_url(url.as_ref()));
+                self
+            }
+
+            #[allow(unused_mut)]
+            fn attachment<T: AsRef<str>>(mut self, filename: T) -> Self {
+                let name = Path::new(filename.as_ref())
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or_default();
+                if let Some(ext) = Path::new(name).extension().and_then(|ext| ext.to_str()) {
+                    let mime = ext_to_mime(&ext.to_ascii_lowercase());
+                    self = self.content_type(mime);
+                }
+                try_insert_header(
+                    &mut self.headers,
+                    CONTENT_DISPOSITION,
+                    content_disposition(name),
+                );
                 self
             }
 
@@ -414,12 +1053,19 @@ macro_rules! impl_express_response {
             #[inline]
             #[allow(unused_mut)]
             fn cookie(mut self, cookie: Cookie<'_>) -> Self {
-                if let Ok(val) = HeaderValue::from_str(&cookie.to_string()) {
+                if let Some(val) = header_value(&SET_COOKIE, cookie.to_string()) {
                     self.headers.append(SET_COOKIE, val);
                 }
                 self
             }
 
+            fn signed_cookie(self, name: &str, value: &str, signer: &impl CookieSigner) -> Self {
+                if !signer.signs() {
+                    warn!("no cookie signing keys configured; `{name}` is sent unsigned");
+                }
+                self.cookie(signer.session_cookie(name, value))
+            }
+
             #[inline]
             fn ok(self) -> Self {
                 self.status(StatusCode::OK)
@@ -452,7 +1098,7 @@ fn cow_to_bytes(cow: Cow<'static, str>) -> Bytes {
 
 /// Fast lookup for common MIME types to avoid `HeaderValue::from_str` validation and allocation.
 #[inline]
-fn mime_to_header_value(mime: &str) -> Option<HeaderValue> {
+pub(crate) fn mime_to_header_value(mime: &str) -> Option<HeaderValue> {
     match mime {
         "application/json" => Some(HeaderValue::from_static("application/json")),
         "text/plain; charset=utf-8" => Some(HeaderValue::from_static("text/plain; charset=utf-8")),
@@ -474,8 +1120,105 @@ fn mime_to_header_value(mime: &str) -> Option<HeaderValue> {
 impl_express_response!(Response);
 impl_express_response!(&mut Response);
 
-fn sanitize_header_value(val: &str) -> HeaderValue {
-    HeaderValue::from_str(val).unwrap_or_else(|_| HeaderValue::from_static(""))
+/// Adds `Accept-Encoding` to the `Vary` header unless it is already covered.
+pub(crate) fn vary_by_accept_encoding(headers: &mut HeaderMap) {
+    vary_by(headers, "Accept-Encoding");
+}
+
+/// Adds `name` to the `Vary` header unless it is already covered.
+fn vary_by(headers: &mut HeaderMap, name: &'static str) {
+    let covered = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case(name));
+    if !covered {
+        headers.append(VARY, HeaderValue::from_static(name));
+    }
+}
+
+/// Converts `value` into a value for the `name` header, logging a warning
+/// naming the header instead if it isn't valid, e.g. holds a newline.
+pub(crate) fn header_value<V>(name: &HeaderName, value: V) -> Option<HeaderValue>
+where
+    V: TryInto<HeaderValue>,
+    V::Error: std::fmt::Display,
+{
+    match value.try_into() {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("dropping invalid value for the {name} header: {e}");
+            None
+        }
+    }
+}
+
+/// Sets the `name` header to `value` if it is a valid header value, and
+/// otherwise leaves `headers` untouched and logs a warning. Never panics.
+///
+/// Returns whether the header was set.
+pub(crate) fn try_insert_header<V>(headers: &mut HeaderMap, name: HeaderName, value: V) -> bool
+where
+    V: TryInto<HeaderValue>,
+    V::Error: std::fmt::Display,
+{
+    match header_value(&name, value) {
+        Some(value) => {
+            headers.insert(name, value);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Percent-encodes the bytes of `url` that may not appear in a URL as is:
+/// controls, spaces and non-ASCII. Existing escapes are kept.
+fn encode_url(url: &str) -> Cow<'_, str> {
+    if url.bytes().all(|b| b.is_ascii_graphic()) {
+        return Cow::Borrowed(url);
+    }
+    let mut out = String::with_capacity(url.len() + 8);
+    for b in url.bytes() {
+        if b.is_ascii_graphic() {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// An `attachment` `Content-Disposition` value (RFC 6266). Names that aren't
+/// plain ASCII get a `_`-substituted `filename` and the exact UTF-8 name in
+/// `filename*`.
+fn content_disposition(name: &str) -> String {
+    if name.is_empty() {
+        return "attachment".to_owned();
+    }
+    let fallback: String = name
+        .chars()
+        .map(|c| {
+            if c == ' ' || c.is_ascii_graphic() {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let quoted = fallback.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut value = format!("attachment; filename=\"{quoted}\"");
+    if fallback != name {
+        value.push_str("; filename*=UTF-8''");
+        for b in name.bytes() {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                value.push(b as char);
+            } else {
+                value.push_str(&format!("%{b:02X}"));
+            }
+        }
+    }
+    value
 }
 
 /// Maps a file extension to its canonical MIME type string.
@@ -537,6 +1280,7 @@ static FILE_CACHE: Lazy<Cache<String, (HeaderValue, Bytes)>> = Lazy::new(|| Cach
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_logger;
     use hyper::StatusCode;
 
     #[test]
@@ -554,6 +1298,111 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_send_bytes_ranged() {
+        let data = &b"0123456789"[..];
+
+        let res = Response::new().send_bytes_ranged(data, Some("bytes=2-4"));
+        assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers[CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(res.headers[ACCEPT_RANGES], "bytes");
+        assert!(matches!(res.body, ResponseBody::Full(ref b) if b == "234"));
+
+        let res = Response::new().send_bytes_ranged(data, Some("bytes=-3"));
+        assert_eq!(res.headers[CONTENT_RANGE], "bytes 7-9/10");
+        assert!(matches!(res.body, ResponseBody::Full(ref b) if b == "789"));
+
+        let res = Response::new().send_bytes_ranged(data, Some("bytes=10-"));
+        assert_eq!(res.status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers[CONTENT_RANGE], "bytes */10");
+        assert!(res.body.is_empty());
+
+        let res = Response::new().send_bytes_ranged(data, Some("bytes=x"));
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(res.headers.get(CONTENT_RANGE).is_none());
+        assert!(matches!(res.body, ResponseBody::Full(ref b) if b == data));
+    }
+
+    #[test]
+    fn test_send_bytes_ranged_if_range() {
+        let data = &b"0123456789"[..];
+        let tagged = || Response::new().header(ETAG, HeaderValue::from_static("\"v2\""));
+
+        // Unchanged: the range is honoured.
+        let res = tagged().send_bytes_ranged_if(data, Some("bytes=2-4"), Some("\"v2\""));
+        assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers[CONTENT_RANGE], "bytes 2-4/10");
+
+        // Changed since: the whole new content.
+        let res = tagged().send_bytes_ranged_if(data, Some("bytes=2-4"), Some("\"v1\""));
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(res.headers.get(CONTENT_RANGE).is_none());
+        assert!(matches!(res.body, ResponseBody::Full(ref b) if b == data));
+
+        let modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let dated = || Response::new().header(LAST_MODIFIED, HeaderValue::from_static(modified));
+        let res = dated().send_bytes_ranged_if(data, Some("bytes=-3"), Some(modified));
+        assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+        let earlier = "Tue, 20 Oct 2015 07:28:00 GMT";
+        let res = dated().send_bytes_ranged_if(data, Some("bytes=-3"), Some(earlier));
+        assert_eq!(res.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_send_bytes_ranged_multipart() {
+        let data = &b"0123456789abcdefghij0123456789"[..];
+        let res = Response::new()
+            .content_type("text/plain")
+            .send_bytes_ranged(data, Some("bytes=0-3,20-"));
+        assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+        assert!(res.headers.get(CONTENT_RANGE).is_none());
+
+        let content_type = res.headers[CONTENT_TYPE].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let ResponseBody::Buffered(chunks) = &res.body else {
+            panic!("expected a buffered body");
+        };
+        let body = String::from_utf8(chunks.concat()).unwrap();
+        assert_eq!(res.body.content_length(), Some(body.len() as u64));
+
+        // Split on the delimiters and parse each part's headers and bytes.
+        let body = body
+            .strip_suffix(&format!("\r\n--{boundary}--\r\n"))
+            .unwrap();
+        let parts: Vec<_> = body
+            .strip_prefix(&format!("--{boundary}\r\n"))
+            .unwrap()
+            .split(&format!("\r\n--{boundary}\r\n"))
+            .map(|part| {
+                let (head, bytes) = part.split_once("\r\n\r\n").unwrap();
+                let headers: Vec<_> = head.split("\r\n").collect();
+                (headers, bytes)
+            })
+            .collect();
+
+        assert_eq!(
+            parts,
+            [
+                (
+                    vec![
+                        "Content-Type: text/plain; charset=utf-8",
+                        "Content-Range: bytes 0-3/30"
+                    ],
+                    "0123"
+                ),
+                (
+                    vec![
+                        "Content-Type: text/plain; charset=utf-8",
+                        "Content-Range: bytes 20-29/30"
+                    ],
+                    "0123456789"
+                ),
+            ]
+        );
+    }
+
     #[test]
     fn test_response_json() {
         let data = serde_json::json!({"foo": "bar"});
@@ -566,31 +1415,360 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_body_negotiated_follows_accept() {
+        #[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct User {
+            id: u32,
+            name: String,
+        }
+        let user = User {
+            id: 7,
+            name: "ada".into(),
+        };
+        let negotiate = |accept: Option<&str>| {
+            let mut req = Request::builder();
+            if let Some(accept) = accept {
+                req = req.header(ACCEPT, accept);
+            }
+            let res = Response::new().body_negotiated(&req.body(()).unwrap(), &user);
+            assert_eq!(res.headers[VARY], "Accept");
+            let ResponseBody::Full(body) = res.body else {
+                panic!("expected a full body");
+            };
+            (res.headers[CONTENT_TYPE].to_str().unwrap().to_owned(), body)
+        };
+
+        for accept in [
+            None,
+            Some("*/*"),
+            Some("application/json"),
+            Some("text/html"),
+        ] {
+            let (content_type, body) = negotiate(accept);
+            assert_eq!(content_type, "application/json", "{accept:?}");
+            assert_eq!(serde_json::from_slice::<User>(&body).unwrap(), user);
+        }
+
+        let (content_type, body) = negotiate(Some("application/msgpack, */*;q=0.1"));
+        assert_eq!(content_type, "application/msgpack");
+        assert_eq!(rmp_serde::from_slice::<User>(&body).unwrap(), user);
+
+        let (content_type, body) = negotiate(Some("application/json;q=0.5, application/cbor"));
+        assert_eq!(content_type, "application/cbor");
+        assert_eq!(ciborium::from_reader::<User, _>(&body[..]).unwrap(), user);
+    }
+
+    #[test]
+    fn test_paginated_middle_page() {
+        let meta = PageMeta::new(3, 2, 9).base_url("/todos?done=false&page=1");
+        let res = Response::new().paginated(&["e", "f"], &meta);
+
+        assert_eq!(
+            res.headers[LINK],
+            "</todos?done=false&page=1&per_page=2>; rel=\"first\", \
+             </todos?done=false&page=2&per_page=2>; rel=\"prev\", \
+             </todos?done=false&page=4&per_page=2>; rel=\"next\", \
+             </todos?done=false&page=5&per_page=2>; rel=\"last\""
+        );
+        let ResponseBody::Full(body) = res.body else {
+            panic!("expected a full body");
+        };
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "data": ["e", "f"],
+                "page": 3,
+                "per_page": 2,
+                "total": 9,
+                "out_of_range": false,
+                "links": {
+                    "first": "/todos?done=false&page=1&per_page=2",
+                    "prev": "/todos?done=false&page=2&per_page=2",
+                    "next": "/todos?done=false&page=4&per_page=2",
+                    "last": "/todos?done=false&page=5&per_page=2",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_paginated_first_page_of_empty_collection() {
+        let meta = PageMeta::new(1, 10, 0);
+        assert_eq!(meta.last_page(), 1);
+        assert!(!meta.out_of_range());
+
+        let res = Response::new().paginated::<&str>(&[], &meta);
+        assert_eq!(
+            res.headers[LINK],
+            "<?page=1&per_page=10>; rel=\"first\", <?page=1&per_page=10>; rel=\"last\""
+        );
+        let ResponseBody::Full(body) = res.body else {
+            panic!("expected a full body");
+        };
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"], serde_json::json!([]));
+        assert_eq!(body["links"]["prev"], serde_json::Value::Null);
+        assert_eq!(body["links"]["next"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_paginated_page_past_the_last() {
+        let meta = PageMeta::new(7, 2, 9).base_url("/todos");
+        assert_eq!(meta.last_page(), 5);
+        assert!(meta.out_of_range());
+
+        let res = Response::new().paginated::<&str>(&[], &meta);
+        assert_eq!(
+            res.headers[LINK],
+            "</todos?page=1&per_page=2>; rel=\"first\", \
+             </todos?page=5&per_page=2>; rel=\"prev\", \
+             </todos?page=5&per_page=2>; rel=\"last\""
+        );
+        let ResponseBody::Full(body) = res.body else {
+            panic!("expected a full body");
+        };
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["page"], 7);
+        assert_eq!(body["out_of_range"], true);
+        assert_eq!(body["links"]["prev"], "/todos?page=5&per_page=2");
+        assert_eq!(body["links"]["next"], serde_json::Value::Null);
+    }
+
     #[test]
     fn test_respond_error_sets_body() {
         let mut res = Response::new();
-        res.respond_error(
-            429,
-            "Rate limit exceeded",
-            serde_json::json!({"error": "rate limited"}),
-            false,
-        );
+        res.respond_error(429, "Rate limit exceeded", serde_json::Value::Null, false);
         assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
         assert!(!res.body.is_empty(), "respond_error must set a body");
     }
 
     #[test]
-    fn test_respond_error_json_sets_body() {
+    fn test_respond_error_json_sends_the_body_as_is() {
         let mut res = Response::new();
-        res.respond_error(
+        res.respond_error(409, "conflict", serde_json::json!({ "taken": true }), true);
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        assert!(matches!(&res.body, ResponseBody::Full(b) if b == r#"{"taken":true}"#));
+    }
+
+    #[test]
+    fn test_respond_enveloped_error_json_sets_body() {
+        let mut res = Response::new();
+        res.respond_enveloped_error(
             400,
             "bad request",
-            serde_json::json!({"error": "bad"}),
+            serde_json::json!({"field": "name"}),
             true,
         );
         assert_eq!(res.status, StatusCode::BAD_REQUEST);
         assert_eq!(res.headers.get(CONTENT_TYPE).unwrap(), "application/json");
-        assert!(!res.body.is_empty());
+        let ResponseBody::Full(body) = &res.body else {
+            panic!("expected a full body");
+        };
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": {
+                "code": "bad_request",
+                "message": "bad request",
+                "details": { "field": "name" },
+            } })
+        );
+
+        // An app's envelope travels in the response extensions.
+        let mut res = Response::new();
+        res.extensions.insert(ErrorEnvelope::custom(
+            |e| serde_json::json!({ "title": e.message }),
+        ));
+        res.respond_enveloped_error(400, "bad request", serde_json::Value::Null, true);
+        let ResponseBody::Full(body) = &res.body else {
+            panic!("expected a full body");
+        };
+        assert_eq!(body, r#"{"title":"bad request"}"#);
+    }
+
+    #[test]
+    fn test_too_many_requests() {
+        let res = Response::too_many_requests(Duration::from_millis(1500));
+        assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers.get(RETRY_AFTER).unwrap(), "2");
+        assert!(matches!(&res.body, ResponseBody::Full(b) if b == "Rate limit exceeded"));
+
+        let mut res = Response::new();
+        res.respond_too_many_requests(Duration::from_secs(30), true);
+        assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers.get(RETRY_AFTER).unwrap(), "30");
+        let ResponseBody::Full(body) = &res.body else {
+            panic!("expected a full body");
+        };
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(body["error"]["details"]["retry_after"], 30);
+        assert_eq!(body["error"]["message"], "Rate limit exceeded");
+    }
+
+    #[tokio::test]
+    async fn test_no_content_body_is_stripped() {
+        test_logger::capture();
+        let res = Response::new()
+            .status(StatusCode::NO_CONTENT)
+            .header(CONTENT_LENGTH, HeaderValue::from_static("5"))
+            .body("oops!");
+
+        let res = res.into_hyper();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(res.headers().get(CONTENT_LENGTH).is_none());
+        assert!(
+            res.into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .is_empty()
+        );
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1, "{records:?}");
+        assert_eq!(records[0].level, log::Level::Warn);
+        assert!(records[0].message.contains("204 No Content"));
+    }
+
+    #[tokio::test]
+    async fn test_not_modified_body_is_stripped() {
+        test_logger::capture();
+        // The status is set after the body, as a conditional-GET middleware would.
+        let res = Response::new()
+            .write("cached")
+            .write(" page")
+            .header(CONTENT_LENGTH, HeaderValue::from_static("999"))
+            .status(StatusCode::NOT_MODIFIED);
+
+        let res = res.into_hyper();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get(CONTENT_LENGTH).unwrap(), "11");
+        assert!(
+            res.into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .is_empty()
+        );
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1, "{records:?}");
+        assert!(records[0].message.contains("304 Not Modified"));
+
+        // A body of unknown length can't be measured, so its header goes.
+        let res = Response::new()
+            .lazy_body(|| Bytes::from_static(b"page"))
+            .header(CONTENT_LENGTH, HeaderValue::from_static("999"))
+            .status(StatusCode::NOT_MODIFIED)
+            .into_hyper();
+        assert!(res.headers().get(CONTENT_LENGTH).is_none());
+
+        // Without a body, a manual value describes the representation.
+        let res = Response::new()
+            .header(CONTENT_LENGTH, HeaderValue::from_static("999"))
+            .status(StatusCode::NOT_MODIFIED)
+            .into_hyper();
+        assert_eq!(res.headers()[CONTENT_LENGTH], "999");
+    }
+
+    #[tokio::test]
+    async fn test_lazy_body_runs_only_when_serialized() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let lazy = || {
+            let calls = Arc::clone(&calls);
+            Response::new().lazy_body(move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                "expensive"
+            })
+        };
+
+        // Replaced before serialization, as a middleware stopping the chain would.
+        let res = lazy().status(StatusCode::FORBIDDEN).send_text("denied");
+        let body = res.into_hyper().into_body().collect().await.unwrap();
+        assert_eq!(body.to_bytes(), "denied");
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let res = lazy();
+        assert_eq!(res.body.content_length(), None);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        let body = res.into_hyper().into_body().collect().await.unwrap();
+        assert_eq!(body.to_bytes(), "expensive");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_adversarial_header_values_never_panic() {
+        test_logger::capture();
+        let header = |res: &Response, name| res.headers[name].to_str().unwrap().to_owned();
+
+        // Controls and non-ASCII are percent-encoded, so no header can be injected.
+        let res = Response::new().location("/a\r\nSet-Cookie: x=1");
+        assert_eq!(header(&res, LOCATION), "/a%0D%0ASet-Cookie:%20x=1");
+        let res = Response::new().location("/café?q=%20");
+        assert_eq!(header(&res, LOCATION), "/caf%C3%A9?q=%20");
+
+        let res = Response::new().attachment("reports/q1 \"final\".csv");
+        assert_eq!(
+            header(&res, CONTENT_DISPOSITION),
+            r#"attachment; filename="q1 \"final\".csv""#
+        );
+        assert_eq!(header(&res, CONTENT_TYPE), "text/csv");
+        let res = Response::new().attachment("résumé\n.PDF");
+        assert_eq!(
+            header(&res, CONTENT_DISPOSITION),
+            "attachment; filename=\"r_sum__.PDF\"; filename*=UTF-8''r%C3%A9sum%C3%A9%0A.PDF"
+        );
+        assert_eq!(header(&res, CONTENT_TYPE), "application/pdf");
+        let res = Response::new().attachment("");
+        assert_eq!(header(&res, CONTENT_DISPOSITION), "attachment");
+
+        let res = Response::too_many_requests(Duration::MAX);
+        assert_eq!(header(&res, RETRY_AFTER), u64::MAX.to_string());
+        assert!(test_logger::take().is_empty());
+
+        // Values that can't be repaired are dropped with a warning naming the header.
+        let res = Response::new()
+            .content_type("text/plain")
+            .content_type("text/html\r\nX-Injected: 1");
+        assert_eq!(header(&res, CONTENT_TYPE), "text/plain; charset=utf-8");
+        let res = res.cookie(Cookie::new("id", "a\nb"));
+        assert!(!res.headers.contains_key(SET_COOKIE));
+        let records = test_logger::take();
+        assert_eq!(records.len(), 2, "{records:?}");
+        assert!(records.iter().all(|r| r.level == log::Level::Warn));
+        assert!(records[0].message.contains("content-type"));
+        assert!(records[1].message.contains("set-cookie"));
+    }
+
+    #[tokio::test]
+    async fn test_stale_content_length_is_recomputed() {
+        let stale = || Response::new().header(CONTENT_LENGTH, HeaderValue::from_static("999"));
+
+        let res = stale().body("hello").into_hyper();
+        assert_eq!(res.headers()[CONTENT_LENGTH], "5");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello");
+
+        let mut res = stale().write("ab").write("cde");
+        res.respond_error(400, "bad", serde_json::Value::Null, false);
+        assert_eq!(res.into_hyper().headers()[CONTENT_LENGTH], "3");
+
+        // Streams and empty bodies keep a manual value, e.g. for HEAD.
+        assert_eq!(stale().into_hyper().headers()[CONTENT_LENGTH], "999");
+    }
+
+    #[test]
+    fn test_empty_no_content_does_not_warn() {
+        test_logger::capture();
+        let res = Response::new().status(StatusCode::NO_CONTENT).into_hyper();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(test_logger::take().is_empty());
     }
 
     #[test]
@@ -0,0 +1,200 @@
+use super::into_response::{DefaultErrorBody, ErrorBodyDetails, write_error_body};
+use super::{ExpressResponse, IntoResponse, Response, ResponseError};
+use hyper::StatusCode;
+use log::warn;
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+
+/// An error for JSON APIs, answered with its status in the app's
+/// [`ErrorEnvelope`](crate::prelude::ErrorEnvelope), by default
+/// `{"error": {"code": <code>, "message": <message>, "details": <details>}}`.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// async fn show(req: Request, res: Response) -> Result<Response, ApiError> {
+///     let id = req.params().get("id").unwrap_or_default();
+///     if id != "1" {
+///         return Err(ApiError::not_found("user"));
+///     }
+///     Ok(res.json(&serde_json::json!({ "id": 1 })))
+/// }
+/// ```
+///
+/// Server errors send only the status reason as message, so internals don't
+/// leak to callers; the message is logged by the router instead, or sent with
+/// [`App::error_details`](crate::prelude::App::error_details). A
+/// [`ResponseError`] converts with `?`.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{message}")]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+    details: Option<Value>,
+}
+
+impl ApiError {
+    /// Creates an error with any status.
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    /// `400 Bad Request`.
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    /// `401 Unauthorized`.
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    /// `403 Forbidden`.
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, message)
+    }
+
+    /// `404 Not Found`, with the message `"<resource> not found"`.
+    pub fn not_found(resource: impl AsRef<str>) -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            format!("{} not found", resource.as_ref()),
+        )
+    }
+
+    /// `409 Conflict`.
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, message)
+    }
+
+    /// `422 Unprocessable Entity`, e.g. for a body that parsed but failed
+    /// validation.
+    pub fn unprocessable(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, message)
+    }
+
+    /// `500 Internal Server Error`. The message is logged, not sent, unless
+    /// the app enables [`App::error_details`](crate::prelude::App::error_details).
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    /// Adds machine-readable details, e.g. the fields that failed
+    /// validation. Details that fail to serialize are dropped with a warning.
+    pub fn with_details(mut self, details: impl Serialize) -> Self {
+        match serde_json::to_value(details) {
+            Ok(details) => self.details = Some(details),
+            Err(e) => warn!("dropping ApiError details that failed to serialize: {e}"),
+        }
+        self
+    }
+
+    /// The status the error responds with.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The message, as given.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The details, if any.
+    pub fn details(&self) -> Option<&Value> {
+        self.details.as_ref()
+    }
+}
+
+impl From<ResponseError> for ApiError {
+    fn from(e: ResponseError) -> Self {
+        Self::new(e.status(), e.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(mut self) -> Response {
+        let mut res = Response::new().status(self.status);
+        if let Some(details) = self.details.take() {
+            res.extensions.insert(ErrorBodyDetails(details));
+        }
+        res.error = Some(if self.status.is_server_error() {
+            ResponseError::Internal(self.message)
+        } else {
+            ResponseError::Api(self)
+        });
+        write_error_body(&mut res, true, false);
+        res.extensions.insert(DefaultErrorBody { json: true });
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::response::ResponseBody;
+    use serde_json::json;
+
+    fn body(res: &Response) -> Value {
+        match &res.body {
+            ResponseBody::Full(bytes) => serde_json::from_slice(bytes).unwrap(),
+            body => panic!("unexpected body {body:?}"),
+        }
+    }
+
+    #[test]
+    fn test_client_errors_carry_message_and_details() {
+        let res = ApiError::not_found("user").into_response();
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        assert_eq!(res.headers["content-type"], "application/json");
+        assert_eq!(
+            body(&res),
+            json!({ "error": {
+                "code": "not_found",
+                "message": "user not found",
+                "details": null,
+            } })
+        );
+
+        let res = ApiError::unprocessable("invalid user")
+            .with_details(json!({ "email": "must contain @" }))
+            .into_response();
+        assert_eq!(res.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body(&res),
+            json!({ "error": {
+                "code": "unprocessable_entity",
+                "message": "invalid user",
+                "details": { "email": "must contain @" },
+            } })
+        );
+
+        let handler_result: Result<Response, ApiError> = Err(ApiError::conflict("taken"));
+        assert_eq!(handler_result.into_response().status, StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn test_server_errors_hide_the_message() {
+        let res = ApiError::internal("db password rejected")
+            .with_details(json!({ "retry": true }))
+            .into_response();
+        assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            body(&res),
+            json!({ "error": {
+                "code": "internal_server_error",
+                "message": "Internal Server Error",
+                "details": { "retry": true },
+            } })
+        );
+        // Kept for the app to log.
+        assert_eq!(res.error.unwrap().to_string(), "db password rejected");
+
+        let converted = ApiError::from(ResponseError::PayloadTooLarge(1024));
+        assert_eq!(converted.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,127 @@
+use super::{Response, header_value};
+use hyper::StatusCode;
+use hyper::header::{CACHE_CONTROL, HeaderValue};
+
+/// Default `Cache-Control` values by response status, for responses whose
+/// handler and middleware didn't set one. Set it app-wide with
+/// [`App::cache_policy`](crate::prelude::App::cache_policy).
+///
+/// A value for an exact status wins over the one for its class; statuses
+/// with neither are left alone.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+/// use hyper::StatusCode;
+///
+/// let mut app = express();
+/// app.cache_policy(
+///     CachePolicy::new()
+///         .server_errors("no-store")
+///         .client_errors("no-store")
+///         .status(StatusCode::NOT_FOUND, "max-age=60"),
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CachePolicy {
+    statuses: Vec<(StatusCode, HeaderValue)>,
+    /// Indexed by the first digit of the status.
+    classes: [Option<HeaderValue>; 6],
+}
+
+impl CachePolicy {
+    /// Creates a policy that sets nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the default for one status.
+    pub fn status(mut self, status: StatusCode, cache_control: impl AsRef<str>) -> Self {
+        if let Some(value) = header_value(&CACHE_CONTROL, cache_control.as_ref()) {
+            self.statuses.retain(|(existing, _)| *existing != status);
+            self.statuses.push((status, value));
+        }
+        self
+    }
+
+    /// Sets the default for `2xx` responses.
+    pub fn success(self, cache_control: impl AsRef<str>) -> Self {
+        self.class(2, cache_control)
+    }
+
+    /// Sets the default for `3xx` responses.
+    pub fn redirects(self, cache_control: impl AsRef<str>) -> Self {
+        self.class(3, cache_control)
+    }
+
+    /// Sets the default for `4xx` responses.
+    pub fn client_errors(self, cache_control: impl AsRef<str>) -> Self {
+        self.class(4, cache_control)
+    }
+
+    /// Sets the default for `5xx` responses.
+    pub fn server_errors(self, cache_control: impl AsRef<str>) -> Self {
+        self.class(5, cache_control)
+    }
+
+    fn class(mut self, class: usize, cache_control: impl AsRef<str>) -> Self {
+        if let Some(value) = header_value(&CACHE_CONTROL, cache_control.as_ref()) {
+            self.classes[class] = Some(value);
+        }
+        self
+    }
+
+    /// The default `Cache-Control` for `status`, if any.
+    fn lookup(&self, status: StatusCode) -> Option<&HeaderValue> {
+        self.statuses
+            .iter()
+            .find(|(existing, _)| *existing == status)
+            .map(|(_, value)| value)
+            .or_else(|| {
+                self.classes
+                    .get(usize::from(status.as_u16() / 100))
+                    .and_then(Option::as_ref)
+            })
+    }
+
+    /// Sets the default `Cache-Control` on `res` unless it already has one.
+    pub(crate) fn apply(&self, res: &mut Response) {
+        if res.headers.contains_key(CACHE_CONTROL) {
+            return;
+        }
+        if let Some(value) = self.lookup(res.status) {
+            res.headers.insert(CACHE_CONTROL, value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_status_wins_over_class() {
+        let policy = CachePolicy::new()
+            .client_errors("no-store")
+            .status(StatusCode::NOT_FOUND, "max-age=60")
+            .status(StatusCode::NOT_FOUND, "max-age=30");
+        let lookup = |status| policy.lookup(status).map(|v| v.to_str().unwrap());
+        assert_eq!(lookup(StatusCode::NOT_FOUND), Some("max-age=30"));
+        assert_eq!(lookup(StatusCode::BAD_REQUEST), Some("no-store"));
+        assert_eq!(lookup(StatusCode::OK), None);
+
+        // Invalid values are dropped with a warning.
+        let policy = CachePolicy::new().success("max-age=60\n");
+        assert!(policy.lookup(StatusCode::OK).is_none());
+    }
+
+    #[test]
+    fn test_statuses_beyond_5xx_have_no_class() {
+        let policy = CachePolicy::new().server_errors("no-store");
+        for code in [600, 799, 999] {
+            let mut res = Response::new();
+            res.status = StatusCode::from_u16(code).unwrap();
+            policy.apply(&mut res);
+            assert!(!res.headers.contains_key(CACHE_CONTROL), "{code}");
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Push-style bodies, built by [`Response::channel`](super::Response::channel).
+
+use bytes::Bytes;
+use futures_util::Stream;
+use hyper::body::Frame;
+use std::io;
+use tokio::sync::mpsc;
+
+/// The sending half of a [`Response::channel`](super::Response::channel)
+/// body.
+///
+/// Chunks queue in a channel of bounded capacity, which hyper drains as the
+/// connection accepts data: once `capacity` chunks are waiting,
+/// [`write`](Self::write) waits for the client to catch up instead of
+/// buffering without bound.
+///
+/// Dropping the writer (and its clones) ends the body. When the client
+/// disconnects, or the response is dropped before being sent, the queued
+/// chunks are discarded and every later write fails with
+/// [`io::ErrorKind::BrokenPipe`], so a producer loop stops on its first `?`.
+#[derive(Debug, Clone)]
+pub struct BodyWriter {
+    tx: mpsc::Sender<Bytes>,
+}
+
+impl BodyWriter {
+    /// Queues `chunk` for sending, waiting while the channel is full.
+    pub async fn write(&self, chunk: impl Into<Bytes>) -> io::Result<()> {
+        self.tx.send(chunk.into()).await.map_err(|_| disconnected())
+    }
+
+    /// Whether the body is gone, i.e. writes will fail.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Completes once the body is gone, e.g. to stop a producer that is
+    /// waiting on something other than [`write`](Self::write).
+    pub async fn closed(&self) {
+        self.tx.closed().await
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the response body was dropped")
+}
+
+/// Creates a writer and the body stream it feeds.
+pub(super) fn channel(
+    capacity: usize,
+) -> (
+    BodyWriter,
+    impl Stream<Item = Result<Frame<Bytes>, io::Error>> + Send + Sync + 'static,
+) {
+    let (tx, mut rx) = mpsc::channel(capacity.max(1));
+    let body = futures_util::stream::poll_fn(move |cx| {
+        rx.poll_recv(cx)
+            .map(|chunk| chunk.map(|c| Ok(Frame::data(c))))
+    });
+    (BodyWriter { tx }, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handler::response::Response;
+    use http_body_util::BodyExt;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_writer_blocks_at_capacity_until_the_client_reads() {
+        let (writer, res) = Response::channel(2);
+        let mut body = res.into_hyper().into_body();
+
+        let written = Arc::new(AtomicUsize::new(0));
+        let producer = tokio::spawn({
+            let written = Arc::clone(&written);
+            async move {
+                for i in 0.. {
+                    writer.write(format!("chunk {i}")).await?;
+                    written.fetch_add(1, Ordering::SeqCst);
+                }
+                Ok::<_, std::io::Error>(())
+            }
+        });
+
+        // Nobody reads: the writer stops at the capacity bound.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(written.load(Ordering::SeqCst), 2);
+
+        // Each chunk the slow client reads frees room for one more.
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "chunk 0");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(written.load(Ordering::SeqCst), 3);
+
+        // The client goes away: the pending write fails.
+        drop(body);
+        let err = producer.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert_eq!(written.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_the_writer_ends_the_body() {
+        let (writer, res) = Response::channel(0);
+        let body = res.into_hyper().into_body();
+        tokio::spawn(async move {
+            writer.write("a").await.unwrap();
+            writer.write("b").await.unwrap();
+        });
+        let body = body.collect().await.unwrap().to_bytes();
+        assert_eq!(body, "ab");
+    }
+}
@@ -0,0 +1,134 @@
+use hyper::StatusCode;
+use serde_json::{Value, json};
+use std::fmt;
+use std::sync::Arc;
+
+/// An error answered by the router or a built-in middleware, as handed to
+/// an [`ErrorEnvelope`].
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorInfo<'a> {
+    /// The response status.
+    pub status: StatusCode,
+    /// A machine-readable code derived from the status, e.g. `too_many_requests`.
+    pub code: &'a str,
+    /// The human-readable message, as sent to clients preferring text.
+    pub message: &'a str,
+    /// Error-specific data, e.g. `{"retry_after": 30}`, or `Value::Null`.
+    pub details: &'a Value,
+}
+
+type Render = Arc<dyn Fn(&ErrorInfo<'_>) -> Value + Send + Sync>;
+
+/// The JSON shape of error bodies: those written by
+/// [`Response::respond_enveloped_error`](crate::prelude::Response::respond_enveloped_error),
+/// e.g. by the built-in middleware and the router's own `404` and `405`
+/// answers, and those of [`ResponseError`](crate::prelude::ResponseError)s,
+/// [`ApiError`](crate::prelude::ApiError)s and extractor rejections
+/// formatted as JSON. Set it app-wide with
+/// [`App::error_envelope`](crate::prelude::App::error_envelope).
+///
+/// The default shape is
+/// `{"error": {"code": <code>, "message": <message>, "details": <details>}}`.
+/// Whether a [`ResponseError`](crate::prelude::ResponseError) is answered
+/// in JSON at all is up to the [`ErrorFormat`](crate::prelude::ErrorFormat).
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let mut app = express();
+/// app.error_envelope(ErrorEnvelope::custom(|error| {
+///     serde_json::json!({ "status": error.status.as_u16(), "title": error.message })
+/// }));
+/// ```
+#[derive(Clone, Default)]
+pub struct ErrorEnvelope(Option<Render>);
+
+impl ErrorEnvelope {
+    /// The default envelope.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An envelope built by `render`.
+    pub fn custom<F>(render: F) -> Self
+    where
+        F: Fn(&ErrorInfo<'_>) -> Value + Send + Sync + 'static,
+    {
+        Self(Some(Arc::new(render)))
+    }
+
+    /// The JSON body for `error`.
+    pub fn render(&self, error: &ErrorInfo<'_>) -> Value {
+        match &self.0 {
+            Some(render) => render(error),
+            None => json!({
+                "error": {
+                    "code": error.code,
+                    "message": error.message,
+                    "details": error.details,
+                }
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for ErrorEnvelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ErrorEnvelope")
+            .field(&if self.0.is_some() {
+                "custom"
+            } else {
+                "default"
+            })
+            .finish()
+    }
+}
+
+/// The error code for `status`: its reason in snake case, e.g.
+/// `payload_too_large`.
+pub(crate) fn error_code(status: StatusCode) -> String {
+    match status.canonical_reason() {
+        Some(reason) => reason
+            .chars()
+            .filter_map(|c| match c {
+                ' ' | '-' => Some('_'),
+                c if c.is_ascii_alphanumeric() => Some(c.to_ascii_lowercase()),
+                _ => None,
+            })
+            .collect(),
+        None => status.as_str().to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_and_custom_envelopes() {
+        let details = json!({ "retry_after": 30 });
+        let code = error_code(StatusCode::TOO_MANY_REQUESTS);
+        let error = ErrorInfo {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            code: &code,
+            message: "Too many requests",
+            details: &details,
+        };
+        assert_eq!(
+            ErrorEnvelope::new().render(&error),
+            json!({ "error": {
+                "code": "too_many_requests",
+                "message": "Too many requests",
+                "details": { "retry_after": 30 },
+            } })
+        );
+
+        let flat =
+            ErrorEnvelope::custom(|e| json!({ "code": e.code, "status": e.status.as_u16() }));
+        assert_eq!(
+            flat.render(&error),
+            json!({ "code": "too_many_requests", "status": 429 })
+        );
+        assert_eq!(error_code(StatusCode::IM_A_TEAPOT), "im_a_teapot");
+    }
+}
@@ -0,0 +1,215 @@
+use super::{Response, header_value};
+use hyper::header::{HeaderName, HeaderValue};
+use log::warn;
+
+/// Catch-all parameter appended to override paths so they match sub-paths.
+const REST: &str = "__express_header_policy_rest";
+
+/// Headers guaranteed present or absent on every response, whatever
+/// handlers and middleware set. Set it app-wide with
+/// [`App::header_policy`](crate::prelude::App::header_policy); it is applied
+/// last, after the [`CachePolicy`](crate::prelude::CachePolicy).
+///
+/// [`require`](Self::require)d headers get their default value when missing,
+/// and [`forbid`](Self::forbid)den ones are stripped. A [`path`](Self::path)
+/// override adjusts the rules under a path prefix; when several match a
+/// request, the most specific one applies.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let mut app = express();
+/// app.header_policy(
+///     HeaderPolicy::new()
+///         .require("Cache-Control", "no-store")
+///         .forbid("X-Powered-By")
+///         .forbid("X-Debug-Trace")
+///         .path(
+///             "/assets",
+///             HeaderPolicy::new().require("Cache-Control", "public, max-age=3600"),
+///         )
+///         .path("/internal", HeaderPolicy::new().permit("X-Debug-Trace")),
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HeaderPolicy {
+    rules: Rules,
+    overrides: Vec<Rules>,
+    paths: matchit::Router<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Rules {
+    required: Vec<(HeaderName, HeaderValue)>,
+    forbidden: Vec<HeaderName>,
+    permitted: Vec<HeaderName>,
+}
+
+impl HeaderPolicy {
+    /// Creates a policy that changes nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `name` to `default` on responses that don't have it. Invalid
+    /// names or values are dropped with a warning.
+    pub fn require(mut self, name: impl AsRef<str>, default: impl AsRef<str>) -> Self {
+        let Some(name) = parse_name(name.as_ref()) else {
+            return self;
+        };
+        if let Some(value) = header_value(&name, default.as_ref()) {
+            self.rules
+                .required
+                .retain(|(existing, _)| *existing != name);
+            self.rules.forbidden.retain(|existing| *existing != name);
+            self.rules.required.push((name, value));
+        }
+        self
+    }
+
+    /// Strips `name` from every response.
+    pub fn forbid(mut self, name: impl AsRef<str>) -> Self {
+        if let Some(name) = parse_name(name.as_ref()) {
+            self.rules
+                .required
+                .retain(|(existing, _)| *existing != name);
+            self.rules.permitted.retain(|existing| *existing != name);
+            self.rules.forbidden.push(name);
+        }
+        self
+    }
+
+    /// In a [`path`](Self::path) override, lets responses keep or omit
+    /// `name`, whatever the outer policy requires or forbids.
+    pub fn permit(mut self, name: impl AsRef<str>) -> Self {
+        if let Some(name) = parse_name(name.as_ref()) {
+            self.rules.forbidden.retain(|existing| *existing != name);
+            self.rules.permitted.push(name);
+        }
+        self
+    }
+
+    /// Applies `policy` on top of this one to requests under `path`, a
+    /// prefix written like a middleware path (e.g. `/api/{version}/admin`).
+    /// Its required headers replace the outer defaults of the same name,
+    /// and it can forbid or [`permit`](Self::permit) further headers. Its
+    /// own overrides are ignored.
+    pub fn path(mut self, path: impl AsRef<str>, policy: HeaderPolicy) -> Self {
+        let path = path.as_ref().trim_end_matches('/');
+        let index = self.overrides.len();
+        let inserted = if path.is_empty() {
+            self.paths
+                .insert("/", index)
+                .and_then(|()| self.paths.insert(format!("/{{*{REST}}}"), index))
+        } else {
+            self.paths
+                .insert(path, index)
+                .and_then(|()| self.paths.insert(format!("{path}/{{*{REST}}}"), index))
+        };
+        match inserted {
+            Ok(()) => self.overrides.push(policy.rules),
+            Err(e) => warn!("ignoring header policy override for {path}: {e}"),
+        }
+        self
+    }
+
+    /// Enforces the policy on `res`, the response to a request for `path`.
+    pub(crate) fn apply(&self, path: &str, res: &mut Response) {
+        let local = self
+            .paths
+            .at(path)
+            .ok()
+            .map(|matched| &self.overrides[*matched.value]);
+
+        for name in &self.rules.forbidden {
+            let lifted = local.is_some_and(|local| {
+                local.permitted.contains(name)
+                    || local.required.iter().any(|(required, _)| required == name)
+            });
+            if !lifted {
+                res.headers.remove(name);
+            }
+        }
+        let Some(local) = local else {
+            require(&self.rules.required, res);
+            return;
+        };
+        for name in &local.forbidden {
+            res.headers.remove(name);
+        }
+        require(&local.required, res);
+        let outer =
+            self.rules.required.iter().filter(|(name, _)| {
+                !local.forbidden.contains(name) && !local.permitted.contains(name)
+            });
+        for (name, value) in outer {
+            if !res.headers.contains_key(name) {
+                res.headers.insert(name, value.clone());
+            }
+        }
+    }
+}
+
+fn require(required: &[(HeaderName, HeaderValue)], res: &mut Response) {
+    for (name, value) in required {
+        if !res.headers.contains_key(name) {
+            res.headers.insert(name, value.clone());
+        }
+    }
+}
+
+fn parse_name(name: &str) -> Option<HeaderName> {
+    match HeaderName::try_from(name) {
+        Ok(name) => Some(name),
+        Err(_) => {
+            warn!("ignoring invalid header name in header policy: {name:?}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::ExpressResponse;
+
+    #[test]
+    fn test_overrides_replace_and_relax_the_outer_rules() {
+        let policy = HeaderPolicy::new()
+            .require("Cache-Control", "no-store")
+            .require("X-Frame-Options", "DENY")
+            .forbid("X-Debug")
+            .path(
+                "/assets",
+                HeaderPolicy::new().require("Cache-Control", "max-age=60"),
+            )
+            .path(
+                "/assets/debug",
+                HeaderPolicy::new()
+                    .permit("X-Debug")
+                    .forbid("X-Frame-Options"),
+            );
+        let apply = |path| {
+            let mut res = Response::new()
+                .header("X-Debug", HeaderValue::from_static("1"))
+                .header("X-Frame-Options", HeaderValue::from_static("SAMEORIGIN"));
+            policy.apply(path, &mut res);
+            res.headers
+        };
+
+        let headers = apply("/assets/app.js");
+        assert_eq!(headers["cache-control"], "max-age=60");
+        assert_eq!(headers["x-frame-options"], "SAMEORIGIN");
+        assert!(!headers.contains_key("x-debug"));
+
+        // Only the most specific override applies.
+        let headers = apply("/assets/debug/trace");
+        assert_eq!(headers["cache-control"], "no-store");
+        assert_eq!(headers["x-debug"], "1");
+        assert!(!headers.contains_key("x-frame-options"));
+
+        let headers = apply("/assetsfoo");
+        assert_eq!(headers["cache-control"], "no-store");
+        assert!(!headers.contains_key("x-debug"));
+    }
+}
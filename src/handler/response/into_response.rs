@@ -0,0 +1,353 @@
+use super::{ExpressResponse, Response, ResponseError};
+use hyper::{HeaderMap, StatusCode};
+use serde::Serialize;
+
+/// Conversion of a value into a [`Response`].
+///
+/// Implemented for the building blocks handlers usually return — status codes,
+/// text, [`Json`] — and for tuples combining them with a status and/or headers:
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// # #[derive(Serialize)] struct User { name: String }
+/// # let user = User { name: "Ada".into() };
+/// let res: Response = (StatusCode::CREATED, Json(&user)).into();
+/// assert_eq!(res.get_status(), StatusCode::CREATED);
+/// ```
+///
+/// Handlers may return any `IntoResponse` type, including a `Result` whose
+/// error converts too, so `?` works on fallible steps:
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// async fn show(req: Request, res: Response) -> Result<Response, ResponseError> {
+///     let user: serde_json::Value = req.json().await?;
+///     Ok(res.send_json(&user))
+/// }
+///
+/// let mut app = App::default();
+/// app.post("/users", show);
+/// ```
+pub trait IntoResponse {
+    /// Converts `self` into a response.
+    fn into_response(self) -> Response;
+}
+
+/// Serializes the wrapped value as an `application/json` response body.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+impl IntoResponse for Response {
+    #[inline]
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for StatusCode {
+    #[inline]
+    fn into_response(self) -> Response {
+        Response::new().status(self)
+    }
+}
+
+impl IntoResponse for &'static str {
+    #[inline]
+    fn into_response(self) -> Response {
+        Response::new().send_text(self)
+    }
+}
+
+impl IntoResponse for String {
+    #[inline]
+    fn into_response(self) -> Response {
+        Response::new().send_text(self)
+    }
+}
+
+impl IntoResponse for serde_json::Value {
+    #[inline]
+    fn into_response(self) -> Response {
+        Response::new().send_json(&self)
+    }
+}
+
+/// Responds with [`ResponseError::status`] and keeps the error on
+/// [`Response::error`] for middleware such as `ErrorLogMiddleware`.
+///
+/// Client errors carry the error message as body; server errors only the
+/// status reason, so internals don't leak to callers, unless the app enables
+/// [`App::error_details`](crate::prelude::App::error_details). The body is
+/// plain text unless the app's [`ErrorFormat`] asks for JSON.
+impl IntoResponse for ResponseError {
+    fn into_response(self) -> Response {
+        let mut res = Response::new();
+        res.status = self.status();
+        res.error = Some(self);
+        write_error_body(&mut res, false, false);
+        res.extensions.insert(DefaultErrorBody { json: false });
+        res
+    }
+}
+
+/// How the bodies of responses converted from a [`ResponseError`] are
+/// formatted. Set it app-wide with [`App::error_format`](crate::prelude::App::error_format).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorFormat {
+    /// A `text/plain` message.
+    #[default]
+    Text,
+    /// An `application/json` body in the app's
+    /// [`ErrorEnvelope`](crate::prelude::ErrorEnvelope), by default
+    /// `{"error": {"code": <code>, "message": <message>, "details": null}}`.
+    Json,
+    /// JSON when the request's `Accept` header asks for it, text otherwise.
+    Negotiate,
+}
+
+/// Marks a response whose body was written by [`write_error_body`], so the
+/// router may rewrite it in the app's [`ErrorFormat`] and
+/// [`ErrorEnvelope`](crate::prelude::ErrorEnvelope), or with error details.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DefaultErrorBody {
+    /// Whether the body is JSON whatever the [`ErrorFormat`].
+    pub(crate) json: bool,
+}
+
+/// Extra `details` of a JSON error body, kept for rewrites.
+#[derive(Debug, Clone)]
+pub(crate) struct ErrorBodyDetails(pub(crate) serde_json::Value);
+
+/// Set in request extensions by [`App::error_details`](crate::prelude::App::error_details):
+/// server error bodies carry the error chain instead of the status reason.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ErrorDetails;
+
+/// Whether server error bodies for `req` include the error chain.
+pub(crate) fn verbose_errors<B>(req: &crate::handler::Request<B>) -> bool {
+    req.extensions().get::<ErrorDetails>().is_some()
+}
+
+/// Whether error bodies for `req` are written as JSON under its [`ErrorFormat`].
+pub(crate) fn json_errors<B>(req: &crate::handler::Request<B>) -> bool {
+    use crate::handler::request::RequestExt;
+
+    match req.extensions().get::<ErrorFormat>() {
+        Some(ErrorFormat::Json) => true,
+        Some(ErrorFormat::Negotiate) => req.prefers_json(),
+        Some(ErrorFormat::Text) | None => false,
+    }
+}
+
+/// Writes the client-facing body for `res.error` in text or JSON, the latter
+/// in the [`ErrorEnvelope`](crate::prelude::ErrorEnvelope) found in
+/// `res.extensions`. Server errors only show the status reason unless `verbose`.
+pub(crate) fn write_error_body(res: &mut Response, json: bool, verbose: bool) {
+    let Some(error) = &res.error else {
+        return;
+    };
+    let reason = res.status.canonical_reason().unwrap_or("Error");
+    let message = if res.status.is_client_error() {
+        error.to_string()
+    } else if verbose {
+        crate::middleware::error_chain(error)
+    } else {
+        reason.to_owned()
+    };
+
+    if json {
+        let details = match res.extensions.get::<ErrorBodyDetails>() {
+            Some(ErrorBodyDetails(details)) => details.clone(),
+            None => serde_json::Value::Null,
+        };
+        let body = res.error_envelope_body(&message, &details);
+        res.send_json(&body);
+    } else {
+        res.send_text(message);
+    }
+}
+
+impl IntoResponse for std::convert::Infallible {
+    fn into_response(self) -> Response {
+        match self {}
+    }
+}
+
+impl<R: IntoResponse, E: IntoResponse> IntoResponse for Result<R, E> {
+    #[inline]
+    fn into_response(self) -> Response {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(error) => error.into_response(),
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    #[inline]
+    fn into_response(self) -> Response {
+        Response::new().send_json(&self.0)
+    }
+}
+
+impl<R: IntoResponse> IntoResponse for (StatusCode, R) {
+    fn into_response(self) -> Response {
+        let (status, inner) = self;
+        inner.into_response().status(status)
+    }
+}
+
+impl<R: IntoResponse> IntoResponse for (HeaderMap, R) {
+    fn into_response(self) -> Response {
+        let (headers, inner) = self;
+        let mut res = inner.into_response();
+        // Explicit headers win over the ones derived from the body (e.g. Content-Type).
+        res.headers.extend(headers);
+        res
+    }
+}
+
+impl<R: IntoResponse> IntoResponse for (StatusCode, HeaderMap, R) {
+    fn into_response(self) -> Response {
+        let (status, headers, inner) = self;
+        (headers, inner).into_response().status(status)
+    }
+}
+
+impl<T: Serialize> From<Json<T>> for Response {
+    #[inline]
+    fn from(json: Json<T>) -> Self {
+        json.into_response()
+    }
+}
+
+impl<R: IntoResponse> From<(StatusCode, R)> for Response {
+    #[inline]
+    fn from(parts: (StatusCode, R)) -> Self {
+        parts.into_response()
+    }
+}
+
+impl<R: IntoResponse> From<(HeaderMap, R)> for Response {
+    #[inline]
+    fn from(parts: (HeaderMap, R)) -> Self {
+        parts.into_response()
+    }
+}
+
+impl<R: IntoResponse> From<(StatusCode, HeaderMap, R)> for Response {
+    #[inline]
+    fn from(parts: (StatusCode, HeaderMap, R)) -> Self {
+        parts.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::response::ResponseBody;
+    use hyper::header::{CONTENT_TYPE, HeaderValue};
+
+    fn body(res: &Response) -> &[u8] {
+        match &res.body {
+            ResponseBody::Full(bytes) => bytes,
+            other => panic!("expected full body, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_status_and_str() {
+        let res: Response = (StatusCode::ACCEPTED, "queued").into();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+        assert_eq!(body(&res), b"queued");
+        assert_eq!(
+            res.headers.get(CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+    }
+
+    #[test]
+    fn test_status_headers_and_string() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Trace", HeaderValue::from_static("abc"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/csv"));
+
+        let res: Response = (StatusCode::OK, headers, String::from("a,b")).into();
+        assert_eq!(res.headers.get("X-Trace").unwrap(), "abc");
+        assert_eq!(res.headers.get(CONTENT_TYPE).unwrap(), "text/csv");
+        assert_eq!(body(&res), b"a,b");
+    }
+
+    #[test]
+    fn test_status_and_json() {
+        #[derive(Serialize)]
+        struct User {
+            id: u32,
+        }
+
+        let user = User { id: 7 };
+        let res: Response = (StatusCode::CREATED, Json(&user)).into();
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.headers.get(CONTENT_TYPE).unwrap(), "application/json");
+        assert_eq!(body(&res), br#"{"id":7}"#);
+    }
+
+    #[test]
+    fn test_bare_status_code() {
+        let res = StatusCode::NO_CONTENT.into_response();
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert!(res.body.is_empty());
+    }
+
+    #[test]
+    fn test_response_is_identity() {
+        let res = Response::new().status(StatusCode::ACCEPTED).into_response();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+
+    #[test]
+    fn test_str_and_string() {
+        let res = "hello".into_response();
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(body(&res), b"hello");
+
+        let res = String::from("owned").into_response();
+        assert_eq!(body(&res), b"owned");
+    }
+
+    #[test]
+    fn test_json_value() {
+        let res = serde_json::json!({"ok": true}).into_response();
+        assert_eq!(res.headers.get(CONTENT_TYPE).unwrap(), "application/json");
+        assert_eq!(body(&res), br#"{"ok":true}"#);
+    }
+
+    #[test]
+    fn test_response_error() {
+        let res = ResponseError::PayloadTooLarge(16).into_response();
+        assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body(&res), b"payload too large: limit is 16 bytes");
+        assert!(matches!(
+            res.error,
+            Some(ResponseError::PayloadTooLarge(16))
+        ));
+
+        // Server errors don't expose the error message.
+        let res = ResponseError::MmapError.into_response();
+        assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body(&res), b"Internal Server Error");
+        assert!(res.error.is_some());
+    }
+
+    #[test]
+    fn test_result() {
+        let ok: Result<&'static str, StatusCode> = Ok("fine");
+        assert_eq!(body(&ok.into_response()), b"fine");
+
+        let err: Result<&'static str, StatusCode> = Err(StatusCode::FORBIDDEN);
+        assert_eq!(err.into_response().status, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,143 @@
+//! Streaming `multipart/mixed` bodies, built by [`Response::multipart`](super::Response::multipart).
+
+use super::try_insert_header;
+use crate::handler::request::random_bytes;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::{Stream, StreamExt};
+use hyper::HeaderMap;
+use hyper::body::Frame;
+use hyper::header::{CONTENT_TYPE, HeaderValue, IntoHeaderName};
+use std::fmt::Write;
+use std::io;
+
+/// One part of a [`Response::multipart`](super::Response::multipart) body:
+/// its own headers, then its bytes.
+#[derive(Debug, Clone, Default)]
+pub struct Part {
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Part {
+    /// Creates a part holding `body`, without headers.
+    pub fn new(body: impl Into<Bytes>) -> Self {
+        Self {
+            headers: HeaderMap::new(),
+            body: body.into(),
+        }
+    }
+
+    /// Sets a header of the part.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: IntoHeaderName,
+        V: Into<HeaderValue>,
+    {
+        self.headers.insert(key, value.into());
+        self
+    }
+
+    /// Sets the `Content-Type` of the part. Invalid values are dropped with
+    /// a warning.
+    pub fn content_type(mut self, mime_type: impl AsRef<str>) -> Self {
+        try_insert_header(&mut self.headers, CONTENT_TYPE, mime_type.as_ref());
+        self
+    }
+
+    /// The boundary line and headers that open the part.
+    fn head(&self, boundary: &str) -> Bytes {
+        let mut head = BytesMut::with_capacity(boundary.len() + 64);
+        head.put_slice(b"--");
+        head.put_slice(boundary.as_bytes());
+        head.put_slice(b"\r\n");
+        for (name, value) in &self.headers {
+            head.put_slice(name.as_str().as_bytes());
+            head.put_slice(b": ");
+            head.put_slice(value.as_bytes());
+            head.put_slice(b"\r\n");
+        }
+        head.put_slice(b"\r\n");
+        head.freeze()
+    }
+}
+
+/// A random boundary. The parts aren't known up front, so unlike the
+/// `multipart/byteranges` one it can't be checked against them; 128 bits
+/// from the system's secure generator make a collision with part content
+/// vanishingly unlikely, even for content chosen by a client.
+pub(super) fn boundary() -> String {
+    random_bytes::<16>()
+        .iter()
+        .fold(String::with_capacity(32), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Frames each part as its head, its body and the line break that precedes
+/// the next delimiter, then closes the body after the last part.
+pub(super) fn frames<S>(
+    parts: S,
+    boundary: String,
+) -> impl Stream<Item = Result<Frame<Bytes>, io::Error>> + Send + Sync + 'static
+where
+    S: Stream<Item = Result<Part, io::Error>> + Send + Sync + 'static,
+{
+    let close = Bytes::from(format!("--{boundary}--\r\n"));
+    parts
+        .flat_map(move |part| {
+            let frames = match part {
+                Ok(part) => vec![
+                    Ok(part.head(&boundary)),
+                    Ok(part.body),
+                    Ok(Bytes::from_static(b"\r\n")),
+                ],
+                Err(e) => vec![Err(e)],
+            };
+            futures_util::stream::iter(frames)
+        })
+        .chain(futures_util::stream::once(async move { Ok(close) }))
+        .map(|chunk| chunk.map(Frame::data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::response::Response;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn test_multipart_structure() {
+        let parts = [
+            Part::new(r#"{"id":1}"#)
+                .content_type("application/json")
+                .header("Content-Id", HeaderValue::from_static("<1>")),
+            Part::new("hello"),
+        ];
+        let res = Response::new().multipart(futures_util::stream::iter(parts.map(Ok)));
+        let res = res.into_hyper();
+
+        let content_type = res.headers()[CONTENT_TYPE].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/mixed; boundary=")
+            .unwrap()
+            .to_owned();
+        assert_eq!(boundary.len(), 32);
+        assert_ne!(boundary, super::boundary());
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let expected = format!(
+            "--{boundary}\r\ncontent-type: application/json\r\ncontent-id: <1>\r\n\r\n{{\"id\":1}}\r\n\
+             --{boundary}\r\n\r\nhello\r\n\
+             --{boundary}--\r\n"
+        );
+        assert_eq!(std::str::from_utf8(&body).unwrap(), expected);
+
+        let empty = frames(futures_util::stream::empty(), "b".into());
+        let chunks: Vec<_> = empty
+            .map(|frame| frame.unwrap().into_data().unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks, [Bytes::from_static(b"--b--\r\n")]);
+    }
+}
@@ -0,0 +1,113 @@
+//! The body formats [`Response::body_negotiated`](super::Response::body_negotiated)
+//! picks from, by the request's `Accept` header.
+
+use crate::handler::request::quality_values;
+use serde::Serialize;
+
+/// A format a serializable body can be sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    /// In order of preference when the client accepts several equally.
+    const ALL: [Format; 3] = [Format::Json, Format::MessagePack, Format::Cbor];
+
+    /// The media types naming the format; the first is sent.
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/json"],
+            Format::MessagePack => &[
+                "application/msgpack",
+                "application/x-msgpack",
+                "application/vnd.msgpack",
+            ],
+            Format::Cbor => &["application/cbor"],
+        }
+    }
+
+    /// The `Content-Type` of a body in this format.
+    pub(crate) fn content_type(self) -> &'static str {
+        self.media_types()[0]
+    }
+
+    /// Picks the format from an `Accept` header: the one with the highest
+    /// quality, JSON when there is none or nothing serializable is accepted.
+    pub(crate) fn negotiate(accept: Option<&str>) -> Format {
+        let Some(accept) = accept else {
+            return Format::Json;
+        };
+        let ranges: Vec<(String, f32)> = quality_values(accept)
+            .map(|(range, q)| (range.to_ascii_lowercase(), q))
+            .collect();
+
+        let mut best: Option<(Format, f32)> = None;
+        for format in Format::ALL {
+            let q = format.quality(&ranges);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((format, q));
+            }
+        }
+        best.map_or(Format::Json, |(format, _)| format)
+    }
+
+    /// The quality of the most specific range matching this format.
+    fn quality(self, ranges: &[(String, f32)]) -> f32 {
+        let mut matched: Option<(u8, f32)> = None;
+        for (range, q) in ranges {
+            let specificity = match range.as_str() {
+                "*/*" => 0,
+                "application/*" => 1,
+                range if self.media_types().contains(&range) => 2,
+                _ => continue,
+            };
+            if matched.is_none_or(|(best, _)| specificity > best) {
+                matched = Some((specificity, *q));
+            }
+        }
+        matched.map_or(0.0, |(_, q)| q)
+    }
+
+    /// Serializes `data` in this format.
+    pub(crate) fn serialize<T: Serialize>(self, data: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(data).map_err(|e| e.to_string()),
+            Format::MessagePack => rmp_serde::to_vec_named(data).map_err(|e| e.to_string()),
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(data, &mut bytes).map_err(|e| e.to_string())?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_prefers_quality_then_json() {
+        for (accept, expected) in [
+            (None, Format::Json),
+            (Some("*/*"), Format::Json),
+            (Some("text/html"), Format::Json),
+            (Some("application/msgpack"), Format::MessagePack),
+            (Some("application/x-msgpack"), Format::MessagePack),
+            (
+                Some("application/cbor, application/json;q=0.5"),
+                Format::Cbor,
+            ),
+            (
+                Some("application/*, application/json;q=0"),
+                Format::MessagePack,
+            ),
+            (Some("Application/CBOR;q=0.9, */*;q=0.1"), Format::Cbor),
+        ] {
+            assert_eq!(Format::negotiate(accept), expected, "{accept:?}");
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use serde_json::{Value, json};
+
+/// Where a page sits in a paginated collection, for
+/// [`Response::paginated`](crate::prelude::Response::paginated).
+///
+/// Pages are numbered from 1. The links to other pages are built from
+/// [`base_url`](Self::base_url), usually the request's URL, with its `page`
+/// and `per_page` query parameters replaced and the others kept.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let meta = PageMeta::new(2, 20, 95).base_url("/todos?done=false");
+/// assert_eq!(meta.last_page(), 5);
+/// assert_eq!(meta.page_url(3), "/todos?done=false&page=3&per_page=20");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageMeta {
+    page: u64,
+    per_page: u64,
+    total: u64,
+    base_url: String,
+}
+
+impl PageMeta {
+    /// Page `page` of `per_page` items, out of `total` items.
+    pub fn new(page: u64, per_page: u64, total: u64) -> Self {
+        Self {
+            page: page.max(1),
+            per_page: per_page.max(1),
+            total,
+            base_url: String::new(),
+        }
+    }
+
+    /// Sets the URL the page links are built from, e.g.
+    /// [`RequestExt::original_url`](crate::prelude::RequestExt::original_url).
+    /// Without one, links are relative query strings such as `?page=2&per_page=20`.
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into();
+        self
+    }
+
+    /// The current page.
+    pub fn page(&self) -> u64 {
+        self.page
+    }
+
+    /// The number of items per page.
+    pub fn per_page(&self) -> u64 {
+        self.per_page
+    }
+
+    /// The number of items in the whole collection.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The number of the last page; 1 for an empty collection.
+    pub fn last_page(&self) -> u64 {
+        self.total.div_ceil(self.per_page).max(1)
+    }
+
+    /// Whether the current page is past the last one, e.g. a stale link
+    /// after items were deleted. Such a page has no items and no `next`
+    /// link; its `prev` link points at the last page.
+    pub fn out_of_range(&self) -> bool {
+        self.page > self.last_page()
+    }
+
+    /// The URL of page `page`.
+    pub fn page_url(&self, page: u64) -> String {
+        let (path, query) = self
+            .base_url
+            .split_once('?')
+            .unwrap_or((&self.base_url, ""));
+        let mut serializer = form_urlencoded::Serializer::new(String::new());
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            if name != "page" && name != "per_page" {
+                serializer.append_pair(&name, &value);
+            }
+        }
+        serializer
+            .append_pair("page", &page.to_string())
+            .append_pair("per_page", &self.per_page.to_string());
+        format!("{path}?{}", serializer.finish())
+    }
+
+    /// The `first`, `prev`, `next` and `last` relations and their URLs;
+    /// `prev` and `next` only where such a page exists.
+    pub(crate) fn links(&self) -> Vec<(&'static str, String)> {
+        let last = self.last_page();
+        let mut links = vec![("first", self.page_url(1))];
+        if self.page > 1 {
+            links.push(("prev", self.page_url((self.page - 1).min(last))));
+        }
+        if self.page < last {
+            links.push(("next", self.page_url(self.page + 1)));
+        }
+        links.push(("last", self.page_url(last)));
+        links
+    }
+
+    /// The value of the `Link` header listing [`links`](Self::links).
+    pub(crate) fn link_header(&self) -> String {
+        self.links()
+            .iter()
+            .map(|(rel, url)| format!("<{url}>; rel=\"{rel}\""))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The response body: the page's `data` and its metadata.
+    pub(crate) fn body(&self, data: Value) -> Value {
+        let links = self.links();
+        let link = |rel: &str| {
+            links
+                .iter()
+                .find(|(r, _)| *r == rel)
+                .map(|(_, url)| url.as_str())
+        };
+        json!({
+            "data": data,
+            "page": self.page,
+            "per_page": self.per_page,
+            "total": self.total,
+            "out_of_range": self.out_of_range(),
+            "links": {
+                "first": link("first"),
+                "prev": link("prev"),
+                "next": link("next"),
+                "last": link("last"),
+            },
+        })
+    }
+}
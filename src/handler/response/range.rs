@@ -0,0 +1,171 @@
+//! `Range` request header handling (RFC 9110 §14), shared by every response
+//! that can serve part of a body of known length.
+
+use bytes::Bytes;
+use std::hash::{BuildHasher, RandomState};
+use std::ops::Range;
+
+/// Most ranges served from one request; larger sets get the full body, as
+/// the RFC allows, rather than a response built from thousands of tiny parts.
+const MAX_RANGES: usize = 16;
+
+/// What to send for a `Range` header against a body of a given length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ByteRange {
+    /// No usable range: send the whole body with `200 OK`.
+    Full,
+    /// Send these bytes with `206 Partial Content`.
+    Partial(Range<u64>),
+    /// Send each of these ranges as a part of a `multipart/byteranges` body.
+    Multi(Vec<Range<u64>>),
+    /// No requested byte exists: answer `416 Range Not Satisfiable`.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Resolves a `Range` header value against a body of `len` bytes.
+    ///
+    /// Missing, malformed or non-`bytes` headers are ignored, as the RFC
+    /// allows, and so are sets of more than [`MAX_RANGES`] ranges. Ranges
+    /// past the end are dropped; the header is unsatisfiable only if all are.
+    pub(crate) fn resolve(header: Option<&str>, len: u64) -> Self {
+        let Some(set) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+            return ByteRange::Full;
+        };
+
+        let mut ranges = Vec::new();
+        for (i, spec) in set.split(',').enumerate() {
+            if i == MAX_RANGES {
+                return ByteRange::Full;
+            }
+            match resolve_one(spec, len) {
+                Some(Some(range)) => ranges.push(range),
+                Some(None) => {}
+                None => return ByteRange::Full,
+            }
+        }
+
+        match ranges.len() {
+            0 => ByteRange::Unsatisfiable,
+            1 => ByteRange::Partial(ranges.remove(0)),
+            _ => ByteRange::Multi(ranges),
+        }
+    }
+}
+
+/// Resolves one `first-last` or `-suffix` spec: `None` if it is malformed,
+/// `Some(None)` if no byte of it exists.
+fn resolve_one(spec: &str, len: u64) -> Option<Option<Range<u64>>> {
+    let (start, end) = spec.trim().split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // Suffix range: the last `end` bytes.
+        let suffix = end.parse::<u64>().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(None);
+        }
+        return Some(Some(len.saturating_sub(suffix)..len));
+    }
+
+    let start = start.parse::<u64>().ok()?;
+    let last = if end.is_empty() {
+        u64::MAX
+    } else {
+        end.parse::<u64>().ok().filter(|&last| last >= start)?
+    };
+    if start >= len {
+        return Some(None);
+    }
+    Some(Some(start..last.min(len - 1) + 1))
+}
+
+/// Builds a `multipart/byteranges` body for `ranges` of `bytes`.
+///
+/// Returns the boundary and the body chunks; the parts borrow `bytes`
+/// rather than copying them. Each part carries `content_type` and its own
+/// `Content-Range`.
+pub(crate) fn multipart(
+    bytes: &Bytes,
+    ranges: &[Range<u64>],
+    content_type: &str,
+) -> (String, Vec<Bytes>) {
+    let boundary = boundary_for(bytes);
+    let len = bytes.len();
+    let mut chunks = Vec::with_capacity(ranges.len() * 2 + 1);
+
+    for (i, range) in ranges.iter().enumerate() {
+        let head = format!(
+            "{}--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {}-{}/{len}\r\n\r\n",
+            if i == 0 { "" } else { "\r\n" },
+            range.start,
+            range.end - 1,
+        );
+        chunks.push(Bytes::from(head));
+        chunks.push(bytes.slice(range.start as usize..range.end as usize));
+    }
+    chunks.push(Bytes::from(format!("\r\n--{boundary}--\r\n")));
+    (boundary, chunks)
+}
+
+/// Picks a random boundary that does not occur in `bytes`.
+fn boundary_for(bytes: &[u8]) -> String {
+    loop {
+        let boundary = format!("{:016x}", RandomState::new().hash_one(bytes.len()));
+        if !bytes
+            .windows(boundary.len())
+            .any(|window| window == boundary.as_bytes())
+        {
+            return boundary;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_ranges() {
+        let resolve = |h| ByteRange::resolve(Some(h), 100);
+        assert_eq!(resolve("bytes=0-9"), ByteRange::Partial(0..10));
+        assert_eq!(resolve("bytes=90-"), ByteRange::Partial(90..100));
+        assert_eq!(resolve("bytes=95-200"), ByteRange::Partial(95..100));
+        assert_eq!(resolve("bytes=-10"), ByteRange::Partial(90..100));
+        assert_eq!(resolve("bytes=-500"), ByteRange::Partial(0..100));
+
+        assert_eq!(resolve("bytes=100-"), ByteRange::Unsatisfiable);
+        assert_eq!(resolve("bytes=-0"), ByteRange::Unsatisfiable);
+        assert_eq!(
+            ByteRange::resolve(Some("bytes=0-"), 0),
+            ByteRange::Unsatisfiable
+        );
+
+        assert_eq!(ByteRange::resolve(None, 100), ByteRange::Full);
+        assert_eq!(resolve("items=0-9"), ByteRange::Full);
+        assert_eq!(resolve("bytes=9-0"), ByteRange::Full);
+        assert_eq!(resolve("bytes=abc"), ByteRange::Full);
+    }
+
+    #[test]
+    fn test_resolve_multiple_ranges() {
+        let resolve = |h| ByteRange::resolve(Some(h), 100);
+        assert_eq!(
+            resolve("bytes=0-1, 5-6,-2"),
+            ByteRange::Multi(vec![0..2, 5..7, 98..100])
+        );
+        // Unsatisfiable parts are dropped, leaving a single range.
+        assert_eq!(resolve("bytes=0-1,200-300"), ByteRange::Partial(0..2));
+        assert_eq!(resolve("bytes=100-,200-"), ByteRange::Unsatisfiable);
+        // One malformed part invalidates the whole header.
+        assert_eq!(resolve("bytes=0-1,x"), ByteRange::Full);
+
+        let many = (0..=MAX_RANGES)
+            .map(|i| format!("{i}-{i}"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            resolve(&format!("bytes={}", many.join(","))),
+            ByteRange::Full
+        );
+    }
+}
@@ -7,6 +7,14 @@ mod middleware;
 mod router;
 mod server;
 
+#[cfg(test)]
+mod test_alloc;
+#[cfg(test)]
+mod test_logger;
+
+#[cfg(feature = "client")]
+pub mod client;
+pub mod config;
 pub mod prelude;
 
 // ─── Primary entry-points ─────────────────────────────────────────────────────
@@ -13,10 +13,30 @@ pub enum MiddlewareResult {
 }
 
 /// The base trait for all Express-like middleware components.
+///
+/// # Cancellation
+///
+/// When a client disconnects, the future handling its request is dropped at
+/// whatever `.await` it is parked on, in the middle of [`call`](Middleware::call)
+/// or of the handler, and no [`finish`](Middleware::finish) hook runs. Keep
+/// shared state consistent across every `.await` (don't leave a counter
+/// incremented or a lock-protected value half-updated until a later step),
+/// and put cleanup that must happen in a `Drop` guard rather than after an
+/// `.await`. Work spawned off the request can stop early by watching
+/// [`RequestExt::on_disconnect`](crate::prelude::RequestExt::on_disconnect).
 #[async_trait]
 pub trait Middleware<B = Incoming>: Send + Sync + 'static {
     /// Executes the middleware function to mutate request and response structures inline.
     async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult;
+
+    /// Invoked once the final response for the request has been produced.
+    ///
+    /// Hooks run in reverse order of the `call`s that happened for the request
+    /// (including the middleware that returned [`MiddlewareResult::Stop`]), so the
+    /// first middleware to see the request is the last to see the response.
+    /// State captured in `call` can be carried over in [`Response::extensions`],
+    /// which the router preserves even if the handler builds a fresh response.
+    fn finish(&self, _res: &mut Response) {}
 }
 
 /// Helper function to yield execution to the next layer in the router stack.
@@ -57,21 +77,50 @@ where
 /// Authentication module.
 pub mod auth;
 mod cache;
+mod compression;
 mod cors;
+mod deprecation;
+mod digest;
+mod error_log;
+mod from_fn;
+mod https_redirect;
 mod limit_body;
 mod logging;
+mod metrics;
 mod normalize_path;
+mod path_rewrite;
 mod rate_limit;
 mod security_headers;
+mod server_timing;
+mod single_flight;
 mod static_serve;
 
-pub use auth::AuthMiddleware;
+pub use auth::{
+    AuthError, AuthLevel, AuthMiddleware, AuthenticatedUser, CachingTokenValidator, CookiePrefix,
+    JwtTokenValidator, SessionInfo, SessionTokenValidator, TokenValidator,
+};
 pub use cache::CacheMiddleware;
+pub(crate) use compression::BodyDecoding;
+pub use compression::{CompressionMiddleware, Encoding};
 pub use cors::CorsMiddleware;
-pub use logging::LoggingMiddleware;
+pub use deprecation::{Deprecation, DeprecationMiddleware};
+pub use digest::DigestVerificationMiddleware;
+pub(crate) use digest::{BodyDigest, DigestCheck};
+pub use error_log::{ErrorLogMiddleware, ErrorLogged, ErrorReport};
+pub(crate) use error_log::{RequestSummary, error_chain};
+pub use from_fn::{
+    MiddlewareFn, MiddlewareFnWithState, MiddlewareFuture, from_fn_with_state, middleware_fn,
+};
+pub use https_redirect::HttpsRedirectMiddleware;
+pub use limit_body::BodySizeLimitMiddleware;
+pub use logging::{LogFormatError, LogPolicy, LogRequest, Logger, LoggingMiddleware};
+pub use metrics::MetricsMiddleware;
 pub use normalize_path::NormalizePathMiddleware;
+pub use path_rewrite::PathRewriteMiddleware;
 pub use rate_limit::RateLimitMiddleware;
-pub use security_headers::SecurityHeadersMiddleware;
+pub use security_headers::{SecurityHeaders, SecurityHeadersMiddleware};
+pub use server_timing::ServerTimingMiddleware;
+pub use single_flight::SingleFlightMiddleware;
 pub use static_serve::StaticServeMiddleware;
 
 /// Initializes a new `express` application.
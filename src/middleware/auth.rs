@@ -1,5 +1,7 @@
 /// Authentication middleware builder pattern
 pub mod builder;
+/// Caching decorator for token validators
+pub mod caching;
 /// Authentication configuration structure
 pub mod config;
 /// HTTP cookie mechanisms tailored for auth
@@ -17,21 +19,42 @@ pub mod user;
 /// Common interface over validation logic
 pub mod validator;
 
-use crate::handler::{ExpressResponse, Request, Response};
+use crate::handler::request::RequestExt;
+use crate::handler::response::try_insert_header;
+use crate::handler::{Request, Response};
 use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
 use config::CookieAuthConfig;
 use cookies::CookieHandler;
-use error::{AuthError, AuthResult};
-use hyper::header::{HeaderValue, LOCATION};
-use jwt::JwtTokenValidator;
-use session::SessionTokenValidator;
+use error::AuthResult;
+use hyper::StatusCode;
+use hyper::header::{HeaderName, HeaderValue, LOCATION};
+use serde_json::Value;
 use std::sync::Arc;
-use user::{AuthLevel, AuthenticatedUser};
-use validator::TokenValidator;
+use std::time::SystemTime;
 
 pub use builder::AuthMiddlewareBuilder;
+pub use caching::CachingTokenValidator;
+pub use config::CookiePrefix;
+pub use error::AuthError;
+pub use jwt::JwtTokenValidator;
+pub use session::{SessionInfo, SessionTokenValidator};
+pub use user::{AuthLevel, AuthenticatedUser};
+pub use validator::TokenValidator;
+
+/// Header announcing that the user's token is about to expire.
+const X_AUTH_EXPIRES: HeaderName = HeaderName::from_static("x-auth-expires");
+
+/// The expiry of a token within [`CookieAuthConfig::expiry_warning`], kept
+/// for `finish`.
+#[derive(Debug, Clone, Copy)]
+struct AuthExpiry(SystemTime);
 
 /// Main authentication middleware
+///
+/// Unauthenticated requests are redirected to the configured login page,
+/// except those preferring JSON, which get a `401 Unauthorized` (or `403
+/// Forbidden` for insufficient permissions) in the app's
+/// [`ErrorEnvelope`](crate::prelude::ErrorEnvelope).
 #[derive(Clone)]
 pub struct AuthMiddleware {
     config: CookieAuthConfig,
@@ -41,41 +64,58 @@ pub struct AuthMiddleware {
 
 impl AuthMiddleware {
     /// Creates a new `AuthMiddleware` with JWT validation
+    ///
+    /// # Panics
+    ///
+    /// If the config breaks the rules of its cookie prefix.
     pub fn with_jwt(
         config: CookieAuthConfig,
         protected_routes: matchit::Router<AuthLevel>,
         jwt_validator: JwtTokenValidator,
     ) -> Self {
-        Self {
-            config,
-            protected_routes,
-            token_validator: Arc::new(jwt_validator),
-        }
+        Self::new(config, protected_routes, Arc::new(jwt_validator))
     }
 
     /// Creates a new `AuthMiddleware` with session validation
+    ///
+    /// # Panics
+    ///
+    /// If the config breaks the rules of its cookie prefix.
     pub fn with_sessions(
         config: CookieAuthConfig,
         protected_routes: matchit::Router<AuthLevel>,
         validator: SessionTokenValidator,
     ) -> Self {
-        Self {
-            config,
-            protected_routes,
-            token_validator: Arc::new(validator),
-        }
+        Self::new(config, protected_routes, Arc::new(validator))
     }
 
     /// Creates a new `AuthMiddleware` with custom token validator
+    ///
+    /// # Panics
+    ///
+    /// If the config breaks the rules of its cookie prefix.
     pub fn with_validator(
         config: CookieAuthConfig,
         protected_routes: matchit::Router<AuthLevel>,
         validator: Arc<dyn TokenValidator>,
     ) -> Self {
+        Self::new(config, protected_routes, validator)
+    }
+
+    /// Checks the config before storing it; an invalid one is a setup bug, so
+    /// it fails fast at startup rather than on the first request.
+    fn new(
+        config: CookieAuthConfig,
+        protected_routes: matchit::Router<AuthLevel>,
+        token_validator: Arc<dyn TokenValidator>,
+    ) -> Self {
+        if let Err(e) = config.validate() {
+            panic!("{e}");
+        }
         Self {
             config,
             protected_routes,
-            token_validator: validator,
+            token_validator,
         }
     }
 
@@ -84,6 +124,12 @@ impl AuthMiddleware {
         AuthMiddlewareBuilder::new()
     }
 
+    /// The cookie settings, for signing session cookies with
+    /// [`ExpressResponse::signed_cookie`](crate::prelude::ExpressResponse::signed_cookie).
+    pub fn config(&self) -> &CookieAuthConfig {
+        &self.config
+    }
+
     /// Checks if a path is protected and returns the required authorization level
     fn get_required_auth_level(&self, path: &str) -> Option<&AuthLevel> {
         self.protected_routes.at(path).ok().map(|m| m.value)
@@ -96,11 +142,12 @@ impl AuthMiddleware {
     }
 
     /// Extracts and validates authentication token from request
-    async fn extract_and_validate_token(
+    async fn extract_and_validate_token<B>(
         &self,
-        req: &Request,
+        req: &Request<B>,
     ) -> AuthResult<Option<AuthenticatedUser>> {
-        let token = CookieHandler::get_cookie_value(req, &self.config.cookie_name)?;
+        let name = self.config.prefixed_cookie_name();
+        let token = CookieHandler::get_verified_cookie_value(req, &name, &self.config)?;
 
         match token {
             Some(token) => {
@@ -122,11 +169,21 @@ impl AuthMiddleware {
 
     /// Creates a redirect response to the login page
     fn create_redirect_response(&self, res: &mut Response) -> MiddlewareResult {
-        res.status_code(302).header(
-            LOCATION,
-            HeaderValue::from_str(&self.config.login_redirect)
-                .unwrap_or_else(|_| HeaderValue::from_static("/login")),
-        );
+        res.status = StatusCode::FOUND;
+        if !try_insert_header(&mut res.headers, LOCATION, &self.config.login_redirect) {
+            res.headers
+                .insert(LOCATION, HeaderValue::from_static("/login"));
+        }
+        stop_res()
+    }
+
+    /// Answers a request preferring JSON with the error in the app's envelope.
+    fn create_error_response(&self, error: &AuthError, res: &mut Response) -> MiddlewareResult {
+        let status = match error {
+            AuthError::InsufficientPermissions => 403,
+            _ => 401,
+        };
+        res.respond_enveloped_error(status, &error.to_string(), Value::Null, true);
         stop_res()
     }
 
@@ -152,8 +209,8 @@ impl std::fmt::Debug for AuthMiddleware {
 }
 
 #[async_trait::async_trait]
-impl Middleware for AuthMiddleware {
-    async fn call(&self, req: &mut Request, res: &mut Response) -> MiddlewareResult {
+impl<B: Send + Sync + 'static> Middleware<B> for AuthMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
         let path = req.uri().path().to_owned();
 
         let required_level = match self.get_required_auth_level(&path) {
@@ -177,16 +234,35 @@ impl Middleware for AuthMiddleware {
 
         match auth_result {
             Ok(user) => {
+                if let (Some(window), Some(expires_at)) =
+                    (self.config.expiry_warning, user.expires_at)
+                    && expires_at
+                        .duration_since(SystemTime::now())
+                        .is_ok_and(|left| left <= window)
+                {
+                    res.extensions.insert(AuthExpiry(expires_at));
+                }
                 req.extensions_mut().insert(user);
                 self.log_auth_attempt(&path, &Ok(()));
                 next_res()
             }
             Err(auth_error) => {
-                self.log_auth_attempt(&path, &Err(auth_error));
-                self.create_redirect_response(res)
+                self.log_auth_attempt(&path, &Err(auth_error.clone()));
+                if req.prefers_json() {
+                    self.create_error_response(&auth_error, res)
+                } else {
+                    self.create_redirect_response(res)
+                }
             }
         }
     }
+
+    fn finish(&self, res: &mut Response) {
+        if let Some(AuthExpiry(expires_at)) = res.extensions.remove::<AuthExpiry>() {
+            let date = httpdate::fmt_http_date(expires_at);
+            try_insert_header(&mut res.headers, X_AUTH_EXPIRES, date);
+        }
+    }
 }
 
 impl AuthMiddleware {
@@ -1,7 +1,10 @@
 use super::AuthMiddleware;
 use crate::middleware::auth::{
-    config::CookieAuthConfig, jwt::JwtTokenValidator, session::SessionTokenValidator,
-    user::AuthLevel, validator::TokenValidator,
+    config::{CookieAuthConfig, CookiePrefix, CookieSigningKeys},
+    jwt::JwtTokenValidator,
+    session::SessionTokenValidator,
+    user::AuthLevel,
+    validator::TokenValidator,
 };
 use std::sync::Arc;
 
@@ -57,6 +60,29 @@ impl AuthMiddlewareBuilder {
         self
     }
 
+    /// Prefixes the auth cookie name with `__Host-` or `__Secure-`.
+    ///
+    /// The prefix's rules are checked when the middleware is built, which
+    /// panics if, say, a `__Host-` cookie was given a domain.
+    pub fn cookie_prefix(mut self, prefix: CookiePrefix) -> Self {
+        self.config.cookie_prefix = Some(prefix);
+        self
+    }
+
+    /// Requires the auth cookie to be signed with one of these keys.
+    pub fn signing_keys(mut self, keys: CookieSigningKeys) -> Self {
+        self.config.signing_keys = Some(keys);
+        self
+    }
+
+    /// Adds an `X-Auth-Expires` header, the token's expiry as an HTTP date,
+    /// to the responses of users whose token expires within `window`, so
+    /// clients can refresh it in time.
+    pub fn expiry_warning(mut self, window: std::time::Duration) -> Self {
+        self.config.expiry_warning = Some(window);
+        self
+    }
+
     /// Configures token length limits.
     pub fn token_length_limits(mut self, min: usize, max: usize) -> Self {
         self.config.min_token_length = min;
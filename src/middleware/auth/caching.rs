@@ -0,0 +1,125 @@
+use super::{error::AuthResult, user::AuthenticatedUser, validator::TokenValidator};
+use async_trait::async_trait;
+use quick_cache::sync::Cache;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Number of tokens a [`CachingTokenValidator`] remembers by default.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Longest time a failed validation may be cached, whatever the configuration.
+pub const MAX_FAILURE_TTL: Duration = Duration::from_secs(5);
+
+/// A [`TokenValidator`] decorator that remembers recent validation results.
+///
+/// Successful validations are reused for `ttl`, but never past the token's own
+/// [`expires_at`](AuthenticatedUser::expires_at). Failures are cached for a
+/// much shorter window (one second by default, at most [`MAX_FAILURE_TTL`]) so
+/// a token that becomes valid, e.g. after a login, isn't locked out for long.
+/// Tokens with an invalid format are never cached.
+pub struct CachingTokenValidator<V> {
+    inner: V,
+    ttl: Duration,
+    failure_ttl: Duration,
+    cache: Cache<String, CachedResult>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedResult {
+    result: AuthResult<AuthenticatedUser>,
+    valid_until: Instant,
+}
+
+impl<V: TokenValidator> CachingTokenValidator<V> {
+    /// Wraps `inner`, caching successful validations for `ttl`.
+    pub fn new(inner: V, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            failure_ttl: Duration::from_secs(1),
+            cache: Cache::new(DEFAULT_CAPACITY),
+        }
+    }
+
+    /// Sets how many tokens are remembered. A full cache evicts with
+    /// CLOCK-PRO, keeping the tokens that are validated most often.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.cache = Cache::new(capacity);
+        self
+    }
+
+    /// Sets how long failed validations are cached, clamped to [`MAX_FAILURE_TTL`].
+    /// `Duration::ZERO` disables failure caching.
+    pub fn failure_ttl(mut self, ttl: Duration) -> Self {
+        self.failure_ttl = ttl.min(MAX_FAILURE_TTL);
+        self
+    }
+
+    /// Forgets the cached result for `token`, e.g. after a logout.
+    pub fn invalidate(&self, token: &str) {
+        self.cache.remove(token);
+    }
+
+    /// Returns the wrapped validator.
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for CachingTokenValidator<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachingTokenValidator")
+            .field("inner", &self.inner)
+            .field("ttl", &self.ttl)
+            .field("failure_ttl", &self.failure_ttl)
+            .field("cached", &self.cache.len())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<V: TokenValidator> TokenValidator for CachingTokenValidator<V> {
+    async fn validate_token(&self, token: &str) -> AuthResult<AuthenticatedUser> {
+        let now = Instant::now();
+        if let Some(cached) = self.cache.get(token) {
+            if cached.valid_until > now {
+                return cached.result;
+            }
+            self.cache.remove(token);
+        }
+
+        let result = self.inner.validate_token(token).await;
+        if !self.inner.is_valid_format(token) {
+            return result;
+        }
+
+        let valid_until = match &result {
+            Ok(user) => {
+                let until = now + self.ttl;
+                user.expires_at.map_or(until, |expiry| {
+                    let left = expiry.duration_since(SystemTime::now()).unwrap_or_default();
+                    (now + left).min(until)
+                })
+            }
+            Err(_) => now + self.failure_ttl,
+        };
+        if valid_until > now {
+            self.cache.insert(
+                token.to_owned(),
+                CachedResult {
+                    result: result.clone(),
+                    valid_until,
+                },
+            );
+        }
+        result
+    }
+
+    fn is_valid_format(&self, token: &str) -> bool {
+        self.inner.is_valid_format(token)
+    }
+
+    async fn refresh_token(&self, token: &str) -> AuthResult<Option<String>> {
+        self.inner.refresh_token(token).await
+    }
+}
@@ -1,3 +1,92 @@
+use super::error::{AuthError, AuthResult};
+use cookie::{Cookie, CookieJar, Key};
+use std::borrow::Cow;
+
+/// A cookie name prefix that makes browsers enforce extra attributes.
+///
+/// See [RFC 6265bis §4.1.3](https://datatracker.ietf.org/doc/html/draft-ietf-httpbis-rfc6265bis#section-4.1.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookiePrefix {
+    /// `__Secure-`: the cookie must be `Secure`.
+    Secure,
+    /// `__Host-`: the cookie must be `Secure`, have `Path=/` and no `Domain`,
+    /// locking it to the exact host that set it.
+    Host,
+}
+
+impl CookiePrefix {
+    /// The literal prefix prepended to the cookie name.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            CookiePrefix::Secure => "__Secure-",
+            CookiePrefix::Host => "__Host-",
+        }
+    }
+
+    /// Prepends the prefix to `name` unless it is already there.
+    pub fn apply(self, name: &str) -> Cow<'_, str> {
+        if name.starts_with(self.as_str()) {
+            Cow::Borrowed(name)
+        } else {
+            Cow::Owned(format!("{}{name}", self.as_str()))
+        }
+    }
+}
+
+/// Keys used to sign auth cookies, supporting zero-downtime rotation.
+///
+/// New cookies are always signed with the primary key; verification tries the
+/// primary first and then each previous key, so cookies issued before a
+/// rotation stay valid until the old key is dropped.
+#[derive(Debug, Clone)]
+pub struct CookieSigningKeys {
+    primary: Key,
+    previous: Vec<Key>,
+}
+
+impl CookieSigningKeys {
+    /// Creates a key set with a single primary key.
+    pub fn new(primary: Key) -> Self {
+        Self {
+            primary,
+            previous: Vec::new(),
+        }
+    }
+
+    /// Adds a previous key that is still accepted for verification.
+    pub fn with_previous(mut self, key: Key) -> Self {
+        self.previous.push(key);
+        self
+    }
+
+    /// Makes `key` the primary key, keeping the current one for verification.
+    pub fn rotate(&mut self, key: Key) {
+        let old = std::mem::replace(&mut self.primary, key);
+        self.previous.insert(0, old);
+    }
+
+    /// The key new cookies are signed with.
+    pub fn primary(&self) -> &Key {
+        &self.primary
+    }
+
+    /// Signs `cookie` with the primary key.
+    pub fn sign(&self, cookie: Cookie<'static>) -> Cookie<'static> {
+        let name = cookie.name().to_owned();
+        let mut jar = CookieJar::new();
+        jar.signed_mut(&self.primary).add(cookie);
+        jar.get(&name).cloned().expect("cookie was just added")
+    }
+
+    /// Verifies a signed cookie against the primary, then the previous keys,
+    /// returning it with the signature stripped from its value.
+    pub fn verify(&self, cookie: Cookie<'static>) -> Option<Cookie<'static>> {
+        std::iter::once(&self.primary)
+            .chain(&self.previous)
+            .find_map(|key| CookieJar::new().signed(key).verify(cookie.clone()))
+    }
+}
+
 /// Configuration for cookie authentication
 #[derive(Debug, Clone)]
 pub struct CookieAuthConfig {
@@ -19,6 +108,49 @@ pub struct CookieAuthConfig {
     pub cookie_path: String,
     /// Cookie SameSite policy
     pub same_site: Option<cookie::SameSite>,
+    /// Keys used to sign and verify the session cookie; unsigned when `None`
+    pub signing_keys: Option<CookieSigningKeys>,
+    /// Prefix prepended to `cookie_name`, whose rules the cookie must follow
+    pub cookie_prefix: Option<CookiePrefix>,
+    /// How long before its token expires a user's responses carry an
+    /// `X-Auth-Expires` header; never when `None`
+    pub expiry_warning: Option<std::time::Duration>,
+}
+
+impl CookieAuthConfig {
+    /// The session cookie name as sent to the browser, with the prefix applied.
+    pub fn prefixed_cookie_name(&self) -> Cow<'_, str> {
+        match self.cookie_prefix {
+            Some(prefix) => prefix.apply(&self.cookie_name),
+            None => Cow::Borrowed(&self.cookie_name),
+        }
+    }
+
+    /// Checks that the cookie settings satisfy the rules of `cookie_prefix`,
+    /// which browsers would otherwise enforce by silently dropping the cookie.
+    pub fn validate(&self) -> AuthResult<()> {
+        let Some(prefix) = self.cookie_prefix else {
+            return Ok(());
+        };
+        if !self.secure_cookies {
+            return Err(AuthError::InvalidCookieConfig(
+                "prefixed cookies must be Secure",
+            ));
+        }
+        if prefix == CookiePrefix::Host {
+            if self.cookie_path != "/" {
+                return Err(AuthError::InvalidCookieConfig(
+                    "__Host- cookies must have Path=/",
+                ));
+            }
+            if self.cookie_domain.is_some() {
+                return Err(AuthError::InvalidCookieConfig(
+                    "__Host- cookies must not set a Domain",
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Default for CookieAuthConfig {
@@ -33,6 +165,9 @@ impl Default for CookieAuthConfig {
             cookie_domain: None,
             cookie_path: "/".to_string(),
             same_site: Some(cookie::SameSite::Strict),
+            signing_keys: None,
+            cookie_prefix: None,
+            expiry_warning: None,
         }
     }
 }
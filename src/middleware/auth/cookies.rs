@@ -1,8 +1,9 @@
 use super::{
-    config::CookieAuthConfig,
+    config::{CookieAuthConfig, CookiePrefix},
     error::{AuthError, AuthResult},
 };
 use crate::handler::Request;
+use crate::handler::response::CookieSigner;
 use cookie::{Cookie, CookieJar};
 use hyper::header::COOKIE;
 
@@ -11,20 +12,45 @@ use hyper::header::COOKIE;
 pub struct CookieHandler;
 
 impl CookieHandler {
+    #[allow(dead_code)]
     /// Extracts a specific cookie value from the request.
-    pub fn get_cookie_value(req: &Request, cookie_name: &str) -> AuthResult<Option<String>> {
+    pub fn get_cookie_value<B>(req: &Request<B>, cookie_name: &str) -> AuthResult<Option<String>> {
         let jar = Self::build_jar(req)?;
         Ok(jar.get(cookie_name).map(|c| c.value().to_string()))
     }
 
+    /// Extracts a cookie value, verifying its signature when the config has
+    /// [`signing_keys`](CookieAuthConfig::signing_keys).
+    ///
+    /// A cookie with a missing or invalid signature is rejected with
+    /// [`AuthError::InvalidToken`].
+    pub fn get_verified_cookie_value<B>(
+        req: &Request<B>,
+        cookie_name: &str,
+        config: &CookieAuthConfig,
+    ) -> AuthResult<Option<String>> {
+        let jar = Self::build_jar(req)?;
+        let Some(cookie) = jar.get(cookie_name) else {
+            return Ok(None);
+        };
+
+        match &config.signing_keys {
+            Some(keys) => keys
+                .verify(cookie.clone())
+                .map(|verified| Some(verified.value().to_string()))
+                .ok_or(AuthError::InvalidToken),
+            None => Ok(Some(cookie.value().to_string())),
+        }
+    }
+
     #[allow(dead_code)]
     /// Gets all cookies from the request as a `CookieJar`.
-    pub fn get_all_cookies(req: &Request) -> AuthResult<CookieJar> {
+    pub fn get_all_cookies<B>(req: &Request<B>) -> AuthResult<CookieJar> {
         Self::build_jar(req)
     }
 
     /// Parses the `Cookie` header into a `CookieJar`.
-    fn build_jar(req: &Request) -> AuthResult<CookieJar> {
+    fn build_jar<B>(req: &Request<B>) -> AuthResult<CookieJar> {
         let mut jar = CookieJar::new();
 
         for cookie_header in req.headers().get_all(COOKIE) {
@@ -47,21 +73,19 @@ impl CookieHandler {
     }
 
     #[allow(dead_code)]
-    /// Creates a new session cookie with the given config.
+    /// Creates a new session cookie with the given config, signed with the
+    /// primary key if signing keys are configured.
+    ///
+    /// With a [`cookie_prefix`](CookieAuthConfig::cookie_prefix), the name is
+    /// prefixed and the attributes the prefix mandates are enforced: `Secure`,
+    /// plus `Path=/` and no `Domain` for `__Host-`.
     pub fn create_session_cookie(
         name: &str,
         value: &str,
         config: &CookieAuthConfig,
         max_age: Option<std::time::Duration>,
     ) -> Cookie<'static> {
-        let mut cookie = Cookie::build((name.to_owned(), value.to_owned()))
-            .path(config.cookie_path.clone())
-            .secure(config.secure_cookies)
-            .http_only(true);
-
-        if let Some(domain) = &config.cookie_domain {
-            cookie = cookie.domain(domain.clone());
-        }
+        let mut cookie = Self::base_cookie(name, value, config).http_only(true);
 
         if let Some(same_site) = config.same_site {
             cookie = cookie.same_site(same_site);
@@ -71,17 +95,60 @@ impl CookieHandler {
             cookie = cookie.max_age(cookie::time::Duration::seconds(max_age.as_secs() as i64));
         }
 
-        cookie.build()
+        match &config.signing_keys {
+            Some(keys) => keys.sign(cookie.build()),
+            None => cookie.build(),
+        }
     }
 
     #[allow(dead_code)]
     /// Creates a cookie that clears the session (logout).
     pub fn create_logout_cookie(name: &str, config: &CookieAuthConfig) -> Cookie<'static> {
-        Cookie::build((name.to_owned(), ""))
-            .path(config.cookie_path.clone())
-            .secure(config.secure_cookies)
+        Self::base_cookie(name, "", config)
             .http_only(true)
             .max_age(cookie::time::Duration::seconds(0))
             .build()
     }
+
+    /// Starts a cookie with the config's name prefix, path, domain and
+    /// `Secure` flag, overridden where the prefix requires it.
+    fn base_cookie(
+        name: &str,
+        value: &str,
+        config: &CookieAuthConfig,
+    ) -> cookie::CookieBuilder<'static> {
+        let (name, path, domain, secure) = match config.cookie_prefix {
+            Some(CookiePrefix::Host) => (CookiePrefix::Host.apply(name), "/", None, true),
+            Some(prefix) => (
+                prefix.apply(name),
+                config.cookie_path.as_str(),
+                config.cookie_domain.as_ref(),
+                true,
+            ),
+            None => (
+                name.into(),
+                config.cookie_path.as_str(),
+                config.cookie_domain.as_ref(),
+                config.secure_cookies,
+            ),
+        };
+
+        let mut cookie = Cookie::build((name.into_owned(), value.to_owned()))
+            .path(path.to_owned())
+            .secure(secure);
+        if let Some(domain) = domain {
+            cookie = cookie.domain(domain.clone());
+        }
+        cookie
+    }
+}
+
+impl CookieSigner for CookieAuthConfig {
+    fn session_cookie(&self, name: &str, value: &str) -> Cookie<'static> {
+        CookieHandler::create_session_cookie(name, value, self, None)
+    }
+
+    fn signs(&self) -> bool {
+        self.signing_keys.is_some()
+    }
 }
@@ -21,6 +21,9 @@ pub enum AuthError {
     /// The cookie could not be parsed.
     #[error("Failed to parse cookie")]
     CookieParseError,
+    /// The cookie settings break the rules of the configured cookie prefix.
+    #[error("Invalid cookie configuration: {0}")]
+    InvalidCookieConfig(&'static str),
 }
 
 /// Result type for authentication operations
@@ -1,4 +1,7 @@
 use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use std::time::{Duration, SystemTime};
 
 use super::{
     error::{AuthError, AuthResult},
@@ -59,7 +62,8 @@ impl TokenValidator for JwtTokenValidator {
         // 4. Extract user claims (id, role, etc.)
 
         // For now, simulate validation based on token format
-        if token.starts_with("expired.") {
+        let expires_at = expiry(token);
+        if token.starts_with("expired.") || expires_at.is_some_and(|exp| exp <= SystemTime::now()) {
             return Err(AuthError::TokenExpired);
         }
 
@@ -74,6 +78,7 @@ impl TokenValidator for JwtTokenValidator {
         Ok(AuthenticatedUser {
             token: token.to_string(),
             level: auth_level,
+            expires_at,
         })
     }
 
@@ -89,3 +94,11 @@ impl TokenValidator for JwtTokenValidator {
         Ok(None)
     }
 }
+
+/// The `exp` claim of `token`'s payload, read without verifying the token.
+fn expiry(token: &str) -> Option<SystemTime> {
+    let payload = URL_SAFE_NO_PAD.decode(token.split('.').nth(1)?).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    let exp = claims.get("exp")?.as_u64()?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(exp))
+}
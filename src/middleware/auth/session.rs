@@ -1,17 +1,72 @@
 use super::{
     error::{AuthError, AuthResult},
-    user::AuthenticatedUser,
+    user::{AuthLevel, AuthenticatedUser},
     validator::TokenValidator,
 };
 use async_trait::async_trait;
 use dashmap::DashMap;
-use std::sync::Arc;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often a capped store drops its expired sessions, when it is full.
+const EXPIRED_PURGE_INTERVAL: Duration = Duration::from_secs(60);
 
 /// Session-based token validator with async support
 #[derive(Debug, Clone)]
 pub struct SessionTokenValidator {
     // In production, this would be a database connection or Redis client
     sessions: Arc<DashMap<String, SessionData>>,
+    max_sessions: Option<usize>,
+    recency: Arc<Mutex<Recency>>,
+    evicted: Arc<Mutex<Evicted>>,
+}
+
+/// The sessions of a capped store by last access, least recent first, so
+/// the one to evict is found without scanning the store.
+#[derive(Debug)]
+struct Recency {
+    /// Tokens by the access tick of their session.
+    order: BTreeMap<u64, String>,
+    next_tick: u64,
+    last_purge: Instant,
+}
+
+impl Recency {
+    /// Indexes `token` as the most recently accessed session, returning its tick.
+    fn touch(&mut self, token: &str) -> u64 {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.order.insert(tick, token.to_owned());
+        tick
+    }
+}
+
+/// Hashes of the tokens evicted by the session cap, oldest first, so they
+/// validate as expired rather than unknown. Bounded by the cap.
+#[derive(Debug, Default)]
+struct Evicted {
+    order: VecDeque<String>,
+    hashes: HashSet<String>,
+}
+
+/// A snapshot of one stored session, as returned by
+/// [`SessionTokenValidator::list_sessions`].
+///
+/// The raw token is never exposed; only its SHA-256 digest, which is enough to
+/// tell sessions apart or match one against a token you already hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// Lowercase hex SHA-256 digest of the session token.
+    pub token_hash: String,
+    /// The authorization level of the session's user.
+    pub level: AuthLevel,
+    /// When the session expires.
+    pub expires_at: std::time::Instant,
+    /// When the session was last refreshed.
+    pub last_accessed: std::time::Instant,
 }
 
 #[derive(Debug, Clone)]
@@ -19,6 +74,8 @@ struct SessionData {
     user: AuthenticatedUser,
     expires_at: std::time::Instant,
     last_accessed: std::time::Instant,
+    /// The session's key in [`Recency::order`]; unused without a cap.
+    tick: u64,
 }
 
 impl SessionTokenValidator {
@@ -26,9 +83,29 @@ impl SessionTokenValidator {
     pub fn new() -> Self {
         Self {
             sessions: Arc::new(DashMap::new()),
+            max_sessions: None,
+            recency: Arc::new(Mutex::new(Recency {
+                order: BTreeMap::new(),
+                next_tick: 0,
+                last_purge: Instant::now(),
+            })),
+            evicted: Arc::default(),
         }
     }
 
+    /// Caps the store at `max` sessions, bounding its memory under a flood of
+    /// logins. Adding a session beyond the cap evicts the least recently
+    /// accessed one, after dropping the expired sessions if that wasn't done
+    /// in the last minute; an evicted token then fails validation with
+    /// [`AuthError::TokenExpired`].
+    ///
+    /// With a cap, validating a session counts as accessing it, as does
+    /// [`update_last_accessed`](Self::update_last_accessed).
+    pub fn max_sessions(mut self, max: usize) -> Self {
+        self.max_sessions = Some(max.max(1));
+        self
+    }
+
     /// Adds a valid session to the in-memory cache.
     pub async fn add_session(
         &self,
@@ -37,37 +114,161 @@ impl SessionTokenValidator {
         ttl: std::time::Duration,
     ) {
         let now = std::time::Instant::now();
-        let session_data = SessionData {
+        let mut session_data = SessionData {
             user,
             expires_at: now + ttl,
             last_accessed: now,
+            tick: 0,
+        };
+
+        let Some(max) = self.max_sessions else {
+            self.sessions.insert(token, session_data);
+            return;
         };
+        self.forget_eviction(&token);
+        let mut recency = self.recency.lock().unwrap_or_else(|e| e.into_inner());
+        session_data.tick = recency.touch(&token);
+        if let Some(replaced) = self.sessions.insert(token, session_data) {
+            recency.order.remove(&replaced.tick);
+        }
+        self.enforce_cap(&mut recency, max);
+    }
+
+    /// Evicts the least recently accessed sessions until at most `max` are left.
+    fn enforce_cap(&self, recency: &mut Recency, max: usize) {
+        if self.sessions.len() <= max {
+            return;
+        }
+        if recency.last_purge.elapsed() >= EXPIRED_PURGE_INTERVAL {
+            self.purge_expired(recency);
+        }
+        while self.sessions.len() > max {
+            let Some((_, token)) = recency.order.pop_first() else {
+                break;
+            };
+            if self.sessions.remove(&token).is_some() {
+                self.record_eviction(&token, max);
+            }
+        }
+    }
 
-        self.sessions.insert(token, session_data);
+    /// Drops the expired sessions of a capped store, and their index entries.
+    fn purge_expired(&self, recency: &mut Recency) {
+        let now = Instant::now();
+        self.sessions.retain(|_, session| {
+            let live = session.expires_at > now;
+            if !live {
+                recency.order.remove(&session.tick);
+            }
+            live
+        });
+        recency.last_purge = now;
+    }
+
+    fn record_eviction(&self, token: &str, max: usize) {
+        let mut evicted = self.evicted.lock().unwrap_or_else(|e| e.into_inner());
+        let hash = hash_token(token);
+        if evicted.hashes.insert(hash.clone()) {
+            evicted.order.push_back(hash);
+        }
+        while evicted.order.len() > max {
+            if let Some(oldest) = evicted.order.pop_front() {
+                evicted.hashes.remove(&oldest);
+            }
+        }
+    }
+
+    /// Whether `token` was evicted by the session cap.
+    fn was_evicted(&self, token: &str) -> bool {
+        self.max_sessions.is_some()
+            && self
+                .evicted
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .hashes
+                .contains(&hash_token(token))
+    }
+
+    /// Drops `token` from the evicted set, once it is issued again or removed.
+    fn forget_eviction(&self, token: &str) {
+        if self.max_sessions.is_none() {
+            return;
+        }
+        let mut evicted = self.evicted.lock().unwrap_or_else(|e| e.into_inner());
+        let hash = hash_token(token);
+        if evicted.hashes.remove(&hash) {
+            evicted.order.retain(|h| *h != hash);
+        }
     }
 
     /// Deletes a session by its token.
     pub async fn remove_session(&self, token: &str) {
-        self.sessions.remove(token);
+        if self.max_sessions.is_none() {
+            self.sessions.remove(token);
+            return;
+        }
+        self.forget_eviction(token);
+        let mut recency = self.recency.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, session)) = self.sessions.remove(token) {
+            recency.order.remove(&session.tick);
+        }
     }
 
     /// Clears expired entries out of the session store.
     pub async fn cleanup_expired_sessions(&self) {
+        if self.max_sessions.is_some() {
+            let mut recency = self.recency.lock().unwrap_or_else(|e| e.into_inner());
+            self.purge_expired(&mut recency);
+            return;
+        }
         let now = std::time::Instant::now();
         self.sessions.retain(|_, session| session.expires_at > now);
     }
 
     /// Refreshes the last-accessed timestamp for a session.
     pub async fn update_last_accessed(&self, token: &str) -> AuthResult<()> {
-        if let Some(mut session) = self.sessions.get_mut(token) {
-            session.last_accessed = std::time::Instant::now();
-            Ok(())
-        } else {
-            Err(AuthError::UserNotFound)
+        // Locked first, like every change to a capped store.
+        let mut recency = self
+            .max_sessions
+            .map(|_| self.recency.lock().unwrap_or_else(|e| e.into_inner()));
+        let Some(mut session) = self.sessions.get_mut(token) else {
+            return Err(AuthError::UserNotFound);
+        };
+        session.last_accessed = std::time::Instant::now();
+        if let Some(recency) = &mut recency {
+            recency.order.remove(&session.tick);
+            session.tick = recency.touch(token);
         }
+        Ok(())
+    }
+
+    /// Lists every stored session, expired ones included, for debugging.
+    ///
+    /// Tokens are reported hashed, but the listing still reveals who is logged
+    /// in and at which level: only expose it behind an admin-guarded route.
+    pub async fn list_sessions(&self) -> Vec<SessionInfo> {
+        self.sessions
+            .iter()
+            .map(|entry| SessionInfo {
+                token_hash: hash_token(entry.key()),
+                level: entry.user.level.clone(),
+                expires_at: entry.expires_at,
+                last_accessed: entry.last_accessed,
+            })
+            .collect()
     }
 }
 
+/// Lowercase hex SHA-256 digest of `token`.
+pub(crate) fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
 impl Default for SessionTokenValidator {
     fn default() -> Self {
         Self::new()
@@ -90,13 +291,23 @@ impl TokenValidator for SessionTokenValidator {
                 if s.expires_at <= now {
                     Err(AuthError::TokenExpired)
                 } else {
-                    Ok(s.user.clone())
+                    let left = s.expires_at - now;
+                    Ok(AuthenticatedUser {
+                        expires_at: Some(std::time::SystemTime::now() + left),
+                        ..s.user.clone()
+                    })
                 }
             })
         }; // read-lock dropped here
 
         match user_opt {
-            Some(result) => result,
+            Some(result) => {
+                if result.is_ok() && self.max_sessions.is_some() {
+                    let _ = self.update_last_accessed(token).await;
+                }
+                result
+            }
+            None if self.was_evicted(token) => Err(AuthError::TokenExpired),
             None => Err(AuthError::UserNotFound),
         }
     }
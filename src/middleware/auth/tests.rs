@@ -1 +1,542 @@
+#![cfg(test)]
 
+use super::caching::CachingTokenValidator;
+use super::config::{CookieAuthConfig, CookiePrefix, CookieSigningKeys};
+use super::cookies::CookieHandler;
+use super::error::{AuthError, AuthResult};
+use super::session::{SessionTokenValidator, hash_token};
+use super::user::{AuthLevel, AuthenticatedUser};
+use super::validator::TokenValidator;
+use async_trait::async_trait;
+use cookie::{Cookie, Key};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+fn session_cookie(keys: &CookieSigningKeys) -> Cookie<'static> {
+    let config = CookieAuthConfig {
+        signing_keys: Some(keys.clone()),
+        ..CookieAuthConfig::default()
+    };
+    CookieHandler::create_session_cookie("session_token", "user-42", &config, None)
+}
+
+#[test]
+fn test_host_prefixed_cookie_has_mandated_attributes() {
+    let config = CookieAuthConfig {
+        cookie_prefix: Some(CookiePrefix::Host),
+        ..CookieAuthConfig::default()
+    };
+    config.validate().unwrap();
+    assert_eq!(config.prefixed_cookie_name(), "__Host-session_token");
+
+    let cookie = CookieHandler::create_session_cookie("session_token", "v", &config, None);
+    assert_eq!(cookie.name(), "__Host-session_token");
+    assert_eq!(cookie.secure(), Some(true));
+    assert_eq!(cookie.path(), Some("/"));
+    assert_eq!(cookie.domain(), None);
+
+    let logout = CookieHandler::create_logout_cookie("__Host-session_token", &config);
+    assert_eq!(logout.name(), "__Host-session_token");
+    assert_eq!(logout.secure(), Some(true));
+}
+
+#[test]
+fn test_cookie_prefix_rules_are_validated() {
+    let host = |config: CookieAuthConfig| CookieAuthConfig {
+        cookie_prefix: Some(CookiePrefix::Host),
+        ..config
+    };
+    let invalid = [
+        host(CookieAuthConfig {
+            cookie_domain: Some("example.com".into()),
+            ..CookieAuthConfig::default()
+        }),
+        host(CookieAuthConfig {
+            cookie_path: "/app".into(),
+            ..CookieAuthConfig::default()
+        }),
+        CookieAuthConfig {
+            cookie_prefix: Some(CookiePrefix::Secure),
+            secure_cookies: false,
+            ..CookieAuthConfig::default()
+        },
+    ];
+    for config in invalid {
+        assert!(matches!(
+            config.validate(),
+            Err(AuthError::InvalidCookieConfig(_))
+        ));
+    }
+
+    // `__Secure-` only requires `Secure`; path and domain are free.
+    let secure = CookieAuthConfig {
+        cookie_prefix: Some(CookiePrefix::Secure),
+        cookie_domain: Some("example.com".into()),
+        cookie_path: "/app".into(),
+        ..CookieAuthConfig::default()
+    };
+    secure.validate().unwrap();
+    let cookie = CookieHandler::create_session_cookie("sid", "v", &secure, None);
+    assert_eq!(cookie.name(), "__Secure-sid");
+    assert_eq!(cookie.domain(), Some("example.com"));
+}
+
+#[test]
+#[should_panic(expected = "__Host- cookies must not set a Domain")]
+fn test_builder_rejects_invalid_host_prefix() {
+    super::AuthMiddleware::builder()
+        .cookie_prefix(CookiePrefix::Host)
+        .cookie_domain("example.com")
+        .build_with_sessions(SessionTokenValidator::new());
+}
+
+#[test]
+fn test_signed_cookie_round_trip() {
+    let keys = CookieSigningKeys::new(Key::generate());
+    let cookie = session_cookie(&keys);
+
+    assert_ne!(cookie.value(), "user-42");
+    assert!(cookie.http_only().unwrap_or(false));
+    assert_eq!(keys.verify(cookie).unwrap().value(), "user-42");
+}
+
+#[test]
+fn test_tampered_cookie_is_rejected() {
+    let keys = CookieSigningKeys::new(Key::generate());
+    let mut cookie = session_cookie(&keys);
+    cookie.set_value(cookie.value().replace("user-42", "user-1"));
+
+    assert!(keys.verify(cookie).is_none());
+}
+
+#[test]
+fn test_key_rotation_keeps_old_cookies_valid() {
+    let old_key = Key::generate();
+    let new_key = Key::generate();
+
+    let mut keys = CookieSigningKeys::new(old_key.clone());
+    let old_cookie = session_cookie(&keys);
+
+    keys.rotate(new_key.clone());
+    assert_eq!(keys.primary(), &new_key);
+
+    // Cookies issued before the rotation still verify...
+    assert_eq!(keys.verify(old_cookie.clone()).unwrap().value(), "user-42");
+
+    // ...while new cookies are signed with the new primary key only.
+    let new_cookie = session_cookie(&keys);
+    assert!(
+        CookieSigningKeys::new(new_key)
+            .verify(new_cookie.clone())
+            .is_some()
+    );
+    assert!(CookieSigningKeys::new(old_key).verify(new_cookie).is_none());
+
+    // Once the old key is retired, its cookies stop verifying.
+    let retired = CookieSigningKeys::new(keys.primary().clone());
+    assert!(retired.verify(old_cookie).is_none());
+}
+
+/// Counts calls and accepts tokens starting with `ok-`.
+#[derive(Debug, Default)]
+struct CountingValidator {
+    calls: AtomicUsize,
+}
+
+#[async_trait]
+impl TokenValidator for CountingValidator {
+    async fn validate_token(&self, token: &str) -> AuthResult<AuthenticatedUser> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if token.starts_with("ok-") {
+            Ok(AuthenticatedUser {
+                token: token.to_owned(),
+                level: AuthLevel::User,
+                expires_at: None,
+            })
+        } else {
+            Err(AuthError::InvalidToken)
+        }
+    }
+
+    fn is_valid_format(&self, token: &str) -> bool {
+        !token.is_empty()
+    }
+}
+
+fn calls(validator: &CachingTokenValidator<CountingValidator>) -> usize {
+    validator.inner().calls.load(Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn test_caching_validator_reuses_success_within_ttl() {
+    let validator =
+        CachingTokenValidator::new(CountingValidator::default(), Duration::from_millis(100));
+
+    let user = validator.validate_token("ok-alice").await.unwrap();
+    assert_eq!(user.level, AuthLevel::User);
+    validator.validate_token("ok-alice").await.unwrap();
+    assert_eq!(calls(&validator), 1);
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    validator.validate_token("ok-alice").await.unwrap();
+    assert_eq!(calls(&validator), 2);
+
+    validator.invalidate("ok-alice");
+    validator.validate_token("ok-alice").await.unwrap();
+    assert_eq!(calls(&validator), 3);
+}
+
+#[tokio::test]
+async fn test_caching_validator_caches_failures_briefly() {
+    let validator =
+        CachingTokenValidator::new(CountingValidator::default(), Duration::from_secs(60))
+            .failure_ttl(Duration::from_millis(50));
+
+    assert_eq!(
+        validator.validate_token("bad").await.unwrap_err(),
+        AuthError::InvalidToken
+    );
+    validator.validate_token("bad").await.unwrap_err();
+    assert_eq!(calls(&validator), 1);
+
+    tokio::time::sleep(Duration::from_millis(80)).await;
+    validator.validate_token("bad").await.unwrap_err();
+    assert_eq!(calls(&validator), 2);
+
+    // Failure caching can't be configured beyond the hard cap, and can be disabled.
+    let validator =
+        CachingTokenValidator::new(CountingValidator::default(), Duration::from_secs(60))
+            .failure_ttl(Duration::ZERO);
+    validator.validate_token("bad").await.unwrap_err();
+    validator.validate_token("bad").await.unwrap_err();
+    assert_eq!(calls(&validator), 2);
+    assert!(format!("{validator:?}").contains("failure_ttl: 0ns"));
+
+    let capped = CachingTokenValidator::new(CountingValidator::default(), Duration::from_secs(60))
+        .failure_ttl(Duration::from_secs(3600));
+    assert!(format!("{capped:?}").contains("failure_ttl: 5s"));
+}
+
+#[tokio::test]
+async fn test_caching_validator_respects_token_expiry() {
+    let sessions = SessionTokenValidator::new();
+    let token = "session-token-0123456789";
+    sessions
+        .add_session(
+            token.to_owned(),
+            AuthenticatedUser {
+                token: token.to_owned(),
+                level: AuthLevel::Admin,
+                expires_at: None,
+            },
+            Duration::from_millis(50),
+        )
+        .await;
+
+    let validator = CachingTokenValidator::new(sessions, Duration::from_secs(60));
+    let user = validator.validate_token(token).await.unwrap();
+    assert!(user.expires_at.is_some());
+
+    // The cache TTL is a minute, but the session itself expires first.
+    tokio::time::sleep(Duration::from_millis(80)).await;
+    assert_eq!(
+        validator.validate_token(token).await.unwrap_err(),
+        AuthError::TokenExpired
+    );
+}
+
+#[tokio::test]
+async fn test_list_sessions_hashes_tokens() {
+    let sessions = SessionTokenValidator::new();
+    let token = "session-token-0123456789";
+    sessions
+        .add_session(
+            token.to_owned(),
+            AuthenticatedUser {
+                token: token.to_owned(),
+                level: AuthLevel::User,
+                expires_at: None,
+            },
+            Duration::from_secs(60),
+        )
+        .await;
+
+    let listed = sessions.list_sessions().await;
+    assert_eq!(listed.len(), 1);
+    let info = &listed[0];
+    assert_eq!(info.level, AuthLevel::User);
+    assert!(info.expires_at > info.last_accessed);
+    assert_eq!(info.token_hash, hash_token(token));
+    assert_eq!(info.token_hash.len(), 64);
+    assert!(!format!("{listed:?}").contains(token));
+
+    // Known SHA-256 vector, so the digest is a real hash and not an encoding.
+    assert_eq!(
+        hash_token("abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
+
+#[tokio::test]
+async fn test_session_cap_evicts_least_recently_accessed() {
+    let sessions = SessionTokenValidator::new().max_sessions(2);
+    let add = |token: &'static str| {
+        let sessions = sessions.clone();
+        async move {
+            let user = AuthenticatedUser {
+                token: token.to_owned(),
+                level: AuthLevel::User,
+                expires_at: None,
+            };
+            sessions
+                .add_session(token.to_owned(), user, Duration::from_secs(60))
+                .await;
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+    };
+    add("session-token-aaaaaaaaaa").await;
+    add("session-token-bbbbbbbbbb").await;
+    // Validating `a` makes `b` the least recently accessed.
+    sessions
+        .validate_token("session-token-aaaaaaaaaa")
+        .await
+        .unwrap();
+    add("session-token-cccccccccc").await;
+
+    assert_eq!(sessions.list_sessions().await.len(), 2);
+    assert_eq!(
+        sessions
+            .validate_token("session-token-bbbbbbbbbb")
+            .await
+            .unwrap_err(),
+        AuthError::TokenExpired
+    );
+    for token in ["session-token-aaaaaaaaaa", "session-token-cccccccccc"] {
+        assert!(sessions.validate_token(token).await.is_ok(), "{token}");
+    }
+    assert_eq!(
+        sessions
+            .validate_token("session-token-unknown000")
+            .await
+            .unwrap_err(),
+        AuthError::UserNotFound
+    );
+
+    // Once re-issued and then logged out, an evicted token is simply unknown.
+    add("session-token-bbbbbbbbbb").await;
+    sessions.remove_session("session-token-bbbbbbbbbb").await;
+    assert_eq!(
+        sessions
+            .validate_token("session-token-bbbbbbbbbb")
+            .await
+            .unwrap_err(),
+        AuthError::UserNotFound
+    );
+}
+
+#[tokio::test]
+async fn test_session_cap_orders_reissued_tokens_by_their_last_login() {
+    let sessions = SessionTokenValidator::new().max_sessions(2);
+    let add = |token: &'static str| {
+        let sessions = sessions.clone();
+        async move {
+            let user = AuthenticatedUser {
+                token: token.to_owned(),
+                level: AuthLevel::User,
+                expires_at: None,
+            };
+            sessions
+                .add_session(token.to_owned(), user, Duration::from_secs(60))
+                .await;
+        }
+    };
+    add("session-token-aaaaaaaaaa").await;
+    add("session-token-bbbbbbbbbb").await;
+    // Logging in again makes `a` the most recent session.
+    add("session-token-aaaaaaaaaa").await;
+    add("session-token-cccccccccc").await;
+
+    assert_eq!(
+        sessions
+            .validate_token("session-token-bbbbbbbbbb")
+            .await
+            .unwrap_err(),
+        AuthError::TokenExpired
+    );
+    for token in ["session-token-aaaaaaaaaa", "session-token-cccccccccc"] {
+        assert!(sessions.validate_token(token).await.is_ok(), "{token}");
+    }
+
+    // A removed session leaves nothing behind to evict.
+    sessions.remove_session("session-token-aaaaaaaaaa").await;
+    add("session-token-dddddddddd").await;
+    for token in ["session-token-cccccccccc", "session-token-dddddddddd"] {
+        assert!(sessions.validate_token(token).await.is_ok(), "{token}");
+    }
+}
+
+#[tokio::test]
+async fn test_signed_cookie_round_trip_through_middleware() {
+    use crate::handler::{ExpressResponse, Request, Response};
+    use crate::middleware::Middleware;
+    use hyper::header::{COOKIE, LOCATION, SET_COOKIE};
+
+    let token = "session-token-0123456789";
+    let sessions = SessionTokenValidator::new();
+    sessions
+        .add_session(
+            token.to_owned(),
+            AuthenticatedUser {
+                token: token.to_owned(),
+                level: AuthLevel::User,
+                expires_at: None,
+            },
+            Duration::from_secs(60),
+        )
+        .await;
+    let mw = super::AuthMiddleware::builder()
+        .signing_keys(CookieSigningKeys::new(Key::generate()))
+        .protect_route("/account", AuthLevel::User)
+        .build_with_sessions(sessions);
+
+    let res = Response::new().signed_cookie("session_token", token, mw.config());
+    let set_cookie = res.headers[SET_COOKIE].to_str().unwrap();
+    let sent = Cookie::parse(set_cookie.to_owned()).unwrap();
+    assert_eq!(sent.name(), "session_token");
+    assert_ne!(sent.value(), token);
+
+    let call = |value: String| {
+        let mw = &mw;
+        async move {
+            let mut req = Request::builder()
+                .uri("/account")
+                .header(COOKIE, format!("session_token={value}"))
+                .body(())
+                .unwrap();
+            let mut res = Response::new();
+            let result = mw.call(&mut req, &mut res).await;
+            (result.is_next(), req, res)
+        }
+    };
+
+    let (passed, req, _) = call(sent.value().to_owned()).await;
+    assert!(passed);
+    assert_eq!(
+        req.extensions().get::<AuthenticatedUser>().unwrap().token,
+        token
+    );
+
+    // A swapped token, with or without the original signature, is rejected.
+    let signature = &sent.value()[..sent.value().len() - token.len()];
+    for value in [
+        format!("{signature}session-token-9876543210"),
+        token.to_owned(),
+    ] {
+        let (passed, _, res) = call(value).await;
+        assert!(!passed);
+        assert_eq!(res.headers[LOCATION], "/login");
+    }
+}
+
+#[tokio::test]
+async fn test_validators_populate_token_expiry() {
+    use super::jwt::JwtTokenValidator;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let jwt = |exp: u64| {
+        let payload = URL_SAFE_NO_PAD.encode(format!(r#"{{"sub":"user-1","exp":{exp}}}"#));
+        format!("eyJhbGciOiJIUzI1NiJ9.{payload}.signature")
+    };
+    let validator = JwtTokenValidator::new("secret");
+    let exp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 600;
+    let user = validator.validate_token(&jwt(exp)).await.unwrap();
+    assert_eq!(user.expires_at, Some(UNIX_EPOCH + Duration::from_secs(exp)));
+    assert_eq!(
+        validator
+            .validate_token(&jwt(exp - 1200))
+            .await
+            .unwrap_err(),
+        AuthError::TokenExpired
+    );
+    let opaque = validator.validate_token("a.b.c").await.unwrap();
+    assert_eq!(opaque.expires_at, None);
+
+    let sessions = SessionTokenValidator::new();
+    let token = "session-token-0123456789";
+    sessions
+        .add_session(
+            token.to_owned(),
+            AuthenticatedUser {
+                token: token.to_owned(),
+                level: AuthLevel::User,
+                expires_at: None,
+            },
+            Duration::from_secs(60),
+        )
+        .await;
+    let expires_at = sessions.validate_token(token).await.unwrap().expires_at;
+    let left = expires_at
+        .unwrap()
+        .duration_since(SystemTime::now())
+        .unwrap();
+    assert!(left > Duration::from_secs(55) && left <= Duration::from_secs(60));
+}
+
+#[tokio::test]
+async fn test_near_expiry_is_announced() {
+    use crate::handler::request::RequestExt;
+    use crate::handler::{Request, Response};
+    use crate::middleware::Middleware;
+    use hyper::header::COOKIE;
+
+    let sessions = SessionTokenValidator::new();
+    for (token, ttl) in [
+        ("session-token-soon-expiring", 30),
+        ("session-token-long-lasting", 3600),
+    ] {
+        let user = AuthenticatedUser {
+            token: token.to_owned(),
+            level: AuthLevel::User,
+            expires_at: None,
+        };
+        sessions
+            .add_session(token.to_owned(), user, Duration::from_secs(ttl))
+            .await;
+    }
+    let mw = super::AuthMiddleware::builder()
+        .protect_route("/account", AuthLevel::User)
+        .expiry_warning(Duration::from_secs(300))
+        .build_with_sessions(sessions);
+
+    let call = |token: &'static str| {
+        let mw = &mw;
+        async move {
+            let mut req = Request::builder()
+                .uri("/account")
+                .header(COOKIE, format!("session_token={token}"))
+                .body(())
+                .unwrap();
+            let mut res = Response::new();
+            assert!(mw.call(&mut req, &mut res).await.is_next());
+            Middleware::<()>::finish(mw, &mut res);
+            (req, res)
+        }
+    };
+
+    let (req, res) = call("session-token-soon-expiring").await;
+    let expires_at = req.auth_expires_at().unwrap();
+    assert_eq!(
+        res.headers["x-auth-expires"],
+        httpdate::fmt_http_date(expires_at).as_str()
+    );
+
+    let (req, res) = call("session-token-long-lasting").await;
+    assert!(req.auth_expires_at().is_some());
+    assert!(!res.headers.contains_key("x-auth-expires"));
+}
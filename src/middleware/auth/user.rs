@@ -16,4 +16,7 @@ pub struct AuthenticatedUser {
     pub token: String,
     /// The user's authorization level.
     pub level: AuthLevel,
+    /// When the token stops being valid, if the validator knows: a JWT's
+    /// `exp` claim or a session's expiry.
+    pub expires_at: Option<std::time::SystemTime>,
 }
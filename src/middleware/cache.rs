@@ -1,7 +1,8 @@
-use crate::handler::{ExpressResponse, Request, Response};
+use crate::handler::response::try_insert_header;
+use crate::handler::{Request, Response};
 use crate::middleware::{Middleware, MiddlewareResult, next_res};
 use async_trait::async_trait;
-use hyper::header::{CACHE_CONTROL, HeaderValue};
+use hyper::header::CACHE_CONTROL;
 
 /// Middleware to set Cache-Control headers on responses.
 #[derive(Debug, Clone)]
@@ -36,9 +37,7 @@ impl CacheMiddleware {
 #[async_trait]
 impl<B: Send + Sync + 'static> Middleware<B> for CacheMiddleware {
     async fn call(&self, _req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
-        if let Ok(val) = HeaderValue::from_str(&self.value) {
-            res.header(CACHE_CONTROL, val);
-        }
+        try_insert_header(&mut res.headers, CACHE_CONTROL, &self.value);
         next_res()
     }
 }
@@ -0,0 +1,531 @@
+mod codec;
+
+use crate::handler::request::{RequestExt, quality_values};
+use crate::handler::response::{ResponseBody, try_insert_header, vary_by_accept_encoding};
+use crate::handler::{Request, Response, ResponseError};
+use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
+use async_trait::async_trait;
+use bytes::Bytes;
+use codec::EncodeStream;
+use hyper::header::{
+    ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, HeaderMap, HeaderValue,
+};
+use hyper::{Method, StatusCode};
+use log::error;
+use serde_json::json;
+use std::sync::Arc;
+
+/// A content coding that [`CompressionMiddleware`] can apply and remove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// `br`, Brotli.
+    Brotli,
+    /// `gzip`.
+    Gzip,
+    /// `deflate`: zlib-wrapped DEFLATE, as HTTP defines it.
+    Deflate,
+}
+
+impl Encoding {
+    /// The content-coding token, as used in `Content-Encoding`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Parses a content-coding token, case-insensitively.
+    pub fn parse(token: &str) -> Option<Self> {
+        let token = token.trim();
+        [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate]
+            .into_iter()
+            .find(|e| token.eq_ignore_ascii_case(e.as_str()))
+            .or_else(|| {
+                token
+                    .eq_ignore_ascii_case("x-gzip")
+                    .then_some(Encoding::Gzip)
+            })
+    }
+}
+
+/// Content types worth compressing: text, and the structured text formats
+/// commonly served by APIs and web apps.
+const DEFAULT_ALLOWED: [&str; 8] = [
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "image/svg+xml",
+    "+json",
+    "+xml",
+];
+
+/// Content types that are already compressed, so compressing them again
+/// only costs CPU. Streams of server-sent events are excluded too.
+const DEFAULT_EXCLUDED: [&str; 13] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "video/",
+    "audio/",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "text/event-stream",
+];
+
+/// Middleware that compresses responses and decompresses request bodies.
+///
+/// **Responses** are compressed with the best coding the client lists in
+/// `Accept-Encoding`, ties going to the order given to
+/// [`algorithms`](Self::algorithms). Only content types on the allowlist
+/// (text, JSON, JavaScript, XML, SVG and WebAssembly by default; see
+/// [`allow_content_type`](Self::allow_content_type)) are compressed, so no
+/// CPU is spent on images, archives, other binary formats or responses
+/// without a `Content-Type`. Bodies shorter than
+/// [`min_size`](Self::min_size), excluded content types, partial (`206`)
+/// responses, `Cache-Control: no-transform` and bodies that already have a
+/// `Content-Encoding` are left alone. Streaming bodies are compressed chunk
+/// by chunk. `Vary: Accept-Encoding` is added to every response of a
+/// compressible type, even one too small to compress, so caches keep the
+/// encodings apart; strong `ETag`s are weakened when the body is compressed.
+///
+/// **Requests** with a `Content-Encoding` in [`algorithms`](Self::algorithms)
+/// are decoded transparently by [`RequestExt::bytes`](crate::prelude::RequestExt::bytes),
+/// [`RequestExt::json`](crate::prelude::RequestExt::json) and the `Json`
+/// extractor, up to [`max_decompressed_size`](Self::max_decompressed_size).
+/// Other codings are answered with `415 Unsupported Media Type`.
+///
+/// ```rust,no_run
+/// use expressjs::prelude::*;
+///
+/// # async fn run() {
+/// let mut app = express();
+/// app.use_global(
+///     CompressionMiddleware::new()
+///         .algorithms([Encoding::Brotli, Encoding::Gzip])
+///         .min_size(512),
+/// );
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompressionMiddleware {
+    algorithms: Arc<[Encoding]>,
+    min_size: usize,
+    allowed: Arc<[Arc<str>]>,
+    excluded: Arc<[Arc<str>]>,
+    max_decompressed_size: usize,
+}
+
+/// Request extension telling the body readers to decode the body.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BodyDecoding {
+    encoding: Encoding,
+    max_size: usize,
+}
+
+impl BodyDecoding {
+    /// Decodes a request body, allowing at most `limit` bytes of output
+    /// (further capped by the middleware's maximum).
+    pub(crate) fn decode(&self, body: &[u8], limit: usize) -> Result<Bytes, ResponseError> {
+        codec::decode(self.encoding, body, limit.min(self.max_size))
+    }
+}
+
+/// Per-request state carried from `call` to `finish` in the response extensions:
+/// the coding negotiated for the response, if any.
+#[derive(Debug, Clone, Copy)]
+struct Negotiated(Option<Encoding>);
+
+impl Default for CompressionMiddleware {
+    fn default() -> Self {
+        Self {
+            algorithms: [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate].into(),
+            min_size: 1024,
+            allowed: DEFAULT_ALLOWED.iter().map(|&t| t.into()).collect(),
+            excluded: DEFAULT_EXCLUDED.iter().map(|&t| t.into()).collect(),
+            max_decompressed_size: 10 * 1024 * 1024, // 10 MB
+        }
+    }
+}
+
+impl CompressionMiddleware {
+    /// Creates a middleware with Brotli, gzip and deflate enabled, a 1 KB
+    /// minimum size and a 10 MB decompression limit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the codings used in both directions, most preferred first.
+    pub fn algorithms(mut self, algorithms: impl IntoIterator<Item = Encoding>) -> Self {
+        let mut list: Vec<Encoding> = Vec::new();
+        for encoding in algorithms {
+            if !list.contains(&encoding) {
+                list.push(encoding);
+            }
+        }
+        self.algorithms = list.into();
+        self
+    }
+
+    /// Sets the smallest response body, in bytes, worth compressing.
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Also compresses responses of this content type. A value ending in
+    /// `/` (such as `text/`) allows a whole top-level type, and one starting
+    /// with `+` (such as `+json`) every type with that structured suffix.
+    pub fn allow_content_type(mut self, content_type: impl AsRef<str>) -> Self {
+        let mut allowed = self.allowed.to_vec();
+        allowed.push(content_type.as_ref().to_ascii_lowercase().into());
+        self.allowed = allowed.into();
+        self
+    }
+
+    /// Replaces the allowlist of content types to compress, written as for
+    /// [`allow_content_type`](Self::allow_content_type).
+    pub fn content_types<I>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.allowed = content_types
+            .into_iter()
+            .map(|t| t.as_ref().to_ascii_lowercase().into())
+            .collect();
+        self
+    }
+
+    /// Never compresses responses of this content type, even an allowed
+    /// one. A value ending in `/` (such as `video/`) excludes a whole
+    /// top-level type.
+    pub fn exclude_content_type(mut self, content_type: impl AsRef<str>) -> Self {
+        let mut excluded = self.excluded.to_vec();
+        excluded.push(content_type.as_ref().to_ascii_lowercase().into());
+        self.excluded = excluded.into();
+        self
+    }
+
+    /// Sets the largest decoded request body accepted, in bytes. Larger
+    /// bodies fail with `413 Payload Too Large`, which stops decompression bombs.
+    pub fn max_decompressed_size(mut self, bytes: usize) -> Self {
+        self.max_decompressed_size = bytes;
+        self
+    }
+
+    /// Picks the response coding from an `Accept-Encoding` header.
+    fn negotiate(&self, accept: Option<&str>) -> Option<Encoding> {
+        let accept = accept?;
+        let mut wildcard = None;
+        let mut listed: Vec<(&str, f32)> = Vec::new();
+        for (token, q) in quality_values(accept) {
+            if token == "*" {
+                wildcard = Some(q);
+            } else {
+                listed.push((token, q));
+            }
+        }
+
+        let quality = |encoding: Encoding| {
+            listed
+                .iter()
+                .find(|(token, _)| Encoding::parse(token) == Some(encoding))
+                .map(|&(_, q)| q)
+                .or(wildcard)
+                .unwrap_or(0.0)
+        };
+        let mut best: Option<(Encoding, f32)> = None;
+        for &encoding in self.algorithms.iter() {
+            let q = quality(encoding);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    /// Whether a response may be compressed at all, whatever the client accepts.
+    fn eligible(&self, res: &Response) -> bool {
+        let status = res.status;
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::PARTIAL_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || res.body.is_empty()
+            || res.headers.contains_key(CONTENT_RANGE)
+            || !self.compressible(res)
+        {
+            return false;
+        }
+        res.body
+            .content_length()
+            .is_none_or(|len| len >= self.min_size as u64)
+    }
+
+    /// Whether the content of `res` is of a kind this middleware compresses:
+    /// allowed and not excluded by type, not already encoded and not
+    /// `no-transform`.
+    /// Such responses vary by `Accept-Encoding` even when this one is too
+    /// small to compress, as the next one may not be.
+    fn compressible(&self, res: &Response) -> bool {
+        if res.headers.contains_key(CONTENT_ENCODING)
+            || header_str(&res.headers, CACHE_CONTROL)
+                .is_some_and(|v| v.to_ascii_lowercase().contains("no-transform"))
+        {
+            return false;
+        }
+        let Some(mime) = header_str(&res.headers, CONTENT_TYPE) else {
+            return false;
+        };
+        let mime = mime
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        self.allowed
+            .iter()
+            .any(|pattern| mime_matches(pattern, &mime))
+            && !self
+                .excluded
+                .iter()
+                .any(|pattern| mime_matches(pattern, &mime))
+    }
+
+    /// Replaces the body of `res` with its `encoding`-compressed form.
+    fn compress(res: &mut Response, encoding: Encoding) {
+        res.body = match std::mem::take(&mut res.body) {
+            ResponseBody::Stream(stream) => {
+                ResponseBody::Stream(Box::pin(EncodeStream::new(stream, encoding)))
+            }
+            body => {
+                let body = match body {
+                    ResponseBody::Lazy(produce) => ResponseBody::Full(produce()),
+                    body => body,
+                };
+                let chunks = match &body {
+                    ResponseBody::Full(bytes) => std::slice::from_ref(bytes),
+                    ResponseBody::Buffered(chunks) => chunks.as_slice(),
+                    _ => &[],
+                };
+                match codec::encode(encoding, chunks) {
+                    Ok(bytes) => ResponseBody::Full(bytes),
+                    Err(e) => {
+                        // Sending the body uncompressed is still a valid answer.
+                        error!("failed to compress response: {e}");
+                        res.body = body;
+                        return;
+                    }
+                }
+            }
+        };
+
+        res.headers.remove(CONTENT_LENGTH);
+        res.headers.insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+        // The compressed body is no longer byte-for-byte the one a strong
+        // validator was computed for.
+        if let Some(etag) = header_str(&res.headers, ETAG)
+            && etag.starts_with('"')
+        {
+            let weak = format!("W/{etag}");
+            try_insert_header(&mut res.headers, ETAG, weak);
+        }
+    }
+}
+
+/// Matches a lowercase essence such as `application/ld+json` against an
+/// exact type, a `type/` prefix or a `+suffix`.
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    if pattern.ends_with('/') {
+        mime.starts_with(pattern)
+    } else if pattern.starts_with('+') {
+        mime.ends_with(pattern)
+    } else {
+        mime == pattern
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: hyper::header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for CompressionMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        if let Some(coding) = header_str(req.headers(), CONTENT_ENCODING) {
+            let coding = coding.trim();
+            match Encoding::parse(coding).filter(|e| self.algorithms.contains(e)) {
+                Some(encoding) => {
+                    req.extensions_mut().insert(BodyDecoding {
+                        encoding,
+                        max_size: self.max_decompressed_size,
+                    });
+                    // Handlers see the decoded body, whose length is unknown.
+                    req.headers_mut().remove(CONTENT_ENCODING);
+                    req.headers_mut().remove(CONTENT_LENGTH);
+                }
+                None if coding.eq_ignore_ascii_case("identity") => {
+                    req.headers_mut().remove(CONTENT_ENCODING);
+                }
+                None => {
+                    let supported: Vec<&str> = self.algorithms.iter().map(|e| e.as_str()).collect();
+                    try_insert_header(&mut res.headers, ACCEPT_ENCODING, supported.join(", "));
+                    res.respond_enveloped_error(
+                        415,
+                        &format!("Unsupported Content-Encoding: {coding}"),
+                        json!({ "supported": supported }),
+                        req.prefers_json(),
+                    );
+                    return stop_res();
+                }
+            }
+        }
+
+        let encoding = if req.method() == Method::HEAD {
+            None
+        } else {
+            self.negotiate(header_str(req.headers(), ACCEPT_ENCODING))
+        };
+        res.extensions.insert(Negotiated(encoding));
+        next_res()
+    }
+
+    fn finish(&self, res: &mut Response) {
+        let Some(Negotiated(encoding)) = res.extensions.remove::<Negotiated>() else {
+            return;
+        };
+        // Responses without a type, e.g. `204`s, aren't compressible and
+        // have nothing to vary.
+        if self.compressible(res) {
+            vary_by_accept_encoding(&mut res.headers);
+        }
+        if let Some(encoding) = encoding
+            && self.eligible(res)
+        {
+            Self::compress(res, encoding);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::ExpressResponse;
+    use hyper::header::VARY;
+
+    #[test]
+    fn test_negotiate_accept_encoding() {
+        let mw = CompressionMiddleware::new();
+        let negotiate = |accept| mw.negotiate(Some(accept));
+
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(mw.negotiate(None), None);
+
+        let gzip_only = CompressionMiddleware::new().algorithms([Encoding::Gzip]);
+        assert_eq!(gzip_only.negotiate(Some("br")), None);
+        assert_eq!(
+            gzip_only.negotiate(Some("br, x-gzip")),
+            Some(Encoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn test_round_trip_every_encoding() {
+        let body = Bytes::from("hello ".repeat(1000));
+        for encoding in [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate] {
+            let compressed = codec::encode(encoding, std::slice::from_ref(&body)).unwrap();
+            assert!(compressed.len() < body.len(), "{encoding:?}");
+            assert_eq!(codec::decode(encoding, &compressed, 6000).unwrap(), body);
+            assert!(matches!(
+                codec::decode(encoding, &compressed, 5999),
+                Err(ResponseError::PayloadTooLarge(5999))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_vary_on_compressible_types_only() {
+        let mw = CompressionMiddleware::new();
+        let finish = |res: Response| {
+            let mut res = res;
+            res.extensions.insert(Negotiated(Some(Encoding::Gzip)));
+            Middleware::<()>::finish(&mw, &mut res);
+            res
+        };
+
+        // Too small to compress, but the next JSON body may not be.
+        let json = finish(Response::new().send_json(&[1, 2, 3]));
+        assert!(!json.headers.contains_key(CONTENT_ENCODING));
+        assert_eq!(json.headers[VARY], "Accept-Encoding");
+
+        let png = finish(
+            Response::new()
+                .content_type("image/png")
+                .body(vec![0u8; 4096]),
+        );
+        assert!(!png.headers.contains_key(CONTENT_ENCODING));
+        assert!(!png.headers.contains_key(VARY));
+
+        // An existing `Vary` is extended, not duplicated.
+        let varied = finish(
+            Response::new()
+                .header(VARY, HeaderValue::from_static("Origin, accept-encoding"))
+                .send_text("hi"),
+        );
+        assert_eq!(varied.headers.get_all(VARY).iter().count(), 1);
+        let manual = finish(Response::new().vary_by_accept_encoding().send_text("hi"));
+        assert_eq!(manual.headers.get_all(VARY).iter().count(), 1);
+    }
+
+    #[test]
+    fn test_only_allowed_content_types_are_compressed() {
+        let mw = CompressionMiddleware::new();
+        let finish = |mw: &CompressionMiddleware, content_type: Option<&str>| {
+            let mut res = Response::new().body(vec![b'a'; 4096]);
+            if let Some(content_type) = content_type {
+                res = res.content_type(content_type);
+            }
+            res.extensions.insert(Negotiated(Some(Encoding::Gzip)));
+            Middleware::<()>::finish(mw, &mut res);
+            res.headers.contains_key(CONTENT_ENCODING)
+        };
+
+        for compressed in [
+            "application/json",
+            "application/problem+json; charset=utf-8",
+            "text/html; charset=utf-8",
+            "image/svg+xml",
+        ] {
+            assert!(finish(&mw, Some(compressed)), "{compressed}");
+        }
+        for skipped in ["image/png", "image/jpeg", "application/octet-stream"] {
+            assert!(!finish(&mw, Some(skipped)), "{skipped}");
+        }
+        assert!(!finish(&mw, None));
+        // The denylist wins over the allowlist.
+        assert!(!finish(&mw, Some("text/event-stream")));
+
+        let custom = CompressionMiddleware::new()
+            .content_types(["application/json"])
+            .allow_content_type("application/x-ndjson");
+        assert!(finish(&custom, Some("application/x-ndjson")));
+        assert!(!finish(&custom, Some("text/plain")));
+    }
+}
@@ -0,0 +1,173 @@
+use super::Encoding;
+use crate::handler::ResponseError;
+use bytes::Bytes;
+use flate2::Compression;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures_util::Stream;
+use hyper::body::Frame;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+/// Brotli quality used for responses: far faster than the maximum of 11 and
+/// still smaller than gzip, which suits compressing on the fly.
+const BROTLI_QUALITY: u32 = 4;
+/// Brotli window size, as a power of two.
+const BROTLI_WINDOW: u32 = 22;
+/// Internal buffer size of the Brotli encoder and decoder.
+const BROTLI_BUFFER: usize = 4096;
+
+/// An encoder writing into an in-memory buffer, drained as output is produced.
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            Encoding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default()))
+            }
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            ))),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Gzip(w) => w,
+            Encoder::Deflate(w) => w,
+            Encoder::Brotli(w) => w.as_mut(),
+        }
+    }
+
+    /// Takes the output produced so far.
+    fn take(&mut self) -> Bytes {
+        let buffer = match self {
+            Encoder::Gzip(w) => w.get_mut(),
+            Encoder::Deflate(w) => w.get_mut(),
+            Encoder::Brotli(w) => w.get_mut(),
+        };
+        std::mem::take(buffer).into()
+    }
+
+    /// Compresses `data` and flushes, so the peer can decode everything
+    /// sent so far without waiting for the end of the stream.
+    fn write_flushed(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let writer = self.writer();
+        writer.write_all(data)?;
+        writer.flush()?;
+        Ok(self.take())
+    }
+
+    /// Ends the stream and returns the remaining output.
+    fn finish(self) -> io::Result<Bytes> {
+        Ok(match self {
+            Encoder::Gzip(w) => w.finish()?,
+            Encoder::Deflate(w) => w.finish()?,
+            Encoder::Brotli(w) => w.into_inner(),
+        }
+        .into())
+    }
+}
+
+/// Compresses a body held in memory in one go.
+pub(super) fn encode(encoding: Encoding, chunks: &[Bytes]) -> io::Result<Bytes> {
+    let mut encoder = Encoder::new(encoding);
+    for chunk in chunks {
+        encoder.writer().write_all(chunk)?;
+    }
+    encoder.finish()
+}
+
+/// Decompresses a request body, failing once the output passes `limit` bytes.
+pub(super) fn decode(
+    encoding: Encoding,
+    body: &[u8],
+    limit: usize,
+) -> Result<Bytes, ResponseError> {
+    let reader: Box<dyn Read + '_> = match encoding {
+        Encoding::Gzip => Box::new(MultiGzDecoder::new(body)),
+        Encoding::Deflate => Box::new(ZlibDecoder::new(body)),
+        Encoding::Brotli => Box::new(brotli::Decompressor::new(body, BROTLI_BUFFER)),
+    };
+
+    let mut out = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| {
+            ResponseError::BodyReadError(format!("invalid {} body: {e}", encoding.as_str()))
+        })?;
+    if out.len() > limit {
+        return Err(ResponseError::PayloadTooLarge(limit));
+    }
+    Ok(out.into())
+}
+
+/// The body stream type of [`ResponseBody::Stream`](crate::handler::response::ResponseBody::Stream).
+pub(super) type BodyStream =
+    Pin<Box<dyn Stream<Item = Result<Frame<Bytes>, io::Error>> + Send + Sync>>;
+
+/// Compresses a streaming body chunk by chunk, flushing after each one so
+/// long-lived streams keep delivering data as it is produced.
+pub(super) struct EncodeStream {
+    inner: BodyStream,
+    /// `None` once the compressed stream has been finished.
+    encoder: Option<Encoder>,
+    /// A trailers frame held back until the compressed data has ended.
+    trailers: Option<Frame<Bytes>>,
+}
+
+impl EncodeStream {
+    pub(super) fn new(inner: BodyStream, encoding: Encoding) -> Self {
+        Self {
+            inner,
+            encoder: Some(Encoder::new(encoding)),
+            trailers: None,
+        }
+    }
+}
+
+impl Stream for EncodeStream {
+    type Item = Result<Frame<Bytes>, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(this.trailers.take().map(Ok));
+            };
+
+            let frame = match ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => {
+                    let tail = this.encoder.take().unwrap().finish();
+                    return Poll::Ready(Some(tail.map(Frame::data)));
+                }
+            };
+            match frame.into_data() {
+                Ok(data) => {
+                    let out = encoder.write_flushed(&data)?;
+                    if !out.is_empty() {
+                        return Poll::Ready(Some(Ok(Frame::data(out))));
+                    }
+                }
+                Err(trailers) => {
+                    this.trailers = Some(trailers);
+                    let tail = this.encoder.take().unwrap().finish();
+                    return Poll::Ready(Some(tail.map(Frame::data)));
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use crate::handler::request::RequestExt;
+use crate::handler::response::try_insert_header;
 use crate::handler::{ExpressResponse, Request, Response};
 use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
 use async_trait::async_trait;
-use hyper::header::HeaderValue;
+use hyper::header::{ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue, ORIGIN};
 use rustc_hash::FxHashSet;
 
 /// Middleware that adds [CORS](https://developer.mozilla.org/en-US/docs/Web/HTTP/CORS) headers to responses,
@@ -29,7 +31,7 @@ pub struct CorsMiddleware {
 }
 
 impl CorsMiddleware {
-    fn new_inner(
+    pub(crate) fn new_inner(
         allowed_origins: FxHashSet<String>,
         allowed_methods: FxHashSet<String>,
         allowed_headers: FxHashSet<String>,
@@ -106,9 +108,9 @@ impl Default for CorsMiddleware {
 }
 
 #[async_trait]
-impl Middleware for CorsMiddleware {
-    async fn call(&self, req: &mut Request, res: &mut Response) -> MiddlewareResult {
-        let origin = req.headers().get("Origin").and_then(|h| h.to_str().ok());
+impl<B: Send + Sync + 'static> Middleware<B> for CorsMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        let origin = req.header(ORIGIN);
 
         let is_allowed_origin = match origin {
             Some(o) => self.allowed_origins.contains("*") || self.allowed_origins.contains(o),
@@ -117,9 +119,8 @@ impl Middleware for CorsMiddleware {
 
         if let Some(o) = origin
             && is_allowed_origin
-            && let Ok(val) = HeaderValue::from_str(o)
         {
-            res.header("Access-Control-Allow-Origin", val);
+            try_insert_header(&mut res.headers, ACCESS_CONTROL_ALLOW_ORIGIN, o);
         }
 
         if self.allow_credentials {
@@ -148,3 +149,49 @@ impl Middleware for CorsMiddleware {
         next_res()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Joins a set the way a per-request implementation would.
+    fn joined(set: &FxHashSet<String>) -> String {
+        set.iter().cloned().collect::<Vec<_>>().join(", ")
+    }
+
+    #[test]
+    fn test_precomputed_headers_match_dynamic_values() {
+        for cors in [CorsMiddleware::default(), CorsMiddleware::permissive()] {
+            assert_eq!(
+                cors.methods_header.as_ref().unwrap(),
+                joined(&cors.allowed_methods).as_str()
+            );
+            match &cors.headers_header {
+                Some(value) => assert_eq!(value, joined(&cors.allowed_headers).as_str()),
+                None => assert!(cors.allowed_headers.is_empty()),
+            }
+            assert_eq!(
+                cors.max_age_header
+                    .as_ref()
+                    .map(|v| v.to_str().unwrap().to_owned()),
+                cors.max_age.map(|age| age.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_precomputed_headers_list_every_entry() {
+        let cors = CorsMiddleware::permissive();
+        let methods: FxHashSet<String> = cors
+            .methods_header
+            .as_ref()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split(", ")
+            .map(String::from)
+            .collect();
+        assert_eq!(methods, cors.allowed_methods);
+        assert_eq!(cors.max_age_header.unwrap(), "86400");
+    }
+}
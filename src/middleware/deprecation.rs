@@ -0,0 +1,178 @@
+use crate::handler::{Request, Response};
+use crate::middleware::{Middleware, MiddlewareResult, next_res};
+use async_trait::async_trait;
+use hyper::header::{HeaderName, HeaderValue, LINK};
+use log::warn;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// The deprecation of an API: when it was deprecated, when it goes away and
+/// where its replacement is documented.
+///
+/// Responses get `Deprecation: @<unix time>` (RFC 9745), `Sunset:
+/// <HTTP-date>` (RFC 8594) and `Link: <url>; rel="deprecation"`. Attach it to
+/// a route with [`Route::deprecated`](crate::prelude::Route::deprecated), or
+/// to everything under a prefix with [`DeprecationMiddleware`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deprecation {
+    since: SystemTime,
+    sunset: Option<SystemTime>,
+    link: Option<String>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Deprecation {
+    /// A deprecation effective since `since`, without a sunset date or link.
+    pub fn new(since: SystemTime) -> Self {
+        let mut deprecation = Self {
+            since,
+            sunset: None,
+            link: None,
+            headers: Vec::new(),
+        };
+        deprecation.build_headers();
+        deprecation
+    }
+
+    /// Announces that the API stops working at `at`.
+    pub fn sunset(mut self, at: SystemTime) -> Self {
+        self.sunset = Some(at);
+        self.build_headers();
+        self
+    }
+
+    /// Points clients at documentation of the deprecation, e.g. a migration guide.
+    pub fn link(mut self, url: impl Into<String>) -> Self {
+        self.link = Some(url.into());
+        self.build_headers();
+        self
+    }
+
+    /// When the API was deprecated.
+    pub fn since(&self) -> SystemTime {
+        self.since
+    }
+
+    /// When the API stops working, if announced.
+    pub fn sunset_date(&self) -> Option<SystemTime> {
+        self.sunset
+    }
+
+    /// The documentation link, if any.
+    pub fn link_url(&self) -> Option<&str> {
+        self.link.as_deref()
+    }
+
+    fn build_headers(&mut self) {
+        let since = self
+            .since
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let mut headers = vec![(DEPRECATION, format!("@{since}"))];
+        if let Some(sunset) = self.sunset {
+            headers.push((SUNSET, httpdate::fmt_http_date(sunset)));
+        }
+        if let Some(link) = &self.link {
+            headers.push((LINK, format!("<{link}>; rel=\"deprecation\"")));
+        }
+        self.headers = headers
+            .into_iter()
+            .filter_map(|(name, value)| match HeaderValue::try_from(value) {
+                Ok(value) => Some((name, value)),
+                Err(_) => {
+                    warn!("ignoring invalid {name} value for a deprecation");
+                    None
+                }
+            })
+            .collect();
+    }
+
+    /// Adds the deprecation headers to `res`, keeping `Link`s it already has.
+    pub(crate) fn apply(&self, res: &mut Response) {
+        for (name, value) in &self.headers {
+            if *name == LINK {
+                res.headers.append(name, value.clone());
+            } else {
+                res.headers.insert(name, value.clone());
+            }
+        }
+    }
+}
+
+/// Middleware marking every response under a path prefix as deprecated, e.g.
+/// a whole `/v1` API. See [`Deprecation`].
+///
+/// ```rust
+/// use expressjs::prelude::*;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let v1 = Deprecation::new(UNIX_EPOCH + Duration::from_secs(1_767_225_600))
+///     .sunset(UNIX_EPOCH + Duration::from_secs(1_782_864_000))
+///     .link("https://example.com/docs/v2-migration");
+/// let mut app = express();
+/// app.use_global(DeprecationMiddleware::for_prefix("/v1", v1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct DeprecationMiddleware {
+    prefix: String,
+    deprecation: Arc<Deprecation>,
+}
+
+impl DeprecationMiddleware {
+    /// Deprecates the paths at or below `prefix`.
+    pub fn for_prefix(prefix: impl AsRef<str>, deprecation: Deprecation) -> Self {
+        Self {
+            prefix: prefix.as_ref().trim_end_matches('/').to_owned(),
+            deprecation: Arc::new(deprecation),
+        }
+    }
+
+    fn covers(&self, path: &str) -> bool {
+        path.strip_prefix(self.prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// The deprecation applying to a response, kept for `finish`.
+#[derive(Clone)]
+struct Deprecated(Arc<Deprecation>);
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for DeprecationMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        if self.covers(req.uri().path()) {
+            res.extensions
+                .insert(Deprecated(Arc::clone(&self.deprecation)));
+        }
+        next_res()
+    }
+
+    fn finish(&self, res: &mut Response) {
+        if let Some(Deprecated(deprecation)) = res.extensions.remove::<Deprecated>() {
+            deprecation.apply(res);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_for_prefix_covers_whole_segments() {
+        let since = UNIX_EPOCH + Duration::from_secs(1_767_225_600);
+        let mw = DeprecationMiddleware::for_prefix("/v1/", Deprecation::new(since));
+        for (uri, deprecated) in [("/v1", true), ("/v1/users", true), ("/v10/users", false)] {
+            let mut req = Request::builder().uri(uri).body(()).unwrap();
+            let mut res = Response::new();
+            mw.call(&mut req, &mut res).await;
+            Middleware::<()>::finish(&mw, &mut res);
+            assert_eq!(res.headers.contains_key(DEPRECATION), deprecated, "{uri}");
+            assert!(!res.headers.contains_key(SUNSET), "{uri}");
+        }
+    }
+}
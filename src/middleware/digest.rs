@@ -0,0 +1,342 @@
+use crate::handler::extract::merge_response;
+use crate::handler::request::RequestExt;
+use crate::handler::response::{ResponseBody, json_errors, write_error_body};
+use crate::handler::{Request, Response, ResponseError};
+use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hyper::HeaderMap;
+use hyper::header::{HeaderName, HeaderValue};
+use log::warn;
+use serde_json::json;
+use sha2::{Digest, Sha256, Sha512};
+use std::sync::{Arc, OnceLock};
+
+const DIGEST: HeaderName = HeaderName::from_static("digest");
+const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+
+/// Middleware that rejects request bodies not matching their `Digest`
+/// header (RFC 3230), e.g. `Digest: sha-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=`.
+///
+/// `sha-256` and `sha-512` are supported, the stronger one winning when a
+/// request sends both. The digest is checked against the body as received,
+/// before any decompression, when a reader such as [`RequestExt::bytes`](crate::prelude::RequestExt::bytes),
+/// [`RequestExt::json`](crate::prelude::RequestExt::json) or the
+/// [`Json`](crate::prelude::Json) extractor has consumed it: the reader fails
+/// with [`ResponseError::DigestMismatch`], and whatever the handler responds is
+/// replaced with `422 Unprocessable Entity`. Bodies the handler never reads
+/// aren't checked.
+///
+/// Requests with only unsupported algorithms (including `Content-MD5`) pass
+/// through unchecked, with a warning.
+#[derive(Debug, Clone, Default)]
+pub struct DigestVerificationMiddleware {
+    response_digest: bool,
+}
+
+impl DigestVerificationMiddleware {
+    /// Creates the middleware, which leaves responses alone.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also sends a `sha-256` `Digest` header with every response whose body
+    /// is held in memory. Register the middleware before a
+    /// [`CompressionMiddleware`](crate::prelude::CompressionMiddleware) so
+    /// the digest covers the compressed body actually sent.
+    pub fn response_digest(mut self) -> Self {
+        self.response_digest = true;
+        self
+    }
+}
+
+/// A digest algorithm that can be verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn parse(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("sha-256") {
+            Some(Self::Sha256)
+        } else if name.eq_ignore_ascii_case("sha-512") {
+            Some(Self::Sha512)
+        } else {
+            None
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha-256",
+            Self::Sha512 => "sha-512",
+        }
+    }
+}
+
+/// Request extension telling the body readers which digest the body must have.
+#[derive(Debug, Clone)]
+pub(crate) struct BodyDigest {
+    algorithm: Algorithm,
+    expected: Arc<[u8]>,
+    /// Whether the body matched, once a reader has checked it.
+    verified: Arc<OnceLock<bool>>,
+}
+
+impl BodyDigest {
+    /// Starts hashing a body read in chunks.
+    pub(crate) fn check(&self) -> DigestCheck {
+        let hasher = match self.algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        };
+        DigestCheck {
+            digest: self.clone(),
+            hasher,
+        }
+    }
+
+    /// Checks a whole body.
+    pub(crate) fn verify(&self, body: &[u8]) -> Result<(), ResponseError> {
+        let mut check = self.check();
+        check.update(body);
+        check.finish()
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+/// The running hash of a body, from [`BodyDigest::check`].
+pub(crate) struct DigestCheck {
+    digest: BodyDigest,
+    hasher: Hasher,
+}
+
+impl DigestCheck {
+    pub(crate) fn update(&mut self, chunk: &[u8]) {
+        match &mut self.hasher {
+            Hasher::Sha256(hasher) => hasher.update(chunk),
+            Hasher::Sha512(hasher) => hasher.update(chunk),
+        }
+    }
+
+    /// Compares the hash of everything read to the expected digest, and
+    /// records the outcome for the middleware's `finish` hook.
+    pub(crate) fn finish(self) -> Result<(), ResponseError> {
+        let matches = match self.hasher {
+            Hasher::Sha256(hasher) => hasher.finalize()[..] == *self.digest.expected,
+            Hasher::Sha512(hasher) => hasher.finalize()[..] == *self.digest.expected,
+        };
+        let _ = self.digest.verified.set(matches);
+        if matches {
+            Ok(())
+        } else {
+            Err(ResponseError::DigestMismatch(
+                self.digest.algorithm.as_str(),
+            ))
+        }
+    }
+}
+
+/// Per-request state carried from `call` to `finish` in the response extensions.
+#[derive(Debug, Clone)]
+struct Verification {
+    algorithm: Algorithm,
+    verified: Arc<OnceLock<bool>>,
+    json_errors: bool,
+}
+
+/// The strongest supported digest in the `Digest` headers, `Ok(None)` if
+/// there is none, or `Err` with the algorithm whose value isn't base64.
+fn requested_digest(headers: &HeaderMap) -> Result<Option<(Algorithm, Vec<u8>)>, &str> {
+    let mut best: Option<(Algorithm, &str)> = None;
+    for value in headers.get_all(DIGEST) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for entry in value.split(',') {
+            let Some((name, encoded)) = entry.trim().split_once('=') else {
+                continue;
+            };
+            if let Some(algorithm) = Algorithm::parse(name)
+                && best.is_none_or(|(best, _)| algorithm > best)
+            {
+                best = Some((algorithm, encoded));
+            }
+        }
+    }
+    match best {
+        Some((algorithm, encoded)) => match STANDARD.decode(encoded) {
+            Ok(expected) => Ok(Some((algorithm, expected))),
+            Err(_) => Err(algorithm.as_str()),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Sets a `sha-256` `Digest` header for a body held in memory.
+fn set_response_digest(res: &mut Response) {
+    res.body = match std::mem::take(&mut res.body) {
+        ResponseBody::Lazy(produce) => ResponseBody::Full(produce()),
+        body => body,
+    };
+    let mut hasher = Sha256::new();
+    match &res.body {
+        ResponseBody::Empty => {}
+        ResponseBody::Full(bytes) => hasher.update(bytes),
+        ResponseBody::Buffered(chunks) => chunks.iter().for_each(|chunk| hasher.update(chunk)),
+        _ => return,
+    }
+    let value = format!("sha-256={}", STANDARD.encode(hasher.finalize()));
+    if let Ok(value) = HeaderValue::try_from(value) {
+        res.headers.insert(DIGEST, value);
+    }
+}
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for DigestVerificationMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        match requested_digest(req.headers()) {
+            Ok(Some((algorithm, expected))) => {
+                let verified = Arc::new(OnceLock::new());
+                res.extensions.insert(Verification {
+                    algorithm,
+                    verified: Arc::clone(&verified),
+                    json_errors: json_errors(req),
+                });
+                req.extensions_mut().insert(BodyDigest {
+                    algorithm,
+                    expected: expected.into(),
+                    verified,
+                });
+            }
+            Ok(None) => {
+                if req.headers().contains_key(DIGEST) || req.headers().contains_key(CONTENT_MD5) {
+                    warn!("no supported digest algorithm in the request; its body is not verified");
+                }
+            }
+            Err(algorithm) => {
+                let message = format!("invalid {algorithm} value in the Digest header");
+                let details = json!({ "algorithm": algorithm });
+                res.respond_enveloped_error(400, &message, details, req.prefers_json());
+                return stop_res();
+            }
+        }
+        next_res()
+    }
+
+    fn finish(&self, res: &mut Response) {
+        if let Some(verification) = res.extensions.get::<Verification>()
+            && verification.verified.get() == Some(&false)
+            && !matches!(res.error, Some(ResponseError::DigestMismatch(_)))
+        {
+            let mut rejected = Response::new();
+            let error = ResponseError::DigestMismatch(verification.algorithm.as_str());
+            rejected.status = error.status();
+            rejected.error = Some(error);
+            write_error_body(&mut rejected, verification.json_errors, false);
+            // Keeps what the middleware before this one set, e.g. CORS headers.
+            *res = merge_response(std::mem::take(res), rejected);
+        }
+        if self.response_digest {
+            set_response_digest(res);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(digests: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for digest in digests {
+            headers.append(DIGEST, digest.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_requested_digest_prefers_the_strongest() {
+        let sha256 = STANDARD.encode(Sha256::digest(b"body"));
+        let sha512 = STANDARD.encode(Sha512::digest(b"body"));
+
+        let single = headers(&[&format!("SHA-256={sha256}")]);
+        let requested = requested_digest(&single);
+        assert_eq!(
+            requested,
+            Ok(Some((Algorithm::Sha256, Sha256::digest(b"body").to_vec())))
+        );
+        let both = format!("sha-512={sha512}, md5=abc");
+        let both = headers(&[&format!("sha-256={sha256}"), &both]);
+        let requested = requested_digest(&both);
+        assert_eq!(
+            requested,
+            Ok(Some((Algorithm::Sha512, Sha512::digest(b"body").to_vec())))
+        );
+
+        assert_eq!(requested_digest(&headers(&["md5=abc"])), Ok(None));
+        assert_eq!(requested_digest(&headers(&[])), Ok(None));
+        assert_eq!(
+            requested_digest(&headers(&["sha-256=not base64!"])),
+            Err("sha-256")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_algorithms_pass_with_a_warning() {
+        crate::test_logger::capture();
+        let mw = DigestVerificationMiddleware::new();
+        for (name, value) in [
+            ("Digest", "md5=HUXZLQLMuI/KZ5KDcJPcOA=="),
+            ("Content-MD5", "HUXZLQLMuI/KZ5KDcJPcOA=="),
+        ] {
+            let mut req = Request::builder().header(name, value).body(()).unwrap();
+            let mut res = Response::new();
+            assert!(mw.call(&mut req, &mut res).await.is_next());
+            assert!(req.extensions().get::<BodyDigest>().is_none());
+
+            let records = crate::test_logger::take();
+            assert_eq!(records.len(), 1, "{records:?}");
+            assert_eq!(records[0].level, log::Level::Warn);
+        }
+
+        let mut req = Request::builder().body(()).unwrap();
+        mw.call(&mut req, &mut Response::new()).await;
+        assert!(crate::test_logger::take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_body_reader_checks_the_digest_at_the_end() {
+        use crate::handler::request::RequestExt;
+        use bytes::Bytes;
+        use http_body_util::Full;
+        use tokio::io::AsyncReadExt;
+
+        let mw = DigestVerificationMiddleware::new();
+        let digest = format!("sha-512={}", STANDARD.encode(Sha512::digest(b"payload")));
+        for (body, ok) in [("payload", true), ("pay1oad", false)] {
+            let mut req = Request::builder()
+                .header("Digest", &digest)
+                .body(Full::new(Bytes::from(body)))
+                .unwrap();
+            let mut res = Response::new();
+            mw.call(&mut req, &mut res).await;
+
+            let mut read = Vec::new();
+            let result = req.body_reader().unwrap().read_to_end(&mut read).await;
+            assert_eq!(result.is_ok(), ok, "{body}");
+            assert_eq!(read, body.as_bytes());
+
+            Middleware::<Full<Bytes>>::finish(&mw, &mut res);
+            let expected = if ok { 200 } else { 422 };
+            assert_eq!(res.get_status().as_u16(), expected, "{body}");
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use crate::handler::catch_panic::{self, PanicBacktrace};
+use crate::handler::request::RequestExt;
+use crate::handler::{Request, Response, ResponseError};
+use crate::middleware::{Middleware, MiddlewareResult, next_res};
+use async_trait::async_trait;
+use hyper::header::HeaderValue;
+use hyper::{Method, StatusCode, Uri};
+use log::{Level, log};
+use std::backtrace::Backtrace;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::sync::Arc;
+
+/// Log target used for request error lines.
+const ERROR_TARGET: &str = "express_rs::error";
+
+/// Marker placed in [`Response::extensions`] once a request's error has been logged.
+///
+/// [`ErrorLogMiddleware`] skips responses carrying it, so a handler that
+/// already logged its own error can insert it to avoid a duplicate line.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorLogged;
+
+/// Everything known about a failed request, handed to the reporter hook.
+#[derive(Debug)]
+pub struct ErrorReport<'a> {
+    /// The request method.
+    pub method: &'a Method,
+    /// The request path.
+    pub path: &'a str,
+    /// The route pattern that matched, if any.
+    pub route: Option<&'a str>,
+    /// The `X-Request-Id` request header, if any.
+    pub request_id: Option<&'a str>,
+    /// The response status.
+    pub status: StatusCode,
+    /// The error attached to the response.
+    pub error: &'a ResponseError,
+    /// Where a handler panic happened, when `RUST_BACKTRACE` is enabled.
+    pub backtrace: Option<&'a Backtrace>,
+}
+
+type Reporter = Arc<dyn Fn(&ErrorReport<'_>) + Send + Sync>;
+
+/// Middleware that logs, from one central place, every request whose response
+/// carries a [`ResponseError`], including handlers that panicked.
+///
+/// Each failure produces exactly one line with the route, request id and the
+/// error's source chain, logged under the `express_rs::error` target. Handler
+/// panics also get a backtrace when `RUST_BACKTRACE` is set.
+///
+/// ```rust
+/// # use expressjs::prelude::*;
+/// let errors = ErrorLogMiddleware::new().reporter(|report| {
+///     // forward to an external error tracker
+///     let _ = (report.path, report.error.to_string());
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ErrorLogMiddleware {
+    level: Level,
+    reporter: Option<Reporter>,
+}
+
+/// Request details captured in `call` for use in `finish`.
+#[derive(Debug, Clone)]
+struct ErrorContext {
+    method: Method,
+    path: Box<str>,
+    route: Option<Box<str>>,
+    request_id: Option<Box<str>>,
+}
+
+impl Default for ErrorLogMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ErrorLogMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErrorLogMiddleware")
+            .field("level", &self.level)
+            .field("reporter", &self.reporter.is_some())
+            .finish()
+    }
+}
+
+impl ErrorLogMiddleware {
+    /// Create a new ErrorLogMiddleware logging at `ERROR`.
+    ///
+    /// Installs a panic hook (once per process) that records backtraces of
+    /// panicking handlers; the previously installed hook keeps running.
+    pub fn new() -> Self {
+        catch_panic::install_backtrace_hook();
+        Self {
+            level: Level::Error,
+            reporter: None,
+        }
+    }
+
+    /// Set the level error lines are logged at.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Call `reporter` for every logged error, e.g. to forward it to an
+    /// external error tracker.
+    pub fn reporter<F>(mut self, reporter: F) -> Self
+    where
+        F: Fn(&ErrorReport<'_>) + Send + Sync + 'static,
+    {
+        self.reporter = Some(Arc::new(reporter));
+        self
+    }
+}
+
+/// Formats `error` followed by each of its sources, separated by `: `.
+pub(crate) fn error_chain(error: &dyn Error) -> String {
+    let mut out = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let _ = write!(out, ": {cause}");
+        source = cause.source();
+    }
+    out
+}
+
+/// The log line for `report`: method, path, route, request id, status and
+/// error chain, then the backtrace if there is one.
+fn report_line(report: &ErrorReport<'_>) -> String {
+    let mut line = format!(
+        "{} {} route={} request_id={} status={}: {}",
+        report.method,
+        report.path,
+        report.route.unwrap_or("-"),
+        report.request_id.unwrap_or("-"),
+        report.status.as_u16(),
+        error_chain(report.error),
+    );
+    if let Some(backtrace) = report.backtrace {
+        let _ = write!(line, "\nbacktrace:\n{backtrace}");
+    }
+    line
+}
+
+/// What the app keeps of a request to log the server error of its response
+/// if no [`ErrorLogMiddleware`] did.
+pub(crate) struct RequestSummary {
+    method: Method,
+    uri: Uri,
+    request_id: Option<HeaderValue>,
+}
+
+impl RequestSummary {
+    pub(crate) fn capture<B>(req: &Request<B>) -> Self {
+        Self {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            request_id: req.headers().get("x-request-id").cloned(),
+        }
+    }
+
+    /// The request path, as received.
+    pub(crate) fn path(&self) -> &str {
+        self.uri.path()
+    }
+
+    /// Logs the error of `res` at `ERROR` if it is a server error nothing
+    /// logged yet, and marks it [`ErrorLogged`].
+    pub(crate) fn log_server_error(&self, res: &mut Response) {
+        let Some(error) = &res.error else {
+            return;
+        };
+        if !res.status.is_server_error() || res.extensions.get::<ErrorLogged>().is_some() {
+            return;
+        }
+        let report = ErrorReport {
+            method: &self.method,
+            path: self.uri.path(),
+            route: None,
+            request_id: self.request_id.as_ref().and_then(|id| id.to_str().ok()),
+            status: res.status,
+            error,
+            backtrace: res.extensions.get::<PanicBacktrace>().map(|bt| &*bt.0),
+        };
+        log!(target: ERROR_TARGET, Level::Error, "{}", report_line(&report));
+        res.extensions.insert(ErrorLogged);
+    }
+}
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for ErrorLogMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        res.extensions.insert(ErrorContext {
+            method: req.method().clone(),
+            path: req.uri().path().into(),
+            route: req.matched_path().map(Into::into),
+            request_id: req.header("x-request-id").map(Into::into),
+        });
+        next_res()
+    }
+
+    fn finish(&self, res: &mut Response) {
+        let Some(ctx) = res.extensions.remove::<ErrorContext>() else {
+            return;
+        };
+        let Some(error) = &res.error else {
+            return;
+        };
+        if res.extensions.get::<ErrorLogged>().is_some() {
+            return;
+        }
+
+        let backtrace = res.extensions.get::<PanicBacktrace>().map(|bt| &*bt.0);
+        let report = ErrorReport {
+            method: &ctx.method,
+            path: &ctx.path,
+            route: ctx.route.as_deref(),
+            request_id: ctx.request_id.as_deref(),
+            status: res.status,
+            error,
+            backtrace,
+        };
+
+        log!(target: ERROR_TARGET, self.level, "{}", report_line(&report));
+
+        if let Some(reporter) = &self.reporter {
+            reporter(&report);
+        }
+        res.extensions.insert(ErrorLogged);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::App;
+    use crate::handler::ExpressResponse;
+    use crate::test_logger;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn app(errors: ErrorLogMiddleware) -> App<()> {
+        let mut app = App::<()>::default();
+        app.use_global(errors);
+        app.get("/files/{name}", |_, res: Response| async move {
+            res.send_file("/definitely/not/here.txt").await
+        });
+        app.get("/panic", |_, _res: Response| async move {
+            if true {
+                panic!("kaboom");
+            }
+            Response::new()
+        });
+        app.get("/handled", |_, res: Response| async move {
+            let mut res = res.send_file("/definitely/not/here.txt").await;
+            log::error!("handler logged it itself");
+            res.extensions.insert(ErrorLogged);
+            res
+        });
+        app.get("/ok", |_, res: Response| async move { res.send_text("ok") });
+        app
+    }
+
+    async fn request(app: &App<()>, uri: &str) -> Response {
+        let req = Request::builder()
+            .uri(uri)
+            .header("x-request-id", "req-9")
+            .body(())
+            .unwrap();
+        app.handle(req, Response::new()).await
+    }
+
+    #[tokio::test]
+    async fn test_failing_handler_logs_one_line() {
+        test_logger::capture();
+        let reported = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&reported);
+        let app = app(ErrorLogMiddleware::new().reporter(move |report| {
+            assert_eq!(report.route, Some("/files/{name}"));
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        request(&app, "/files/a.txt").await;
+        request(&app, "/ok").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1, "{records:?}");
+        assert_eq!(records[0].level, Level::Error);
+        assert_eq!(records[0].target, "express_rs::error");
+        assert!(
+            records[0].message.starts_with(
+                "GET /files/a.txt route=/files/{name} request_id=req-9 status=200: file open error:"
+            ),
+            "{}",
+            records[0].message
+        );
+        assert_eq!(reported.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler_logs_one_line() {
+        test_logger::capture();
+        let app = app(ErrorLogMiddleware::new().level(Level::Warn));
+
+        let res = request(&app, "/panic").await;
+        assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1, "{records:?}");
+        assert_eq!(records[0].level, Level::Warn);
+        assert!(records[0].message.starts_with(
+            "GET /panic route=/panic request_id=req-9 status=500: handler panicked: kaboom"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_logged_marker_prevents_duplicates() {
+        test_logger::capture();
+        let app = app(ErrorLogMiddleware::new());
+
+        request(&app, "/handled").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1, "{records:?}");
+        assert_eq!(records[0].message, "handler logged it itself");
+    }
+
+    #[tokio::test]
+    async fn test_app_logs_server_errors_without_the_middleware() {
+        test_logger::capture();
+        let mut app = App::<()>::default();
+        app.get("/panic", |_, _res: Response| async move {
+            if true {
+                panic!("kaboom");
+            }
+            Response::new()
+        });
+        app.get("/files/{name}", |_, res: Response| async move {
+            res.send_file("/definitely/not/here.txt").await
+        });
+
+        let res = request(&app, "/panic").await;
+        assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(res.extensions.get::<ErrorLogged>().is_some());
+        // Only server errors are logged.
+        request(&app, "/files/a.txt").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1, "{records:?}");
+        assert_eq!(records[0].level, Level::Error);
+        assert_eq!(records[0].target, "express_rs::error");
+        assert!(records[0].message.starts_with(
+            "GET /panic route=- request_id=req-9 status=500: handler panicked: kaboom"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_routers_served_directly_log_server_errors_once() {
+        test_logger::capture();
+        let mut router = crate::router::Router::<()>::default();
+        router.get("/boom", |_, _res: Response| async move {
+            Err::<Response, _>(crate::prelude::ApiError::internal("disk full"))
+        });
+        let boom = || Request::builder().uri("/boom").body(()).unwrap();
+
+        let res = router.handle(boom(), Response::new()).await;
+        assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+        let frozen = router.freeze().unwrap();
+        frozen.handle(boom(), Response::new()).await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 2, "{records:?}");
+        for record in records {
+            assert_eq!(record.target, "express_rs::error");
+            assert!(record.message.contains("disk full"), "{}", record.message);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use crate::handler::{Request, Response};
+use crate::middleware::{Middleware, MiddlewareResult};
+use async_trait::async_trait;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+/// The boxed future returned by [`middleware_fn`] and [`from_fn_with_state`]
+/// closures. It may borrow the request and response for its whole lifetime.
+pub type MiddlewareFuture<'a> = Pin<Box<dyn Future<Output = MiddlewareResult> + Send + 'a>>;
+
+/// Middleware built from a closure by [`middleware_fn`].
+#[derive(Clone)]
+pub struct MiddlewareFn<F> {
+    f: F,
+}
+
+/// Middleware built from a state value and a closure by [`from_fn_with_state`].
+#[derive(Clone)]
+pub struct MiddlewareFnWithState<S, F> {
+    state: S,
+    f: F,
+}
+
+/// Turns a closure into middleware whose async block may use `req` and `res`
+/// mutably across `.await` points.
+///
+/// The plain closure impl of [`Middleware`] requires a `'static` future, so
+/// anything read from the request has to be copied out before the `async`
+/// block. Here the closure returns a boxed [`MiddlewareFuture`] tied to the
+/// borrows instead:
+///
+/// ```rust,no_run
+/// use expressjs::prelude::*;
+/// use hyper::header::HeaderValue;
+/// use std::sync::Arc;
+///
+/// # async fn run() {
+/// let powered_by = Arc::new(HeaderValue::from_static("DevYatsu"));
+/// let mut app = express();
+/// app.use_with(
+///     "/hello",
+///     middleware_fn(move |req, res| {
+///         let powered_by = powered_by.clone();
+///         Box::pin(async move {
+///             tokio::task::yield_now().await;
+///             if req.headers().contains_key("x-debug") {
+///                 res.header("x-powered-by", (*powered_by).clone());
+///             }
+///             next_res()
+///         })
+///     }),
+/// );
+/// # }
+/// ```
+pub fn middleware_fn<B, F>(f: F) -> MiddlewareFn<F>
+where
+    F: for<'a> Fn(&'a mut Request<B>, &'a mut Response) -> MiddlewareFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    MiddlewareFn { f }
+}
+
+/// Like [`middleware_fn`], but hands the closure a reference to `state` so
+/// nothing has to be cloned into the async block.
+///
+/// ```rust,no_run
+/// use expressjs::prelude::*;
+///
+/// struct Config {
+///     api_key: String,
+/// }
+///
+/// # async fn run() {
+/// let config = Config { api_key: "secret".into() };
+/// let mut app = express();
+/// app.use_with(
+///     "/api",
+///     from_fn_with_state(config, |config, req, res| {
+///         Box::pin(async move {
+///             let key = req.headers().get("x-api-key").and_then(|v| v.to_str().ok());
+///             if key == Some(config.api_key.as_str()) {
+///                 return next_res();
+///             }
+///             res.status_code(401).send_text("Unauthorized");
+///             stop_res()
+///         })
+///     }),
+/// );
+/// # }
+/// ```
+pub fn from_fn_with_state<S, B, F>(state: S, f: F) -> MiddlewareFnWithState<S, F>
+where
+    S: Send + Sync + 'static,
+    F: for<'a> Fn(&'a S, &'a mut Request<B>, &'a mut Response) -> MiddlewareFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    MiddlewareFnWithState { state, f }
+}
+
+#[async_trait]
+impl<B, F> Middleware<B> for MiddlewareFn<F>
+where
+    B: Send + Sync + 'static,
+    F: for<'a> Fn(&'a mut Request<B>, &'a mut Response) -> MiddlewareFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        (self.f)(req, res).await
+    }
+}
+
+#[async_trait]
+impl<S, B, F> Middleware<B> for MiddlewareFnWithState<S, F>
+where
+    S: Send + Sync + 'static,
+    B: Send + Sync + 'static,
+    F: for<'a> Fn(&'a S, &'a mut Request<B>, &'a mut Response) -> MiddlewareFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        (self.f)(&self.state, req, res).await
+    }
+}
+
+impl<F> fmt::Debug for MiddlewareFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareFn").finish_non_exhaustive()
+    }
+}
+
+impl<S: fmt::Debug, F> fmt::Debug for MiddlewareFnWithState<S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareFnWithState")
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::response::ExpressResponse;
+    use crate::middleware::{next_res, stop_res};
+    use hyper::header::HeaderValue;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_middleware_fn_uses_captures_across_awaits() {
+        let prefix = Arc::new(String::from("seen:"));
+        let mw = middleware_fn(move |req: &mut Request<()>, res| {
+            let prefix = prefix.clone();
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                let value = format!("{prefix}{}", req.uri().path());
+                res.header("x-seen", HeaderValue::try_from(value).unwrap());
+                req.extensions_mut().insert(7u8);
+                next_res()
+            })
+        });
+
+        let mut req = Request::builder().uri("/hello").body(()).unwrap();
+        let mut res = Response::new();
+        assert!(mw.call(&mut req, &mut res).await.is_next());
+        assert_eq!(res.headers["x-seen"], "seen:/hello");
+        assert_eq!(req.extensions().get::<u8>(), Some(&7));
+    }
+
+    #[tokio::test]
+    async fn test_from_fn_with_state_borrows_state() {
+        let mw = from_fn_with_state(AtomicUsize::new(0), |hits, req: &mut Request<()>, res| {
+            Box::pin(async move {
+                let n = hits.fetch_add(1, Ordering::SeqCst) + 1;
+                tokio::task::yield_now().await;
+                if n > 1 || req.headers().contains_key("x-block") {
+                    res.status_code(429);
+                    return stop_res();
+                }
+                next_res()
+            })
+        });
+
+        let mut req = Request::builder().uri("/").body(()).unwrap();
+        assert!(mw.call(&mut req, &mut Response::new()).await.is_next());
+        let mut res = Response::new();
+        assert!(mw.call(&mut req, &mut res).await.is_stop());
+        assert_eq!(res.status, 429);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::handler::request::RequestExt;
+use crate::handler::{ExpressResponse, Request, Response};
+use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
+use async_trait::async_trait;
+use hyper::{Method, StatusCode};
+use serde_json::Value;
+
+/// Middleware that moves plain-HTTP clients over to HTTPS.
+///
+/// A request counts as secure when it arrived over TLS or, behind a
+/// [trusted proxy](crate::prelude::TrustProxy) terminating TLS, when the proxy
+/// forwarded `https` (see [`RequestExt::protocol`]). Secure requests pass
+/// through untouched.
+///
+/// Insecure `GET` and `HEAD` requests are redirected to the same host, path
+/// and query over `https://`. Other methods are rejected instead: a client
+/// following the redirect would have already sent its body in the clear.
+#[derive(Debug, Clone)]
+pub struct HttpsRedirectMiddleware {
+    redirect_status: StatusCode,
+    reject_status: StatusCode,
+    https_port: Option<u16>,
+}
+
+impl Default for HttpsRedirectMiddleware {
+    fn default() -> Self {
+        Self {
+            redirect_status: StatusCode::MOVED_PERMANENTLY,
+            reject_status: StatusCode::FORBIDDEN,
+            https_port: None,
+        }
+    }
+}
+
+impl HttpsRedirectMiddleware {
+    /// Redirects with `301 Moved Permanently` and rejects with `403 Forbidden`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the redirect status, e.g. `308 Permanent Redirect`, or a
+    /// temporary one while trying HTTPS out, since browsers cache `301`s.
+    pub fn redirect_status(mut self, status: StatusCode) -> Self {
+        self.redirect_status = status;
+        self
+    }
+
+    /// Sets the status for insecure requests that aren't redirected, e.g.
+    /// `400 Bad Request`.
+    pub fn reject_status(mut self, status: StatusCode) -> Self {
+        self.reject_status = status;
+        self
+    }
+
+    /// Sets the port HTTPS is served on, when it isn't 443. The port of the
+    /// request's `Host` is always dropped from the redirect.
+    pub fn https_port(mut self, port: u16) -> Self {
+        self.https_port = Some(port);
+        self
+    }
+
+    /// The `https://` URL of the request, or `None` without a usable host.
+    fn https_url<B>(&self, req: &Request<B>) -> Option<String> {
+        let host = req.host_name().or_else(|| req.uri().host())?;
+        let host = strip_port(host).filter(|host| !host.is_empty())?;
+        let path = req.uri().path_and_query().map_or("/", |pq| pq.as_str());
+        Some(match self.https_port {
+            Some(port) if port != 443 => format!("https://{host}:{port}{path}"),
+            _ => format!("https://{host}{path}"),
+        })
+    }
+}
+
+/// `host` without its port, keeping the brackets of an IPv6 literal.
+fn strip_port(host: &str) -> Option<&str> {
+    if host.starts_with('[') {
+        let end = host.find(']')?;
+        return Some(&host[..=end]);
+    }
+    Some(host.split_once(':').map_or(host, |(host, _)| host))
+}
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for HttpsRedirectMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        if req.protocol() == "https" {
+            return next_res();
+        }
+
+        let wants_json = req.prefers_json();
+        if !matches!(*req.method(), Method::GET | Method::HEAD) {
+            res.respond_enveloped_error(
+                self.reject_status.as_u16(),
+                "HTTPS required",
+                Value::Null,
+                wants_json,
+            );
+            return stop_res();
+        }
+
+        match self.https_url(req) {
+            Some(url) => {
+                res.status(self.redirect_status).location(url);
+            }
+            None => {
+                res.respond_enveloped_error(400, "Missing Host header", Value::Null, wants_json);
+            }
+        }
+        stop_res()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::request::{ConnectionInfo, RequestMetadataInternal, TlsInfo, TrustProxy};
+    use hyper::header::LOCATION;
+    use std::sync::Arc;
+
+    async fn run(
+        mw: &HttpsRedirectMiddleware,
+        mut req: Request<()>,
+    ) -> (MiddlewareResult, Response) {
+        let mut res = Response::new();
+        let result = mw.call(&mut req, &mut res).await;
+        (result, res)
+    }
+
+    fn insecure(method: Method, uri: &str) -> Request<()> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Host", "example.com:8080")
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_secure_requests_pass_through() {
+        let mw = HttpsRedirectMiddleware::new();
+
+        let mut req = insecure(Method::POST, "/orders");
+        req.set_connection(Arc::new(ConnectionInfo {
+            tls: Some(TlsInfo::default()),
+            ..(*ConnectionInfo::peer("203.0.113.7:5000")).clone()
+        }));
+        let (result, res) = run(&mw, req).await;
+        assert!(result.is_next());
+        assert_eq!(res.get_status(), StatusCode::OK);
+
+        // TLS terminated by a trusted proxy.
+        let mut req = insecure(Method::POST, "/orders");
+        req.headers_mut()
+            .insert("X-Forwarded-Proto", "https".parse().unwrap());
+        req.set_connection(ConnectionInfo::peer("10.0.0.1:5000"));
+        req.extensions_mut().insert(TrustProxy::All);
+        let (result, _) = run(&mw, req).await;
+        assert!(result.is_next());
+    }
+
+    #[tokio::test]
+    async fn test_insecure_reads_are_redirected() {
+        let mw = HttpsRedirectMiddleware::new();
+        let (result, res) = run(&mw, insecure(Method::GET, "/a/b?q=1&r=2")).await;
+        assert!(result.is_stop());
+        assert_eq!(res.get_status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers[LOCATION], "https://example.com/a/b?q=1&r=2");
+
+        // An untrusted peer can't claim HTTPS.
+        let mut req = insecure(Method::HEAD, "/");
+        req.headers_mut()
+            .insert("X-Forwarded-Proto", "https".parse().unwrap());
+        req.set_connection(ConnectionInfo::peer("10.0.0.1:5000"));
+        let (result, res) = run(&mw, req).await;
+        assert!(result.is_stop());
+        assert_eq!(res.headers[LOCATION], "https://example.com/");
+
+        let mw = mw
+            .redirect_status(StatusCode::PERMANENT_REDIRECT)
+            .https_port(8443);
+        let mut req = insecure(Method::GET, "/x");
+        req.headers_mut()
+            .insert("Host", "[::1]:8080".parse().unwrap());
+        let (_, res) = run(&mw, req).await;
+        assert_eq!(res.get_status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(res.headers[LOCATION], "https://[::1]:8443/x");
+    }
+
+    #[tokio::test]
+    async fn test_insecure_writes_are_rejected() {
+        let mw = HttpsRedirectMiddleware::new();
+        let (result, res) = run(&mw, insecure(Method::POST, "/orders")).await;
+        assert!(result.is_stop());
+        assert_eq!(res.get_status(), StatusCode::FORBIDDEN);
+        assert!(!res.headers.contains_key(LOCATION));
+
+        let mw = mw.reject_status(StatusCode::BAD_REQUEST);
+        let (_, res) = run(&mw, insecure(Method::DELETE, "/orders/1")).await;
+        assert_eq!(res.get_status(), StatusCode::BAD_REQUEST);
+
+        let req = Request::builder().uri("/").body(()).unwrap();
+        let (_, res) = run(&mw, req).await;
+        assert_eq!(res.get_status(), StatusCode::BAD_REQUEST);
+        assert!(!res.headers.contains_key(LOCATION));
+    }
+}
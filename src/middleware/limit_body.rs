@@ -1,12 +1,17 @@
-use crate::handler::{Request, Response, request::RequestExt};
+use crate::handler::request::{BodyLimit, RequestExt};
+use crate::handler::{Request, Response};
 use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
 use async_trait::async_trait;
+use hyper::header::CONTENT_LENGTH;
 use log::warn;
 use serde_json::json;
 
 /// Middleware that rejects requests with a `Content-Length` exceeding the allowed limit.
 /// Can respond in either JSON or plain text depending on the `Accept` header.
-#[allow(dead_code)] // Public API: constructed by library users.
+///
+/// Bodies without a `Content-Length` (e.g. chunked) can't be checked up
+/// front, so the limit is also attached to the request as a [`BodyLimit`],
+/// which [`RequestExt::bytes`] and [`RequestExt::json`] enforce while reading.
 #[derive(Debug, Clone)]
 pub struct BodySizeLimitMiddleware {
     /// Max body size in bytes.
@@ -25,37 +30,36 @@ impl Default for BodySizeLimitMiddleware {
 }
 
 #[async_trait]
-impl Middleware for BodySizeLimitMiddleware {
-    async fn call(&self, req: &mut Request, res: &mut Response) -> MiddlewareResult {
+impl<B: Send + Sync + 'static> Middleware<B> for BodySizeLimitMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        // A WebSocket handshake has no body and its upgraded stream isn't ours to limit.
+        if req.is_websocket_upgrade() {
+            return next_res();
+        }
+
+        req.extensions_mut().insert(BodyLimit(self.max_size_bytes));
+
         let wants_json = req.prefers_json();
 
         // Handle missing Content-Length
-        let Some(header) = req.headers().get("Content-Length") else {
+        if !req.headers().contains_key(CONTENT_LENGTH) {
             if self.strict {
                 warn!("Strict mode: Content-Length header is missing.");
 
-                res.respond_error(
+                res.respond_enveloped_error(
                     411,
                     "Content-Length header required",
-                    json!({
-                        "error": "Content-Length header required",
-                        "max_size_bytes": self.max_size_bytes
-                    }),
+                    json!({ "max_size_bytes": self.max_size_bytes }),
                     wants_json,
                 );
 
                 return stop_res();
             }
             return next_res();
-        };
-
-        let Ok(length_str) = header.to_str() else {
-            warn!("Invalid Content-Length header format.");
-            return next_res();
-        };
+        }
 
-        let Ok(length) = length_str.parse::<usize>() else {
-            warn!("Unable to parse Content-Length as usize.");
+        let Some(length) = req.header_parsed::<usize, _>(CONTENT_LENGTH) else {
+            warn!("Invalid Content-Length header.");
             return next_res();
         };
 
@@ -65,11 +69,10 @@ impl Middleware for BodySizeLimitMiddleware {
                 length, self.max_size_bytes
             );
 
-            res.respond_error(
+            res.respond_enveloped_error(
                 413,
                 "Payload too large",
                 json!({
-                    "error": "Payload too large",
                     "max_size_bytes": self.max_size_bytes,
                     "actual_size": length
                 }),
@@ -1,24 +1,923 @@
+mod filter;
+mod format;
+mod json;
+mod policy;
+
+pub use format::{LogFormatError, LogRequest};
+pub use policy::LogPolicy;
+
+use crate::handler::request::RequestExt;
 use crate::handler::{Request, Response};
 use crate::middleware::{Middleware, MiddlewareResult, next_res};
 use async_trait::async_trait;
-use log::info;
+use filter::{LogFilter, Sampler};
+use format::{LogFormat, PendingLine, TokenFn};
+use hyper::StatusCode;
+use hyper::header::{
+    AUTHORIZATION, COOKIE, HeaderName, PROXY_AUTHORIZATION, SET_COOKIE, USER_AGENT,
+};
+use json::{ACCESS_TARGET, JsonOutput};
+use log::{Level, info, log, warn};
+use once_cell::sync::Lazy;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
-/// Middleware that logs each incoming HTTP request to the console or logger.
-#[derive(Debug, Clone)]
+/// `:method :url :status :response-time ms - :res[content-length]`
+const DEV: &str = ":method :url :status :response-time ms - :res[content-length]";
+/// Apache combined log format.
+const COMBINED: &str = r#":remote-addr - :remote-user [:date[clf]] ":method :url HTTP/:http-version" :status :res[content-length] ":referrer" ":user-agent""#;
+/// `:method :url :status :res[content-length] - :response-time ms`
+const TINY: &str = ":method :url :status :res[content-length] - :response-time ms";
+
+/// Middleware that logs each incoming HTTP request to the console or logger,
+/// with the default [`Logger`] settings.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let mut app = express();
+/// app.use_global(LoggingMiddleware);
+/// app.use_global(Logger::tiny().skip_paths(["/health"])); // or configured
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
 pub struct LoggingMiddleware;
 
+/// The shared [`Logger`] behind [`LoggingMiddleware`].
+static DEFAULT_LOGGER: Lazy<Logger> = Lazy::new(Logger::default);
+
 #[async_trait]
-impl Middleware for LoggingMiddleware {
-    async fn call(&self, req: &mut Request, _res: &mut Response) -> MiddlewareResult {
-        info!(
-            "{} {} - User-Agent: {}",
-            req.method(),
-            req.uri().path(),
-            req.headers()
-                .get("User-Agent")
-                .and_then(|h| h.to_str().ok())
-                .unwrap_or("Unknown")
-        );
+impl<B: Send + Sync + 'static> Middleware<B> for LoggingMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        Middleware::<B>::call(&*DEFAULT_LOGGER, req, res).await
+    }
+
+    fn finish(&self, res: &mut Response) {
+        Middleware::<B>::finish(&*DEFAULT_LOGGER, res);
+    }
+}
+
+/// Configurable request logging middleware; [`LoggingMiddleware`] is one
+/// with the default settings.
+///
+/// By default a single completion line is logged once the response is ready,
+/// using the [`dev`](Self::dev) format, e.g. `GET /users/42 200 1.234 ms - 128`.
+/// Responses with a status `>= 500` are logged at `ERROR`, `>= 400` at `WARN`
+/// and everything else at `INFO`.
+///
+/// The line is described by a morgan-style format string made of `:token`s
+/// (see [`format`](Self::format)), parsed once when the middleware is built.
+/// Use [`json`](Self::json) for structured output instead.
+///
+/// Noisy traffic can be left out with [`skip_paths`](Self::skip_paths),
+/// [`skip`](Self::skip) and [`sample`](Self::sample). The `Authorization`,
+/// `Proxy-Authorization`, `Cookie` and `Set-Cookie` headers are always
+/// rendered as `[redacted]`; see [`redact_header`](Self::redact_header).
+///
+/// Requests abandoned by the client never produce a completion line; enable
+/// [`log_aborted`](Self::log_aborted) to log them with their elapsed time.
+#[derive(Clone)]
+pub struct Logger {
+    entry_only: bool,
+    log_aborted: bool,
+    format: Arc<LogFormat>,
+    tokens: FxHashMap<String, TokenFn>,
+    redacted: FxHashSet<HeaderName>,
+    json: Option<JsonOutput>,
+    filter: LogFilter,
+    policies: Arc<matchit::Router<Arc<LogPolicy>>>,
+}
+
+/// Per-request state carried from `call` to `finish` in the response extensions.
+#[derive(Debug, Clone)]
+struct RequestLog {
+    line: PendingRecord,
+    policy: Option<Arc<LogPolicy>>,
+    start: Instant,
+    abort: Option<Arc<AbortWatch>>,
+}
+
+/// Logs the request as aborted when dropped before `finish` ran, i.e. when
+/// the request future was dropped because the client went away.
+#[derive(Debug)]
+struct AbortWatch {
+    request: String,
+    start: Instant,
+    finished: AtomicBool,
+}
+
+impl Drop for AbortWatch {
+    fn drop(&mut self) {
+        if !*self.finished.get_mut() {
+            let elapsed = self.start.elapsed().as_secs_f64() * 1000.0;
+            warn!("{} aborted by client after {elapsed:.3} ms", self.request);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PendingRecord {
+    Text(PendingLine),
+    Json(Map<String, Value>),
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::dev()
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logger")
+            .field("entry_only", &self.entry_only)
+            .field("log_aborted", &self.log_aborted)
+            .field("format", &self.format.source())
+            .field("json", &self.json.is_some())
+            .field("tokens", &self.tokens.keys().collect::<Vec<_>>())
+            .field("redacted", &self.redacted)
+            .field("skip_paths", &self.filter.skip_paths)
+            .field("sampler", &self.filter.sampler)
+            .field("policies", &self.policies)
+            .finish()
+    }
+}
+
+impl Logger {
+    /// Create a new Logger that logs one line per completed request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn preset(format: &str) -> Self {
+        let redacted: FxHashSet<HeaderName> =
+            [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE]
+                .into_iter()
+                .collect();
+        Self {
+            entry_only: false,
+            log_aborted: false,
+            format: Arc::new(
+                LogFormat::parse(format, &FxHashMap::default(), &redacted)
+                    .expect("valid preset format"),
+            ),
+            tokens: FxHashMap::default(),
+            redacted,
+            json: None,
+            filter: LogFilter::default(),
+            policies: Arc::default(),
+        }
+    }
+
+    /// Re-resolves the current format after tokens or redactions changed.
+    /// Neither can make a valid format invalid.
+    fn reparse(&mut self) {
+        if let Ok(format) = LogFormat::parse(self.format.source(), &self.tokens, &self.redacted) {
+            self.format = Arc::new(format);
+        }
+    }
+
+    /// Concise output for development:
+    /// `:method :url :status :response-time ms - :res[content-length]`.
+    pub fn dev() -> Self {
+        Self::preset(DEV)
+    }
+
+    /// Apache combined log output:
+    /// `:remote-addr - :remote-user [:date[clf]] ":method :url HTTP/:http-version" :status :res[content-length] ":referrer" ":user-agent"`.
+    pub fn combined() -> Self {
+        Self::preset(COMBINED)
+    }
+
+    /// Minimal output: `:method :url :status :res[content-length] - :response-time ms`.
+    pub fn tiny() -> Self {
+        Self::preset(TINY)
+    }
+
+    /// One JSON object per completed request, logged with the target
+    /// `express_rs::access` so it can be filtered or routed separately.
+    ///
+    /// The object always has the following fields (`null` when unknown):
+    ///
+    /// | field           | type   | description                                   |
+    /// |-----------------|--------|-----------------------------------------------|
+    /// | `timestamp`     | string | completion time, RFC 3339 in UTC              |
+    /// | `level`         | string | log level, picked from the status as usual    |
+    /// | `method`        | string | request method                                |
+    /// | `path`          | string | request path, without the query string        |
+    /// | `matched_route` | string | route pattern that matched, e.g. `/users/{id}`|
+    /// | `status`        | number | response status code                          |
+    /// | `duration_ms`   | number | time from the middleware to the response      |
+    /// | `bytes`         | number | response body size (`null` for streams)       |
+    /// | `remote_addr`   | string | client IP address, see [`TrustProxy`](crate::prelude::TrustProxy) |
+    /// | `forwarded_for` | array  | trusted forwarded chain, client first         |
+    /// | `protocol`      | string | `http` or `https`                             |
+    /// | `http_version`  | string | e.g. `1.1` or `2.0`                           |
+    /// | `request_id`    | string | `X-Request-Id` request header                 |
+    /// | `user_agent`    | string | `User-Agent` request header                   |
+    ///
+    /// More fields can be added with [`json_fields`](Self::json_fields).
+    pub fn json() -> Self {
+        Self {
+            json: Some(JsonOutput::default()),
+            ..Self::default()
+        }
+    }
+
+    /// Add fields computed from the request to every JSON log line.
+    ///
+    /// Switches the output to JSON if it wasn't already. Fields named like a
+    /// built-in one are ignored.
+    ///
+    /// ```rust
+    /// # use expressjs::prelude::*;
+    /// let logger = Logger::json().json_fields(|req| {
+    ///     let mut fields = serde_json::Map::new();
+    ///     fields.insert("host".into(), req.uri().host().into());
+    ///     fields
+    /// });
+    /// ```
+    pub fn json_fields<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&LogRequest<'_>) -> Map<String, Value> + Send + Sync + 'static,
+    {
+        self.json = Some(JsonOutput {
+            extra: Some(Arc::new(f)),
+        });
+        self
+    }
+
+    /// Use a custom format string.
+    ///
+    /// Built-in tokens are `:method`, `:url`, `:status`, `:response-time[digits]`,
+    /// `:remote-addr`, `:remote-user`, `:http-version`, `:referrer`, `:user-agent`,
+    /// `:date[clf|iso|web]`, `:req[header]` and `:res[header]`, plus
+    /// `:forwarded-for` (the trusted proxy chain) and `:protocol` (`http`/`https`).
+    /// `:remote-addr` and `:forwarded-for` follow the app's
+    /// [`TrustProxy`](crate::prelude::TrustProxy) setting, so spoofed
+    /// `X-Forwarded-For` headers are never logged as the client. Anything else must
+    /// be registered with [`token`](Self::token) first, otherwise
+    /// [`LogFormatError::UnknownToken`] is returned.
+    ///
+    /// ```rust
+    /// # use expressjs::prelude::*;
+    /// let logger = Logger::new()
+    ///     .format(r#":method :url :status ":user-agent""#)
+    ///     .unwrap();
+    /// ```
+    pub fn format(mut self, format: &str) -> Result<Self, LogFormatError> {
+        self.format = Arc::new(LogFormat::parse(format, &self.tokens, &self.redacted)?);
+        self.json = None;
+        Ok(self)
+    }
+
+    /// Register a custom `:name` token rendered from the request.
+    ///
+    /// Custom tokens take precedence over built-ins of the same name.
+    ///
+    /// ```rust
+    /// # use expressjs::prelude::*;
+    /// let logger = Logger::new()
+    ///     .token("host", |req| req.uri().host().unwrap_or("-").to_string())
+    ///     .format(":method :host:url :status")
+    ///     .unwrap();
+    /// ```
+    pub fn token<F>(mut self, name: impl Into<String>, f: F) -> Self
+    where
+        F: Fn(&LogRequest<'_>) -> String + Send + Sync + 'static,
+    {
+        self.tokens.insert(name.into(), Arc::new(f));
+        self.reparse();
+        self
+    }
+
+    /// Never log the value of this header; `:req[..]` and `:res[..]` tokens
+    /// render `[redacted]` instead.
+    pub fn redact_header(mut self, name: HeaderName) -> Self {
+        self.redacted.insert(name);
+        self.reparse();
+        self
+    }
+
+    /// Don't log requests whose path is exactly one of `paths`, e.g. health checks.
+    pub fn skip_paths<I, S>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<Box<str>>,
+    {
+        self.filter
+            .skip_paths
+            .extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    /// Don't log requests for which `f` returns `true`.
+    ///
+    /// ```rust
+    /// # use expressjs::prelude::*;
+    /// let logger = Logger::new().skip(|req| req.uri().path().starts_with("/assets/"));
+    /// ```
+    pub fn skip<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&LogRequest<'_>) -> bool + Send + Sync + 'static,
+    {
+        self.filter.skip = Some(Arc::new(f));
+        self
+    }
+
+    /// Log only about one request in `one_in`, picked at random.
+    ///
+    /// Skipped and unsampled requests cost nothing beyond the check itself.
+    pub fn sample(mut self, one_in: u64) -> Self {
+        self.filter.sampler = Some(Arc::new(Sampler::new(one_in, None)));
+        self
+    }
+
+    /// Like [`sample`](Self::sample), but with a fixed seed so the sequence
+    /// of sampled requests is reproducible.
+    pub fn sample_seeded(mut self, one_in: u64, seed: u64) -> Self {
+        self.filter.sampler = Some(Arc::new(Sampler::new(one_in, Some(seed))));
+        self
+    }
+
+    /// Apply `policy` to requests whose path matches `pattern` (same syntax as
+    /// routes, e.g. `/debug/{*p}`), instead of the global settings.
+    ///
+    /// An invalid or conflicting pattern is ignored with a warning.
+    pub fn policy(mut self, pattern: impl Into<String>, policy: LogPolicy) -> Self {
+        let pattern = pattern.into();
+        if let Err(e) = Arc::make_mut(&mut self.policies).insert(&pattern, Arc::new(policy)) {
+            warn!("Ignoring log policy for {pattern}: {e}");
+        }
+        self
+    }
+
+    /// Set whether requests the client abandons before the response is ready
+    /// are logged, at `WARN`, as `GET /path aborted by client after 12.345 ms`.
+    ///
+    /// Off by default. Has no effect with [`entry_only`](Self::entry_only).
+    pub fn log_aborted(mut self, log_aborted: bool) -> Self {
+        self.log_aborted = log_aborted;
+        self
+    }
+
+    /// Set whether only the request line should be logged when the request arrives,
+    /// instead of a completion line with status, size and latency.
+    pub fn entry_only(mut self, entry_only: bool) -> Self {
+        self.entry_only = entry_only;
+        self
+    }
+}
+
+/// Picks the log level for a completed request from its status code.
+fn level_for(status: StatusCode) -> Level {
+    if status.is_server_error() {
+        Level::Error
+    } else if status.is_client_error() {
+        Level::Warn
+    } else {
+        Level::Info
+    }
+}
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for Logger {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        let request = LogRequest::new(req);
+        if !self.filter.should_log(&request) {
+            return next_res();
+        }
+
+        if self.entry_only {
+            info!(
+                "{} {} - User-Agent: {}",
+                req.method(),
+                req.uri().path(),
+                req.header(USER_AGENT).unwrap_or("Unknown")
+            );
+        } else {
+            let line = match &self.json {
+                Some(json) => PendingRecord::Json(json.begin(&request, &self.redacted)),
+                None => PendingRecord::Text(self.format.begin(&request)),
+            };
+            let policy = self
+                .policies
+                .at(req.uri().path())
+                .ok()
+                .map(|matched| Arc::clone(matched.value));
+            let start = Instant::now();
+            let abort = self.log_aborted.then(|| {
+                Arc::new(AbortWatch {
+                    request: format!("{} {}", req.method(), req.uri()),
+                    start,
+                    finished: AtomicBool::new(false),
+                })
+            });
+            res.extensions.insert(RequestLog {
+                line,
+                policy,
+                start,
+                abort,
+            });
+        }
         next_res()
     }
+
+    fn finish(&self, res: &mut Response) {
+        let Some(entry) = res.extensions.remove::<RequestLog>() else {
+            return;
+        };
+        if let Some(abort) = &entry.abort {
+            abort.finished.store(true, Ordering::Relaxed);
+        }
+
+        let policy = entry.policy.as_deref();
+        let level = policy
+            .and_then(LogPolicy::level_override)
+            .unwrap_or_else(|| level_for(res.status));
+        let elapsed = entry.start.elapsed();
+        let line = match (entry.line, &self.json) {
+            (PendingRecord::Json(mut fields), Some(json)) => {
+                if let Some(policy) = policy {
+                    policy.extend_json(&mut fields, res);
+                }
+                let line = json.finish(fields, res, elapsed, level);
+                log!(target: ACCESS_TARGET, level, "{line}");
+                line
+            }
+            (PendingRecord::Text(line), _) => {
+                let mut line = self.format.finish(line, res, elapsed);
+                if let Some(policy) = policy {
+                    policy.extend_text(&mut line, res);
+                }
+                log!(level, "{line}");
+                line
+            }
+            (PendingRecord::Json(_), None) => return,
+        };
+
+        if let Some(target) = policy.and_then(LogPolicy::audit) {
+            log!(target: target, level, "{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::App;
+    use crate::handler::ExpressResponse;
+    use crate::handler::request::TrustProxy;
+    use crate::test_logger;
+
+    async fn request(app: &App<()>, uri: &str) {
+        let req = Request::builder().uri(uri).body(()).unwrap();
+        app.handle(req, Response::new()).await;
+    }
+
+    fn app_with(logger: impl Middleware<()>) -> App<()> {
+        let mut app = App::<()>::default();
+        app.use_global(logger);
+        app.get(
+            "/ok",
+            |_, res: Response| async move { res.send_text("hello") },
+        );
+        app.get("/boom", |_, res: Response| async move {
+            res.status_code(500).send_text("boom")
+        });
+        app
+    }
+
+    fn app() -> App<()> {
+        app_with(LoggingMiddleware)
+    }
+
+    /// Replaces the variable `:response-time` part of a line with `<t>`.
+    fn mask_time(line: &str) -> String {
+        let mut out = String::new();
+        for word in line.split(' ') {
+            if !out.is_empty() {
+                out.push(' ');
+            }
+            if word.contains('.') && word.parse::<f64>().is_ok() {
+                out.push_str("<t>");
+            } else {
+                out.push_str(word);
+            }
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn test_logs_one_completion_line_per_request() {
+        test_logger::capture();
+        let app = app();
+
+        request(&app, "/ok").await;
+        request(&app, "/missing").await;
+        request(&app, "/boom").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 3, "{records:?}");
+
+        assert_eq!(records[0].level, Level::Info);
+        assert_eq!(mask_time(&records[0].message), "GET /ok 200 <t> ms - 5");
+
+        assert_eq!(records[1].level, Level::Warn);
+        assert_eq!(
+            mask_time(&records[1].message),
+            "GET /missing 404 <t> ms - 9"
+        );
+
+        assert_eq!(records[2].level, Level::Error);
+        assert_eq!(mask_time(&records[2].message), "GET /boom 500 <t> ms - 4");
+    }
+
+    #[tokio::test]
+    async fn test_tiny_preset() {
+        test_logger::capture();
+        let app = app_with(Logger::tiny());
+
+        request(&app, "/ok?page=2").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            mask_time(&records[0].message),
+            "GET /ok?page=2 200 5 - <t> ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_combined_preset() {
+        test_logger::capture();
+        let app = app_with(Logger::combined());
+
+        let req = Request::builder()
+            .uri("/ok")
+            .header("user-agent", "curl/8.0")
+            .header("referer", "http://example.com/")
+            // alice:secret
+            .header("authorization", "Basic YWxpY2U6c2VjcmV0")
+            .body(())
+            .unwrap();
+        app.handle(req, Response::new()).await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1);
+        let line = &records[0].message;
+        let (prefix, rest) = line.split_once(" [").unwrap();
+        let (date, suffix) = rest.split_once("] ").unwrap();
+        assert_eq!(prefix, "- - alice");
+        assert!(date.ends_with(" +0000"), "{date}");
+        assert_eq!(
+            suffix,
+            r#""GET /ok HTTP/1.1" 200 5 "http://example.com/" "curl/8.0""#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_format_and_token() {
+        test_logger::capture();
+        let logger = Logger::new()
+            .token("id", |req| {
+                req.headers()
+                    .get("x-request-id")
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("-")
+                    .to_string()
+            })
+            .format(r#"[:id] :method :url :status ":user-agent" :res[x-missing]"#)
+            .unwrap();
+        let app = app_with(logger);
+
+        let req = Request::builder()
+            .uri("/boom")
+            .header("x-request-id", "abc123")
+            .body(())
+            .unwrap();
+        app.handle(req, Response::new()).await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, r#"[abc123] GET /boom 500 "-" -"#);
+    }
+
+    #[tokio::test]
+    async fn test_json_output_schema() {
+        test_logger::capture();
+        let logger = Logger::json().json_fields(|req| {
+            let mut fields = Map::new();
+            fields.insert("tenant".into(), req.uri().path().len().into());
+            fields.insert("status".into(), "shadowed".into());
+            fields
+        });
+        let mut app = app_with(logger);
+        app.get("/users/{id}", |_, res: Response| async move {
+            res.send_text("user")
+        });
+
+        let req = Request::builder()
+            .uri("/users/7?full=1")
+            .header("x-request-id", "req-1")
+            .header("user-agent", "curl/8.0")
+            .body(())
+            .unwrap();
+        app.handle(req, Response::new()).await;
+        request(&app, "/missing").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.target == "express_rs::access"));
+
+        let line: Value = serde_json::from_str(&records[0].message).unwrap();
+        let mut keys: Vec<_> = line.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "bytes",
+                "duration_ms",
+                "forwarded_for",
+                "http_version",
+                "level",
+                "matched_route",
+                "method",
+                "path",
+                "protocol",
+                "remote_addr",
+                "request_id",
+                "status",
+                "tenant",
+                "timestamp",
+                "user_agent",
+            ]
+        );
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["method"], "GET");
+        assert_eq!(line["path"], "/users/7");
+        assert_eq!(line["matched_route"], "/users/{id}");
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["bytes"], 4);
+        assert_eq!(line["remote_addr"], Value::Null);
+        assert_eq!(line["request_id"], "req-1");
+        assert_eq!(line["user_agent"], "curl/8.0");
+        assert_eq!(line["tenant"], 8);
+        assert!(line["duration_ms"].as_f64().unwrap() >= 0.0);
+        assert!(chrono::DateTime::parse_from_rfc3339(line["timestamp"].as_str().unwrap()).is_ok());
+
+        let line: Value = serde_json::from_str(&records[1].message).unwrap();
+        assert_eq!(records[1].level, Level::Warn);
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["status"], 404);
+        assert_eq!(line["matched_route"], Value::Null);
+        assert_eq!(line["request_id"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_skipped_requests_emit_nothing() {
+        test_logger::capture();
+        let mut app = app_with(
+            Logger::new()
+                .skip_paths(["/healthz", "/metrics"])
+                .skip(|req| req.uri().path().starts_with("/assets/")),
+        );
+        app.get("/healthz", |_, res: Response| async move { res });
+        app.get("/assets/{*file}", |_, res: Response| async move { res });
+
+        let res = app
+            .handle(
+                Request::builder().uri("/healthz").body(()).unwrap(),
+                Response::new(),
+            )
+            .await;
+        assert!(res.extensions.get::<RequestLog>().is_none());
+        request(&app, "/assets/app.css").await;
+        request(&app, "/ok").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1, "{records:?}");
+        assert!(records[0].message.starts_with("GET /ok 200 "));
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_headers_are_redacted() {
+        test_logger::capture();
+        let logger = Logger::new()
+            .format(":req[authorization] :req[cookie] :req[x-api-key] :req[accept]")
+            .unwrap()
+            .redact_header(HeaderName::from_static("x-api-key"));
+        let app = app_with(logger);
+
+        let req = Request::builder()
+            .uri("/ok")
+            .header("authorization", "Bearer secret")
+            .header("x-api-key", "secret")
+            .header("accept", "text/plain")
+            .body(())
+            .unwrap();
+        app.handle(req, Response::new()).await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, "[redacted] - [redacted] text/plain");
+    }
+
+    #[tokio::test]
+    async fn test_sampling_logs_about_one_in_n() {
+        test_logger::capture();
+        let app = app_with(Logger::tiny().sample_seeded(2, 42));
+
+        for _ in 0..100 {
+            request(&app, "/ok").await;
+        }
+
+        let logged = test_logger::take().len();
+        assert!((35..=65).contains(&logged), "logged {logged} of 100");
+    }
+
+    async fn log_proxied(app: &App<()>, peer: &str) -> String {
+        use crate::handler::request::{ConnectionInfo, RequestMetadataInternal};
+
+        let mut req = Request::builder()
+            .uri("/ok")
+            .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+            .header("x-forwarded-proto", "https")
+            .body(())
+            .unwrap();
+        req.set_connection(ConnectionInfo::peer(peer));
+        app.handle(req, Response::new()).await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1);
+        records[0].message.clone()
+    }
+
+    fn proxy_app(trust: Option<TrustProxy>) -> App<()> {
+        let logger = Logger::new()
+            .format(":remote-addr [:forwarded-for] :protocol HTTP/:http-version")
+            .unwrap();
+        let mut app = app_with(logger);
+        if let Some(trust) = trust {
+            app.trust_proxy(trust);
+        }
+        app
+    }
+
+    #[tokio::test]
+    async fn test_logs_direct_client_address() {
+        test_logger::capture();
+        let app = proxy_app(None);
+        assert_eq!(
+            log_proxied(&app, "198.51.100.1:5000").await,
+            "198.51.100.1 [-] http HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_logs_client_behind_trusted_proxy() {
+        test_logger::capture();
+        let app = proxy_app(Some(TrustProxy::ips(["10.0.0.9".parse().unwrap()])));
+        assert_eq!(
+            log_proxied(&app, "10.0.0.9:5000").await,
+            "10.0.0.1 [10.0.0.1] https HTTP/1.1"
+        );
+
+        let app = proxy_app(Some(TrustProxy::All));
+        assert_eq!(
+            log_proxied(&app, "10.0.0.9:5000").await,
+            "203.0.113.7 [203.0.113.7, 10.0.0.1] https HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_does_not_log_spoofed_forwarded_for() {
+        test_logger::capture();
+        let app = proxy_app(Some(TrustProxy::ips(["10.0.0.9".parse().unwrap()])));
+        assert_eq!(
+            log_proxied(&app, "198.51.100.1:5000").await,
+            "198.51.100.1 [-] http HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_route_policies_override_global_settings() {
+        test_logger::capture();
+        let logger = Logger::tiny()
+            .policy(
+                "/debug/{*p}",
+                LogPolicy::new().level(Level::Debug).include_body(true),
+            )
+            .policy(
+                "/api/payments",
+                LogPolicy::new()
+                    .audit_target("payments::audit")
+                    .field("team", "billing"),
+            );
+        let mut app = app_with(logger);
+        app.get("/debug/{*p}", |_, res: Response| async move {
+            res.send_text("state: ok")
+        });
+        app.post("/api/payments", |_, res: Response| async move {
+            res.status_code(402).send_text("declined")
+        });
+
+        request(&app, "/debug/cache").await;
+        let req = Request::builder()
+            .method("POST")
+            .uri("/api/payments")
+            .body(())
+            .unwrap();
+        app.handle(req, Response::new()).await;
+        request(&app, "/boom").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 4, "{records:?}");
+
+        assert_eq!(records[0].level, Level::Debug);
+        assert_eq!(
+            mask_time(&records[0].message),
+            r#"GET /debug/cache 200 9 - <t> ms body="state: ok""#
+        );
+
+        // The status-derived level still applies when the policy doesn't set one.
+        assert_eq!(records[1].level, Level::Warn);
+        assert_eq!(
+            mask_time(&records[1].message),
+            "POST /api/payments 402 8 - <t> ms team=billing"
+        );
+        assert_eq!(records[2].target, "payments::audit");
+        assert_eq!(records[2].message, records[1].message);
+
+        // Fallback to the global config.
+        assert_eq!(records[3].level, Level::Error);
+        assert_eq!(mask_time(&records[3].message), "GET /boom 500 4 - <t> ms");
+    }
+
+    #[test]
+    fn test_invalid_formats_are_rejected() {
+        let err = |format: &str| Logger::new().format(format).unwrap_err();
+
+        assert_eq!(
+            err(":method :custom"),
+            LogFormatError::UnknownToken("custom".into())
+        );
+        assert_eq!(err(":req"), LogFormatError::MissingArgument("req".into()));
+        assert_eq!(
+            err(":date[unix]"),
+            LogFormatError::InvalidArgument("date".into(), "unix".into())
+        );
+        assert_eq!(
+            err(":res[content-length"),
+            LogFormatError::UnterminatedArgument("res".into())
+        );
+
+        // A lone colon is not a token.
+        assert!(Logger::new().format("time: :status").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_entry_only_logs_request_line() {
+        test_logger::capture();
+        let mut app = App::<()>::default();
+        app.use_global(Logger::new().entry_only(true));
+        app.get("/ok", |_, res: Response| async move { res });
+
+        request(&app, "/ok").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, Level::Info);
+        assert_eq!(records[0].message, "GET /ok - User-Agent: Unknown");
+    }
+
+    #[tokio::test]
+    async fn test_logs_aborted_requests() {
+        test_logger::capture();
+        let mut app = app_with(Logger::new().log_aborted(true));
+        app.get("/hang", |_, res: Response| async move {
+            std::future::pending::<()>().await;
+            res
+        });
+
+        // Dropping the request future is what hyper does when the client leaves.
+        let req = Request::builder().uri("/hang?x=1").body(()).unwrap();
+        let handling = app.handle(req, Response::new());
+        let timeout = std::time::Duration::from_millis(10);
+        assert!(tokio::time::timeout(timeout, handling).await.is_err());
+        request(&app, "/ok").await;
+
+        let records = test_logger::take();
+        assert_eq!(records.len(), 2, "{records:?}");
+        assert_eq!(records[0].level, Level::Warn);
+        assert_eq!(
+            mask_time(&records[0].message),
+            "GET /hang?x=1 aborted by client after <t> ms"
+        );
+        // Completed requests are logged as usual, without an abort line.
+        assert_eq!(mask_time(&records[1].message), "GET /ok 200 <t> ms - 5");
+    }
 }
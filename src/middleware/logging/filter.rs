@@ -0,0 +1,63 @@
+use super::LogRequest;
+use rustc_hash::FxHashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A user-supplied predicate registered with [`Logger::skip`](super::Logger::skip).
+pub(crate) type SkipFn = Arc<dyn Fn(&LogRequest<'_>) -> bool + Send + Sync>;
+
+/// Decides, when a request arrives, whether it gets logged at all.
+#[derive(Clone, Default)]
+pub(crate) struct LogFilter {
+    pub(crate) skip_paths: FxHashSet<Box<str>>,
+    pub(crate) skip: Option<SkipFn>,
+    pub(crate) sampler: Option<Arc<Sampler>>,
+}
+
+impl LogFilter {
+    pub(crate) fn should_log(&self, req: &LogRequest<'_>) -> bool {
+        if self.skip_paths.contains(req.uri().path()) {
+            return false;
+        }
+        if self.skip.as_ref().is_some_and(|skip| skip(req)) {
+            return false;
+        }
+        self.sampler.as_ref().is_none_or(|sampler| sampler.keep())
+    }
+}
+
+/// Keeps roughly one request in `one_in`, using a lock-free SplitMix64 sequence.
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    one_in: u64,
+    state: AtomicU64,
+}
+
+impl Sampler {
+    pub(crate) fn new(one_in: u64, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        Self {
+            one_in: one_in.max(1),
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    fn keep(&self) -> bool {
+        if self.one_in == 1 {
+            return true;
+        }
+        let mut z = self
+            .state
+            .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        z.is_multiple_of(self.one_in)
+    }
+}
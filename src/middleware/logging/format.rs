@@ -0,0 +1,431 @@
+use crate::handler::Response;
+use crate::handler::request::{client_ip, forwarded_ips, protocol};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hyper::header::{self, HeaderName};
+use hyper::http::Extensions;
+use hyper::{HeaderMap, Method, Uri, Version};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// Rendered in place of a redacted header's value.
+pub(crate) const REDACTED: &str = "[redacted]";
+
+/// A user-supplied token renderer registered with [`Logger::token`](super::Logger::token).
+pub(crate) type TokenFn = Arc<dyn Fn(&LogRequest<'_>) -> String + Send + Sync>;
+
+/// Errors raised while parsing a log format string.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LogFormatError {
+    /// The format references a token that is neither built in nor registered.
+    #[error("unknown log token `:{0}`")]
+    UnknownToken(String),
+    /// The token requires a `[argument]`, e.g. `:req[header]`.
+    #[error("log token `:{0}` requires an argument")]
+    MissingArgument(String),
+    /// The token argument could not be understood.
+    #[error("invalid argument `{1}` for log token `:{0}`")]
+    InvalidArgument(String, String),
+    /// A `[` was opened after a token but never closed.
+    #[error("unterminated argument for log token `:{0}`")]
+    UnterminatedArgument(String),
+}
+
+/// Read-only view of the request handed to custom log tokens.
+pub struct LogRequest<'a> {
+    method: &'a Method,
+    uri: &'a Uri,
+    version: Version,
+    headers: &'a HeaderMap,
+    extensions: &'a Extensions,
+}
+
+impl<'a> LogRequest<'a> {
+    pub(crate) fn new<B>(req: &'a hyper::Request<B>) -> Self {
+        Self {
+            method: req.method(),
+            uri: req.uri(),
+            version: req.version(),
+            headers: req.headers(),
+            extensions: req.extensions(),
+        }
+    }
+
+    /// The request method.
+    pub fn method(&self) -> &Method {
+        self.method
+    }
+
+    /// The request URI.
+    pub fn uri(&self) -> &Uri {
+        self.uri
+    }
+
+    /// The HTTP version of the request.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// The request headers.
+    pub fn headers(&self) -> &HeaderMap {
+        self.headers
+    }
+
+    /// The request extensions.
+    pub fn extensions(&self) -> &Extensions {
+        self.extensions
+    }
+
+    /// The client address, honouring the app's [`TrustProxy`](crate::prelude::TrustProxy) setting.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        client_ip(self.headers, self.extensions)
+    }
+
+    /// The trusted `Forwarded`/`X-Forwarded-For` addresses, client first.
+    pub fn forwarded_ips(&self) -> Vec<IpAddr> {
+        forwarded_ips(self.headers, self.extensions)
+    }
+
+    /// `"https"` or `"http"`, honouring `X-Forwarded-Proto` from a trusted proxy.
+    pub fn protocol(&self) -> &'static str {
+        protocol(self.headers, self.extensions)
+    }
+}
+
+/// How `:date` is rendered.
+#[derive(Debug, Clone, Copy)]
+enum DateFormat {
+    /// `10/Oct/2000:13:55:36 +0000`
+    Clf,
+    /// `2000-10-10T13:55:36.000Z`
+    Iso,
+    /// `Tue, 10 Oct 2000 13:55:36 GMT`
+    Web,
+}
+
+#[derive(Clone)]
+enum Token {
+    Literal(Box<str>),
+    // Rendered when the request arrives.
+    Method,
+    Url,
+    HttpVersion,
+    RemoteAddr,
+    ForwardedFor,
+    Protocol,
+    RemoteUser,
+    ReqHeader(HeaderName),
+    RedactedReqHeader(HeaderName),
+    Custom(TokenFn),
+    // Rendered once the response is ready.
+    Status,
+    ResponseTime(usize),
+    ResHeader(HeaderName),
+    RedactedResHeader(HeaderName),
+    Date(DateFormat),
+}
+
+impl Token {
+    fn is_deferred(&self) -> bool {
+        matches!(
+            self,
+            Token::Status
+                | Token::ResponseTime(_)
+                | Token::ResHeader(_)
+                | Token::RedactedResHeader(_)
+                | Token::Date(_)
+        )
+    }
+}
+
+/// A parsed log format, e.g. `:method :url :status :response-time ms`.
+#[derive(Clone)]
+pub(crate) struct LogFormat {
+    source: Box<str>,
+    tokens: Vec<Token>,
+}
+
+/// A log line with its request-side tokens rendered and the response-side ones
+/// still pending, carried from `call` to `finish`.
+#[derive(Debug, Clone)]
+pub(crate) struct PendingLine {
+    segments: SmallVec<[Segment; 4]>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Text(String),
+    Deferred(usize),
+}
+
+impl LogFormat {
+    /// Parses `format`, resolving `:name` tokens against the built-ins and the
+    /// registered `custom` tokens. A `:` not followed by a letter is kept as is.
+    /// Header tokens for a `redacted` header never render its value.
+    pub(crate) fn parse(
+        format: &str,
+        custom: &FxHashMap<String, TokenFn>,
+        redacted: &FxHashSet<HeaderName>,
+    ) -> Result<Self, LogFormatError> {
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut rest = format;
+
+        while let Some(idx) = rest.find(':') {
+            literal.push_str(&rest[..idx]);
+            let after = &rest[idx + 1..];
+            let name_len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .unwrap_or(after.len());
+
+            if !after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                literal.push(':');
+                rest = after;
+                continue;
+            }
+
+            let name = &after[..name_len];
+            let mut tail = &after[name_len..];
+            let arg = match tail.strip_prefix('[') {
+                Some(inner) => {
+                    let end = inner
+                        .find(']')
+                        .ok_or_else(|| LogFormatError::UnterminatedArgument(name.to_string()))?;
+                    tail = &inner[end + 1..];
+                    Some(&inner[..end])
+                }
+                None => None,
+            };
+
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal).into()));
+            }
+            tokens.push(match Self::token(name, arg, custom)? {
+                Token::ReqHeader(header) if redacted.contains(&header) => {
+                    Token::RedactedReqHeader(header)
+                }
+                Token::ResHeader(header) if redacted.contains(&header) => {
+                    Token::RedactedResHeader(header)
+                }
+                token => token,
+            });
+            rest = tail;
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal.into()));
+        }
+
+        Ok(Self {
+            source: format.into(),
+            tokens,
+        })
+    }
+
+    /// The format string this was parsed from.
+    pub(crate) fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn token(
+        name: &str,
+        arg: Option<&str>,
+        custom: &FxHashMap<String, TokenFn>,
+    ) -> Result<Token, LogFormatError> {
+        let invalid = |arg: &str| LogFormatError::InvalidArgument(name.to_string(), arg.into());
+        let header_arg = || {
+            let arg = arg.ok_or_else(|| LogFormatError::MissingArgument(name.to_string()))?;
+            HeaderName::from_bytes(arg.as_bytes()).map_err(|_| invalid(arg))
+        };
+
+        if let Some(f) = custom.get(name) {
+            return Ok(Token::Custom(f.clone()));
+        }
+
+        Ok(match name {
+            "method" => Token::Method,
+            "url" => Token::Url,
+            "http-version" => Token::HttpVersion,
+            "remote-addr" => Token::RemoteAddr,
+            "forwarded-for" => Token::ForwardedFor,
+            "protocol" => Token::Protocol,
+            "remote-user" => Token::RemoteUser,
+            "referrer" | "referer" => Token::ReqHeader(header::REFERER),
+            "user-agent" => Token::ReqHeader(header::USER_AGENT),
+            "req" => Token::ReqHeader(header_arg()?),
+            "status" => Token::Status,
+            "response-time" => match arg {
+                None => Token::ResponseTime(3),
+                Some(digits) => Token::ResponseTime(digits.parse().map_err(|_| invalid(digits))?),
+            },
+            "res" => Token::ResHeader(header_arg()?),
+            "date" => Token::Date(match arg {
+                None | Some("web") => DateFormat::Web,
+                Some("clf") => DateFormat::Clf,
+                Some("iso") => DateFormat::Iso,
+                Some(other) => return Err(invalid(other)),
+            }),
+            _ => return Err(LogFormatError::UnknownToken(name.to_string())),
+        })
+    }
+
+    /// Renders the request-side tokens, leaving response-side ones pending.
+    pub(crate) fn begin(&self, req: &LogRequest<'_>) -> PendingLine {
+        let mut segments = SmallVec::new();
+        let mut text = String::new();
+
+        for (idx, token) in self.tokens.iter().enumerate() {
+            if token.is_deferred() {
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                segments.push(Segment::Deferred(idx));
+                continue;
+            }
+
+            match token {
+                Token::Literal(s) => text.push_str(s),
+                Token::Method => text.push_str(req.method.as_str()),
+                Token::Url => text.push_str(
+                    req.uri
+                        .path_and_query()
+                        .map_or_else(|| req.uri.path(), |pq| pq.as_str()),
+                ),
+                Token::HttpVersion => text.push_str(http_version(req.version)),
+                Token::RemoteAddr => match req.client_ip() {
+                    Some(addr) => {
+                        let _ = write!(text, "{addr}");
+                    }
+                    None => text.push('-'),
+                },
+                Token::ForwardedFor => {
+                    let chain = req.forwarded_ips();
+                    if chain.is_empty() {
+                        text.push('-');
+                    }
+                    for (i, addr) in chain.iter().enumerate() {
+                        if i > 0 {
+                            text.push_str(", ");
+                        }
+                        let _ = write!(text, "{addr}");
+                    }
+                }
+                Token::Protocol => text.push_str(req.protocol()),
+                Token::RemoteUser => text.push_str(&remote_user(req.headers).unwrap_or("-".into())),
+                Token::ReqHeader(name) => text.push_str(header_str(req.headers, name)),
+                Token::RedactedReqHeader(name) => text.push_str(redacted(req.headers, name)),
+                Token::Custom(f) => text.push_str(&f(req)),
+                _ => unreachable!("deferred tokens are handled above"),
+            }
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        PendingLine { segments }
+    }
+
+    /// Completes a pending line with the response-side tokens.
+    pub(crate) fn finish(&self, line: PendingLine, res: &Response, elapsed: Duration) -> String {
+        let mut out = String::new();
+
+        for segment in line.segments {
+            let idx = match segment {
+                Segment::Text(text) => {
+                    out.push_str(&text);
+                    continue;
+                }
+                Segment::Deferred(idx) => idx,
+            };
+
+            match &self.tokens[idx] {
+                Token::Status => {
+                    let _ = write!(out, "{}", res.status.as_u16());
+                }
+                Token::ResponseTime(digits) => {
+                    let ms = elapsed.as_secs_f64() * 1000.0;
+                    let _ = write!(out, "{ms:.digits$}");
+                }
+                Token::ResHeader(name) if *name == header::CONTENT_LENGTH => {
+                    // The header is only set by hyper when the response is
+                    // written, so fall back to the body's known size.
+                    match res.headers.get(name).and_then(|v| v.to_str().ok()) {
+                        Some(value) => out.push_str(value),
+                        None => match res.body.content_length() {
+                            Some(len) => {
+                                let _ = write!(out, "{len}");
+                            }
+                            None => out.push('-'),
+                        },
+                    }
+                }
+                Token::ResHeader(name) => out.push_str(header_str(&res.headers, name)),
+                Token::RedactedResHeader(name) => out.push_str(redacted(&res.headers, name)),
+                Token::Date(format) => render_date(&mut out, *format),
+                _ => unreachable!("only deferred tokens are pending"),
+            }
+        }
+
+        out
+    }
+}
+
+fn header_str<'h>(headers: &'h HeaderMap, name: &HeaderName) -> &'h str {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+}
+
+fn redacted(headers: &HeaderMap, name: &HeaderName) -> &'static str {
+    if headers.contains_key(name) {
+        REDACTED
+    } else {
+        "-"
+    }
+}
+
+pub(crate) fn http_version(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2.0",
+        Version::HTTP_3 => "3.0",
+        _ => "1.1",
+    }
+}
+
+/// Extracts the user name from a `Basic` authorization header.
+fn remote_user(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, _) = decoded.split_once(':')?;
+    Some(user.to_string())
+}
+
+fn render_date(out: &mut String, format: DateFormat) {
+    match format {
+        DateFormat::Web => out.push_str(&httpdate::fmt_http_date(SystemTime::now())),
+        DateFormat::Clf => {
+            let _ = write!(out, "{}", chrono::Utc::now().format("%d/%b/%Y:%H:%M:%S %z"));
+        }
+        DateFormat::Iso => {
+            let _ = write!(
+                out,
+                "{}",
+                chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            );
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use super::LogRequest;
+use super::format::{REDACTED, http_version};
+use crate::handler::Response;
+use crate::handler::request::MatchedPath;
+use hyper::header::{HeaderName, USER_AGENT};
+use log::Level;
+use rustc_hash::FxHashSet;
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Log target used for JSON access logs, so they can be routed separately.
+pub(crate) const ACCESS_TARGET: &str = "express_rs::access";
+
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Extra fields added to every JSON access log line.
+pub(crate) type JsonFieldsFn = Arc<dyn Fn(&LogRequest<'_>) -> Map<String, Value> + Send + Sync>;
+
+/// Renders one JSON object per completed request.
+#[derive(Clone, Default)]
+pub(crate) struct JsonOutput {
+    pub(crate) extra: Option<JsonFieldsFn>,
+}
+
+impl JsonOutput {
+    /// Collects the request-side fields when the request arrives.
+    pub(crate) fn begin(
+        &self,
+        req: &LogRequest<'_>,
+        redacted: &FxHashSet<HeaderName>,
+    ) -> Map<String, Value> {
+        let mut fields = match &self.extra {
+            Some(extra) => extra(req),
+            None => Map::new(),
+        };
+
+        let header = |name: &HeaderName| match req.headers().get(name) {
+            Some(_) if redacted.contains(name) => Value::String(REDACTED.into()),
+            Some(value) => value.to_str().map_or(Value::Null, |v| v.into()),
+            None => Value::Null,
+        };
+
+        // Built-in fields are inserted last so extras can never shadow them.
+        fields.insert("method".into(), req.method().as_str().into());
+        fields.insert("path".into(), req.uri().path().into());
+        fields.insert(
+            "matched_route".into(),
+            req.extensions()
+                .get::<MatchedPath>()
+                .map_or(Value::Null, |m| m.0.as_ref().into()),
+        );
+        fields.insert(
+            "remote_addr".into(),
+            req.client_ip()
+                .map_or(Value::Null, |addr| addr.to_string().into()),
+        );
+        fields.insert(
+            "forwarded_for".into(),
+            req.forwarded_ips()
+                .iter()
+                .map(|addr| Value::String(addr.to_string()))
+                .collect(),
+        );
+        fields.insert("protocol".into(), req.protocol().into());
+        fields.insert("http_version".into(), http_version(req.version()).into());
+        fields.insert("request_id".into(), header(&REQUEST_ID));
+        fields.insert("user_agent".into(), header(&USER_AGENT));
+        fields
+    }
+
+    /// Adds the response-side fields and serializes the line.
+    pub(crate) fn finish(
+        &self,
+        mut fields: Map<String, Value>,
+        res: &Response,
+        elapsed: Duration,
+        level: Level,
+    ) -> String {
+        fields.insert(
+            "timestamp".into(),
+            chrono::Utc::now()
+                .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+                .into(),
+        );
+        fields.insert("level".into(), level.as_str().into());
+        fields.insert("status".into(), res.status.as_u16().into());
+        fields.insert(
+            "duration_ms".into(),
+            (elapsed.as_secs_f64() * 1000.0).into(),
+        );
+        fields.insert(
+            "bytes".into(),
+            res.body.content_length().map_or(Value::Null, Value::from),
+        );
+        Value::Object(fields).to_string()
+    }
+}
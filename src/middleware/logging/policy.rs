@@ -0,0 +1,119 @@
+use crate::handler::Response;
+use crate::handler::response::ResponseBody;
+use log::Level;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Longest response body logged by [`LogPolicy::include_body`], in bytes.
+const MAX_LOGGED_BODY: usize = 1024;
+
+/// Per-route overrides for [`Logger`](super::Logger),
+/// registered with [`Logger::policy`](super::Logger::policy).
+///
+/// Anything left unset falls back to the middleware's global configuration.
+///
+/// ```rust
+/// # use expressjs::prelude::*;
+/// let logger = Logger::new()
+///     .policy("/debug/{*p}", LogPolicy::new().level(log::Level::Debug).include_body(true))
+///     .policy("/api/payments", LogPolicy::new().audit_target("payments::audit"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LogPolicy {
+    level: Option<Level>,
+    include_body: bool,
+    audit_target: Option<Arc<str>>,
+    fields: Vec<(Box<str>, Box<str>)>,
+}
+
+impl LogPolicy {
+    /// Create a policy that changes nothing until configured.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log matching requests at `level` instead of the status-derived one.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Append the response body (up to 1 KiB) to the log line.
+    pub fn include_body(mut self, include: bool) -> Self {
+        self.include_body = include;
+        self
+    }
+
+    /// Also emit each line under this log target, e.g. for an audit trail.
+    pub fn audit_target(mut self, target: impl Into<Arc<str>>) -> Self {
+        self.audit_target = Some(target.into());
+        self
+    }
+
+    /// Add a static `key=value` field to every matching line.
+    pub fn field(mut self, key: impl Into<Box<str>>, value: impl Into<Box<str>>) -> Self {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+
+    pub(crate) fn level_override(&self) -> Option<Level> {
+        self.level
+    }
+
+    pub(crate) fn audit(&self) -> Option<&str> {
+        self.audit_target.as_deref()
+    }
+
+    /// Appends the policy's fields (and body) to a text line.
+    pub(crate) fn extend_text(&self, line: &mut String, res: &Response) {
+        for (key, value) in &self.fields {
+            let _ = write!(line, " {key}={value}");
+        }
+        if self.include_body {
+            let _ = write!(line, " body={:?}", body_preview(&res.body));
+        }
+    }
+
+    /// Adds the policy's fields (and body) to a JSON line, never replacing
+    /// a field that is already present.
+    pub(crate) fn extend_json(&self, fields: &mut Map<String, Value>, res: &Response) {
+        for (key, value) in &self.fields {
+            fields
+                .entry(key.as_ref())
+                .or_insert_with(|| value.as_ref().into());
+        }
+        if self.include_body {
+            fields
+                .entry("body")
+                .or_insert_with(|| body_preview(&res.body).into());
+        }
+    }
+}
+
+/// The body as (lossy) UTF-8, truncated to [`MAX_LOGGED_BODY`] bytes.
+fn body_preview(body: &ResponseBody) -> String {
+    let bytes: &[u8] = match body {
+        ResponseBody::Empty => return String::new(),
+        ResponseBody::Full(bytes) => bytes,
+        ResponseBody::Buffered(chunks) => match chunks.as_slice() {
+            [only] => only,
+            _ => {
+                let joined: Vec<u8> = chunks.iter().flat_map(|c| c.iter().copied()).collect();
+                return truncated(&joined);
+            }
+        },
+        ResponseBody::Stream(_) => return "<stream>".to_owned(),
+        // Producing it here would defeat the point of deferring it.
+        ResponseBody::Lazy(_) => return "<lazy>".to_owned(),
+    };
+    truncated(bytes)
+}
+
+fn truncated(bytes: &[u8]) -> String {
+    if bytes.len() <= MAX_LOGGED_BODY {
+        String::from_utf8_lossy(bytes).into_owned()
+    } else {
+        format!("{}…", String::from_utf8_lossy(&bytes[..MAX_LOGGED_BODY]))
+    }
+}
@@ -0,0 +1,274 @@
+use crate::handler::request::RequestExt;
+use crate::handler::{ExpressResponse, Request, Response};
+use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use hyper::Method;
+use std::fmt::{self, Write};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The Prometheus client libraries' default buckets, in seconds.
+const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Route label for requests that matched no route, so 404 scans share one series.
+const UNMATCHED: &str = "unmatched";
+
+/// Middleware that records request durations and serves them in the
+/// Prometheus text format.
+///
+/// Each request is timed into the `http_request_duration_seconds` histogram,
+/// labelled by method and by the matched route *pattern* (`/users/{id}`, not
+/// `/users/42`), which keeps the number of series bounded by the routes.
+/// Requests that match no route share the `unmatched` label.
+///
+/// `GET` requests to the metrics path (`/metrics` by default) are answered
+/// with the exposition text and are not themselves recorded. Mount the
+/// middleware globally so every route is timed:
+///
+/// ```rust,no_run
+/// use expressjs::prelude::*;
+///
+/// # async fn run() {
+/// let mut app = express();
+/// app.use_global(MetricsMiddleware::new());
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct MetricsMiddleware {
+    path: Arc<str>,
+    buckets: Arc<[f64]>,
+    histograms: Arc<DashMap<SeriesKey, Histogram>>,
+}
+
+/// Labels of one histogram series.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct SeriesKey {
+    route: Arc<str>,
+    method: &'static str,
+}
+
+/// Observations of one series. `counts[i]` is the number of observations in
+/// bucket `i` alone; the exposition makes them cumulative.
+#[derive(Debug, Clone)]
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Per-request state carried from `call` to `finish` in the response extensions.
+#[derive(Debug, Clone)]
+struct RequestTimer {
+    key: SeriesKey,
+    start: Instant,
+}
+
+impl Default for MetricsMiddleware {
+    fn default() -> Self {
+        Self {
+            path: "/metrics".into(),
+            buckets: DEFAULT_BUCKETS.into(),
+            histograms: Arc::new(DashMap::new()),
+        }
+    }
+}
+
+impl fmt::Debug for MetricsMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsMiddleware")
+            .field("path", &self.path)
+            .field("buckets", &self.buckets)
+            .field("series", &self.histograms.len())
+            .finish()
+    }
+}
+
+impl MetricsMiddleware {
+    /// Creates a metrics middleware serving `/metrics` with the default buckets.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the path the metrics are served on.
+    pub fn path(mut self, path: impl AsRef<str>) -> Self {
+        self.path = path.as_ref().into();
+        self
+    }
+
+    /// Replaces the histogram bucket upper bounds, in seconds.
+    ///
+    /// The bounds are sorted and deduplicated; `+Inf` is always added.
+    pub fn buckets(mut self, buckets: impl IntoIterator<Item = f64>) -> Self {
+        let mut buckets: Vec<f64> = buckets.into_iter().filter(|b| b.is_finite()).collect();
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        self.buckets = buckets.into();
+        self
+    }
+
+    /// Renders every series in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut series: Vec<_> = self
+            .histograms
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        series.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut out = String::from(
+            "# HELP http_request_duration_seconds Duration of HTTP requests in seconds.\n\
+             # TYPE http_request_duration_seconds histogram\n",
+        );
+        for (key, histogram) in series {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\"",
+                key.method,
+                escape_label(&key.route)
+            );
+            let mut cumulative = 0;
+            for (bound, count) in self.buckets.iter().zip(&histogram.counts) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            );
+        }
+        out
+    }
+
+    fn observe(&self, key: SeriesKey, seconds: f64) {
+        let mut histogram = self.histograms.entry(key).or_insert_with(|| Histogram {
+            counts: vec![0; self.buckets.len()],
+            sum: 0.0,
+            count: 0,
+        });
+        if let Some(i) = self.buckets.iter().position(|&bound| seconds <= bound) {
+            histogram.counts[i] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+}
+
+/// A bounded method label: anything outside the standard methods is `OTHER`.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::PATCH => "PATCH",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::CONNECT => "CONNECT",
+        _ => "OTHER",
+    }
+}
+
+/// Escapes a label value as the exposition format requires.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for MetricsMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        if req.method() == Method::GET && req.uri().path() == &*self.path {
+            res.content_type("text/plain; version=0.0.4")
+                .body(self.render());
+            return stop_res();
+        }
+
+        let route = req
+            .matched_path()
+            .map_or_else(|| UNMATCHED.into(), Arc::from);
+        res.extensions.insert(RequestTimer {
+            key: SeriesKey {
+                route,
+                method: method_label(req.method()),
+            },
+            start: Instant::now(),
+        });
+        next_res()
+    }
+
+    fn finish(&self, res: &mut Response) {
+        if let Some(timer) = res.extensions.remove::<RequestTimer>() {
+            self.observe(timer.key, timer.start.elapsed().as_secs_f64());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::response::ResponseBody;
+    use crate::router::Router;
+
+    async fn get(router: &Router<()>, uri: &str) -> Response {
+        let req = Request::builder().uri(uri).body(()).unwrap();
+        router.handle(req, Response::new()).await
+    }
+
+    #[tokio::test]
+    async fn test_requests_populate_duration_histogram() {
+        let mut router = Router::<()>::default();
+        router.use_with("/", MetricsMiddleware::new().buckets([0.0, 60.0]));
+        router.get("/users/{id}", async |_req: Request<()>, res: Response| {
+            res.send_text("user")
+        });
+
+        for id in 1..=3 {
+            get(&router, &format!("/users/{id}")).await;
+        }
+        get(&router, "/missing").await;
+
+        let res = get(&router, "/metrics").await;
+        assert_eq!(res.headers["content-type"], "text/plain; version=0.0.4");
+        let ResponseBody::Full(body) = &res.body else {
+            panic!("expected a full body");
+        };
+        let text = std::str::from_utf8(body).unwrap();
+
+        let users = r#"method="GET",route="/users/{id}""#;
+        for line in [
+            "# TYPE http_request_duration_seconds histogram".to_owned(),
+            format!(r#"http_request_duration_seconds_bucket{{{users},le="0"}} 0"#),
+            format!(r#"http_request_duration_seconds_bucket{{{users},le="60"}} 3"#),
+            format!(r#"http_request_duration_seconds_bucket{{{users},le="+Inf"}} 3"#),
+            format!("http_request_duration_seconds_count{{{users}}} 3"),
+            r#"http_request_duration_seconds_count{method="GET",route="unmatched"} 1"#.to_owned(),
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {line:?} in:\n{text}"
+            );
+        }
+        // Concrete paths never become labels, and scrapes aren't recorded.
+        assert!(!text.contains("/users/1"));
+        assert!(!text.contains("route=\"/metrics\""));
+    }
+}
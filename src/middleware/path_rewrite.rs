@@ -0,0 +1,138 @@
+use crate::handler::request::OriginalUri;
+use crate::handler::request::RequestExt;
+use crate::handler::{Request, Response};
+use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
+use async_trait::async_trait;
+use hyper::Uri;
+use serde_json::Value;
+
+/// Middleware that rewrites a path prefix, e.g. for an app deployed behind a
+/// proxy at `/app` whose routes are registered without it.
+///
+/// The router routes the rest of the chain by the rewritten path, so mount
+/// it first, at `/`: with [`strip_prefix("/app")`](Self::strip_prefix),
+/// `/app/users?page=2` is served by the `/users` route and `/app` by `/`.
+/// [`RequestExt::original_url`](crate::prelude::RequestExt::original_url)
+/// still returns the path as requested.
+///
+/// Requests without the prefix are answered with `404 Not Found`, unless
+/// [`pass_unmatched`](Self::pass_unmatched) lets them through unchanged.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let mut app = express();
+/// app.use_with_phase("/", Phase::PreRouting, PathRewriteMiddleware::strip_prefix("/app"));
+/// app.get("/users", async |_req, res| res.send_text("users")); // GET /app/users
+/// ```
+#[derive(Debug, Clone)]
+pub struct PathRewriteMiddleware {
+    from: String,
+    to: String,
+    pass_unmatched: bool,
+}
+
+impl PathRewriteMiddleware {
+    /// Removes `prefix` from request paths.
+    pub fn strip_prefix(prefix: impl AsRef<str>) -> Self {
+        Self::replace_prefix(prefix, "/")
+    }
+
+    /// Replaces the leading segments `from` of request paths with `to`, e.g.
+    /// `/v1` with `/api/v1`.
+    pub fn replace_prefix(from: impl AsRef<str>, to: impl AsRef<str>) -> Self {
+        Self {
+            from: from.as_ref().trim_end_matches('/').to_owned(),
+            to: to.as_ref().trim_end_matches('/').to_owned(),
+            pass_unmatched: false,
+        }
+    }
+
+    /// Lets requests without the prefix through unchanged instead of
+    /// answering `404 Not Found`.
+    pub fn pass_unmatched(mut self, pass: bool) -> Self {
+        self.pass_unmatched = pass;
+        self
+    }
+
+    /// The rewritten path, if `path` starts with the prefix segments.
+    fn rewrite(&self, path: &str) -> Option<String> {
+        let rest = path.strip_prefix(self.from.as_str())?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        let path = format!("{}{rest}", self.to);
+        Some(if path.is_empty() {
+            "/".to_owned()
+        } else {
+            path
+        })
+    }
+}
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for PathRewriteMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        let Some(path) = self.rewrite(req.uri().path()) else {
+            if self.pass_unmatched {
+                return next_res();
+            }
+            res.respond_enveloped_error(404, "Not Found", Value::Null, req.prefers_json());
+            return stop_res();
+        };
+
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        };
+        let mut parts = req.uri().clone().into_parts();
+        parts.path_and_query = path_and_query.parse().ok();
+        let Ok(uri) = Uri::from_parts(parts) else {
+            return next_res();
+        };
+        let original = std::mem::replace(req.uri_mut(), uri);
+        if req.extensions().get::<OriginalUri>().is_none() {
+            req.extensions_mut().insert(OriginalUri(original));
+        }
+        next_res()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+
+    async fn rewrite(mw: &PathRewriteMiddleware, uri: &str) -> (Request<()>, Response) {
+        let mut req = Request::builder().uri(uri).body(()).unwrap();
+        let mut res = Response::new();
+        mw.call(&mut req, &mut res).await;
+        (req, res)
+    }
+
+    #[tokio::test]
+    async fn test_rewrites_prefixed_paths_only() {
+        let mw = PathRewriteMiddleware::strip_prefix("/app/");
+        let (req, _) = rewrite(&mw, "/app/users?page=2").await;
+        assert_eq!(req.uri().path(), "/users");
+        assert_eq!(req.uri().query(), Some("page=2"));
+        assert_eq!(req.original_url(), "/app/users?page=2");
+
+        for root in ["/app", "/app/"] {
+            let (req, _) = rewrite(&mw, root).await;
+            assert_eq!(req.uri().path(), "/", "{root}");
+        }
+
+        let (req, res) = rewrite(&mw, "/application").await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        assert_eq!(req.original_url(), "/application");
+
+        let mw = mw.pass_unmatched(true);
+        let (req, res) = rewrite(&mw, "/users").await;
+        assert_eq!((req.uri().path(), res.status), ("/users", StatusCode::OK));
+
+        let mw = PathRewriteMiddleware::replace_prefix("/v1", "/api/v1");
+        let (req, _) = rewrite(&mw, "/v1/jobs").await;
+        assert_eq!(req.uri().path(), "/api/v1/jobs");
+    }
+}
@@ -1,12 +1,12 @@
-use crate::handler::{ExpressResponse, Request, Response, request::RequestExt};
+use crate::handler::response::try_insert_header;
+use crate::handler::{Request, Response, request::RequestExt};
 use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
 use async_trait::async_trait;
 use dashmap::DashMap;
-use hyper::header::HeaderValue;
-use serde_json::json;
+use hyper::header::RETRY_AFTER;
 use std::{
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
 /// Middleware that limits the number of requests a client can make within a given time window.
@@ -15,6 +15,14 @@ use std::{
 /// If the number of requests from a given IP exceeds the configured `requests_per_minute`
 /// within the `window_size`, subsequent requests are blocked until the window resets.
 ///
+/// An optional bandwidth budget ([`RateLimitMiddleware::bandwidth_limit`]) additionally
+/// caps the number of bytes a client may transfer per window: request bodies are
+/// charged from their `Content-Length` and response bodies once they are produced.
+///
+/// Blocked requests get a `429` whose `Retry-After` is the time left until the
+/// client's window resets, as delta-seconds or, with
+/// [`RateLimitMiddleware::retry_after_http_date`], as an HTTP date.
+///
 /// Note: In a production setting, a distributed store like Redis should be preferred.
 #[derive(Debug, Clone)]
 pub struct RateLimitMiddleware {
@@ -24,6 +32,12 @@ pub struct RateLimitMiddleware {
     /// The size of the rate limit window (e.g. 60 seconds).
     pub window_size: Duration,
 
+    /// The maximum number of request + response body bytes per client within the time window.
+    pub bytes_per_window: Option<u64>,
+
+    /// Whether `Retry-After` is sent as an HTTP date instead of delta-seconds.
+    pub retry_after_date: bool,
+
     /// Internal in-memory store mapping IP addresses to rate limit state.
     store: SharedRateLimitStore,
 }
@@ -41,13 +55,21 @@ type SharedRateLimitStore = Arc<DashMap<String, RateLimitEntry>>;
 struct RateLimitEntry {
     timestamp: std::time::Instant,
     count: u32,
+    bytes: u64,
 }
 
+/// The client key a request was accounted under, kept so response bytes can be
+/// charged to the same entry in `finish`.
+#[derive(Debug, Clone)]
+struct RateLimitKey(String);
+
 impl Default for RateLimitMiddleware {
     fn default() -> Self {
         Self {
             requests_per_minute: 60,
             window_size: Duration::from_secs(60),
+            bytes_per_window: None,
+            retry_after_date: false,
             store: Arc::new(DashMap::new()),
         }
     }
@@ -56,41 +78,52 @@ impl Default for RateLimitMiddleware {
 #[async_trait]
 impl<B: Send + Sync + 'static> Middleware<B> for RateLimitMiddleware {
     async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
-        // Use the real socket address as the primary key — it cannot be spoofed
-        // unlike X-Forwarded-For headers. Fall back to proxy headers only when
-        // the socket address is unavailable (shouldn't happen in practice).
-        let client_ip: String = req
-            .ip()
-            .map(|addr| addr.ip().to_string())
-            .unwrap_or_else(|| {
-                req.get_header("X-Forwarded-For")
-                    .or_else(|| req.get_header("X-Real-IP"))
-                    .unwrap_or("unknown")
-                    .to_string()
-            });
-
-        if self.is_rate_limited(&client_ip) {
-            let retry_after = self.window_size.as_secs().to_string();
-            res.header("Retry-After", HeaderValue::from_str(&retry_after).unwrap());
-
-            let wants_json = req.prefers_json();
-
-            res.respond_error(
-                429,
-                "Rate limit exceeded",
-                json!({
-                    "error": "Rate limit exceeded",
-                    "message": "Too many requests",
-                    "retry_after": retry_after
-                }),
-                wants_json,
-            );
+        // Key on the client address, which only follows `Forwarded` /
+        // X-Forwarded-For from trusted proxies and is otherwise the socket
+        // address, so it cannot be spoofed. Fall back to proxy headers only
+        // when the socket address is unavailable (shouldn't happen in practice).
+        let client_ip: String = req.client_ip().map(|ip| ip.to_string()).unwrap_or_else(|| {
+            req.get_header("X-Forwarded-For")
+                .or_else(|| req.get_header("X-Real-IP"))
+                .unwrap_or("unknown")
+                .to_string()
+        });
 
+        let request_bytes = req
+            .get_header("Content-Length")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0);
+
+        if let Err(wait) = self.check(&client_ip, request_bytes) {
+            res.respond_too_many_requests(wait, req.prefers_json());
+            if self.retry_after_date
+                && let Some(at) = SystemTime::now().checked_add(wait)
+            {
+                try_insert_header(&mut res.headers, RETRY_AFTER, httpdate::fmt_http_date(at));
+            }
             return stop_res();
         }
 
+        if self.bytes_per_window.is_some() {
+            res.extensions.insert(RateLimitKey(client_ip));
+        }
+
         next_res()
     }
+
+    fn finish(&self, res: &mut Response) {
+        if self.bytes_per_window.is_none() {
+            return;
+        }
+        let Some(RateLimitKey(key)) = res.extensions.get::<RateLimitKey>() else {
+            return;
+        };
+        if let Some(len) = res.body.content_length()
+            && let Some(mut entry) = self.store.get_mut(key)
+        {
+            entry.bytes = entry.bytes.saturating_add(len);
+        }
+    }
 }
 
 impl RateLimitMiddleware {
@@ -99,27 +132,52 @@ impl RateLimitMiddleware {
         Self {
             requests_per_minute,
             window_size,
+            bytes_per_window: None,
+            retry_after_date: false,
             store: std::sync::Arc::new(DashMap::new()),
         }
     }
 
-    fn is_rate_limited(&self, ip: &str) -> bool {
+    /// Sets a bandwidth budget: the maximum number of request and response body
+    /// bytes a client may transfer within one window.
+    pub fn bandwidth_limit(mut self, bytes_per_window: u64) -> Self {
+        self.bytes_per_window = Some(bytes_per_window);
+        self
+    }
+
+    /// Sends `Retry-After` as an HTTP date (`Wed, 21 Oct 2015 07:28:00 GMT`)
+    /// rather than a number of seconds.
+    pub fn retry_after_http_date(mut self) -> Self {
+        self.retry_after_date = true;
+        self
+    }
+
+    /// Accounts a request, or returns how long until the client's window resets.
+    fn check(&self, ip: &str, request_bytes: u64) -> Result<(), Duration> {
         let now = Instant::now();
 
         let mut entry = self.store.entry(ip.to_string()).or_insert(RateLimitEntry {
             timestamp: now,
             count: 0,
+            bytes: 0,
         });
 
         if now.duration_since(entry.timestamp) > self.window_size {
             entry.timestamp = now;
-            entry.count = 1;
-            false
-        } else if entry.count >= self.requests_per_minute {
-            true
+            entry.count = 0;
+            entry.bytes = 0;
+        }
+
+        let over_bandwidth = self
+            .bytes_per_window
+            .is_some_and(|budget| entry.bytes.saturating_add(request_bytes) > budget);
+
+        if entry.count >= self.requests_per_minute || over_bandwidth {
+            Err((entry.timestamp + self.window_size).saturating_duration_since(now))
         } else {
             entry.count += 1;
-            false
+            entry.bytes = entry.bytes.saturating_add(request_bytes);
+            Ok(())
         }
     }
 }
@@ -127,7 +185,7 @@ impl RateLimitMiddleware {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::handler::Response;
+    use crate::handler::{ExpressResponse, Response};
     use std::time::Duration;
 
     #[tokio::test]
@@ -162,4 +220,74 @@ mod tests {
         let mut req3 = Request::builder().uri("/").body(()).unwrap();
         assert!(mw.call(&mut req3, &mut res).await.is_next());
     }
+
+    #[tokio::test]
+    async fn test_retry_after_counts_down_to_window_reset() {
+        let mw = RateLimitMiddleware::new(1, Duration::from_secs(60));
+        let mut req = Request::builder().uri("/").body(()).unwrap();
+        assert!(mw.call(&mut req, &mut Response::new()).await.is_next());
+
+        let mut res = Response::new();
+        assert!(mw.call(&mut req, &mut res).await.is_stop());
+        assert_eq!(res.headers[RETRY_AFTER], "60");
+
+        // Late in the window, only the remaining seconds are advertised.
+        mw.store.get_mut("unknown").unwrap().timestamp -= Duration::from_secs(55);
+        let mut res = Response::new();
+        assert!(mw.call(&mut req, &mut res).await.is_stop());
+        let secs: u64 = res.headers[RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((1..=5).contains(&secs), "Retry-After: {secs}");
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_http_date() {
+        let mw = RateLimitMiddleware::new(0, Duration::from_secs(30)).retry_after_http_date();
+        let mut req = Request::builder().uri("/").body(()).unwrap();
+        let mut res = Response::new();
+        assert!(mw.call(&mut req, &mut res).await.is_stop());
+
+        let header = res.headers[RETRY_AFTER].to_str().unwrap();
+        let at = httpdate::parse_http_date(header).unwrap();
+        let wait = at.duration_since(SystemTime::now()).unwrap_or_default();
+        assert!(wait <= Duration::from_secs(30) && wait >= Duration::from_secs(28));
+    }
+
+    fn upload(len: usize) -> Request<()> {
+        Request::builder()
+            .uri("/")
+            .header("Content-Length", len)
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth_limit_request_bytes() {
+        let mw = RateLimitMiddleware::new(1000, Duration::from_secs(60)).bandwidth_limit(1024);
+
+        for _ in 0..10 {
+            let mut res = Response::new();
+            assert!(mw.call(&mut upload(50), &mut res).await.is_next());
+        }
+
+        let mut res = Response::new();
+        assert!(mw.call(&mut upload(600), &mut res).await.is_stop());
+        assert_eq!(res.get_status(), hyper::StatusCode::TOO_MANY_REQUESTS);
+
+        // Small requests that still fit in the remaining budget are accepted.
+        let mut res = Response::new();
+        assert!(mw.call(&mut upload(10), &mut res).await.is_next());
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth_limit_response_bytes() {
+        let mw = RateLimitMiddleware::new(1000, Duration::from_secs(60)).bandwidth_limit(1024);
+
+        let mut res = Response::new();
+        assert!(mw.call(&mut upload(0), &mut res).await.is_next());
+        res = res.body(vec![0u8; 2048]);
+        Middleware::<()>::finish(&mw, &mut res);
+
+        let mut res = Response::new();
+        assert!(mw.call(&mut upload(0), &mut res).await.is_stop());
+    }
 }
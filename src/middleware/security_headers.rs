@@ -1,11 +1,59 @@
+use crate::handler::request::Nonce;
 use crate::handler::{ExpressResponse, Request, Response};
 use crate::middleware::{Middleware, MiddlewareResult, next_res};
 use async_trait::async_trait;
-use hyper::header::HeaderValue;
+use hyper::header::{CONTENT_SECURITY_POLICY, HeaderName, HeaderValue};
+use log::warn;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::sync::{Arc, OnceLock};
 
-/// Middleware that injects common HTTP security headers into the response.
+/// The Content-Security-Policy, with `script-src` allowing `'self'` and
+/// `script_source`: `'unsafe-inline'`, or a nonce in nonce mode.
+fn content_security_policy(script_source: &str) -> String {
+    format!(
+        "default-src 'self'; script-src 'self' {script_source}; style-src 'self' 'unsafe-inline';"
+    )
+}
+
+static CONTENT_SECURITY_POLICY_VALUE: Lazy<HeaderValue> = Lazy::new(|| {
+    HeaderValue::try_from(content_security_policy("'unsafe-inline'"))
+        .expect("the default policy is a valid header value")
+});
+
+/// How long browsers keep the reporting configuration, in seconds (30 days).
+const REPORTING_MAX_AGE: u32 = 30 * 24 * 60 * 60;
+
+/// Middleware that injects common HTTP security headers into the response,
+/// with the default [`SecurityHeaders`] settings.
+///
+/// ```rust
+/// use expressjs::prelude::*;
 ///
-/// This middleware enhances basic security by setting the following headers:
+/// let mut app = express();
+/// app.use_global(SecurityHeadersMiddleware);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SecurityHeadersMiddleware;
+
+/// The shared [`SecurityHeaders`] behind [`SecurityHeadersMiddleware`].
+static DEFAULT_SECURITY_HEADERS: Lazy<SecurityHeaders> = Lazy::new(SecurityHeaders::default);
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for SecurityHeadersMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        Middleware::<B>::call(&*DEFAULT_SECURITY_HEADERS, req, res).await
+    }
+
+    fn finish(&self, res: &mut Response) {
+        Middleware::<B>::finish(&*DEFAULT_SECURITY_HEADERS, res);
+    }
+}
+
+/// Configurable security headers middleware; [`SecurityHeadersMiddleware`]
+/// is one with the default settings.
+///
+/// It enhances basic security by setting the following headers:
 /// - `Content-Security-Policy`
 /// - `X-XSS-Protection`
 /// - `X-Content-Type-Options`
@@ -15,21 +63,195 @@ use hyper::header::HeaderValue;
 ///
 /// These headers help mitigate common browser-based attacks like XSS, MIME sniffing,
 /// clickjacking, and downgrade attacks.
-#[derive(Debug, Clone)]
-pub struct SecurityHeadersMiddleware;
+///
+/// Browsers can also report violations and network errors to endpoints of
+/// yours, with `Report-To`/`Reporting-Endpoints`, `NEL` and `Expect-CT`
+/// and the CSP `report-to`/`report-uri` directives. They are off by default:
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let headers = SecurityHeaders::new()
+///     .report_to("default", "https://reports.example.com/browser")
+///     .csp_report_to("default")
+///     .nel("default");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeaders {
+    script_src_nonce: bool,
+    endpoints: Vec<(String, String)>,
+    nel: Option<String>,
+    expect_ct: Option<String>,
+    /// Directives appended to the CSP, e.g. ` report-to default;`.
+    csp_reporting: String,
+    /// The reporting headers, built from the fields above.
+    reporting: Vec<(HeaderName, HeaderValue)>,
+    /// Guards the check that the NEL group is declared, run on first use.
+    nel_checked: Arc<OnceLock<()>>,
+}
+
+impl SecurityHeaders {
+    /// Creates the middleware with its default headers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows inline scripts by nonce rather than with `'unsafe-inline'`.
+    ///
+    /// Each request gets a fresh [`Nonce`], which handlers read with
+    /// [`RequestExt::csp_nonce`](crate::prelude::RequestExt::csp_nonce) and
+    /// which is added to the `script-src` directive once the response is done.
+    pub fn script_src_nonce(mut self) -> Self {
+        self.script_src_nonce = true;
+        self
+    }
+
+    /// Declares the reporting endpoint `group` at `url`, sent in both the
+    /// `Report-To` and `Reporting-Endpoints` headers. Reports go to a group
+    /// named by [`csp_report_to`](Self::csp_report_to) or [`nel`](Self::nel).
+    pub fn report_to(mut self, group: impl Into<String>, url: impl Into<String>) -> Self {
+        let group = group.into();
+        self.endpoints.retain(|(existing, _)| *existing != group);
+        self.endpoints.push((group, url.into()));
+        self.build_reporting();
+        self
+    }
+
+    /// Enables Network Error Logging, with reports sent to the endpoint `group`.
+    pub fn nel(mut self, group: impl Into<String>) -> Self {
+        self.nel = Some(group.into());
+        self.build_reporting();
+        self
+    }
+
+    /// Sends CSP violation reports to the endpoint `group`, with the
+    /// `report-to` directive. A group that can't be written in the policy,
+    /// e.g. one containing `;`, is ignored with a warning.
+    pub fn csp_report_to(self, group: impl AsRef<str>) -> Self {
+        self.csp_directive("report-to", group.as_ref())
+    }
+
+    /// Sends CSP violation reports to `uri` with the older `report-uri`
+    /// directive, for browsers without `report-to` support. Validated like
+    /// [`csp_report_to`](Self::csp_report_to).
+    pub fn csp_report_uri(self, uri: impl AsRef<str>) -> Self {
+        self.csp_directive("report-uri", uri.as_ref())
+    }
+
+    /// Appends `directive value` to the policy, unless `value` could end the
+    /// directive or start another one.
+    fn csp_directive(mut self, directive: &str, value: &str) -> Self {
+        let valid = !value.is_empty()
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && b != b';' && b != b',');
+        if valid {
+            self.csp_reporting
+                .push_str(&format!(" {directive} {value};"));
+        } else {
+            warn!("ignoring invalid CSP {directive} value {value:?}");
+        }
+        self
+    }
+
+    /// Sends `Expect-CT`, asking browsers to report certificates missing
+    /// from Certificate Transparency logs to `report_uri`. Only older
+    /// browsers still read it.
+    pub fn expect_ct(mut self, report_uri: impl Into<String>) -> Self {
+        self.expect_ct = Some(report_uri.into());
+        self.build_reporting();
+        self
+    }
+
+    fn build_reporting(&mut self) {
+        let mut headers = Vec::new();
+        if !self.endpoints.is_empty() {
+            let groups: Vec<String> = self
+                .endpoints
+                .iter()
+                .map(|(group, url)| {
+                    json!({
+                        "group": group,
+                        "max_age": REPORTING_MAX_AGE,
+                        "endpoints": [{ "url": url }],
+                    })
+                    .to_string()
+                })
+                .collect();
+            headers.push(("report-to", groups.join(", ")));
+            let endpoints: Vec<String> = self
+                .endpoints
+                .iter()
+                .map(|(group, url)| format!("{group}={}", json!(url)))
+                .collect();
+            headers.push(("reporting-endpoints", endpoints.join(", ")));
+        }
+        if let Some(group) = &self.nel {
+            let policy = json!({ "report_to": group, "max_age": REPORTING_MAX_AGE });
+            headers.push(("nel", policy.to_string()));
+        }
+        if let Some(uri) = &self.expect_ct {
+            headers.push((
+                "expect-ct",
+                format!("max-age={REPORTING_MAX_AGE}, report-uri={}", json!(uri)),
+            ));
+        }
+
+        self.reporting = headers
+            .into_iter()
+            .filter_map(|(name, value)| match HeaderValue::try_from(value) {
+                Ok(value) => Some((HeaderName::from_static(name), value)),
+                Err(_) => {
+                    warn!("ignoring {name} header with an invalid endpoint");
+                    None
+                }
+            })
+            .collect();
+    }
+
+    /// Warns once if NEL reports go to a group no endpoint declares; only
+    /// known once the builder calls are done.
+    fn check_nel_group(&self) {
+        self.nel_checked.get_or_init(|| {
+            if let Some(group) = &self.nel
+                && !self.endpoints.iter().any(|(existing, _)| existing == group)
+            {
+                warn!("NEL reports go to {group:?}, which no report_to endpoint declares");
+            }
+        });
+    }
+
+    /// The policy allowing `script_source`, with the reporting directives
+    /// appended.
+    fn policy(&self, script_source: &str) -> HeaderValue {
+        let policy = content_security_policy(script_source);
+        HeaderValue::try_from(format!("{policy}{}", self.csp_reporting)).unwrap_or_else(|_| {
+            warn!("sending the Content-Security-Policy without its reporting directives");
+            HeaderValue::try_from(policy).unwrap_or_else(|_| CONTENT_SECURITY_POLICY_VALUE.clone())
+        })
+    }
+}
 
 #[async_trait]
-impl<B: Send + Sync + 'static> Middleware<B> for SecurityHeadersMiddleware {
+impl<B: Send + Sync + 'static> Middleware<B> for SecurityHeaders {
     /// Injects security headers into the response.
     ///
     /// This middleware does **not** inspect the request or block it based on policy.
     /// It simply adds defensive headers for the response.
-    async fn call(&self, _req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
-        // Content Security Policy
-        res.header(
-            "Content-Security-Policy",
-            HeaderValue::from_static("default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline';")
-        );
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        // Content Security Policy, completed in `finish` once a nonce is in use
+        if self.script_src_nonce {
+            let nonce = Nonce::default();
+            req.extensions_mut().insert(nonce.clone());
+            res.extensions.insert(nonce);
+        } else if self.csp_reporting.is_empty() {
+            res.header(
+                CONTENT_SECURITY_POLICY,
+                CONTENT_SECURITY_POLICY_VALUE.clone(),
+            );
+        } else {
+            res.header(CONTENT_SECURITY_POLICY, self.policy("'unsafe-inline'"));
+        }
 
         // XSS Protection
         res.header(
@@ -58,8 +280,25 @@ impl<B: Send + Sync + 'static> Middleware<B> for SecurityHeadersMiddleware {
             HeaderValue::from_static("max-age=31536000; includeSubDomains"),
         );
 
+        // Where browsers send their reports
+        self.check_nel_group();
+        for (name, value) in &self.reporting {
+            res.headers.insert(name, value.clone());
+        }
+
         next_res()
     }
+
+    fn finish(&self, res: &mut Response) {
+        let Some(nonce) = res.extensions.get::<Nonce>() else {
+            return;
+        };
+        // A policy the handler set on purpose is kept.
+        if !res.headers.contains_key(CONTENT_SECURITY_POLICY) {
+            let policy = self.policy(&format!("'nonce-{}'", nonce.get()));
+            res.headers.insert(CONTENT_SECURITY_POLICY, policy);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -92,4 +331,112 @@ mod tests {
             "max-age=31536000; includeSubDomains"
         );
     }
+
+    async fn headers_of(mw: SecurityHeaders) -> hyper::HeaderMap {
+        let mut req = Request::builder().uri("/").body(()).unwrap();
+        let mut res = Response::new();
+        mw.call(&mut req, &mut res).await;
+        Middleware::<()>::finish(&mw, &mut res);
+        res.headers
+    }
+
+    #[tokio::test]
+    async fn test_reporting_headers_when_configured() {
+        let plain = headers_of(SecurityHeaders::new()).await;
+        for name in ["report-to", "reporting-endpoints", "nel", "expect-ct"] {
+            assert!(!plain.contains_key(name), "{name}");
+        }
+
+        let mw = SecurityHeaders::new()
+            .report_to("default", "https://reports.example.com/browser")
+            .report_to("csp", "https://reports.example.com/csp")
+            .nel("default")
+            .csp_report_to("csp")
+            .csp_report_uri("/csp-reports")
+            .expect_ct("https://reports.example.com/ct");
+        let headers = headers_of(mw.clone()).await;
+        assert_eq!(
+            headers["report-to"],
+            r#"{"endpoints":[{"url":"https://reports.example.com/browser"}],"group":"default","max_age":2592000}, {"endpoints":[{"url":"https://reports.example.com/csp"}],"group":"csp","max_age":2592000}"#
+        );
+        assert_eq!(
+            headers["reporting-endpoints"],
+            r#"default="https://reports.example.com/browser", csp="https://reports.example.com/csp""#
+        );
+        assert_eq!(
+            headers["nel"],
+            r#"{"max_age":2592000,"report_to":"default"}"#
+        );
+        assert_eq!(
+            headers["expect-ct"],
+            r#"max-age=2592000, report-uri="https://reports.example.com/ct""#
+        );
+        let policy = headers["content-security-policy"].to_str().unwrap();
+        assert!(policy.ends_with("'unsafe-inline'; report-to csp; report-uri /csp-reports;"));
+
+        // The nonce policy keeps the reporting directives too.
+        let headers = headers_of(mw.script_src_nonce()).await;
+        let policy = headers["content-security-policy"].to_str().unwrap();
+        assert!(policy.contains("'nonce-") && policy.ends_with("report-uri /csp-reports;"));
+    }
+
+    #[tokio::test]
+    async fn test_nonce_policy_keeps_a_handler_policy() {
+        let mw = SecurityHeaders::new().script_src_nonce();
+        let mut req = Request::builder().uri("/").body(()).unwrap();
+        let mut res = Response::new();
+        mw.call(&mut req, &mut res).await;
+        res.headers.insert(
+            CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("default-src 'none'"),
+        );
+        Middleware::<()>::finish(&mw, &mut res);
+        assert_eq!(res.headers[CONTENT_SECURITY_POLICY], "default-src 'none'");
+
+        let headers = headers_of(SecurityHeaders::new().script_src_nonce()).await;
+        let policy = headers[CONTENT_SECURITY_POLICY].to_str().unwrap();
+        let nonce = policy
+            .split("'nonce-")
+            .nth(1)
+            .and_then(|rest| rest.split('\'').next())
+            .unwrap();
+        assert_eq!(policy, content_security_policy(&format!("'nonce-{nonce}'")));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_csp_reporting_values_keep_the_base_policy() {
+        crate::test_logger::capture();
+        let ignored = [
+            SecurityHeaders::new().csp_report_to("x; script-src *"),
+            SecurityHeaders::new().csp_report_uri("/a, /b"),
+            SecurityHeaders::new().csp_report_uri("/csp\nreports"),
+        ];
+        let records = crate::test_logger::take();
+        assert_eq!(records.len(), 3, "{records:?}");
+        assert!(
+            records[0]
+                .message
+                .starts_with("ignoring invalid CSP report-to")
+        );
+        for mw in ignored {
+            for mw in [mw.clone(), mw.script_src_nonce()] {
+                let headers = headers_of(mw).await;
+                let policy = headers["content-security-policy"].to_str().unwrap();
+                assert!(policy.starts_with("default-src 'self';"), "{policy}");
+                assert!(
+                    !policy.contains("report") && !policy.contains('*'),
+                    "{policy}"
+                );
+            }
+        }
+
+        // The NEL group may be declared after `nel`.
+        let mw = SecurityHeaders::new()
+            .nel("default")
+            .report_to("default", "https://reports.example.com/browser");
+        headers_of(mw).await;
+        assert!(crate::test_logger::take().is_empty());
+        headers_of(SecurityHeaders::new().nel("default")).await;
+        assert_eq!(crate::test_logger::take().len(), 1);
+    }
 }
@@ -0,0 +1,54 @@
+use crate::handler::request::ServerTimings;
+use crate::handler::{Request, Response};
+use crate::middleware::{Middleware, MiddlewareResult, next_res};
+use async_trait::async_trait;
+use hyper::header::{HeaderName, HeaderValue};
+
+const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+/// Middleware that reports the durations handlers record with
+/// [`RequestExt::time_mark`](crate::prelude::RequestExt::time_mark) in a
+/// `Server-Timing` header, shown by browser dev tools next to the request.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let mut app = express();
+/// app.use_global(ServerTimingMiddleware::new());
+/// app.get("/users", async |req, res| {
+///     let _db = req.time_mark("db");
+///     // ... query the database ...
+///     res.send_text("users")
+/// });
+/// ```
+///
+/// Timings tell clients how long internal operations took, so enable it
+/// where that is acceptable, e.g. behind an internal route or in staging.
+#[derive(Debug, Clone, Default)]
+pub struct ServerTimingMiddleware;
+
+impl ServerTimingMiddleware {
+    /// Creates the middleware.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for ServerTimingMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        let timings = ServerTimings::default();
+        req.extensions_mut().insert(timings.clone());
+        res.extensions.insert(timings);
+        next_res()
+    }
+
+    fn finish(&self, res: &mut Response) {
+        if let Some(timings) = res.extensions.remove::<ServerTimings>()
+            && let Some(value) = timings.header_value()
+            && let Ok(value) = HeaderValue::try_from(value)
+        {
+            res.headers.append(SERVER_TIMING, value);
+        }
+    }
+}
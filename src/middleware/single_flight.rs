@@ -0,0 +1,252 @@
+use crate::handler::response::ResponseBody;
+use crate::handler::{Request, Response};
+use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
+use async_trait::async_trait;
+use bytes::Bytes;
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use hyper::header::{HeaderName, SET_COOKIE};
+use hyper::{HeaderMap, Method, StatusCode};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Middleware that coalesces identical concurrent `GET` and `HEAD` requests:
+/// the first one runs the handler while the others wait, then they all answer
+/// with a copy of its response. Under a burst of requests for an expensive
+/// resource, the handler runs once instead of once per request.
+///
+/// Requests are identical when they share method, path, query and the values
+/// of the headers listed with [`vary`](Self::vary). Nothing is cached: a
+/// request arriving after the response was produced runs the handler again.
+///
+/// The response is shared between different clients, so list every request
+/// header it depends on, e.g. `Authorization` or `Accept-Language`, and
+/// register the middleware after those that set per-request headers. A
+/// response that sets a cookie or has a streaming body isn't shared; nor is
+/// one whose request was cancelled. In those cases each waiting request runs
+/// the handler itself. Other methods pass through.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let mut app = express();
+/// app.use_with("/reports", SingleFlightMiddleware::new().vary("Authorization"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SingleFlightMiddleware {
+    vary: Vec<HeaderName>,
+    flights: Arc<DashMap<String, Flight>>,
+}
+
+/// Resolves once the leading request has a response, or to `None` when it
+/// has none that can be shared.
+type Flight = watch::Receiver<Option<Option<Arc<SharedResponse>>>>;
+
+impl SingleFlightMiddleware {
+    /// Creates the middleware, keying requests by method, path and query.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a request header whose value tells requests apart.
+    pub fn vary(mut self, header: impl TryInto<HeaderName>) -> Self {
+        match header.try_into() {
+            Ok(header) => self.vary.push(header),
+            Err(_) => log::warn!("ignoring invalid header name passed to vary"),
+        }
+        self
+    }
+
+    fn key<B>(&self, req: &Request<B>) -> String {
+        let mut key = format!("{} {}", req.method(), req.uri());
+        for name in &self.vary {
+            for value in req.headers().get_all(name) {
+                key.push('\n');
+                key.push_str(name.as_str());
+                key.push(':');
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+        key
+    }
+}
+
+/// What a waiting request copies from the leading one.
+#[derive(Debug)]
+struct SharedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Option<Vec<Bytes>>,
+}
+
+impl SharedResponse {
+    /// Copies `res` unless its body streams or it sets a cookie. Lazy bodies
+    /// are produced now, so the leader sends the same bytes.
+    fn capture(res: &mut Response) -> Option<Self> {
+        if res.headers.contains_key(SET_COOKIE) {
+            return None;
+        }
+        res.body = match std::mem::take(&mut res.body) {
+            ResponseBody::Lazy(produce) => ResponseBody::Full(produce()),
+            body => body,
+        };
+        let body = match &res.body {
+            ResponseBody::Empty => None,
+            ResponseBody::Full(bytes) => Some(vec![bytes.clone()]),
+            ResponseBody::Buffered(chunks) => Some(chunks.clone()),
+            _ => return None,
+        };
+        Some(Self {
+            status: res.status,
+            headers: res.headers.clone(),
+            body,
+        })
+    }
+
+    fn write_to(&self, res: &mut Response) {
+        res.status = self.status;
+        for name in self.headers.keys() {
+            res.headers.remove(name);
+        }
+        for (name, value) in &self.headers {
+            res.headers.append(name, value.clone());
+        }
+        res.body = match &self.body {
+            None => ResponseBody::Empty,
+            Some(chunks) => ResponseBody::Buffered(chunks.clone()),
+        };
+    }
+}
+
+/// Held in the leading request's response extensions. Dropping it, whether
+/// after publishing or because the request was cancelled, ends the flight;
+/// requests still waiting then run the handler themselves.
+#[derive(Debug, Clone)]
+struct Leader(Arc<LeaderGuard>);
+
+#[derive(Debug)]
+struct LeaderGuard {
+    key: String,
+    flights: Arc<DashMap<String, Flight>>,
+    tx: watch::Sender<Option<Option<Arc<SharedResponse>>>>,
+}
+
+impl Drop for LeaderGuard {
+    fn drop(&mut self) {
+        // Only the leader removes its key, so the entry is this flight's.
+        self.flights.remove(&self.key);
+    }
+}
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for SingleFlightMiddleware {
+    async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return next_res();
+        }
+
+        let key = self.key(req);
+        let mut flight = match self.flights.entry(key.clone()) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                let (tx, rx) = watch::channel(None);
+                entry.insert(rx);
+                res.extensions.insert(Leader(Arc::new(LeaderGuard {
+                    key,
+                    flights: Arc::clone(&self.flights),
+                    tx,
+                })));
+                return next_res();
+            }
+        };
+
+        let shared = match flight.wait_for(Option::is_some).await {
+            Ok(outcome) => outcome.clone().flatten(),
+            Err(_) => None,
+        };
+        match shared {
+            Some(shared) => {
+                shared.write_to(res);
+                stop_res()
+            }
+            None => next_res(),
+        }
+    }
+
+    fn finish(&self, res: &mut Response) {
+        if let Some(Leader(guard)) = res.extensions.remove::<Leader>() {
+            let shared = SharedResponse::capture(res).map(Arc::new);
+            guard.tx.send_replace(Some(shared));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::ExpressResponse;
+
+    #[tokio::test]
+    async fn test_waiting_requests_share_the_leaders_response() {
+        let mw = SingleFlightMiddleware::new();
+        let get = || Request::builder().uri("/report?y=2024").body(()).unwrap();
+
+        let mut leader = Response::new();
+        assert!(mw.call(&mut get(), &mut leader).await.is_next());
+
+        let follower = tokio::spawn({
+            let mw = mw.clone();
+            async move {
+                let mut res = Response::new();
+                let result = mw.call(&mut get(), &mut res).await;
+                (result, res)
+            }
+        });
+        tokio::task::yield_now().await;
+        assert!(!follower.is_finished());
+
+        // A different query isn't the same request.
+        let mut other = Request::builder().uri("/report?y=2025").body(()).unwrap();
+        assert!(mw.call(&mut other, &mut Response::new()).await.is_next());
+
+        let mut leader = leader.status_code(201).send_text("expensive");
+        for link in ["</a>; rel=\"next\"", "</z>; rel=\"last\""] {
+            leader.headers.append("link", link.parse().unwrap());
+        }
+        Middleware::<()>::finish(&mw, &mut leader);
+        let (result, res) = follower.await.unwrap();
+        assert!(result.is_stop());
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body.content_length(), Some(9));
+        assert_eq!(res.headers["content-type"], leader.headers["content-type"]);
+        let links: Vec<_> = res.headers.get_all("link").iter().collect();
+        assert_eq!(links, ["</a>; rel=\"next\"", "</z>; rel=\"last\""]);
+
+        // The flight is over: the next request leads a new one.
+        assert!(mw.call(&mut get(), &mut Response::new()).await.is_next());
+    }
+
+    #[tokio::test]
+    async fn test_unshareable_or_abandoned_flights_release_waiters() {
+        let mw = SingleFlightMiddleware::new();
+        let get = || Request::builder().uri("/").body(()).unwrap();
+
+        for outcome in ["cookie", "cancelled"] {
+            let mut leader = Response::new();
+            assert!(mw.call(&mut get(), &mut leader).await.is_next());
+            let follower = tokio::spawn({
+                let mw = mw.clone();
+                async move { mw.call(&mut get(), &mut Response::new()).await }
+            });
+            tokio::task::yield_now().await;
+
+            if outcome == "cookie" {
+                let mut leader = leader.cookie(cookie::Cookie::new("sid", "1"));
+                Middleware::<()>::finish(&mw, &mut leader);
+            } else {
+                drop(leader);
+            }
+            assert!(follower.await.unwrap().is_next(), "{outcome}");
+        }
+    }
+}
@@ -1,10 +1,9 @@
+use crate::handler::response::{ResponseBody, try_insert_header};
 use crate::handler::{ExpressResponse, Request, Response};
 use crate::middleware::{Middleware, MiddlewareResult, next_res, stop_res};
 use crate::prelude::RequestExt;
 use async_trait::async_trait;
-use hyper::header::{
-    CACHE_CONTROL, ETAG, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
-};
+use hyper::header::{CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
 use std::path::{Component, Path, PathBuf};
 use std::time::SystemTime;
 
@@ -51,6 +50,13 @@ impl StaticServeMiddleware {
     }
 }
 
+/// Turns `res` into an empty `304 Not Modified`, keeping headers and extensions
+/// set by earlier middleware.
+fn not_modified(res: &mut Response) {
+    res.status = hyper::StatusCode::NOT_MODIFIED;
+    res.body = ResponseBody::Empty;
+}
+
 #[async_trait]
 impl<B: Send + Sync + 'static> Middleware<B> for StaticServeMiddleware {
     async fn call(&self, req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
@@ -104,20 +110,17 @@ impl<B: Send + Sync + 'static> Middleware<B> for StaticServeMiddleware {
         let etag_val = format!("W/\"{:x}-{:x}\"", metadata.len(), timestamp);
 
         // Check conditional match (ETag)
-        if let Some(if_none_match) = req.headers().get(IF_NONE_MATCH)
-            && if_none_match.to_str().unwrap_or_default() == etag_val
-        {
-            *res = Response::new().status(hyper::StatusCode::NOT_MODIFIED);
+        if req.header(IF_NONE_MATCH) == Some(etag_val.as_str()) {
+            not_modified(res);
             return stop_res();
         }
 
         // Check conditional match (Last-Modified)
-        if let Some(if_modified_since) = req.headers().get(IF_MODIFIED_SINCE)
-            && let Ok(since) =
-                httpdate::parse_http_date(if_modified_since.to_str().unwrap_or_default())
+        if let Some(if_modified_since) = req.header(IF_MODIFIED_SINCE)
+            && let Ok(since) = httpdate::parse_http_date(if_modified_since)
             && last_modified <= since
         {
-            *res = Response::new().status(hyper::StatusCode::NOT_MODIFIED);
+            not_modified(res);
             return stop_res();
         }
 
@@ -130,19 +133,15 @@ impl<B: Send + Sync + 'static> Middleware<B> for StaticServeMiddleware {
         }
 
         // Apply caching headers
-        if let Some(max_age) = self.max_age
-            && let Ok(val) = HeaderValue::from_str(&format!("public, max-age={}", max_age))
-        {
-            new_res = new_res.header(CACHE_CONTROL, val);
+        if let Some(max_age) = self.max_age {
+            let value = format!("public, max-age={}", max_age);
+            try_insert_header(&mut new_res.headers, CACHE_CONTROL, value);
         }
 
         // Add ETag and Last-Modified
-        if let Ok(val) = HeaderValue::from_str(&etag_val) {
-            new_res = new_res.header(ETAG, val);
-        }
-        if let Ok(date) = httpdate::fmt_http_date(last_modified).parse::<HeaderValue>() {
-            new_res = new_res.header(LAST_MODIFIED, date);
-        }
+        try_insert_header(&mut new_res.headers, ETAG, etag_val);
+        let date = httpdate::fmt_http_date(last_modified);
+        try_insert_header(&mut new_res.headers, LAST_MODIFIED, date);
 
         *res = new_res.status_code(200);
         stop_res()
@@ -8,25 +8,46 @@
 //! ```
 //!
 //! This covers:
-//! - [`App`] and the [`express`] / [`app`](crate::app) factory functions
+//! - [`App`], [`FrozenApp`] and the [`express`] / [`app`](crate::app) factory functions
 //! - [`Request`] and [`Response`] builder API ([`ExpressResponse`], [`RequestExt`])
 //! - All built-in middleware types and the [`Middleware`] trait
-//! - [`Router`], [`MethodKind`]
+//! - [`Router`], [`FrozenRouter`], [`FreezeError`], [`MethodKind`], [`Phase`]
 //! - [`StatusCode`] from `hyper`
 //! - The [`macro@async_trait`] attribute macro (for custom middleware impls)
 //! - The [`Serialize`] / [`Deserialize`] derive macros from `serde`
 
-pub use crate::application::App;
+pub use crate::application::{App, FrozenApp};
 pub use crate::express;
-pub use crate::handler::request::{Locals, RequestExt};
-pub use crate::handler::response::{ExpressResponse, ResponseError};
+pub use crate::handler::extract::{
+    Cookies, ExtractHandler, ExtractRejection, FromRequest, FromRequestParts, Headers, Path, Query,
+    State,
+};
+pub use crate::handler::request::{
+    BodyLimit, ConnectionInfo, Deadline, DuplicateKeys, IfMatch, JsonLimits, Locals,
+    MissingContentType, Nonce, QueryOptions, RequestExt, ServerTimings, TimingMark, TlsInfo,
+    TrustProxy,
+};
+pub use crate::handler::response::{
+    ApiError, BodyWriter, CachePolicy, CookieSigner, ErrorEnvelope, ErrorFormat, ErrorInfo,
+    ExpressResponse, HeaderPolicy, IntoResponse, Json, PageMeta, Part, ResponseError,
+};
 pub use crate::handler::{Handler, Request, Response};
 pub use crate::middleware::{
-    AuthMiddleware, CacheMiddleware, CorsMiddleware, LoggingMiddleware, Middleware,
-    MiddlewareResult, NormalizePathMiddleware, RateLimitMiddleware, SecurityHeadersMiddleware,
-    StaticServeMiddleware, next_res, stop_res,
+    AuthError, AuthLevel, AuthMiddleware, AuthenticatedUser, BodySizeLimitMiddleware,
+    CacheMiddleware, CachingTokenValidator, CompressionMiddleware, CookiePrefix, CorsMiddleware,
+    Deprecation, DeprecationMiddleware, DigestVerificationMiddleware, Encoding, ErrorLogMiddleware,
+    ErrorLogged, ErrorReport, HttpsRedirectMiddleware, JwtTokenValidator, LogFormatError,
+    LogPolicy, LogRequest, Logger, LoggingMiddleware, MetricsMiddleware, Middleware, MiddlewareFn,
+    MiddlewareFnWithState, MiddlewareFuture, MiddlewareResult, NormalizePathMiddleware,
+    PathRewriteMiddleware, RateLimitMiddleware, SecurityHeaders, SecurityHeadersMiddleware,
+    ServerTimingMiddleware, SessionInfo, SessionTokenValidator, SingleFlightMiddleware,
+    StaticServeMiddleware, TokenValidator, from_fn_with_state, middleware_fn, next_res, stop_res,
+};
+pub use crate::router::{
+    DuplicateRoutes, FreezeError, FrozenRouter, MethodKind, PathPattern, PatternError, Phase,
+    ResourceHandlers, Route, RouteInfo, Router, SubApp, UnknownMethod,
 };
-pub use crate::router::{MethodKind, Router};
+pub use crate::server::DrainState;
 
 // Proc-macros and common derives — re-exported so users need zero extra deps.
 pub use crate::async_trait;
@@ -1,27 +1,127 @@
 use self::interner::INTERNER;
 use crate::{
-    handler::{ExpressResponse, Handler, Request, Response, request::RequestMetadataInternal},
-    prelude::Middleware,
+    handler::{
+        Handler, IntoResponse, Request, Response, catch_panic,
+        request::{MatchedPath, RequestExt, RequestMetadataInternal, RouteParams},
+        response::{
+            DefaultErrorBody, ErrorEnvelope, header_value, json_errors, mime_to_header_value,
+            verbose_errors, write_error_body,
+        },
+    },
+    middleware::{Deprecation, Middleware, MiddlewareResult, RequestSummary},
 };
+use futures_util::FutureExt;
+use hyper::StatusCode;
 use hyper::body::Incoming;
-use layer::Layer;
+use hyper::header::{ALLOW, CONTENT_TYPE, HeaderValue};
+use layer::{Layer, Step};
+use log::warn;
+use lookup_cache::LookupCache;
 use rustc_hash::FxHashMap;
+use serde_json::{Value, json};
 use smallvec::{SmallVec, smallvec};
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
+use std::time::SystemTime;
 
+mod frozen;
 /// Tools for interning symbols used heavily throughout routing.
 pub mod interner;
 mod layer;
+mod lookup_cache;
 mod method;
+mod pattern;
+mod resource;
 
-pub use method::MethodKind;
+pub use frozen::{DuplicateRoutes, FreezeError, FrozenRouter};
+pub use layer::Phase;
+pub use method::{MethodKind, MethodSet, UnknownMethod};
+pub use pattern::{PathPattern, PatternError};
+pub use resource::ResourceHandlers;
 
 /// Total number of HTTP methods tracked.
 const METHOD_COUNT: usize = 9;
 
+/// Default number of middleware a single request may run through before a
+/// warning is logged (debug builds only).
+pub const DEFAULT_MIDDLEWARE_WARN_THRESHOLD: usize = 64;
+
+/// Methods a new router denies, since security scanners flag servers that
+/// answer them: `TRACE` can reflect credentials back to scripts, and
+/// `CONNECT` is meant for proxies.
+pub const DEFAULT_DENIED_METHODS: [MethodKind; 2] = [MethodKind::Trace, MethodKind::Connect];
+
+/// Name of the wildcard that makes mounted middleware match sub-paths.
+/// It is internal and never exposed through [`RouteParams`](crate::handler::request::RouteParams),
+/// so it can't shadow a route's own `{*path}` parameter.
+const MIDDLEWARE_WILDCARD: &str = "__express_mw_rest";
+
+/// Splits a catch-all route such as `/browse/{*rest}` into its mount prefix
+/// (`/browse`) and the name of the remainder parameter (`rest`).
+fn catch_all(path: &str) -> Option<(&str, &str)> {
+    let (prefix, name) = path.rsplit_once("/{*")?;
+    let name = name.strip_suffix('}')?;
+    Some((if prefix.is_empty() { "/" } else { prefix }, name))
+}
+
+/// The paths a route pattern matches, expanding its trailing optional
+/// segments: `/posts/{id}/{slug?}` gives `/posts/{id}` and
+/// `/posts/{id}/{slug}`.
+fn optional_variants(path: &Arc<str>) -> SmallVec<[Arc<str>; 2]> {
+    if !path.contains("?}") {
+        return smallvec![Arc::clone(path)];
+    }
+    let segments: Vec<&str> = path.split('/').collect();
+    let is_optional = |s: &str| s.starts_with('{') && s.ends_with("?}");
+    let required = segments.len() - segments.iter().rev().take_while(|s| is_optional(s)).count();
+    if segments[..required].iter().any(|s| is_optional(s)) {
+        warn!("{path}: only trailing segments can be optional; the others are required");
+    }
+
+    let mut variants = SmallVec::new();
+    let mut current = String::new();
+    for (i, segment) in segments.iter().enumerate() {
+        if i > 0 {
+            current.push('/');
+        }
+        if i >= required.max(1) {
+            let prefix = if current.len() > 1 {
+                &current[..current.len() - 1]
+            } else {
+                "/"
+            };
+            variants.push(prefix.into());
+        }
+        match segment.strip_suffix("?}") {
+            Some(name) if segment.starts_with('{') => {
+                current.push_str(name);
+                current.push('}');
+            }
+            _ => current.push_str(segment),
+        }
+    }
+    variants.push(current.into());
+    variants
+}
+
 /// A list of layer indices representing handlers resolving to a method.
 pub type LayerIndices = SmallVec<[usize; 8]>;
 
+/// Route parameters collected while matching, by interned name.
+type Params = SmallVec<[(interner::Symbol, Arc<str>); 4]>;
+
+/// What [`Router::lookup`] resolved a request to.
+#[derive(Clone)]
+struct Lookup {
+    /// Matching layers, in the order they run.
+    matched: LayerIndices,
+    params: Params,
+    /// Pattern of the matched route, if a route matched.
+    matched_path: Option<Arc<str>>,
+    /// Whether some route matches the path, under any method (404 vs 405).
+    path_exists: bool,
+}
+
 /// Internal router for a specific HTTP method.
 #[derive(Debug, Default)]
 pub struct MethodRouter {
@@ -31,6 +131,15 @@ pub struct MethodRouter {
     pub indices: Vec<LayerIndices>,
     /// Map from string paths to their indices in the `matcher`.
     pub path_to_idx: FxHashMap<Arc<str>, usize>,
+    /// Bare prefixes inserted so a catch-all also matches an empty remainder
+    /// (`/browse` and `/browse/` for `/browse/{*rest}`). An explicit route on
+    /// the same path replaces them.
+    implicit_prefixes: FxHashMap<Arc<str>, usize>,
+    /// Paths to their indices while no route has parameters; lookups then
+    /// skip the matcher. Cleared, and no longer kept, once one does.
+    static_routes: FxHashMap<Arc<str>, usize>,
+    /// Whether a route has parameters, so only the matcher is used.
+    dynamic: bool,
 }
 
 /// Fixed-size array of per-method routers, indexed by `MethodKind as usize`.
@@ -73,16 +182,47 @@ impl MethodRoutes {
 }
 
 impl MethodRouter {
+    /// Looks up `path`, with a hash map lookup instead of the matcher while
+    /// every route is static.
+    fn at<'m, 'p>(&'m self, path: &'p str) -> Option<matchit::Match<'m, 'p, &'m usize>> {
+        if self.dynamic {
+            return self.matcher.at(path).ok();
+        }
+        self.static_routes.get(path).map(|value| matchit::Match {
+            value,
+            params: matchit::Params::new(),
+        })
+    }
+
     fn add_route(&mut self, path: &Arc<str>, layer_index: usize) {
         if let Some(&idx) = self.path_to_idx.get(path) {
             self.indices[idx].push(layer_index);
-        } else {
-            let idx = self.indices.len();
-            self.indices.push(smallvec![layer_index]);
-            self.path_to_idx.insert(Arc::clone(path), idx);
-            self.matcher
-                .insert(path.as_ref(), idx)
-                .expect("Failed to insert route");
+            return;
+        }
+
+        if self.implicit_prefixes.remove(path).is_some() {
+            self.matcher.remove(path.as_ref());
+        }
+
+        let idx = self.indices.len();
+        self.indices.push(smallvec![layer_index]);
+        self.path_to_idx.insert(Arc::clone(path), idx);
+        if path.contains(['{', '}']) {
+            self.dynamic = true;
+            self.static_routes = FxHashMap::default();
+        } else if !self.dynamic {
+            self.static_routes.insert(Arc::clone(path), idx);
+        }
+        self.matcher
+            .insert(path.as_ref(), idx)
+            .expect("Failed to insert route");
+
+        if let Some((prefix, _)) = catch_all(path)
+            && !self.path_to_idx.contains_key(prefix)
+            && !self.implicit_prefixes.contains_key(prefix)
+            && self.matcher.insert(prefix, idx).is_ok()
+        {
+            self.implicit_prefixes.insert(prefix.into(), idx);
         }
     }
 }
@@ -99,6 +239,24 @@ pub struct MiddlewareMatcher {
 }
 
 /// The core routing engine for `expressjs`.
+///
+/// # Ordering
+///
+/// For each request, every layer whose path matches (middleware by prefix,
+/// routes exactly) runs sorted by [`Phase`], then by registration order:
+///
+/// 1. [`Phase::PreRouting`] middleware.
+/// 2. [`Phase::Routing`] middleware and routes, interleaved as registered.
+///    The first route handler ends the chain.
+/// 3. [`Phase::PostRouting`] middleware, reached only when no route answered.
+///
+/// Any middleware returning [`MiddlewareResult::Stop`](crate::prelude::MiddlewareResult::Stop)
+/// ends the chain early. Mounted routers keep the phases of their layers.
+///
+/// A middleware that rewrites the request path, such as
+/// [`PathRewriteMiddleware`](crate::prelude::PathRewriteMiddleware), reroutes
+/// the rest of the chain: the layers after it that match the new path run
+/// instead, with the new route's parameters.
 pub struct Router<B = Incoming> {
     /// The linear list of layers attached to the router.
     pub stack: Vec<Layer<B>>,
@@ -111,6 +269,16 @@ pub struct Router<B = Incoming> {
     pub routes: MethodRoutes,
     /// Fallback handler executed if no match is found.
     pub not_found_handler: Option<Arc<dyn Handler<B>>>,
+    /// Number of middleware a request may run through before a warning is logged.
+    middleware_warn_threshold: usize,
+    /// Capacity of the lookup cache the router gets once frozen; `0` for none.
+    lookup_cache_capacity: usize,
+    /// How a second handler for the same method and path is treated.
+    duplicate_routes: DuplicateRoutes,
+    /// Methods answered with `405` before routing, whatever is registered.
+    denied_methods: MethodSet,
+    /// Registered paths with broken parameter syntax, reported by `freeze`.
+    invalid_patterns: Vec<PatternError>,
 }
 
 impl<B> Default for Router<B> {
@@ -121,17 +289,47 @@ impl<B> Default for Router<B> {
             middleware_path_index: FxHashMap::default(),
             routes: MethodRoutes::default(),
             not_found_handler: None,
+            middleware_warn_threshold: DEFAULT_MIDDLEWARE_WARN_THRESHOLD,
+            lookup_cache_capacity: 0,
+            duplicate_routes: DuplicateRoutes::default(),
+            denied_methods: DEFAULT_DENIED_METHODS.as_slice().into(),
+            invalid_patterns: Vec::new(),
         }
     }
 }
 
 impl<B: Send + 'static> Router<B> {
     /// Attaches a custom handler to a specific path and HTTP method.
+    ///
+    /// Trailing segments written `{name?}` are optional: `/posts/{id}/{slug?}`
+    /// matches both `/posts/5` and `/posts/5/my-title`, and the `slug`
+    /// parameter is absent from the first. Optional segments anywhere else
+    /// are required, with a warning.
+    ///
+    /// A warning is logged if the method is denied (see [`deny_methods`](Self::deny_methods)),
+    /// since the handler won't run unless the method is allowed again.
     pub fn route(
         &mut self,
         path: impl AsRef<str>,
         handler: impl Handler<B>,
         method: MethodKind,
+    ) -> &mut Layer<B> {
+        if self.denied_methods.contains(method) {
+            warn!(
+                "{method} {} is routed, but {method} is denied and answered with 405; \
+                 see allow_methods",
+                path.as_ref()
+            );
+        }
+        self.insert_route(path, handler, method)
+    }
+
+    /// Adds a route layer, without the denied method check of [`route`](Self::route).
+    fn insert_route(
+        &mut self,
+        path: impl AsRef<str>,
+        handler: impl Handler<B>,
+        method: MethodKind,
     ) -> &mut Layer<B> {
         let mut p = path.as_ref();
         if p.len() > 1 && p.ends_with('/') {
@@ -140,8 +338,8 @@ impl<B: Send + 'static> Router<B> {
         let path: Arc<str> = p.into();
         let layer_index = self.stack.len();
 
-        let method_routes = self.routes.entry_or_default(method);
-        method_routes.add_route(&path, layer_index);
+        self.check_pattern(&path);
+        self.index_route(method, &path, layer_index);
 
         let layer = Layer::route(Arc::clone(&path), method, vec![], Arc::new(handler));
         self.stack.push(layer);
@@ -153,11 +351,14 @@ impl<B: Send + 'static> Router<B> {
     pub fn all<F, Fut>(&mut self, path: impl AsRef<str>, handler: F) -> &mut Self
     where
         F: Fn(Request<B>, Response) -> Fut + Send + Sync + Clone + 'static,
-        Fut: std::future::Future<Output = Response> + Send + 'static,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: IntoResponse,
     {
+        // Denied methods are registered too, but without a warning: they were
+        // not asked for by name.
         let path: Arc<str> = path.as_ref().into();
         for &method in &MethodKind::ALL {
-            self.route(path.as_ref(), handler.clone(), method);
+            self.insert_route(path.as_ref(), handler.clone(), method);
         }
         self
     }
@@ -167,17 +368,85 @@ impl<B: Send + 'static> Router<B> {
         Route {
             router: self,
             path: path.as_ref().into(),
+            content_type: None,
+            deprecation: None,
+        }
+    }
+
+    /// Lists the registered routes, in registration order.
+    pub fn routes(&self) -> impl Iterator<Item = RouteInfo<'_>> {
+        self.stack.iter().filter_map(|layer| {
+            Some(RouteInfo {
+                method: layer.method?,
+                path: &layer.path,
+                deprecation: layer.deprecation.as_deref(),
+            })
+        })
+    }
+
+    /// Scopes registrations under `prefix`; see [`SubApp`].
+    pub fn sub(&mut self, prefix: impl AsRef<str>) -> SubApp<'_, B> {
+        SubApp {
+            prefix: join("", prefix.as_ref()).trim_end_matches('/').to_owned(),
+            router: self,
         }
     }
 
     /// Mounts a middleware function at the specified path prefix.
+    ///
+    /// The middleware runs in [`Phase::Routing`]: in registration order
+    /// relative to the routes, so one added after a route only sees requests
+    /// that route did not answer.
     pub fn use_with(&mut self, path: impl AsRef<str>, middleware: impl Middleware<B>) -> &mut Self {
-        let mut p = path.as_ref();
+        self.mount_middleware(path.as_ref(), Phase::Routing, None, middleware)
+    }
+
+    /// Mounts a middleware function at the specified path prefix in the given
+    /// [`Phase`], whatever its registration order relative to the routes.
+    ///
+    /// ```rust,ignore
+    /// router.use_with_phase("/", Phase::PreRouting, LoggingMiddleware);
+    /// router.use_with_phase("/", Phase::PostRouting, fallback);
+    /// ```
+    pub fn use_with_phase(
+        &mut self,
+        path: impl AsRef<str>,
+        phase: Phase,
+        middleware: impl Middleware<B>,
+    ) -> &mut Self {
+        self.mount_middleware(path.as_ref(), phase, None, middleware)
+    }
+
+    /// Mounts a middleware function at the specified path prefix that only runs
+    /// for the given HTTP methods.
+    pub fn use_for(
+        &mut self,
+        methods: &[MethodKind],
+        path: impl AsRef<str>,
+        middleware: impl Middleware<B>,
+    ) -> &mut Self {
+        self.mount_middleware(
+            path.as_ref(),
+            Phase::Routing,
+            Some(MethodSet::from(methods)),
+            middleware,
+        )
+    }
+
+    fn mount_middleware(
+        &mut self,
+        path: &str,
+        phase: Phase,
+        methods: Option<MethodSet>,
+        middleware: impl Middleware<B>,
+    ) -> &mut Self {
+        let mut p = path;
         if p.len() > 1 && p.ends_with('/') {
             p = &p[..p.len() - 1];
         }
         let path: Arc<str> = p.into();
         let layer_index = self.stack.len();
+        self.check_pattern(&path);
 
         // O(1) lookup using the side-index instead of a linear scan.
         if let Some(&idx) = self.middleware_path_index.get(&path) {
@@ -191,10 +460,13 @@ impl<B: Send + 'static> Router<B> {
             // Express-style prefix matching: /path should match /path, /path/, and /path/sub
             // matchit 0.9+ requires the {*param} wildcard syntax (not the old /*param).
             if p_str == "/" {
-                // Root: /{*path} matches every sub-path; exact "/" was already inserted above.
-                router.insert("/{*path}", ()).ok();
+                // Root: a wildcard matches every sub-path; exact "/" was already inserted above.
+                router
+                    .insert(format!("/{{*{MIDDLEWARE_WILDCARD}}}"), ())
+                    .ok();
             } else {
-                let prefix_path = format!("{}/{{*path}}", p_str.trim_end_matches('/'));
+                let prefix_path =
+                    format!("{}/{{*{MIDDLEWARE_WILDCARD}}}", p_str.trim_end_matches('/'));
                 router.insert(prefix_path, ()).ok();
             }
 
@@ -208,198 +480,574 @@ impl<B: Send + 'static> Router<B> {
                 .insert(Arc::clone(&path), new_idx);
         }
 
-        let layer = Layer::middleware(Arc::clone(&path), vec![Arc::new(middleware)]);
+        let layer = Layer::middleware(
+            Arc::clone(&path),
+            phase,
+            methods,
+            vec![Arc::new(middleware)],
+        );
         self.stack.push(layer);
 
         self
     }
 
+    /// Sets how many middleware a single request may run through before a
+    /// warning is logged, which usually points at an overly broad mount.
+    ///
+    /// The check only runs in debug builds; release builds pay nothing for it.
+    /// Defaults to [`DEFAULT_MIDDLEWARE_WARN_THRESHOLD`].
+    pub fn middleware_warn_threshold(&mut self, threshold: usize) -> &mut Self {
+        self.middleware_warn_threshold = threshold;
+        self
+    }
+
+    /// Caches the route lookups of up to `capacity` method and path pairs once
+    /// the router is [frozen](Self::freeze), sparing hot paths the matching
+    /// work. `0`, the default, disables the cache. When it is full, entries
+    /// are evicted with CLOCK-PRO, which keeps pairs that are hit often.
+    ///
+    /// Each entry holds the path and the matched layers and parameters, so
+    /// memory grows with `capacity`; paths over 256 bytes are never cached.
+    pub fn lookup_cache(&mut self, capacity: usize) -> &mut Self {
+        self.lookup_cache_capacity = capacity;
+        self
+    }
+
+    /// Answers requests using `methods` with `405 Method Not Allowed` instead
+    /// of running their routes; middleware still sees them, e.g. to log them
+    /// or add CORS headers. [`DEFAULT_DENIED_METHODS`] are denied
+    /// from the start. Set the policy before registering routes, so handlers
+    /// for denied methods get a warning.
+    pub fn deny_methods(&mut self, methods: &[MethodKind]) -> &mut Self {
+        for &method in methods {
+            self.denied_methods.insert(method);
+        }
+        self
+    }
+
+    /// Lifts the denial of `methods`, e.g. `TRACE` for a debugging endpoint,
+    /// so they are routed like any other method.
+    pub fn allow_methods(&mut self, methods: &[MethodKind]) -> &mut Self {
+        for &method in methods {
+            self.denied_methods.remove(method);
+        }
+        self
+    }
+
+    /// Sets how a second handler for a method and path already routed is
+    /// treated. Such a handler can never run, so it is usually a copy-paste
+    /// mistake: by default [`freeze`](Self::freeze) rejects the router.
+    pub fn duplicate_routes(&mut self, policy: DuplicateRoutes) -> &mut Self {
+        self.duplicate_routes = policy;
+        self
+    }
+
+    /// Warns about `path` if its parameter syntax is broken, and keeps the
+    /// error for [`freeze`](Self::freeze).
+    fn check_pattern(&mut self, path: &Arc<str>) {
+        if let Err(e) = pattern::validate(path)
+            && !self.invalid_patterns.contains(&e)
+        {
+            warn!("{e}");
+            self.invalid_patterns.push(e);
+        }
+    }
+
+    /// Indexes the route layer at `layer_index` under every path it matches,
+    /// warning about a duplicate when [`DuplicateRoutes::Warn`] is set.
+    fn index_route(&mut self, method: MethodKind, path: &Arc<str>, layer_index: usize) {
+        let method_routes = self.routes.entry_or_default(method);
+        for variant in optional_variants(path) {
+            if self.duplicate_routes == DuplicateRoutes::Warn
+                && let Some(&idx) = method_routes.path_to_idx.get(&variant)
+                && method_routes.indices[idx]
+                    .iter()
+                    .any(|&i| self.stack[i].method == Some(method))
+            {
+                warn!("{method} {variant} is already routed; this handler will never run");
+            }
+            method_routes.add_route(&variant, layer_index);
+        }
+    }
+
     /// Sets a catch-all handler for 404 Not Found scenarios.
     pub fn not_found<F, Fut>(&mut self, handler: F) -> &mut Self
     where
         F: Fn(Request<B>, Response) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = Response> + Send + 'static,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: IntoResponse,
     {
         self.not_found_handler = Some(Arc::new(handler));
         self
     }
 
+    /// A read-only view of the routing structures, used to dispatch requests.
+    fn table(&self) -> Table<'_, B> {
+        Table {
+            stack: &self.stack,
+            middleware_matchers: &self.middleware_matchers,
+            routes: &self.routes,
+            not_found_handler: self.not_found_handler.as_ref(),
+            warn_threshold: self.middleware_warn_threshold,
+            denied_methods: self.denied_methods,
+            // Registration may still change what a path resolves to.
+            lookup_cache: None,
+        }
+    }
+
     /// Responds to an incoming HTTP request by dispatching to the configured handlers.
-    pub async fn handle(&self, mut req: Request<B>, res: Response) -> Response {
-        let raw_path = req.uri().path();
-        let path = if raw_path.len() > 1 && raw_path.ends_with('/') {
-            &raw_path[..raw_path.len() - 1]
+    pub fn handle(&self, req: Request<B>, res: Response) -> impl Future<Output = Response> {
+        self.table().handle(req, res)
+    }
+
+    /// Whether a middleware has written a status or body into the response.
+    ///
+    /// Headers alone don't count: middleware such as CORS or security headers
+    /// decorate every response without producing one.
+    fn is_produced(res: &Response) -> bool {
+        res.status != StatusCode::OK || !res.body.is_empty()
+    }
+
+    /// Invokes a handler, carrying the response extensions over to whatever
+    /// response it returns so middleware `finish` hooks can still find their state.
+    ///
+    /// A panicking handler produces a `500` with [`ResponseError::HandlerPanicked`](crate::handler::ResponseError::HandlerPanicked)
+    /// instead of tearing down the connection.
+    ///
+    /// Error bodies the handler left to the [`ResponseError`](crate::handler::ResponseError)
+    /// conversion are rewritten as JSON if the request's [`ErrorFormat`] asks for it,
+    /// and with the error chain if the app enables
+    /// [`App::error_details`](crate::prelude::App::error_details).
+    async fn call_handler(
+        handler: &Arc<dyn Handler<B>>,
+        req: Request<B>,
+        mut res: Response,
+    ) -> Response {
+        let json_errors = json_errors(&req);
+        let verbose = verbose_errors(&req);
+
+        let extensions = std::mem::take(&mut res.extensions);
+        // The app's error envelope stays visible to the handler, e.g. for
+        // `respond_enveloped_error`.
+        let envelope = extensions.get::<ErrorEnvelope>();
+        if let Some(envelope) = envelope {
+            res.extensions.insert(envelope.clone());
+        }
+        let mut res = match AssertUnwindSafe(handler.call(req, res))
+            .catch_unwind()
+            .await
+        {
+            Ok(res) => res,
+            Err(payload) => catch_panic::panic_response(payload),
+        };
+        if let Some(DefaultErrorBody { json }) = res.extensions.remove()
+            && (verbose || json_errors && !json || json && envelope.is_some())
+        {
+            if let Some(envelope) = envelope {
+                res.extensions.insert(envelope.clone());
+            }
+            write_error_body(&mut res, json || json_errors, verbose);
+        }
+        res.extensions.extend(extensions);
+        res
+    }
+
+    /// Mounts a child router into the current router at the given path prefix.
+    pub fn use_router(&mut self, prefix: impl AsRef<str>, router: Router<B>) -> &mut Self {
+        let prefix = prefix.as_ref().trim_end_matches('/');
+
+        // The child already warned about its own broken paths.
+        for e in router.invalid_patterns {
+            if !self.invalid_patterns.contains(&e) {
+                self.invalid_patterns.push(e);
+            }
+        }
+
+        for layer in router.stack {
+            let new_path: Arc<str> = if layer.path.as_ref() == "/" {
+                prefix.into()
+            } else if layer.path.starts_with('/') {
+                format!("{}{}", prefix, layer.path.as_ref()).into()
+            } else {
+                format!("{}/{}", prefix, layer.path.as_ref()).into()
+            };
+            if pattern::validate(&layer.path).is_ok() {
+                self.check_pattern(&new_path);
+            }
+
+            let layer_index = self.stack.len();
+
+            if let Some(method) = layer.method {
+                self.index_route(method, &new_path, layer_index);
+            } else {
+                // O(1) lookup via side-index.
+                if let Some(&idx) = self.middleware_path_index.get(&new_path) {
+                    self.middleware_matchers[idx].indices.push(layer_index);
+                } else {
+                    let p = new_path.as_ref();
+                    let mut r = matchit::Router::new();
+                    r.insert(p, ()).expect("Failed to insert nested middleware");
+                    // Include wildcard sub-path so mounted middleware also matches /prefix/sub/paths
+                    // matchit 0.9+ requires the {*param} wildcard syntax.
+                    if p == "/" {
+                        r.insert(format!("/{{*{MIDDLEWARE_WILDCARD}}}"), ()).ok();
+                    } else {
+                        let wildcard = format!("{p}/{{*{MIDDLEWARE_WILDCARD}}}");
+                        r.insert(&wildcard, ()).ok();
+                    }
+                    let new_idx = self.middleware_matchers.len();
+                    self.middleware_matchers.push(MiddlewareMatcher {
+                        path: Arc::clone(&new_path),
+                        router: r,
+                        indices: smallvec![layer_index],
+                    });
+                    self.middleware_path_index
+                        .insert(Arc::clone(&new_path), new_idx);
+                }
+            }
+
+            self.stack.push(Layer {
+                path: Arc::clone(&new_path),
+                method: layer.method,
+                phase: layer.phase,
+                methods: layer.methods,
+                steps: layer.steps,
+                content_type: layer.content_type,
+                deprecation: layer.deprecation,
+            });
+        }
+
+        self
+    }
+}
+
+/// A borrowed, read-only view of everything needed to dispatch a request.
+///
+/// [`Router`] and [`FrozenRouter`] store their routing structures
+/// differently but dispatch through this one view, so they behave the same.
+struct Table<'a, B> {
+    stack: &'a [Layer<B>],
+    middleware_matchers: &'a [MiddlewareMatcher],
+    routes: &'a MethodRoutes,
+    not_found_handler: Option<&'a Arc<dyn Handler<B>>>,
+    warn_threshold: usize,
+    denied_methods: MethodSet,
+    lookup_cache: Option<&'a LookupCache>,
+}
+
+impl<B: Send + 'static> Table<'_, B> {
+    /// Resolves the layers that run for a request and the route parameters.
+    ///
+    /// This is the per-request hot path and allocates nothing for a static
+    /// route: matches and parameters live in inline `SmallVec`s, parameter
+    /// names are interned once at registration, and only parameter values are
+    /// copied out of the path.
+    fn lookup(&self, method: MethodKind, path: &str) -> Lookup {
+        let path = if path.len() > 1 && path.ends_with('/') {
+            &path[..path.len() - 1]
         } else {
-            raw_path
+            path
         };
-        let method = MethodKind::from_hyper(req.method());
 
-        let mut matched = SmallVec::<[usize; 8]>::new();
-        let mut route_params = SmallVec::<[(interner::Symbol, Arc<str>); 4]>::new();
+        let mut matched = LayerIndices::new();
+        let mut params = Params::new();
 
-        for matcher in &self.middleware_matchers {
+        for matcher in self.middleware_matchers {
             if let Ok(matched_route) = matcher.router.at(path) {
                 for (k, v) in matched_route.params.iter() {
+                    if k == MIDDLEWARE_WILDCARD {
+                        continue;
+                    }
                     let sym_k = INTERNER.get_or_intern(k);
-                    route_params.push((sym_k, v.into()));
+                    params.push((sym_k, v.into()));
                 }
                 matched.extend(matcher.indices.iter().copied());
             }
         }
 
         let mut path_exists = false;
+        let mut matched_path = None;
 
         if let Some(method_routes) = self.routes.get(method)
-            && let Ok(route_match) = method_routes.matcher.at(path)
+            && let Some(route_match) = method_routes.at(path)
         {
             path_exists = true;
 
-            if !route_match.params.is_empty() {
-                for (k, v) in route_match.params.iter() {
-                    let sym_k = INTERNER.get_or_intern(k);
-                    route_params.push((sym_k, v.into()));
-                }
+            for (k, v) in route_match.params.iter() {
+                let sym_k = INTERNER.get_or_intern(k);
+                params.push((sym_k, v.into()));
             }
 
-            matched.extend(method_routes.indices[*route_match.value].iter().copied());
+            let indices = &method_routes.indices[*route_match.value];
+            matched_path = indices.first().map(|&i| Arc::clone(&self.stack[i].path));
+
+            // Matched through the bare prefix of a catch-all: empty remainder.
+            if let Some((_, name)) = matched_path.as_deref().and_then(catch_all)
+                && !route_match.params.iter().any(|(k, _)| k == name)
+            {
+                params.push((INTERNER.get_or_intern(name), "".into()));
+            }
+            matched.extend(indices.iter().copied());
         }
 
         if !path_exists {
             // Check if path exists under a different method (=> 405 vs 404).
             // O(methods) matchit lookups only on cache misses — acceptable.
             for (m, method_routes) in self.routes.iter() {
-                if m != method && method_routes.matcher.at(path).is_ok() {
+                if m != method && method_routes.at(path).is_some() {
                     path_exists = true;
                     break;
                 }
             }
         }
 
+        // Sort by phase, then by index to keep registration order within a
+        // phase. Middlewares and routes are collected separately, so this is
+        // what interleaves them.
+        if matched.len() > 1 {
+            matched.sort_unstable_by_key(|&i| (self.stack[i].phase, i));
+            matched.dedup();
+        }
+
+        Lookup {
+            matched,
+            params,
+            matched_path,
+            path_exists,
+        }
+    }
+
+    /// Looks up the layers for `path`, through the lookup cache if there is one.
+    fn route(&self, method: MethodKind, path: &str) -> Lookup {
+        match self.lookup_cache {
+            Some(cache) => cache.get_or_insert(method, path, || self.lookup(method, path)),
+            None => self.lookup(method, path),
+        }
+    }
+
+    /// Answers `405 Method Not Allowed`, with an `Allow` header listing the
+    /// permitted methods routed for `path`, in the app's
+    /// [`ErrorEnvelope`](crate::prelude::ErrorEnvelope) if `json`.
+    fn method_not_allowed(&self, path: &str, json: bool, mut res: Response) -> Response {
+        let path = if path.len() > 1 && path.ends_with('/') {
+            &path[..path.len() - 1]
+        } else {
+            path
+        };
+        let allowed: Vec<&str> = self
+            .routes
+            .iter()
+            .filter(|&(m, routes)| !self.denied_methods.contains(m) && routes.at(path).is_some())
+            .map(|(m, _)| m.as_str())
+            .collect();
+        if let Ok(value) = HeaderValue::try_from(allowed.join(", ")) {
+            res.headers.insert(ALLOW, value);
+        }
+        res.respond_enveloped_error(405, "Method Not Allowed", json!({ "allow": allowed }), json);
+        res
+    }
+
+    /// Keeps only the middleware of `matched`, for a denied method, and makes
+    /// the fallback a 405.
+    fn deny(&self, matched: &mut LayerIndices, path_exists: &mut bool) {
+        matched.retain(|i| self.stack[*i].method.is_none());
+        *path_exists = true;
+    }
+
+    /// Dispatches a request through the matched layers, then logs the
+    /// response's server error if nothing logged it yet.
+    async fn handle(self, req: Request<B>, res: Response) -> Response {
+        let summary = RequestSummary::capture(&req);
+        let mut res = self.dispatch(req, res).await;
+        summary.log_server_error(&mut res);
+        res
+    }
+
+    async fn dispatch(self, mut req: Request<B>, res: Response) -> Response {
+        let method = MethodKind::from_hyper(req.method());
+        // A denied method still runs through the middleware, and is answered
+        // with 405 where a route would have run.
+        let denied = self.denied_methods.contains(method);
+        let Lookup {
+            mut matched,
+            mut params,
+            mut matched_path,
+            mut path_exists,
+        } = self.route(method, req.uri().path());
+        if denied {
+            self.deny(&mut matched, &mut path_exists);
+            params.clear();
+            matched_path = None;
+        }
+
         if matched.is_empty() {
             let status = if path_exists { 405 } else { 404 };
 
             if status == 404
-                && let Some(h) = &self.not_found_handler
+                && let Some(h) = self.not_found_handler
             {
                 return h.call(req, res).await;
             }
 
-            return res.status_code(status).send_text(match status {
-                404 => "Not Found",
-                _ => "Method Not Allowed",
-            });
+            if status == 405 {
+                return self.method_not_allowed(req.uri().path(), req.prefers_json(), res);
+            }
+            return not_found(req.prefers_json(), res);
         }
 
-        req.set_params(route_params);
-
-        // Sort by index to maintain registration order across all layers.
-        // This is necessary because middlewares and routes are collected separately.
-        if matched.len() > 1 {
-            matched.sort_unstable();
-            matched.dedup();
-        }
+        set_route(&mut req, params, matched_path);
+        let mut routed_uri = req.uri().clone();
 
-        let mut req_opt = Some(req);
-        let mut res_opt = Some(res);
+        let mut dispatch = Dispatch {
+            req: Some(req),
+            res,
+            called: SmallVec::new(),
+            warn_threshold: self.warn_threshold,
+        };
 
-        for i in matched {
+        let mut next = 0;
+        while let Some(&i) = matched.get(next) {
+            next += 1;
             let layer = &self.stack[i];
 
-            if let Some(m) = &layer.method
-                && *m != method
-            {
+            if !layer.matches_method(method) {
                 continue;
             }
 
-            for mw in &layer.middlewares {
-                let req_mut = req_opt.as_mut().unwrap();
-                let res_mut = res_opt.as_mut().unwrap();
-                if mw.call(req_mut, res_mut).await.is_stop() {
-                    // A middleware signalled Stop — halt the entire chain.
-                    return res_opt.unwrap();
+            for step in &layer.steps {
+                if dispatch.run(step, layer).await.is_stop() {
+                    return dispatch.finish();
                 }
             }
 
-            if let Some(h) = &layer.handler {
-                return h
-                    .call(req_opt.take().unwrap(), res_opt.take().unwrap())
-                    .await;
+            // A middleware rewrote the path: the rest of the chain is the
+            // layers after this one that match the new path.
+            if let Some(req) = dispatch.req.as_mut()
+                && req.uri().path() != routed_uri.path()
+            {
+                routed_uri = req.uri().clone();
+                let mut lookup = self.route(method, routed_uri.path());
+                if denied {
+                    self.deny(&mut lookup.matched, &mut lookup.path_exists);
+                    lookup.params.clear();
+                    lookup.matched_path = None;
+                }
+                let position = (layer.phase, i);
+                matched = lookup.matched;
+                matched.retain(|j| (self.stack[*j].phase, *j) > position);
+                next = 0;
+                path_exists = lookup.path_exists;
+                set_route(req, lookup.params, lookup.matched_path);
             }
         }
 
         let status = if path_exists { 405 } else { 404 };
 
-        if status == 404
-            && let Some(h) = &self.not_found_handler
-        {
-            return h
-                .call(req_opt.take().unwrap(), res_opt.take().unwrap())
-                .await;
+        // A middleware may have produced a response and still called `next()`;
+        // the 404/405 fallback must never overwrite it.
+        if !Router::<B>::is_produced(&dispatch.res) {
+            dispatch.res = if status == 404
+                && let Some(h) = self.not_found_handler
+            {
+                let res = std::mem::take(&mut dispatch.res);
+                Router::call_handler(h, dispatch.req.take().unwrap(), res).await
+            } else {
+                let req = dispatch.req.as_ref();
+                let json = req.is_some_and(|req| req.prefers_json());
+                let res = std::mem::take(&mut dispatch.res);
+                if status == 405 {
+                    let path = req.map_or("/", |req| req.uri().path());
+                    self.method_not_allowed(path, json, res)
+                } else {
+                    not_found(json, res)
+                }
+            };
         }
 
-        res_opt
-            .unwrap()
-            .status_code(status)
-            .send_text(match status {
-                404 => "Not Found",
-                _ => "Method Not Allowed",
-            })
+        dispatch.finish()
     }
+}
 
-    /// Mounts a child router into the current router at the given path prefix.
-    pub fn use_router(&mut self, prefix: impl AsRef<str>, router: Router<B>) -> &mut Self {
-        let prefix = prefix.as_ref().trim_end_matches('/');
+/// Answers `404 Not Found`, in the app's
+/// [`ErrorEnvelope`](crate::prelude::ErrorEnvelope) if `json`.
+fn not_found(json: bool, mut res: Response) -> Response {
+    res.respond_enveloped_error(404, "Not Found", Value::Null, json);
+    res
+}
 
-        for layer in router.stack {
-            let new_path: Arc<str> = if layer.path.as_ref() == "/" {
-                prefix.into()
-            } else if layer.path.starts_with('/') {
-                format!("{}{}", prefix, layer.path.as_ref()).into()
-            } else {
-                format!("{}/{}", prefix, layer.path.as_ref()).into()
-            };
+/// Attaches the parameters and pattern of the matched route to `req`,
+/// replacing those of an earlier lookup.
+fn set_route<B>(req: &mut Request<B>, params: Params, matched_path: Option<Arc<str>>) {
+    if params.is_empty() {
+        req.extensions_mut().remove::<RouteParams>();
+    } else {
+        req.set_params(params);
+    }
+    match matched_path {
+        Some(matched_path) => {
+            req.extensions_mut().insert(MatchedPath(matched_path));
+        }
+        None => {
+            req.extensions_mut().remove::<MatchedPath>();
+        }
+    }
+}
 
-            let layer_index = self.stack.len();
+/// The state of one request moving through the matched layers.
+///
+/// Every [`Step`] runs through [`Dispatch::run`] with the same borrowing,
+/// `Next`/`Stop` contract, whether it is a middleware or a route handler.
+struct Dispatch<'r, B> {
+    /// The request, until a handler takes ownership of it.
+    req: Option<Request<B>>,
+    res: Response,
+    /// Middlewares that were called, in order; their `finish` hooks run in reverse.
+    called: SmallVec<[&'r Arc<dyn Middleware<B>>; 8]>,
+    warn_threshold: usize,
+}
 
-            if let Some(method) = layer.method {
-                let method_routes = self.routes.entry_or_default(method);
-                method_routes.add_route(&new_path, layer_index);
-            } else {
-                // O(1) lookup via side-index.
-                if let Some(&idx) = self.middleware_path_index.get(&new_path) {
-                    self.middleware_matchers[idx].indices.push(layer_index);
-                } else {
-                    let p = new_path.as_ref();
-                    let mut r = matchit::Router::new();
-                    r.insert(p, ()).expect("Failed to insert nested middleware");
-                    // Include wildcard sub-path so mounted middleware also matches /prefix/sub/paths
-                    // matchit 0.9+ requires the {*param} wildcard syntax.
-                    if p == "/" {
-                        r.insert("/{*path}", ()).ok();
-                    } else {
-                        let wildcard = format!("{}/{{*path}}", p);
-                        r.insert(&wildcard, ()).ok();
-                    }
-                    let new_idx = self.middleware_matchers.len();
-                    self.middleware_matchers.push(MiddlewareMatcher {
-                        path: Arc::clone(&new_path),
-                        router: r,
-                        indices: smallvec![layer_index],
-                    });
-                    self.middleware_path_index
-                        .insert(Arc::clone(&new_path), new_idx);
+impl<'r, B: Send + 'static> Dispatch<'r, B> {
+    /// Runs one step of `layer`. Handlers always return [`MiddlewareResult::Stop`](crate::prelude::MiddlewareResult::Stop).
+    async fn run(&mut self, step: &'r Step<B>, layer: &Layer<B>) -> MiddlewareResult {
+        let req = self.req.as_mut().expect("request taken by a handler");
+        match step {
+            Step::Middleware(mw) => {
+                self.called.push(mw);
+                if cfg!(debug_assertions) && self.called.len() == self.warn_threshold + 1 {
+                    warn!(
+                        "{} {} ran through more than {} middleware; check for overly broad mounts",
+                        req.method(),
+                        req.uri().path(),
+                        self.warn_threshold
+                    );
                 }
+                mw.call(req, &mut self.res).await
+            }
+            Step::Handler(h) => {
+                let req = self.req.take().unwrap();
+                let res = std::mem::take(&mut self.res);
+                self.res = Router::call_handler(h, req, res).await;
+                if let Some(content_type) = &layer.content_type
+                    && !self.res.body.is_empty()
+                    && !self.res.headers.contains_key(CONTENT_TYPE)
+                {
+                    self.res.headers.insert(CONTENT_TYPE, content_type.clone());
+                }
+                if let Some(deprecation) = &layer.deprecation {
+                    deprecation.apply(&mut self.res);
+                }
+                MiddlewareResult::Stop
             }
-
-            self.stack.push(Layer {
-                path: Arc::clone(&new_path),
-                method: layer.method,
-                middlewares: layer.middlewares,
-                handler: layer.handler,
-            });
         }
+    }
 
-        self
+    /// Runs the `finish` hooks of the called middlewares in reverse order and
+    /// returns the final response.
+    fn finish(mut self) -> Response {
+        for mw in self.called.iter().rev() {
+            mw.finish(&mut self.res);
+        }
+        self.res
     }
 }
 
@@ -414,7 +1062,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(path, handler, $crate::router::MethodKind::Get)
         }
@@ -425,7 +1074,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(path, handler, $crate::router::MethodKind::Post)
         }
@@ -436,7 +1086,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(path, handler, $crate::router::MethodKind::Put)
         }
@@ -447,7 +1098,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(path, handler, $crate::router::MethodKind::Delete)
         }
@@ -458,7 +1110,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(path, handler, $crate::router::MethodKind::Patch)
         }
@@ -469,7 +1122,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(path, handler, $crate::router::MethodKind::Head)
         }
@@ -480,7 +1134,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(path, handler, $crate::router::MethodKind::Connect)
         }
@@ -491,10 +1146,123 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(path, handler, $crate::router::MethodKind::Trace)
         }
+        /// Registers a handler taking typed extractors for GET requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn get_x<H, T>(&mut self, path: impl AsRef<str>, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                path,
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Get,
+            )
+        }
+        /// Registers a handler taking typed extractors for POST requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn post_x<H, T>(&mut self, path: impl AsRef<str>, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                path,
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Post,
+            )
+        }
+        /// Registers a handler taking typed extractors for PUT requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn put_x<H, T>(&mut self, path: impl AsRef<str>, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                path,
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Put,
+            )
+        }
+        /// Registers a handler taking typed extractors for DELETE requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn delete_x<H, T>(&mut self, path: impl AsRef<str>, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                path,
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Delete,
+            )
+        }
+        /// Registers a handler taking typed extractors for PATCH requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn patch_x<H, T>(&mut self, path: impl AsRef<str>, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                path,
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Patch,
+            )
+        }
+        /// Registers a handler taking typed extractors for HEAD requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn head_x<H, T>(&mut self, path: impl AsRef<str>, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                path,
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Head,
+            )
+        }
+        /// Registers a handler taking typed extractors for CONNECT requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn connect_x<H, T>(&mut self, path: impl AsRef<str>, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                path,
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Connect,
+            )
+        }
+        /// Registers a handler taking typed extractors for TRACE requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn trace_x<H, T>(&mut self, path: impl AsRef<str>, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                path,
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Trace,
+            )
+        }
     };
     (route) => {
         /// Registers a handler for GET requests.
@@ -504,7 +1272,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(handler, $crate::router::MethodKind::Get)
         }
@@ -515,7 +1284,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(handler, $crate::router::MethodKind::Post)
         }
@@ -526,7 +1296,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(handler, $crate::router::MethodKind::Put)
         }
@@ -537,7 +1308,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(handler, $crate::router::MethodKind::Delete)
         }
@@ -548,7 +1320,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(handler, $crate::router::MethodKind::Patch)
         }
@@ -559,7 +1332,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(handler, $crate::router::MethodKind::Head)
         }
@@ -570,7 +1344,8 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(handler, $crate::router::MethodKind::Connect)
         }
@@ -581,10 +1356,115 @@ macro_rules! define_methods {
                 + Send
                 + Sync
                 + 'static,
-            Fut: std::future::Future<Output = $crate::handler::Response> + Send + 'static,
+            Fut: std::future::Future + Send + 'static,
+            Fut::Output: $crate::handler::IntoResponse,
         {
             self.add_route(handler, $crate::router::MethodKind::Trace)
         }
+        /// Registers a handler taking typed extractors for GET requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn get_x<H, T>(&mut self, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Get,
+            )
+        }
+        /// Registers a handler taking typed extractors for POST requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn post_x<H, T>(&mut self, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Post,
+            )
+        }
+        /// Registers a handler taking typed extractors for PUT requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn put_x<H, T>(&mut self, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Put,
+            )
+        }
+        /// Registers a handler taking typed extractors for DELETE requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn delete_x<H, T>(&mut self, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Delete,
+            )
+        }
+        /// Registers a handler taking typed extractors for PATCH requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn patch_x<H, T>(&mut self, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Patch,
+            )
+        }
+        /// Registers a handler taking typed extractors for HEAD requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn head_x<H, T>(&mut self, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Head,
+            )
+        }
+        /// Registers a handler taking typed extractors for CONNECT requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn connect_x<H, T>(&mut self, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Connect,
+            )
+        }
+        /// Registers a handler taking typed extractors for TRACE requests.
+        ///
+        /// Arguments are extractors implementing [`FromRequest`](crate::prelude::FromRequest).
+        pub fn trace_x<H, T>(&mut self, handler: H) -> &mut Self
+        where
+            H: $crate::handler::extract::ExtractHandler<T, B>,
+            T: 'static,
+        {
+            self.add_route(
+                $crate::handler::extract::Extract::new(handler),
+                $crate::router::MethodKind::Trace,
+            )
+        }
     };
 }
 
@@ -612,34 +1492,231 @@ impl std::fmt::Debug for MethodRoutes {
     }
 }
 
+/// A registered route, as listed by [`Router::routes`].
+#[derive(Debug, Clone, Copy)]
+pub struct RouteInfo<'a> {
+    /// The method it answers.
+    pub method: MethodKind,
+    /// The path pattern, e.g. `/users/{id}`.
+    pub path: &'a str,
+    /// Its deprecation, set with [`Route::deprecated`].
+    pub deprecation: Option<&'a Deprecation>,
+}
+
 /// A route builder for a specific path, allowing for method chaining.
 pub struct Route<'a, B = Incoming> {
     router: &'a mut Router<B>,
     path: Arc<str>,
+    content_type: Option<HeaderValue>,
+    deprecation: Option<Arc<Deprecation>>,
 }
 
 impl<'a, B: Send + 'static> Route<'a, B> {
+    /// Sets a default `Content-Type` for the handlers registered after this call.
+    ///
+    /// It is applied to a handler's response when the handler leaves the header
+    /// unset and produces a body, so `send_json` and friends still win:
+    ///
+    /// ```rust,ignore
+    /// app.route("/page")
+    ///     .content_type("text/html")
+    ///     .get(async |_req, res| res.body("<h1>Hello</h1>"));
+    /// ```
+    ///
+    /// An invalid value, e.g. one holding a newline, is logged and ignored,
+    /// keeping the previous default.
+    pub fn content_type(&mut self, mime_type: impl AsRef<str>) -> &mut Self {
+        let mime = mime_type.as_ref();
+        if let Some(value) =
+            mime_to_header_value(mime).or_else(|| header_value(&CONTENT_TYPE, mime))
+        {
+            self.content_type = Some(value);
+        }
+        self
+    }
+
+    /// Marks the handlers registered after this call as deprecated since
+    /// `since`, to be removed at `sunset`, with `link` documenting the
+    /// migration. Their responses get the `Deprecation`, `Sunset` and `Link`
+    /// headers described in [`Deprecation`], and [`Router::routes`] lists it.
+    ///
+    /// ```rust
+    /// use expressjs::prelude::*;
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let mut app = express();
+    /// app.route("/v1/users")
+    ///     .deprecated(
+    ///         UNIX_EPOCH + Duration::from_secs(1_767_225_600),
+    ///         UNIX_EPOCH + Duration::from_secs(1_782_864_000),
+    ///         "https://example.com/docs/v2-migration",
+    ///     )
+    ///     .get(async |_req, res| res.send_text("users"));
+    /// ```
+    pub fn deprecated(
+        &mut self,
+        since: SystemTime,
+        sunset: SystemTime,
+        link: impl Into<String>,
+    ) -> &mut Self {
+        let deprecation = Deprecation::new(since).sunset(sunset).link(link);
+        self.deprecation = Some(Arc::new(deprecation));
+        self
+    }
+
     /// Registers a handler for all HTTP methods on this route.
     pub fn all<F, Fut>(&mut self, handler: F) -> &mut Self
     where
         F: Fn(Request<B>, Response) -> Fut + Send + Sync + Clone + 'static,
-        Fut: std::future::Future<Output = Response> + Send + 'static,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: IntoResponse,
     {
         for &method in &MethodKind::ALL {
-            self.router
-                .route(self.path.as_ref(), handler.clone(), method);
+            let layer = self
+                .router
+                .insert_route(self.path.as_ref(), handler.clone(), method);
+            layer.content_type = self.content_type.clone();
+            layer.deprecation = self.deprecation.clone();
         }
         self
     }
 
     fn add_route(&mut self, handler: impl Handler<B>, method: MethodKind) -> &mut Self {
-        self.router.route(self.path.as_ref(), handler, method);
+        let layer = self.router.route(self.path.as_ref(), handler, method);
+        layer.content_type = self.content_type.clone();
+        layer.deprecation = self.deprecation.clone();
         self
     }
 
     define_methods!(route);
 }
 
+/// A view of a router that registers everything under a path prefix,
+/// created by [`App::sub`](crate::prelude::App::sub) or [`Router::sub`].
+///
+/// Routes and middleware are added to the parent immediately, with the
+/// prefix prepended: middleware mounted at `/` only runs for paths under
+/// the prefix, and routes outside it are unaffected.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let mut app = express();
+/// let mut admin = app.sub("/admin");
+/// admin.use_with("/", CacheMiddleware::no_store()); // only under /admin
+/// admin.get("/", async |_req, res| res.send_text("dashboard")); // GET /admin
+/// let mut v1 = admin.sub("v1");
+/// v1.post("/jobs", async |_req, res| res.send_text("queued")); // POST /admin/v1/jobs
+///
+/// app.get("/", async |_req, res| res.send_text("home")); // unaffected
+/// ```
+pub struct SubApp<'a, B = Incoming> {
+    router: &'a mut Router<B>,
+    prefix: String,
+}
+
+/// The full path of `path` below `prefix`, which has no trailing `/`.
+fn join(prefix: &str, path: &str) -> String {
+    match path {
+        "" | "/" if prefix.is_empty() => "/".to_owned(),
+        "" | "/" => prefix.to_owned(),
+        path if path.starts_with('/') => format!("{prefix}{path}"),
+        path => format!("{prefix}/{path}"),
+    }
+}
+
+impl<B: Send + 'static> SubApp<'_, B> {
+    /// The full path of `path` below the prefix.
+    fn join(&self, path: &str) -> String {
+        join(&self.prefix, path)
+    }
+
+    /// Scopes a further prefix below this one, e.g. `/api` then `/v1`.
+    pub fn sub(&mut self, prefix: impl AsRef<str>) -> SubApp<'_, B> {
+        let prefix = self.join(prefix.as_ref());
+        self.router.sub(prefix)
+    }
+
+    /// Mounts a middleware at a path below the prefix; `/` covers the whole
+    /// prefix. See [`Router::use_with`].
+    pub fn use_with(&mut self, path: impl AsRef<str>, middleware: impl Middleware<B>) -> &mut Self {
+        let path = self.join(path.as_ref());
+        self.router.use_with(path, middleware);
+        self
+    }
+
+    /// Mounts a middleware at a path below the prefix in the given [`Phase`].
+    pub fn use_with_phase(
+        &mut self,
+        path: impl AsRef<str>,
+        phase: Phase,
+        middleware: impl Middleware<B>,
+    ) -> &mut Self {
+        let path = self.join(path.as_ref());
+        self.router.use_with_phase(path, phase, middleware);
+        self
+    }
+
+    /// Mounts a middleware at a path below the prefix, running it only for
+    /// the given methods.
+    pub fn use_for(
+        &mut self,
+        methods: &[MethodKind],
+        path: impl AsRef<str>,
+        middleware: impl Middleware<B>,
+    ) -> &mut Self {
+        let path = self.join(path.as_ref());
+        self.router.use_for(methods, path, middleware);
+        self
+    }
+
+    /// Mounts another `Router` at a path below the prefix.
+    pub fn use_router(&mut self, path: impl AsRef<str>, router: Router<B>) -> &mut Self {
+        let path = self.join(path.as_ref());
+        self.router.use_router(path, router);
+        self
+    }
+
+    /// Registers a handler for all HTTP methods on a path below the prefix.
+    pub fn all<F, Fut>(&mut self, path: impl AsRef<str>, handler: F) -> &mut Self
+    where
+        F: Fn(Request<B>, Response) -> Fut + Send + Sync + Clone + 'static,
+        Fut: std::future::Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        let path = self.join(path.as_ref());
+        self.router.all(path, handler);
+        self
+    }
+
+    /// Creates a route builder for a path below the prefix.
+    pub fn route(&mut self, path: impl AsRef<str>) -> Route<'_, B> {
+        let path = self.join(path.as_ref());
+        self.router.route_builder(path)
+    }
+
+    /// Registers the routes of a REST resource below the prefix; see
+    /// [`ResourceHandlers`].
+    pub fn resource(&mut self, path: impl AsRef<str>, handlers: ResourceHandlers<B>) -> &mut Self {
+        let path = self.join(path.as_ref());
+        self.router.resource(path, handlers);
+        self
+    }
+
+    define_methods!();
+
+    fn add_route(
+        &mut self,
+        path: impl AsRef<str>,
+        handler: impl Handler<B>,
+        method: MethodKind,
+    ) -> &mut Self {
+        let path = self.join(path.as_ref());
+        self.router.route(path, handler, method);
+        self
+    }
+}
+
 impl<B> std::fmt::Debug for Router<B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Router")
@@ -650,7 +1727,7 @@ impl<B> std::fmt::Debug for Router<B> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::handler::Response;
+    use crate::handler::{ExpressResponse, Response};
 
     async fn mock_handler<B: Send + 'static>(_req: Request<B>, res: Response) -> Response {
         res.send_text("ok")
@@ -698,4 +1775,728 @@ mod tests {
         assert_eq!(router.middleware_matchers.len(), 1);
         assert_eq!(router.middleware_matchers[0].path.as_ref(), "/api");
     }
+
+    #[tokio::test]
+    async fn test_long_middleware_chain_warns() {
+        crate::test_logger::capture();
+        let mut router = Router::<()>::default();
+        router.middleware_warn_threshold(3);
+        for _ in 0..5 {
+            router.use_with("/api", |_: &mut Request<()>, _: &mut Response| async {
+                crate::middleware::next_res()
+            });
+        }
+        router.get("/api", mock_handler);
+        router.get("/short", mock_handler);
+
+        let req = |uri| Request::builder().uri(uri).body(()).unwrap();
+        router.handle(req("/short"), Response::new()).await;
+        assert!(crate::test_logger::take().is_empty());
+
+        router.handle(req("/api"), Response::new()).await;
+        let records = crate::test_logger::take();
+        assert_eq!(records.len(), 1, "{records:?}");
+        assert_eq!(records[0].level, log::Level::Warn);
+        assert_eq!(
+            records[0].message,
+            "GET /api ran through more than 3 middleware; check for overly broad mounts"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_param_values_are_not_interned() {
+        use crate::handler::request::RequestExt;
+
+        let mut router = Router::<()>::default();
+        router.get(
+            "/status/{status}",
+            async |req: Request<()>, res: Response| {
+                let status = req.params().get("status").unwrap_or_default().to_owned();
+                res.send_text(status)
+            },
+        );
+
+        for i in 0..1_000 {
+            let value = format!("flood-value-{i}");
+            let req = Request::builder()
+                .uri(format!("/status/{value}"))
+                .body(())
+                .unwrap();
+            let res = router.handle(req, Response::new()).await;
+            assert_eq!(res.body.content_length(), Some(value.len() as u64));
+            assert_eq!(INTERNER.get(&value), None);
+        }
+        assert!(INTERNER.get("status").is_some());
+    }
+
+    #[test]
+    fn test_static_route_dispatch_allocations() {
+        use futures_util::FutureExt;
+
+        let mut router = Router::<()>::default();
+        router.use_with("/", |_: &mut Request<()>, _: &mut Response| async {
+            crate::middleware::next_res()
+        });
+        router.get("/health", async |_req: Request<()>, res: Response| res);
+        router.get("/users/{id}", async |_req: Request<()>, res: Response| res);
+        let request = |path| Request::builder().uri(path).body(()).unwrap();
+        // Warm up: interning and lazy statics allocate once.
+        router
+            .handle(request("/users/1"), Response::new())
+            .now_or_never()
+            .unwrap();
+
+        // The lookup itself allocates nothing for a static route...
+        let (found, allocations) = crate::test_alloc::count(|| {
+            let lookup = router.table().lookup(MethodKind::Get, "/health/");
+            lookup.matched.len() == 2 && lookup.params.is_empty()
+        });
+        assert!(found);
+        assert_eq!(allocations, 0);
+
+        // ...and parameter values are copied out of the path; nothing else allocates.
+        let (_, allocations) =
+            crate::test_alloc::count(|| router.table().lookup(MethodKind::Get, "/users/42"));
+        assert_eq!(allocations, 1);
+
+        // A whole routed request adds a small fixed cost, e.g. the extensions
+        // map, the copy of the URI kept to spot rewrites and the boxed
+        // middleware and handler futures. It doesn't grow from one request
+        // to the next.
+        let allocations: Vec<usize> = (0..4)
+            .map(|_| {
+                let (req, res) = (request("/health"), Response::new());
+                let (res, allocations) =
+                    crate::test_alloc::count(|| router.handle(req, res).now_or_never().unwrap());
+                assert_eq!(res.get_status(), StatusCode::OK);
+                allocations
+            })
+            .collect();
+        assert!(allocations[0] <= 10, "{allocations:?}");
+        assert!(
+            allocations.iter().all(|&n| n == allocations[0]),
+            "{allocations:?}"
+        );
+    }
+
+    #[test]
+    fn test_static_fast_path_matches_like_the_matcher() {
+        // xorshift64*: random route tables and paths from a fixed seed.
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = |bound: u64| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            state.wrapping_mul(0x2545_F491_4F6C_DD1D) % bound
+        };
+        // Few distinct segments, so paths share prefixes and collide.
+        let random_path = |next: &mut dyn FnMut(u64) -> u64| {
+            let segments = ["a", "b", "ab", "users", "u", "%20", "x.json"];
+            let path: String = (0..next(4))
+                .map(|_| format!("/{}", segments[next(segments.len() as u64) as usize]))
+                .collect();
+            match next(4) {
+                0 => format!("{path}/"),
+                _ if path.is_empty() => "/".to_owned(),
+                _ => path,
+            }
+        };
+
+        for _ in 0..200 {
+            let mut routes = MethodRouter::default();
+            for layer_index in 0..next(30) as usize {
+                let path = random_path(&mut next);
+                if path.len() == 1 || !path.ends_with('/') {
+                    routes.add_route(&path.into(), layer_index);
+                }
+            }
+            assert!(!routes.dynamic);
+            for _ in 0..50 {
+                let path = random_path(&mut next);
+                let fast = routes.at(&path).map(|m| (*m.value, m.params.len()));
+                let matcher = routes.matcher.at(&path).ok();
+                let matcher = matcher.map(|m| (*m.value, m.params.len()));
+                assert_eq!(fast, matcher, "{path}");
+            }
+        }
+
+        // A route with parameters switches the table to the matcher.
+        let mut routes = MethodRouter::default();
+        routes.add_route(&"/health".into(), 0);
+        assert_eq!(routes.static_routes.len(), 1);
+        routes.add_route(&"/users/{id}".into(), 1);
+        assert!(routes.dynamic && routes.static_routes.is_empty());
+        assert_eq!(routes.at("/health").map(|m| *m.value), Some(0));
+        assert_eq!(routes.at("/users/7").unwrap().params.get("id"), Some("7"));
+    }
+
+    #[tokio::test]
+    async fn test_route_and_middleware_layers_dispatch() {
+        use crate::middleware::next_res;
+        use layer::LayerKind;
+
+        let mut router = Router::<()>::default();
+        router.use_with("/api", |_: &mut Request<()>, res: &mut Response| {
+            res.header("x-mw", hyper::header::HeaderValue::from_static("1"));
+            async { next_res() }
+        });
+        router.get("/api/items", mock_handler);
+
+        let kinds: Vec<_> = router.stack.iter().map(Layer::kind).collect();
+        assert_eq!(kinds, [LayerKind::Middleware, LayerKind::Route]);
+
+        // Mounting keeps each layer's kind.
+        let mut app = Router::<()>::default();
+        app.use_router("/v1", router);
+        let kinds: Vec<_> = app.stack.iter().map(Layer::kind).collect();
+        assert_eq!(kinds, [LayerKind::Middleware, LayerKind::Route]);
+
+        let req = Request::builder().uri("/v1/api/items").body(()).unwrap();
+        let res = app.handle(req, Response::new()).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.headers["x-mw"], "1");
+        assert_eq!(res.body.content_length(), Some(2));
+    }
+
+    /// Records the order in which layers saw a request.
+    #[derive(Clone, Default)]
+    struct Seen(Arc<std::sync::Mutex<Vec<&'static str>>>);
+
+    impl Seen {
+        fn push(&self, name: &'static str) {
+            self.0.lock().unwrap().push(name);
+        }
+
+        fn take(&self) -> Vec<&'static str> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+
+        fn middleware(
+            &self,
+            name: &'static str,
+            result: crate::middleware::MiddlewareResult,
+        ) -> impl Middleware<()> + use<> {
+            let seen = self.clone();
+            move |_: &mut Request<()>, _: &mut Response| {
+                seen.push(name);
+                async move { result }
+            }
+        }
+
+        fn handler(
+            &self,
+            name: &'static str,
+        ) -> impl Fn(Request<()>, Response) -> std::future::Ready<Response> + Send + Sync + Clone + use<>
+        {
+            let seen = self.clone();
+            move |_, res: Response| {
+                seen.push(name);
+                std::future::ready(res.send_text(name))
+            }
+        }
+    }
+
+    async fn dispatch(router: &Router<()>, method: &str, uri: &str) -> Response {
+        let req = Request::builder().method(method).uri(uri).body(()).unwrap();
+        router.handle(req, Response::new()).await
+    }
+
+    fn text(res: &Response) -> &[u8] {
+        match &res.body {
+            crate::handler::response::ResponseBody::Full(bytes) => bytes,
+            _ => b"",
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_runs_layers_in_phase_then_registration_order() {
+        use crate::middleware::next_res;
+
+        let seen = Seen::default();
+        let mut router = Router::<()>::default();
+        router.use_with_phase("/", Phase::PostRouting, seen.middleware("post", next_res()));
+        router.use_with("/", seen.middleware("a", next_res()));
+        router.get("/items", seen.handler("first"));
+        router.use_with("/items", seen.middleware("after", next_res()));
+        router.get("/items", seen.handler("second"));
+        router.use_with_phase("/", Phase::PreRouting, seen.middleware("pre", next_res()));
+
+        let res = dispatch(&router, "GET", "/items").await;
+        assert_eq!(text(&res), b"first");
+        assert_eq!(seen.take(), ["pre", "a", "first"]);
+
+        // No route answers POST, so dispatch falls through to post-routing.
+        let res = dispatch(&router, "POST", "/items").await;
+        assert_eq!(res.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(seen.take(), ["pre", "a", "after", "post"]);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_stop_ends_the_chain() {
+        use crate::middleware::{next_res, stop_res};
+
+        let seen = Seen::default();
+        let mut router = Router::<()>::default();
+        router.use_with("/", seen.middleware("open", next_res()));
+        router.use_with("/admin", move |_: &mut Request<()>, res: &mut Response| {
+            res.status_code(403);
+            async { stop_res() }
+        });
+        router.use_with("/admin", seen.middleware("unreachable", next_res()));
+        router.get("/admin", seen.handler("admin"));
+        router.get("/public", seen.handler("public"));
+
+        let res = dispatch(&router, "GET", "/admin").await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+        assert!(res.body.is_empty());
+        assert_eq!(seen.take(), ["open"]);
+
+        let res = dispatch(&router, "GET", "/public").await;
+        assert_eq!(text(&res), b"public");
+        assert_eq!(seen.take(), ["open", "public"]);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_exposes_params_to_middleware_and_handlers() {
+        use crate::handler::request::RequestExt;
+        use crate::middleware::next_res;
+
+        let mut router = Router::<()>::default();
+        router.use_with(
+            "/users/{id}",
+            |req: &mut Request<()>, res: &mut Response| {
+                let id = req.params().get("id").unwrap_or_default().to_owned();
+                res.header("x-user", HeaderValue::try_from(id).unwrap());
+                async { next_res() }
+            },
+        );
+        router.get(
+            "/users/{id}/files/{*path}",
+            async |req: Request<()>, res: Response| {
+                let params = req.params();
+                let body = format!(
+                    "{}:{}",
+                    params.get("id").unwrap_or_default(),
+                    params.get("path").unwrap_or_default()
+                );
+                res.send_text(body)
+            },
+        );
+
+        let res = dispatch(&router, "GET", "/users/42/files/a/b.txt").await;
+        assert_eq!(res.headers["x-user"], "42");
+        assert_eq!(text(&res), b"42:a/b.txt");
+
+        let res = dispatch(&router, "GET", "/users/7/files").await;
+        assert_eq!(text(&res), b"7:");
+    }
+
+    #[tokio::test]
+    async fn test_optional_segments_match_with_and_without_them() {
+        let mut router = Router::<()>::default();
+        router.get(
+            "/posts/{id}/{slug?}",
+            async |req: Request<()>, res: Response| {
+                let params = req.params();
+                let body = format!("{}:{:?}", params.get("id").unwrap(), params.get("slug"));
+                res.send_text(body)
+            },
+        );
+        router.get("/{page?}", async |req: Request<()>, res: Response| {
+            res.send_text(format!("{:?}", req.params().get("page")))
+        });
+        assert_eq!(router.stack.len(), 2);
+
+        let res = dispatch(&router, "GET", "/posts/5/my-title").await;
+        assert_eq!(text(&res), b"5:Some(\"my-title\")");
+        let res = dispatch(&router, "GET", "/posts/5").await;
+        assert_eq!(text(&res), b"5:None");
+        let res = dispatch(&router, "GET", "/posts/5/").await;
+        assert_eq!(text(&res), b"5:None");
+        assert_eq!(text(&dispatch(&router, "GET", "/").await), b"None");
+        assert_eq!(text(&dispatch(&router, "GET", "/2").await), b"Some(\"2\")");
+
+        let res = dispatch(&router, "GET", "/posts/5/my-title/extra").await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        assert!(router.route_conflicts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_not_found_and_method_not_allowed() {
+        use crate::middleware::next_res;
+
+        let seen = Seen::default();
+        let mut router = Router::<()>::default();
+        router.use_with("/", seen.middleware("mw", next_res()));
+        router.get("/items", seen.handler("items"));
+
+        let res = dispatch(&router, "DELETE", "/items").await;
+        assert_eq!(res.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(text(&res), b"Method Not Allowed");
+        assert_eq!(res.headers[ALLOW], "GET");
+        assert_eq!(seen.take(), ["mw"]);
+
+        let res = dispatch(&router, "GET", "/missing").await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        assert_eq!(text(&res), b"Not Found");
+        assert_eq!(seen.take(), ["mw"]);
+
+        // The custom handler only replaces 404s, after middleware ran.
+        router.not_found(seen.handler("fallback"));
+        let res = dispatch(&router, "GET", "/missing").await;
+        assert_eq!(text(&res), b"fallback");
+        assert_eq!(seen.take(), ["mw", "fallback"]);
+        let res = dispatch(&router, "DELETE", "/items").await;
+        assert_eq!(res.status, StatusCode::METHOD_NOT_ALLOWED);
+
+        // Nothing matched at all: the fallback runs directly.
+        let mut bare = Router::<()>::default();
+        bare.not_found(seen.handler("bare"));
+        let res = dispatch(&bare, "GET", "/anything").await;
+        assert_eq!(text(&res), b"bare");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_route_content_type_is_reported() {
+        crate::test_logger::capture();
+        let mut router = Router::<()>::default();
+        router
+            .route_builder("/page")
+            .content_type("text/csv")
+            .content_type("text/html\r\nX-Injected: 1")
+            .get(|_req: Request<()>, res: Response| async move { res.body("a,b") });
+
+        let records = crate::test_logger::take();
+        assert_eq!(records.len(), 1, "{records:?}");
+        assert_eq!(records[0].level, log::Level::Warn);
+        assert!(records[0].message.contains("content-type"));
+
+        let req = hyper::Request::builder().uri("/page").body(()).unwrap();
+        let res = router.handle(req, Response::new()).await;
+        assert_eq!(res.headers[CONTENT_TYPE], "text/csv");
+    }
+
+    #[tokio::test]
+    async fn test_denied_methods_are_answered_with_405_after_middleware() {
+        crate::test_logger::capture();
+        let seen = Seen::default();
+        let mut router = Router::<()>::default();
+        router.use_with("/", seen.middleware("mw", crate::middleware::next_res()));
+        router.get("/items", seen.handler("items"));
+        router.post("/items/", seen.handler("create"));
+        router.all("/echo", seen.handler("echo"));
+        assert!(crate::test_logger::take().is_empty());
+
+        router.trace("/items", seen.handler("trace"));
+        let records = crate::test_logger::take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, log::Level::Warn);
+        assert_eq!(
+            records[0].message,
+            "TRACE /items is routed, but TRACE is denied and answered with 405; see allow_methods"
+        );
+
+        for (method, path, allow) in [
+            ("TRACE", "/items/", "GET, POST"),
+            (
+                "CONNECT",
+                "/echo",
+                "GET, POST, PUT, DELETE, PATCH, HEAD, OPTIONS",
+            ),
+            ("TRACE", "/missing", ""),
+        ] {
+            let res = dispatch(&router, method, path).await;
+            assert_eq!(
+                res.status,
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{method} {path}"
+            );
+            assert_eq!(res.headers[ALLOW], allow, "{method} {path}");
+        }
+        // Middleware still saw them, but no route handler ran.
+        assert_eq!(seen.take(), ["mw", "mw", "mw"]);
+
+        router
+            .allow_methods(&[MethodKind::Trace])
+            .deny_methods(&[MethodKind::Delete]);
+        let frozen = router.freeze().unwrap();
+        let res = frozen
+            .handle(
+                hyper::Request::builder()
+                    .method("TRACE")
+                    .uri("/items")
+                    .body(())
+                    .unwrap(),
+                Response::new(),
+            )
+            .await;
+        assert_eq!(text(&res), b"trace");
+        let res = frozen
+            .handle(
+                hyper::Request::builder()
+                    .method("DELETE")
+                    .uri("/echo")
+                    .body(())
+                    .unwrap(),
+                Response::new(),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            res.headers[ALLOW],
+            "GET, POST, PUT, PATCH, HEAD, OPTIONS, TRACE"
+        );
+    }
+
+    #[test]
+    fn test_freeze_rejects_duplicate_routes() {
+        let seen = Seen::default();
+        let mut router = Router::<()>::default();
+        router.use_with(
+            "/items",
+            seen.middleware("mw", crate::middleware::next_res()),
+        );
+        router.get("/items", seen.handler("first"));
+        router.post("/items", seen.handler("create"));
+        router.get("/items/", seen.handler("second"));
+
+        let err = router.freeze().unwrap_err();
+        assert_eq!(
+            err,
+            FreezeError::DuplicateRoute {
+                method: MethodKind::Get,
+                path: "/items".into(),
+                count: 2,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "GET /items has 2 handlers; only the first can ever run"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_freeze_rejects_escaped_middleware_paths() {
+        let seen = Seen::default();
+        let mut router = Router::<()>::default();
+        router.use_with(
+            "/{{*p}}",
+            seen.middleware("escaped", crate::middleware::next_res()),
+        );
+        router.use_with(
+            PathPattern::root().wildcard("p"),
+            seen.middleware("typed", crate::middleware::next_res()),
+        );
+        router.get("/a", seen.handler("a"));
+
+        // The escaped mount only matches a literal brace.
+        dispatch(&router, "GET", "/a").await;
+        assert_eq!(seen.take(), ["typed", "a"]);
+
+        let err = router.freeze().unwrap_err();
+        assert_eq!(
+            err,
+            FreezeError::InvalidPattern(PatternError::EscapedBrace {
+                path: "/{{*p}}".into(),
+                corrected: "/{*p}".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_freeze_rejects_invalid_paths_of_mounted_routers() {
+        let seen = Seen::default();
+        let mut child = Router::<()>::default();
+        child.use_with(
+            "/{{*p}}",
+            seen.middleware("escaped", crate::middleware::next_res()),
+        );
+        let mut router = Router::<()>::default();
+        router.use_router("/api", child);
+        assert_eq!(
+            router.freeze().unwrap_err(),
+            FreezeError::InvalidPattern(PatternError::EscapedBrace {
+                path: "/{{*p}}".into(),
+                corrected: "/{*p}".into(),
+            })
+        );
+
+        // A valid child path can be broken by the prefix it is mounted at.
+        let mut child = Router::<()>::default();
+        child.get("/a", seen.handler("a"));
+        let mut router = Router::<()>::default();
+        router.use_router("/{{version}}", child);
+        assert_eq!(
+            router.freeze().unwrap_err(),
+            FreezeError::InvalidPattern(PatternError::EscapedBrace {
+                path: "/{{version}}/a".into(),
+                corrected: "/{version}/a".into(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_routes_warn_policy() {
+        crate::test_logger::capture();
+        let seen = Seen::default();
+        let mut router = Router::<()>::default();
+        router.duplicate_routes(DuplicateRoutes::Warn);
+        router.get("/items", seen.handler("first"));
+        router.post("/items", seen.handler("create"));
+        assert!(crate::test_logger::take().is_empty());
+
+        router.get("/items", seen.handler("second"));
+        let records = crate::test_logger::take();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, log::Level::Warn);
+        assert_eq!(
+            records[0].message,
+            "GET /items is already routed; this handler will never run"
+        );
+
+        // The conflict is still reported, but no longer stops the router.
+        assert_eq!(
+            router.route_conflicts(),
+            [FreezeError::DuplicateRoute {
+                method: MethodKind::Get,
+                path: "/items".into(),
+                count: 2,
+            }]
+        );
+        let frozen = router.freeze().unwrap();
+        let res = frozen
+            .handle(
+                hyper::Request::builder().uri("/items").body(()).unwrap(),
+                Response::new(),
+            )
+            .await;
+        assert_eq!(text(&res), b"first");
+    }
+
+    #[tokio::test]
+    async fn test_lookup_cache_dispatches_like_uncached() {
+        fn echo(req: Request<()>, res: Response) -> std::future::Ready<Response> {
+            let params =
+                ["org", "id", "rest"].map(|name| req.params().get(name).map(str::to_owned));
+            std::future::ready(res.send_text(format!("{} {params:?}", req.method())))
+        }
+
+        let build = |capacity| {
+            let mut router = Router::<()>::default();
+            router.use_with(
+                "/orgs/{org}",
+                |_: &mut Request<()>, _: &mut Response| async { crate::middleware::next_res() },
+            );
+            router.get("/orgs/{org}/users/{id}", echo);
+            router.head("/orgs/{org}/users/{id}", echo);
+            router.route("/orgs/{org}/users/{id}", echo, MethodKind::Options);
+            router.get("/files/{*rest}", echo);
+            router.lookup_cache(capacity);
+            router.freeze().unwrap()
+        };
+        let uncached = build(0);
+        let cached = build(2);
+
+        // Twice over, with more paths than the cache holds, to go through
+        // evictions too.
+        for _ in 0..2 {
+            for (method, uri) in [
+                ("GET", "/orgs/acme/users/7"),
+                ("GET", "/orgs/acme/users/8/"),
+                ("HEAD", "/orgs/other/users/7"),
+                ("OPTIONS", "/orgs/acme/users/7"),
+                ("GET", "/files"),
+                ("GET", "/files/a/b"),
+                ("POST", "/orgs/acme/users/7"),
+                ("GET", "/missing"),
+            ] {
+                let request = || Request::builder().method(method).uri(uri).body(()).unwrap();
+                let expected = uncached.handle(request(), Response::new()).await;
+                // A miss, then a hit.
+                for _ in 0..2 {
+                    let res = cached.handle(request(), Response::new()).await;
+                    assert_eq!(res.status, expected.status, "{method} {uri}");
+                    assert_eq!(text(&res), text(&expected), "{method} {uri}");
+                }
+            }
+        }
+
+        let req = Request::builder()
+            .uri("/orgs/acme/users/7")
+            .body(())
+            .unwrap();
+        let res = cached.handle(req, Response::new()).await;
+        assert_eq!(text(&res), br#"GET [Some("acme"), Some("7"), None]"#);
+    }
+
+    #[tokio::test]
+    async fn test_frozen_router_dispatches_like_router() {
+        use crate::middleware::next_res;
+
+        let seen = Seen::default();
+        let build = || {
+            let mut router = Router::<()>::default();
+            router.use_with_phase("/", Phase::PostRouting, seen.middleware("post", next_res()));
+            router.use_with("/users", seen.middleware("users", next_res()));
+            router.get("/users/{id}", seen.handler("user"));
+            router.get("/files/{*rest}", seen.handler("files"));
+            router.not_found(seen.handler("fallback"));
+            router
+        };
+        let router = build();
+        let frozen = build().freeze().unwrap();
+
+        for (method, uri) in [
+            ("GET", "/users/7"),
+            ("GET", "/files"),
+            ("GET", "/files/a/b"),
+            ("POST", "/users/7"),
+            ("GET", "/missing"),
+        ] {
+            let expected = dispatch(&router, method, uri).await;
+            let expected_seen = seen.take();
+
+            let req = Request::builder().method(method).uri(uri).body(()).unwrap();
+            let res = frozen.handle(req, Response::new()).await;
+            assert_eq!(res.status, expected.status, "{method} {uri}");
+            assert_eq!(text(&res), text(&expected), "{method} {uri}");
+            assert_eq!(seen.take(), expected_seen, "{method} {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deprecated_routes_send_deprecation_headers() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let since = UNIX_EPOCH + Duration::from_secs(1_767_225_600);
+        let sunset = UNIX_EPOCH + Duration::from_secs(1_782_864_000);
+        let mut router = Router::<()>::default();
+        router.get("/v2/users", mock_handler);
+        router
+            .route_builder("/v1/users")
+            .deprecated(since, sunset, "https://example.com/migrate")
+            .get(mock_handler);
+
+        let res = dispatch(&router, "GET", "/v1/users").await;
+        assert_eq!(res.headers["deprecation"], "@1767225600");
+        assert_eq!(res.headers["sunset"], "Wed, 01 Jul 2026 00:00:00 GMT");
+        assert_eq!(
+            res.headers["link"],
+            "<https://example.com/migrate>; rel=\"deprecation\""
+        );
+
+        let res = dispatch(&router, "GET", "/v2/users").await;
+        for name in ["deprecation", "sunset", "link"] {
+            assert!(!res.headers.contains_key(name), "{name}");
+        }
+
+        let routes: Vec<_> = router
+            .routes()
+            .map(|route| (route.path, route.deprecation.map(|d| d.sunset_date())))
+            .collect();
+        assert_eq!(
+            routes,
+            [("/v2/users", None), ("/v1/users", Some(Some(sunset)))]
+        );
+    }
 }
@@ -0,0 +1,161 @@
+use super::lookup_cache::LookupCache;
+use super::{MethodKind, MethodRoutes, MethodSet, MiddlewareMatcher, PatternError, Router, Table};
+use crate::handler::{Handler, Request, Response};
+use hyper::body::Incoming;
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::layer::Layer;
+
+/// A problem found while [freezing](Router::freeze) a router.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FreezeError {
+    /// Several handlers are registered for the same method and path. The
+    /// first one always ends the chain, so the others could never run.
+    #[error("{method} {path} has {count} handlers; only the first can ever run")]
+    DuplicateRoute {
+        /// The method the handlers are registered for.
+        method: MethodKind,
+        /// The route pattern.
+        path: Arc<str>,
+        /// How many handlers are registered.
+        count: usize,
+    },
+    /// A route or middleware path has broken parameter syntax, so it can't
+    /// match the requests it was meant for.
+    #[error(transparent)]
+    InvalidPattern(#[from] PatternError),
+}
+
+/// What happens when a method and path get a second handler, which could
+/// never run since the first one ends the chain. Set it with
+/// [`Router::duplicate_routes`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateRoutes {
+    /// [`Router::freeze`] fails with [`FreezeError::DuplicateRoute`], so
+    /// the app refuses to start. The default.
+    #[default]
+    Reject,
+    /// A warning is logged when the duplicate is registered and the first
+    /// handler keeps serving.
+    Warn,
+}
+
+/// A [`Router`] compiled for serving, produced by [`Router::freeze`].
+///
+/// It has been validated, its registration-only indexes are dropped and
+/// its layer tables are stored in boxed slices. It has no registration methods,
+/// so routes cannot change once it is serving, which also lets it cache
+/// lookups if [`Router::lookup_cache`] was set.
+pub struct FrozenRouter<B = Incoming> {
+    stack: Box<[Layer<B>]>,
+    middleware_matchers: Box<[MiddlewareMatcher]>,
+    routes: MethodRoutes,
+    not_found_handler: Option<Arc<dyn Handler<B>>>,
+    warn_threshold: usize,
+    denied_methods: MethodSet,
+    lookup_cache: Option<LookupCache>,
+}
+
+impl<B: Send + 'static> Router<B> {
+    /// Validates the router and compiles it into its immutable serving form.
+    ///
+    /// [`App::listen`](crate::prelude::App::listen) does this implicitly.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FreezeError::DuplicateRoute`] if a method and path have more
+    /// than one handler, unless [`DuplicateRoutes::Warn`] is set, and
+    /// [`FreezeError::InvalidPattern`] if a route or middleware path has
+    /// broken parameter syntax.
+    pub fn freeze(self) -> Result<FrozenRouter<B>, FreezeError> {
+        if let Some(e) = self.invalid_patterns.first() {
+            return Err(e.clone().into());
+        }
+        if self.duplicate_routes == DuplicateRoutes::Reject
+            && let Some(conflict) = self.route_conflicts().into_iter().next()
+        {
+            return Err(conflict);
+        }
+
+        let mut stack = self.stack;
+        for layer in &mut stack {
+            layer.steps.shrink_to_fit();
+        }
+        // Only registration needs the path-to-index maps.
+        let mut routes = self.routes;
+        for slot in routes.0.iter_mut().flatten() {
+            slot.indices.shrink_to_fit();
+            slot.path_to_idx = FxHashMap::default();
+            slot.implicit_prefixes = FxHashMap::default();
+            slot.static_routes.shrink_to_fit();
+        }
+        let mut middleware_matchers = self.middleware_matchers;
+        for matcher in &mut middleware_matchers {
+            matcher.indices.shrink_to_fit();
+        }
+
+        Ok(FrozenRouter {
+            stack: stack.into_boxed_slice(),
+            middleware_matchers: middleware_matchers.into_boxed_slice(),
+            routes,
+            not_found_handler: self.not_found_handler,
+            warn_threshold: self.middleware_warn_threshold,
+            denied_methods: self.denied_methods,
+            lookup_cache: (self.lookup_cache_capacity > 0)
+                .then(|| LookupCache::new(self.lookup_cache_capacity)),
+        })
+    }
+}
+
+impl<B> Router<B> {
+    /// Every method and path with more than one handler, grouped by method,
+    /// whatever the [`DuplicateRoutes`] setting.
+    pub fn route_conflicts(&self) -> Vec<FreezeError> {
+        let mut conflicts = Vec::new();
+        for (method, routes) in self.routes.iter() {
+            for indices in &routes.indices {
+                let count = indices
+                    .iter()
+                    .filter(|&&i| self.stack[i].method == Some(method))
+                    .count();
+                if count > 1 {
+                    conflicts.push(FreezeError::DuplicateRoute {
+                        method,
+                        path: Arc::clone(&self.stack[indices[0]].path),
+                        count,
+                    });
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+impl<B: Send + 'static> FrozenRouter<B> {
+    /// Responds to an incoming HTTP request, exactly as [`Router::handle`] would.
+    pub fn handle(&self, req: Request<B>, res: Response) -> impl Future<Output = Response> {
+        self.table().handle(req, res)
+    }
+
+    fn table(&self) -> Table<'_, B> {
+        Table {
+            stack: &self.stack,
+            middleware_matchers: &self.middleware_matchers,
+            routes: &self.routes,
+            not_found_handler: self.not_found_handler.as_ref(),
+            warn_threshold: self.warn_threshold,
+            denied_methods: self.denied_methods,
+            lookup_cache: self.lookup_cache.as_ref(),
+        }
+    }
+}
+
+impl<B> std::fmt::Debug for FrozenRouter<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrozenRouter")
+            .field("stack_len", &self.stack.len())
+            .finish()
+    }
+}
@@ -1,6 +1,6 @@
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub(crate) struct Symbol(pub u32);
@@ -12,20 +12,23 @@ pub(crate) static INTERNER: Lazy<Interner> = Lazy::new(Interner::default);
 ///
 /// # Memory note
 /// Interned strings are leaked via `Box::leak` so they gain `'static` lifetime.
-/// In practice only route-parameter *names* (e.g. `"id"`, `"slug"`) are
-/// interned — a small, bounded set that is fixed at startup.
+/// Only route-parameter *names* (e.g. `"id"`, `"slug"`) may be interned — a
+/// small set bounded by the registered routes. Parameter *values* come from
+/// the request and are stored as plain strings in
+/// [`RouteParams`](crate::handler::request::RouteParams); interning them would
+/// leak memory for every distinct value a client sends.
 pub(crate) struct Interner {
     /// Map from `&'static str` → symbol.
     forward: DashMap<&'static str, Symbol>,
-    /// Atomic counter — each new string gets a unique id.
-    counter: AtomicU32,
+    /// Serializes insertions, guarding the next symbol id.
+    next: Mutex<u32>,
 }
 
 impl Default for Interner {
     fn default() -> Self {
         Self {
             forward: DashMap::new(),
-            counter: AtomicU32::new(0),
+            next: Mutex::new(0),
         }
     }
 }
@@ -34,30 +37,38 @@ impl Interner {
     /// Interns a string or returns its existing symbol.
     ///
     /// ## Race-free design
-    /// We use DashMap's `entry` API which holds the shard write-lock for the
-    /// duration of `or_insert_with`, eliminating the TOCTOU window that existed
-    /// between a bare `get` + `insert`. The fast-path `get` before `entry` is
-    /// an optimistic short-circuit for the common case where the key is already
-    /// present (read-locks only, cheaper).
+    /// Lookups only take a DashMap read-shard lock. Insertions happen under a
+    /// single lock that re-checks the map first, so two threads interning the
+    /// same string agree on one symbol, ids are handed out without gaps, and a
+    /// string is only leaked when it is actually inserted.
     pub fn get_or_intern(&self, s: &str) -> Symbol {
         // Fast path: already interned — acquire only a read-shard lock.
         if let Some(sym) = self.forward.get(s) {
             return *sym;
         }
 
-        // Slow path: atomically check-and-insert inside the shard write-lock.
+        let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(sym) = self.forward.get(s) {
+            return *sym;
+        }
         // Leaking here is intentional: route-param names are a bounded set.
-        let leaked: &'static str = Box::leak(s.to_string().into_boxed_str());
-        *self
-            .forward
-            .entry(leaked)
-            .or_insert_with(|| Symbol(self.counter.fetch_add(1, Ordering::Relaxed)))
+        let leaked: &'static str = Box::leak(s.to_owned().into_boxed_str());
+        let sym = Symbol(*next);
+        *next += 1;
+        self.forward.insert(leaked, sym);
+        sym
     }
 
     /// Gets an existing symbol for a string, if it exists.
     pub fn get(&self, s: &str) -> Option<Symbol> {
         self.forward.get(s).map(|s| *s)
     }
+
+    /// Number of interned strings.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.forward.len()
+    }
 }
 
 #[cfg(test)]
@@ -84,6 +95,41 @@ mod tests {
         assert_eq!(interner.get("test"), Some(sym1));
     }
 
+    #[test]
+    fn test_concurrent_interning_agrees_on_symbols() {
+        let interner = Interner::default();
+        let names: Vec<String> = (0..64).map(|i| format!("name{i}")).collect();
+
+        let per_thread: Vec<Vec<Symbol>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|t| {
+                    let (interner, names) = (&interner, &names);
+                    scope.spawn(move || {
+                        // Each thread walks the names in a different order.
+                        let mut syms = vec![Symbol(u32::MAX); names.len()];
+                        for k in 0..names.len() {
+                            let i = (k * 7 + t * 13) % names.len();
+                            syms[i] = interner.get_or_intern(&names[i]);
+                        }
+                        syms
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        for syms in &per_thread[1..] {
+            assert_eq!(syms, &per_thread[0]);
+        }
+        let mut ids: Vec<u32> = per_thread[0].iter().map(|s| s.0).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..names.len() as u32).collect::<Vec<_>>());
+        assert_eq!(interner.len(), names.len());
+        for (name, sym) in names.iter().zip(&per_thread[0]) {
+            assert_eq!(interner.get(name), Some(*sym));
+        }
+    }
+
     #[test]
     fn test_global_interner() {
         let sym1 = INTERNER.get_or_intern("global");
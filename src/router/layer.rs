@@ -1,15 +1,71 @@
-use super::method::MethodKind;
+use super::method::{MethodKind, MethodSet};
 use crate::handler::Handler;
-use crate::middleware::Middleware;
+use crate::middleware::{Deprecation, Middleware};
 use hyper::body::Incoming;
+use hyper::header::HeaderValue;
 use std::{fmt::Debug, sync::Arc};
 
+/// When a layer runs relative to the others matching the same request.
+///
+/// Matching layers run sorted by phase, then by registration order. Routes
+/// always belong to [`Phase::Routing`], so middleware in that phase interleaves
+/// with them exactly as registered, Express-style.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Phase {
+    /// Runs before every route and routing-phase middleware, e.g. logging or
+    /// request IDs that must see each request first.
+    PreRouting,
+    /// Runs in registration order alongside the routes. The default.
+    #[default]
+    Routing,
+    /// Runs after every route, so it is only reached when no handler answered
+    /// the request, e.g. a catch-all error or fallback middleware.
+    PostRouting,
+}
+
+/// Whether a [`Layer`] is middleware or a route endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+    /// Runs its middlewares for every matching request, then continues.
+    Middleware,
+    /// Runs its handler for one method, ending the chain.
+    Route,
+}
+
+/// One unit of work in a [`Layer`], run in order by the router's single
+/// dispatch loop.
+///
+/// Middleware borrow the request and response and return `Next` or `Stop`.
+/// A route handler owns them instead, so the router adapts it to the same
+/// shape: it takes the pending request and response, stores the handler's
+/// response, and always stops the chain.
+pub enum Step<B = Incoming> {
+    /// A middleware, run for its side effects on the request and response.
+    Middleware(Arc<dyn Middleware<B>>),
+    /// A route handler, producing the final response.
+    Handler(Arc<dyn Handler<B>>),
+}
+
 /// Represents a single routing or middleware layer in the application.
+///
+/// This one type backs both kinds of layer in [`Router::stack`](super::Router::stack):
+/// route layers have a `method` and end with a [`Step::Handler`], middleware
+/// layers have neither. Build them with [`Layer::route`] and [`Layer::middleware`]
+/// so the two stay consistent.
 pub struct Layer<B = Incoming> {
     pub path: Arc<str>,
     pub method: Option<MethodKind>,
-    pub middlewares: Vec<Arc<dyn Middleware<B>>>,
-    pub handler: Option<Arc<dyn Handler<B>>>,
+    /// Ordering phase; always [`Phase::Routing`] for routes.
+    pub phase: Phase,
+    /// Restricts a middleware layer to a subset of methods (`None` = all methods).
+    pub methods: Option<MethodSet>,
+    /// What the layer runs, in order. A route's per-route middleware come
+    /// before its handler.
+    pub steps: Vec<Step<B>>,
+    /// `Content-Type` set on the handler's response when it didn't set one.
+    pub content_type: Option<HeaderValue>,
+    /// Deprecation headers added to the handler's response.
+    pub deprecation: Option<Arc<Deprecation>>,
 }
 
 impl<B: Send + 'static> Layer<B> {
@@ -19,20 +75,55 @@ impl<B: Send + 'static> Layer<B> {
         middlewares: Vec<Arc<dyn Middleware<B>>>,
         handler: Arc<dyn Handler<B>>,
     ) -> Self {
+        let mut steps: Vec<_> = middlewares.into_iter().map(Step::Middleware).collect();
+        steps.push(Step::Handler(handler));
         Self {
             path,
             method: Some(method),
-            middlewares,
-            handler: Some(handler),
+            phase: Phase::Routing,
+            methods: None,
+            steps,
+            content_type: None,
+            deprecation: None,
         }
     }
 
-    pub fn middleware(path: Arc<str>, middlewares: Vec<Arc<dyn Middleware<B>>>) -> Self {
+    pub fn middleware(
+        path: Arc<str>,
+        phase: Phase,
+        methods: Option<MethodSet>,
+        middlewares: Vec<Arc<dyn Middleware<B>>>,
+    ) -> Self {
         Self {
             path,
             method: None,
-            middlewares,
-            handler: None,
+            phase,
+            methods,
+            steps: middlewares.into_iter().map(Step::Middleware).collect(),
+            content_type: None,
+            deprecation: None,
+        }
+    }
+
+    /// Returns `true` if this layer should run for the given request method.
+    #[inline]
+    pub fn matches_method(&self, method: MethodKind) -> bool {
+        match (self.method, self.methods) {
+            (Some(m), _) => m == method,
+            (None, Some(set)) => set.contains(method),
+            (None, None) => true,
+        }
+    }
+}
+
+impl<B> Layer<B> {
+    /// Returns whether this is a middleware or a route layer.
+    #[inline]
+    pub fn kind(&self) -> LayerKind {
+        if self.steps.iter().any(|s| matches!(s, Step::Handler(_))) {
+            LayerKind::Route
+        } else {
+            LayerKind::Middleware
         }
     }
 }
@@ -40,10 +131,14 @@ impl<B: Send + 'static> Layer<B> {
 impl<B> Debug for Layer<B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Layer")
+            .field("kind", &self.kind())
             .field("path", &self.path)
             .field("method", &self.method)
-            .field("middlewares_count", &self.middlewares.len())
-            .field("has_handler", &self.handler.is_some())
+            .field("phase", &self.phase)
+            .field("methods", &self.methods)
+            .field("steps", &self.steps.len())
+            .field("content_type", &self.content_type)
+            .field("deprecation", &self.deprecation)
             .finish()
     }
 }
@@ -0,0 +1,51 @@
+use super::{Lookup, MethodKind};
+use quick_cache::Equivalent;
+use quick_cache::sync::Cache;
+
+/// Longest path whose lookup is cached. Longer ones are rare and would let a
+/// client fill the cache's memory with a handful of requests.
+const MAX_CACHED_PATH_LEN: usize = 256;
+
+/// A bounded cache of [`Lookup`]s keyed by method and raw path. `quick_cache`
+/// evicts with CLOCK-PRO, an approximation of LRU that also weighs how often
+/// an entry is hit, so a burst of one-off paths doesn't flush the hot ones.
+///
+/// Only [`FrozenRouter`](super::FrozenRouter)s use it: their tables never
+/// change, so an entry stays valid for the router's lifetime.
+pub(super) struct LookupCache(Cache<(MethodKind, Box<str>), Lookup>);
+
+/// The borrowed form of a cache key, so hits don't allocate. Hashes like the
+/// owned `(MethodKind, Box<str>)` tuple.
+#[derive(Hash)]
+struct Key<'a>(MethodKind, &'a str);
+
+impl Equivalent<(MethodKind, Box<str>)> for Key<'_> {
+    fn equivalent(&self, key: &(MethodKind, Box<str>)) -> bool {
+        self.0 == key.0 && self.1 == &*key.1
+    }
+}
+
+impl LookupCache {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self(Cache::new(capacity))
+    }
+
+    /// Returns the cached lookup for `method` and `path`, resolving and
+    /// remembering it with `lookup` on a miss.
+    pub(super) fn get_or_insert(
+        &self,
+        method: MethodKind,
+        path: &str,
+        lookup: impl FnOnce() -> Lookup,
+    ) -> Lookup {
+        if path.len() > MAX_CACHED_PATH_LEN {
+            return lookup();
+        }
+        if let Some(hit) = self.0.get(&Key(method, path)) {
+            return hit;
+        }
+        let resolved = lookup();
+        self.0.insert((method, path.into()), resolved.clone());
+        resolved
+    }
+}
@@ -1,4 +1,7 @@
 use hyper::Method;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
 
 /// Represents an HTTP method.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -54,6 +57,21 @@ impl MethodKind {
         }
     }
 
+    /// The method's name as sent on the wire, e.g. `"GET"`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            MethodKind::Get => "GET",
+            MethodKind::Post => "POST",
+            MethodKind::Put => "PUT",
+            MethodKind::Delete => "DELETE",
+            MethodKind::Patch => "PATCH",
+            MethodKind::Head => "HEAD",
+            MethodKind::Options => "OPTIONS",
+            MethodKind::Trace => "TRACE",
+            MethodKind::Connect => "CONNECT",
+        }
+    }
+
     /// Convert array index back to MethodKind (used by MethodRoutes::iter).
     #[inline]
     pub(crate) fn from_index(i: usize) -> Self {
@@ -71,3 +89,110 @@ impl MethodKind {
         }
     }
 }
+
+impl fmt::Display for MethodKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A string that names none of the [`MethodKind`]s.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unknown HTTP method: {0:?}")]
+pub struct UnknownMethod(pub String);
+
+/// Parses a method name. Method names are case-sensitive, so only the
+/// upper-case spelling is accepted.
+impl TryFrom<&str> for MethodKind {
+    type Error = UnknownMethod;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        MethodKind::ALL
+            .into_iter()
+            .find(|method| method.as_str() == name)
+            .ok_or_else(|| UnknownMethod(name.to_owned()))
+    }
+}
+
+impl FromStr for MethodKind {
+    type Err = UnknownMethod;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        MethodKind::try_from(name)
+    }
+}
+
+/// A compact set of [`MethodKind`]s backed by a bitmask.
+///
+/// Used to restrict middleware layers to a subset of HTTP methods without
+/// any allocation or hashing on the dispatch path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MethodSet(u16);
+
+impl MethodSet {
+    /// Returns `true` if the set contains the given method.
+    #[inline]
+    pub const fn contains(&self, method: MethodKind) -> bool {
+        self.0 & (1 << method as u16) != 0
+    }
+
+    /// Adds a method to the set.
+    #[inline]
+    pub fn insert(&mut self, method: MethodKind) {
+        self.0 |= 1 << method as u16;
+    }
+
+    /// Removes a method from the set.
+    #[inline]
+    pub fn remove(&mut self, method: MethodKind) {
+        self.0 &= !(1 << method as u16);
+    }
+
+    /// Returns `true` if the set contains no methods.
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl FromIterator<MethodKind> for MethodSet {
+    fn from_iter<I: IntoIterator<Item = MethodKind>>(iter: I) -> Self {
+        let mut set = MethodSet::default();
+        for method in iter {
+            set.insert(method);
+        }
+        set
+    }
+}
+
+impl From<&[MethodKind]> for MethodSet {
+    fn from(methods: &[MethodKind]) -> Self {
+        methods.iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method_names_round_trip() {
+        for method in MethodKind::ALL {
+            let name = method.as_str();
+            assert_eq!(method.to_string(), name);
+            assert_eq!(MethodKind::try_from(name), Ok(method));
+            assert_eq!(name.parse::<MethodKind>(), Ok(method));
+            assert_eq!(MethodKind::from_hyper(&name.parse().unwrap()), method);
+        }
+
+        assert_eq!(
+            MethodKind::try_from("get"),
+            Err(UnknownMethod("get".to_owned()))
+        );
+        assert!("PURGE".parse::<MethodKind>().is_err());
+        assert_eq!(
+            UnknownMethod("PURGE".to_owned()).to_string(),
+            "unknown HTTP method: \"PURGE\""
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! Route and mount path syntax: validation, and [`PathPattern`] for building
+//! paths that can't be mistyped.
+
+use log::warn;
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// A route or mount path that can never match what it was meant to, found
+/// when it is registered.
+///
+/// Registration logs it as a warning, and [`Router::freeze`](super::Router::freeze)
+/// fails with [`FreezeError::InvalidPattern`](super::FreezeError::InvalidPattern).
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PatternError {
+    /// `{{` or `}}` in a path, as copied from a `format!` string. The router
+    /// reads it as a literal brace, so the mount only matches paths actually
+    /// containing `{`.
+    #[error("`{path}` matches a literal brace; write `{corrected}` for a parameter")]
+    EscapedBrace {
+        /// The registered path.
+        path: Arc<str>,
+        /// The path with its braces unescaped.
+        corrected: String,
+    },
+    /// A `{` without its `}`, or the other way around.
+    #[error("`{path}` has an unbalanced brace; parameters are written `{{name}}` or `{{*name}}`")]
+    UnbalancedBrace {
+        /// The registered path.
+        path: Arc<str>,
+    },
+    /// A parameter without a name, such as `{}` or `{*}`.
+    #[error("`{path}` has a parameter without a name; write e.g. `{{id}}` or `{{*rest}}`")]
+    EmptyParam {
+        /// The registered path.
+        path: Arc<str>,
+    },
+    /// A parameter using syntax the router doesn't know, e.g. a catch-all
+    /// before the last segment or a name containing `*`.
+    #[error(
+        "`{path}`: `{param}` is not a valid parameter; write `{{name}}`, `{{*name}}` as the \
+         last segment, or `{{name?}}` as a trailing segment"
+    )]
+    InvalidParam {
+        /// The registered path.
+        path: Arc<str>,
+        /// The offending parameter, braces included.
+        param: String,
+    },
+}
+
+/// Checks the parameter syntax of a route or mount path.
+pub(crate) fn validate(path: &Arc<str>) -> Result<(), PatternError> {
+    if path.contains("{{") || path.contains("}}") {
+        return Err(PatternError::EscapedBrace {
+            path: Arc::clone(path),
+            corrected: path.replace("{{", "{").replace("}}", "}"),
+        });
+    }
+
+    let segments: Vec<&str> = path.split('/').collect();
+    for (i, segment) in segments.iter().enumerate() {
+        let mut rest = *segment;
+        while let Some(start) = rest.find(['{', '}']) {
+            let unbalanced = || PatternError::UnbalancedBrace {
+                path: Arc::clone(path),
+            };
+            if rest[start..].starts_with('}') {
+                return Err(unbalanced());
+            }
+            let Some(len) = rest[start + 1..].find(['{', '}']) else {
+                return Err(unbalanced());
+            };
+            let end = start + 1 + len;
+            if rest[end..].starts_with('{') {
+                return Err(unbalanced());
+            }
+
+            let param = &rest[start..=end];
+            let whole_segment = param.len() == segment.len();
+            let name = &param[1..param.len() - 1];
+            let (name, valid) = if let Some(name) = name.strip_prefix('*') {
+                (name, whole_segment && i == segments.len() - 1)
+            } else if let Some(name) = name.strip_suffix('?') {
+                (name, whole_segment)
+            } else {
+                (name, true)
+            };
+            if name.is_empty() {
+                return Err(PatternError::EmptyParam {
+                    path: Arc::clone(path),
+                });
+            }
+            if !valid || !is_param_name(name) {
+                return Err(PatternError::InvalidParam {
+                    path: Arc::clone(path),
+                    param: param.to_owned(),
+                });
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` can name a parameter.
+fn is_param_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(['*', '?', '/', '{', '}'])
+        && !name.contains(char::is_whitespace)
+}
+
+/// A route or mount path built segment by segment, e.g. to avoid
+/// `format!` escaping mistakes. It can be passed wherever a path is
+/// expected.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let files = PathPattern::new("/users").param("id").literal("files").wildcard("path");
+/// assert_eq!(files.as_str(), "/users/{id}/files/{*path}");
+///
+/// let mut app = express();
+/// app.use_with(PathPattern::root().wildcard("p"), LoggingMiddleware);
+/// app.get(files, async |_req, res| res.send_text("file"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PathPattern(String);
+
+impl PathPattern {
+    /// Starts a pattern at `/`.
+    pub fn root() -> Self {
+        Self(String::new())
+    }
+
+    /// Starts a pattern at the static path `prefix`, e.g. `/api/v1`.
+    pub fn new(prefix: impl AsRef<str>) -> Self {
+        Self::root().literal(prefix)
+    }
+
+    /// Appends static segments, e.g. `files` or `a/b`.
+    pub fn literal(mut self, segments: impl AsRef<str>) -> Self {
+        let segments = segments.as_ref().trim_matches('/');
+        if !segments.is_empty() {
+            self.0.push('/');
+            self.0.push_str(segments);
+        }
+        self
+    }
+
+    /// Appends a parameter segment, `{name}`.
+    pub fn param(self, name: &str) -> Self {
+        self.push_param("", name, "")
+    }
+
+    /// Appends an optional parameter segment, `{name?}`. Only further
+    /// optional segments may follow.
+    pub fn optional(self, name: &str) -> Self {
+        self.push_param("", name, "?")
+    }
+
+    /// Appends a catch-all segment, `{*name}`, matching the rest of the path.
+    pub fn wildcard(self, name: &str) -> Self {
+        self.push_param("*", name, "")
+    }
+
+    fn push_param(mut self, prefix: &str, name: &str, suffix: &str) -> Self {
+        // An invalid name is kept, so registering the path reports it.
+        if !is_param_name(name) {
+            warn!("path parameter with invalid name {name:?}");
+        }
+        self.0.push_str(&format!("/{{{prefix}{name}{suffix}}}"));
+        self
+    }
+
+    /// The path, as registered.
+    pub fn as_str(&self) -> &str {
+        if self.0.is_empty() { "/" } else { &self.0 }
+    }
+}
+
+impl AsRef<str> for PathPattern {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(path: &str) -> Result<(), PatternError> {
+        validate(&path.into())
+    }
+
+    #[test]
+    fn test_validate_reports_the_corrected_form() {
+        for path in [
+            "/",
+            "/{*p}",
+            "/users/{id}/files/{*path}",
+            "/posts/{id}/{slug?}",
+            "/{id}.json",
+        ] {
+            assert_eq!(check(path), Ok(()), "{path}");
+        }
+
+        let err = check("/{{*p}}").unwrap_err();
+        assert_eq!(
+            err,
+            PatternError::EscapedBrace {
+                path: "/{{*p}}".into(),
+                corrected: "/{*p}".into(),
+            }
+        );
+        assert!(err.to_string().contains("write `/{*p}`"), "{err}");
+
+        assert!(matches!(
+            check("/users/{id"),
+            Err(PatternError::UnbalancedBrace { .. })
+        ));
+        assert!(matches!(
+            check("/users/id}"),
+            Err(PatternError::UnbalancedBrace { .. })
+        ));
+        assert!(matches!(
+            check("/users/{}"),
+            Err(PatternError::EmptyParam { .. })
+        ));
+        for path in ["/{*rest}/edit", "/a{*rest}", "/{a*b}", "/x{slug?}"] {
+            assert!(
+                matches!(check(path), Err(PatternError::InvalidParam { .. })),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_builder_generates_the_equivalent_pattern() {
+        assert_eq!(PathPattern::root().wildcard("p").as_str(), "/{*p}");
+        assert_eq!(PathPattern::root().as_str(), "/");
+        assert_eq!(PathPattern::root().param("id").as_str(), "/{id}");
+        assert_eq!(
+            PathPattern::new("/posts/")
+                .param("id")
+                .optional("slug")
+                .as_str(),
+            "/posts/{id}/{slug?}"
+        );
+        // A mistyped name is kept, so validation reports it.
+        let mistyped = PathPattern::new("api").param("{id}");
+        assert_eq!(mistyped.as_str(), "/api/{{id}}");
+        assert!(matches!(
+            check(mistyped.as_str()),
+            Err(PatternError::EscapedBrace { .. })
+        ));
+        assert!(matches!(
+            check(PathPattern::root().param("user id").as_str()),
+            Err(PatternError::InvalidParam { .. })
+        ));
+    }
+}
@@ -0,0 +1,252 @@
+//! CRUD routes registered in one go, see [`ResourceHandlers`].
+
+use super::layer::Step;
+use super::{MethodKind, Router};
+use crate::handler::extract::{Extract, ExtractHandler};
+use crate::handler::request::RequestExt;
+use crate::handler::response::ResponseBody;
+use crate::handler::{ExpressResponse, Handler, Request, Response};
+use crate::middleware::Middleware;
+use async_trait::async_trait;
+use hyper::StatusCode;
+use hyper::body::Incoming;
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// The handlers of a REST resource, registered with
+/// [`App::resource`](crate::prelude::App::resource) under a collection path
+/// such as `/todos`:
+///
+/// | Action    | Route                                  |
+/// |-----------|----------------------------------------|
+/// | `index`   | `GET /todos`                           |
+/// | `create`  | `POST /todos`                          |
+/// | `show`    | `GET /todos/{id}`                      |
+/// | `update`  | `PUT /todos/{id}`, `PATCH /todos/{id}` |
+/// | `destroy` | `DELETE /todos/{id}`                   |
+///
+/// Every action is optional; methods without one are answered with
+/// `405 Method Not Allowed` and an `Allow` header listing the others. The
+/// `_x` variants take handlers with typed extractors, such as
+/// [`Path`](crate::prelude::Path) for the `id` and
+/// [`Json`](crate::prelude::Json) for the body.
+///
+/// A `create` handler that responds `200` with a JSON object holding an
+/// `id` is answered with `201 Created` and a `Location` pointing at the new
+/// item, unless it set `Location` itself.
+///
+/// ```rust
+/// use expressjs::prelude::*;
+///
+/// let mut app = express();
+/// app.resource(
+///     "/todos",
+///     ResourceHandlers::new()
+///         .index(async |_req: Request, res: Response| res.send_json(&serde_json::json!([])))
+///         .show_x(show)
+///         .middleware(CacheMiddleware::no_store()),
+/// );
+///
+/// async fn show(Path(id): Path<u32>) -> Json<serde_json::Value> {
+///     Json(serde_json::json!({ "id": id }))
+/// }
+/// ```
+pub struct ResourceHandlers<B = Incoming> {
+    index: Option<Arc<dyn Handler<B>>>,
+    show: Option<Arc<dyn Handler<B>>>,
+    create: Option<Arc<dyn Handler<B>>>,
+    update: Option<Arc<dyn Handler<B>>>,
+    destroy: Option<Arc<dyn Handler<B>>>,
+    middleware: Vec<Arc<dyn Middleware<B>>>,
+}
+
+impl<B> Default for ResourceHandlers<B> {
+    fn default() -> Self {
+        Self {
+            index: None,
+            show: None,
+            create: None,
+            update: None,
+            destroy: None,
+            middleware: Vec::new(),
+        }
+    }
+}
+
+impl<B: Send + 'static> ResourceHandlers<B> {
+    /// Creates a resource without actions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the handler listing the collection.
+    pub fn index(mut self, handler: impl Handler<B>) -> Self {
+        self.index = Some(Arc::new(handler));
+        self
+    }
+
+    /// Sets the handler returning the item `{id}`.
+    pub fn show(mut self, handler: impl Handler<B>) -> Self {
+        self.show = Some(Arc::new(handler));
+        self
+    }
+
+    /// Sets the handler adding an item to the collection.
+    pub fn create(mut self, handler: impl Handler<B>) -> Self {
+        self.create = Some(Arc::new(handler));
+        self
+    }
+
+    /// Sets the handler replacing or modifying the item `{id}`.
+    pub fn update(mut self, handler: impl Handler<B>) -> Self {
+        self.update = Some(Arc::new(handler));
+        self
+    }
+
+    /// Sets the handler deleting the item `{id}`.
+    pub fn destroy(mut self, handler: impl Handler<B>) -> Self {
+        self.destroy = Some(Arc::new(handler));
+        self
+    }
+
+    /// Sets the `index` handler, taking typed extractors.
+    pub fn index_x<H, T>(self, handler: H) -> Self
+    where
+        H: ExtractHandler<T, B>,
+        T: 'static,
+    {
+        self.index(Extract::new(handler))
+    }
+
+    /// Sets the `show` handler, taking typed extractors.
+    pub fn show_x<H, T>(self, handler: H) -> Self
+    where
+        H: ExtractHandler<T, B>,
+        T: 'static,
+    {
+        self.show(Extract::new(handler))
+    }
+
+    /// Sets the `create` handler, taking typed extractors.
+    pub fn create_x<H, T>(self, handler: H) -> Self
+    where
+        H: ExtractHandler<T, B>,
+        T: 'static,
+    {
+        self.create(Extract::new(handler))
+    }
+
+    /// Sets the `update` handler, taking typed extractors.
+    pub fn update_x<H, T>(self, handler: H) -> Self
+    where
+        H: ExtractHandler<T, B>,
+        T: 'static,
+    {
+        self.update(Extract::new(handler))
+    }
+
+    /// Sets the `destroy` handler, taking typed extractors.
+    pub fn destroy_x<H, T>(self, handler: H) -> Self
+    where
+        H: ExtractHandler<T, B>,
+        T: 'static,
+    {
+        self.destroy(Extract::new(handler))
+    }
+
+    /// Runs `middleware` before every action of the resource, in the order
+    /// added, after the middleware mounted on the app.
+    pub fn middleware(mut self, middleware: impl Middleware<B>) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+}
+
+impl<B: Send + 'static> Router<B> {
+    /// Registers the routes of a REST resource under the collection path
+    /// `path`; see [`ResourceHandlers`].
+    pub fn resource(&mut self, path: impl AsRef<str>, handlers: ResourceHandlers<B>) -> &mut Self {
+        let collection = path.as_ref().trim_end_matches('/');
+        let item = format!("{collection}/{{id}}");
+        let collection = if collection.is_empty() {
+            "/"
+        } else {
+            collection
+        };
+
+        let create = handlers
+            .create
+            .map(|handler| Arc::new(Create(handler)) as Arc<dyn Handler<B>>);
+        let routes = [
+            (MethodKind::Get, collection, handlers.index),
+            (MethodKind::Post, collection, create),
+            (MethodKind::Get, &item, handlers.show),
+            (MethodKind::Put, &item, handlers.update.clone()),
+            (MethodKind::Patch, &item, handlers.update),
+            (MethodKind::Delete, &item, handlers.destroy),
+        ];
+        for (method, path, handler) in routes {
+            let Some(handler) = handler else {
+                continue;
+            };
+            let layer = self.route(path, Shared(handler), method);
+            let middleware = handlers.middleware.iter().cloned().map(Step::Middleware);
+            layer.steps.splice(0..0, middleware);
+        }
+        self
+    }
+}
+
+/// A handler shared between routes, such as `update` under `PUT` and `PATCH`.
+struct Shared<B>(Arc<dyn Handler<B>>);
+
+#[async_trait]
+impl<B: Send + 'static> Handler<B> for Shared<B> {
+    async fn call(&self, req: Request<B>, res: Response) -> Response {
+        self.0.call(req, res).await
+    }
+}
+
+/// The `create` handler, answering `201 Created` for a new item.
+struct Create<B>(Arc<dyn Handler<B>>);
+
+#[async_trait]
+impl<B: Send + 'static> Handler<B> for Create<B> {
+    async fn call(&self, req: Request<B>, res: Response) -> Response {
+        // The URL the client used, so a rewritten path doesn't leak into `Location`.
+        let url = req.original_url();
+        let path = url.split_once('?').map_or(url, |(path, _)| path);
+        let collection = path.trim_end_matches('/').to_owned();
+        let res = self.0.call(req, res).await;
+        if res.status != StatusCode::OK || res.headers.contains_key(LOCATION) {
+            return res;
+        }
+        match created_id(&res) {
+            Some(id) => {
+                let id: String = form_urlencoded::byte_serialize(id.as_bytes()).collect();
+                let id = id.replace('+', "%20");
+                res.status(StatusCode::CREATED)
+                    .location(format!("{collection}/{id}"))
+            }
+            None => res,
+        }
+    }
+}
+
+/// The `id` of a JSON object body, if it is a string or a number.
+fn created_id(res: &Response) -> Option<String> {
+    let content_type = res.headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    if essence != "application/json" && !essence.ends_with("+json") {
+        return None;
+    }
+    let ResponseBody::Full(bytes) = &res.body else {
+        return None;
+    };
+    match serde_json::from_slice::<Value>(bytes).ok()?.get("id")? {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
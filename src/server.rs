@@ -1,46 +1,104 @@
+use crate::handler::catch_panic::panic_message;
+use crate::handler::request::{ConnectionInfo, TlsInfo};
+use futures_util::FutureExt;
 use hyper::server::conn::http1;
 use hyper::service::Service;
 use hyper::{Request, body::Incoming};
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use std::any::Any;
 use std::convert::Infallible;
+use std::future::Future;
 use std::net::SocketAddr;
-use tokio::net::TcpListener;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::signal;
 
 use std::sync::Arc;
 use tokio_rustls::TlsAcceptor;
 use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
 
 pub use crate::handler::response::ServerResponse;
 
 pub(crate) struct Server;
 
+/// Connection-level settings, configured on the [`App`](crate::prelude::App).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ServerOptions {
+    /// How long a client may take to send a request line and headers.
+    pub(crate) header_read_timeout: Option<Duration>,
+    /// How long the listener stays open once the shutdown signal fired.
+    pub(crate) pre_stop_delay: Duration,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            header_read_timeout: Some(Duration::from_secs(30)),
+            pre_stop_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// Whether an app is draining: its shutdown signal fired, so it is about to
+/// stop accepting connections. Clones share the state, so a handler can
+/// capture one; see [`App::drain_state`](crate::prelude::App::drain_state).
+#[derive(Debug, Clone, Default)]
+pub struct DrainState(Arc<AtomicBool>);
+
+impl DrainState {
+    /// Whether the shutdown signal fired.
+    pub fn is_draining(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn start(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Logs a panic raised while handling the connection from `addr`.
+fn log_panic(stage: &str, addr: SocketAddr, payload: &(dyn Any + Send)) {
+    let message = panic_message(payload).unwrap_or("unknown panic");
+    log::error!("panic while {stage} the connection from {addr}: {message}");
+}
+
 impl Server {
     pub async fn bind<F, S>(
         addr: SocketAddr,
+        options: ServerOptions,
         make_service: F,
+        shutdown: impl Future<Output = ()> + Send + 'static,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
     where
-        F: Fn(SocketAddr) -> S + Send + Sync + 'static + Clone,
+        F: Fn(Arc<ConnectionInfo>) -> S + Send + Sync + 'static + Clone,
         S: Service<Request<Incoming>, Response = ServerResponse, Error = Infallible>
             + Send
             + 'static,
         S::Future: Send + 'static,
     {
         let listener = TcpListener::bind(addr).await?;
-        Self::run(listener, make_service, |stream| async move {
-            Ok(TokioIo::new(stream))
-        })
+        Self::run(
+            listener,
+            options,
+            make_service,
+            shutdown,
+            |stream| async move { Ok((TokioIo::new(stream), None)) },
+        )
         .await
     }
 
     pub async fn bind_tls<F, S>(
         addr: SocketAddr,
+        options: ServerOptions,
         tls_config: Arc<ServerConfig>,
         make_service: F,
+        shutdown: impl Future<Output = ()> + Send + 'static,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
     where
-        F: Fn(SocketAddr) -> S + Send + Sync + 'static + Clone,
+        F: Fn(Arc<ConnectionInfo>) -> S + Send + Sync + 'static + Clone,
         S: Service<Request<Incoming>, Response = ServerResponse, Error = Infallible>
             + Send
             + 'static,
@@ -48,48 +106,92 @@ impl Server {
     {
         let listener = TcpListener::bind(addr).await?;
         let tls_acceptor = TlsAcceptor::from(tls_config);
-        Self::run(listener, make_service, move |stream| {
-            let tls_acceptor = tls_acceptor.clone();
-            async move {
-                let tls_stream = tls_acceptor.accept(stream).await?;
-                Ok(TokioIo::new(tls_stream))
-            }
+        Self::run(listener, options, make_service, shutdown, move |stream| {
+            Self::accept_tls(tls_acceptor.clone(), stream)
         })
         .await
     }
 
+    /// Completes the TLS handshake on `stream`, recording what was negotiated.
+    async fn accept_tls(
+        tls_acceptor: TlsAcceptor,
+        stream: TcpStream,
+    ) -> Result<
+        (TokioIo<TlsStream<TcpStream>>, Option<TlsInfo>),
+        Box<dyn std::error::Error + Send + Sync>,
+    > {
+        let tls_stream = tls_acceptor.accept(stream).await?;
+        let session = tls_stream.get_ref().1;
+        let tls = TlsInfo {
+            alpn_protocol: session.alpn_protocol().map(<[u8]>::to_vec),
+            server_name: session.server_name().map(str::to_owned),
+        };
+        Ok((TokioIo::new(tls_stream), Some(tls)))
+    }
+
+    /// Accepts connections until `shutdown` resolves, serving each on its own task.
+    ///
+    /// A panic while setting up or serving one connection is logged and only
+    /// drops that connection. This relies on unwinding: in a build with
+    /// `panic = "abort"`, any panic still ends the process.
+    ///
+    /// A client that doesn't finish sending a request head (request line and
+    /// headers) within the header read timeout is disconnected without a
+    /// response, so trickling headers can't hold connections open (slowloris).
+    /// TLS handshakes aren't covered by this timeout.
+    ///
+    /// `make_service` runs once the acceptor has finished its handshake, with
+    /// the connection's addresses and TLS details.
     async fn run<F, S, A, Fut, I>(
         listener: TcpListener,
+        options: ServerOptions,
         make_service: F,
+        shutdown: impl Future<Output = ()> + Send + 'static,
         acceptor: A,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
     where
-        F: Fn(SocketAddr) -> S + Send + Sync + 'static + Clone,
+        F: Fn(Arc<ConnectionInfo>) -> S + Send + Sync + 'static + Clone,
         S: Service<Request<Incoming>, Response = ServerResponse, Error = Infallible>
             + Send
             + 'static,
         S::Future: Send + 'static,
-        A: Fn(tokio::net::TcpStream) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = Result<I, Box<dyn std::error::Error + Send + Sync>>>
+        A: Fn(TcpStream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(I, Option<TlsInfo>), Box<dyn std::error::Error + Send + Sync>>>
             + Send
             + 'static,
         I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
     {
-        let mut shutdown = tokio::spawn(async {
-            signal::ctrl_c().await.expect("failed to listen for ctrl_c");
-            log::info!("🛑 Received Ctrl+C, shutting down server...");
-        });
+        let mut shutdown = tokio::spawn(shutdown);
+        let listen_addr = listener.local_addr()?;
+        let mut http = http1::Builder::new();
+        http.timer(TokioTimer::new())
+            .header_read_timeout(options.header_read_timeout);
 
         loop {
             tokio::select! {
                 Ok((stream, addr)) = listener.accept() => {
-                    let service = make_service(addr);
-                    let fut = acceptor(stream);
+                    let local_addr = stream.local_addr().unwrap_or(listen_addr);
+                    // A panic while setting up one connection drops that
+                    // connection only; the loop keeps accepting.
+                    let fut = match catch_unwind(AssertUnwindSafe(|| acceptor(stream))) {
+                        Ok(fut) => fut,
+                        Err(payload) => {
+                            log_panic("setting up", addr, &*payload);
+                            continue;
+                        }
+                    };
 
-                    tokio::spawn(async move {
+                    let http = http.clone();
+                    let make_service = make_service.clone();
+                    let connection = async move {
                         match fut.await {
-                            Ok(io) => {
-                                if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
+                            Ok((io, tls)) => {
+                                let service = make_service(Arc::new(ConnectionInfo {
+                                    remote_addr: addr,
+                                    local_addr,
+                                    tls,
+                                }));
+                                if let Err(err) = http.serve_connection(io, service).await {
                                     log::error!("Connection error: {}", err);
                                 }
                             }
@@ -97,6 +199,11 @@ impl Server {
                                 log::error!("Handshake failed: {}", err);
                             }
                         }
+                    };
+                    tokio::spawn(async move {
+                        if let Err(payload) = AssertUnwindSafe(connection).catch_unwind().await {
+                            log_panic("serving", addr, &*payload);
+                        }
                     });
                 }
                 _ = &mut shutdown => {
@@ -107,4 +214,244 @@ impl Server {
 
         Ok(())
     }
+
+    /// Resolves once the process receives Ctrl+C; the default shutdown signal.
+    pub async fn ctrl_c() {
+        signal::ctrl_c().await.expect("failed to listen for ctrl_c");
+        log::info!("🛑 Received Ctrl+C, shutting down server...");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Full};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn get(addr: SocketAddr) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        // A connection dropped during setup may also surface as a reset.
+        let _ = stream.read_to_string(&mut response).await;
+        response
+    }
+
+    fn ok_service() -> impl Service<
+        Request<Incoming>,
+        Response = ServerResponse,
+        Error = Infallible,
+        Future: Send + 'static,
+    > + Send
+    + 'static {
+        hyper::service::service_fn(|_req| async {
+            let body = Full::new(bytes::Bytes::from("ok"))
+                .map_err(|n| match n {})
+                .boxed();
+            Ok::<_, Infallible>(hyper::Response::new(body))
+        })
+    }
+
+    #[tokio::test]
+    async fn test_connection_setup_panic_is_contained() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let make_service = {
+            let connections = Arc::clone(&connections);
+            move |_conn: Arc<ConnectionInfo>| {
+                if connections.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("setup failed");
+                }
+                ok_service()
+            }
+        };
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(Server::run(
+            listener,
+            ServerOptions::default(),
+            make_service,
+            async {
+                let _ = stopped.await;
+            },
+            |stream| async move { Ok((TokioIo::new(stream), None)) },
+        ));
+
+        assert_eq!(get(addr).await, "");
+        for _ in 0..2 {
+            let response = get(addr).await;
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+            assert!(response.ends_with("ok"), "{response}");
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_head_is_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let timeout = Duration::from_millis(200);
+        let options = ServerOptions {
+            header_read_timeout: Some(timeout),
+            ..ServerOptions::default()
+        };
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(Server::run(
+            listener,
+            options,
+            |_conn: Arc<ConnectionInfo>| ok_service(),
+            async {
+                let _ = stopped.await;
+            },
+            |stream| async move { Ok((TokioIo::new(stream), None)) },
+        ));
+
+        // Headers trickle in, and the head never completes.
+        let start = std::time::Instant::now();
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        tokio::time::sleep(timeout / 2).await;
+        stream.write_all(b"Host: te").await.unwrap();
+
+        let mut response = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+            .await
+            .expect("the connection should be dropped")
+            .unwrap();
+        assert!(
+            response.is_empty(),
+            "{}",
+            String::from_utf8_lossy(&response)
+        );
+        assert!(start.elapsed() >= timeout);
+
+        // Clients sending their head promptly are unaffected.
+        assert!(get(addr).await.starts_with("HTTP/1.1 200 OK"));
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    /// Serves `conn` rendered as the response body.
+    fn connection_service(
+        conn: Arc<ConnectionInfo>,
+    ) -> impl Service<
+        Request<Incoming>,
+        Response = ServerResponse,
+        Error = Infallible,
+        Future: Send + 'static,
+    > + Send
+    + 'static {
+        hyper::service::service_fn(move |_req| {
+            let body = Full::new(bytes::Bytes::from(format!("{conn:?}")))
+                .map_err(|n| match n {})
+                .boxed();
+            async { Ok::<_, Infallible>(hyper::Response::new(body)) }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_connection_info_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(Server::run(
+            listener,
+            ServerOptions::default(),
+            connection_service,
+            async {
+                let _ = stopped.await;
+            },
+            |stream| async move { Ok((TokioIo::new(stream), None)) },
+        ));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let expected = ConnectionInfo {
+            remote_addr: stream.local_addr().unwrap(),
+            local_addr: addr,
+            tls: None,
+        };
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.ends_with(&format!("{expected:?}")), "{response}");
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connection_info_over_tls() {
+        use tokio_rustls::rustls::pki_types::pem::PemObject;
+        use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+        use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+        let cert =
+            CertificateDer::from_pem_slice(include_bytes!("../tests/fixtures/localhost.crt"))
+                .unwrap();
+        let key = PrivateKeyDer::from_pem_slice(include_bytes!("../tests/fixtures/localhost.key"))
+            .unwrap();
+        let mut server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let mut client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tls_acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(Server::run(
+            listener,
+            ServerOptions::default(),
+            connection_service,
+            async {
+                let _ = stopped.await;
+            },
+            move |stream| Server::accept_tls(tls_acceptor.clone(), stream),
+        ));
+
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let remote_addr = tcp.local_addr().unwrap();
+        let mut stream = tokio_rustls::TlsConnector::from(Arc::new(client_config))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        // The server closes without a TLS close_notify once the response is sent.
+        let _ = stream.read_to_string(&mut response).await;
+        let expected = ConnectionInfo {
+            remote_addr,
+            local_addr: addr,
+            tls: Some(TlsInfo {
+                alpn_protocol: Some(b"http/1.1".to_vec()),
+                server_name: Some("localhost".to_owned()),
+            }),
+        };
+        assert!(response.ends_with(&format!("{expected:?}")), "{response}");
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
 }
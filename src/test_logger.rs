@@ -0,0 +1,58 @@
+//! A capturing [`log`] backend shared by unit tests.
+//!
+//! `log` only allows one global logger per process, so every test that asserts
+//! on log output goes through this module. Records are stored per thread, which
+//! keeps parallel tests (each `#[tokio::test]` runs on its own thread) isolated.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::cell::RefCell;
+use std::sync::Once;
+
+/// A single captured log record.
+#[derive(Debug, Clone)]
+pub(crate) struct CapturedRecord {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+thread_local! {
+    static RECORDS: RefCell<Vec<CapturedRecord>> = const { RefCell::new(Vec::new()) };
+}
+
+struct CapturingLogger;
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        RECORDS.with(|records| {
+            records.borrow_mut().push(CapturedRecord {
+                level: record.level(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            })
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger;
+static INIT: Once = Once::new();
+
+/// Installs the capturing logger (once) and clears this thread's records.
+pub(crate) fn capture() {
+    INIT.call_once(|| {
+        log::set_logger(&LOGGER).expect("another logger was already installed");
+        log::set_max_level(LevelFilter::Trace);
+    });
+    RECORDS.with(|records| records.borrow_mut().clear());
+}
+
+/// Drains the records captured on this thread since the last call.
+pub(crate) fn take() -> Vec<CapturedRecord> {
+    RECORDS.with(|records| std::mem::take(&mut *records.borrow_mut()))
+}
@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use expressjs::prelude::*;
+use http_body_util::BodyExt;
 use serde_json::json;
 
 // Define standard async handlers
@@ -210,3 +211,2020 @@ async fn test_error_handlers() {
         .await;
     assert_eq!(res_405.get_status(), hyper::StatusCode::METHOD_NOT_ALLOWED);
 }
+
+#[derive(Clone)]
+struct MarkerMiddleware;
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for MarkerMiddleware {
+    async fn call(&self, _req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        res.header("X-Checked", hyper::header::HeaderValue::from_static("1"));
+        next_res()
+    }
+}
+
+#[tokio::test]
+async fn test_use_for_restricts_methods() {
+    let mut app = App::<()>::default();
+
+    app.use_for(
+        &[MethodKind::Post, MethodKind::Put],
+        "/items",
+        MarkerMiddleware,
+    );
+    app.all(
+        "/items",
+        |_, res: Response| async move { res.send_text("ok") },
+    );
+
+    for (method, expected) in [("POST", true), ("PUT", true), ("GET", false)] {
+        let res = app
+            .handle(
+                hyper::Request::builder()
+                    .uri("/items")
+                    .method(method)
+                    .body(())
+                    .unwrap(),
+                Response::new(),
+            )
+            .await;
+        assert_eq!(res.get_status(), hyper::StatusCode::OK);
+        assert_eq!(
+            res.headers.contains_key("X-Checked"),
+            expected,
+            "unexpected middleware behaviour for {method}"
+        );
+    }
+}
+
+/// Writes a full response but still lets the request continue.
+#[derive(Clone)]
+struct ProducingMiddleware(u16);
+
+#[async_trait]
+impl<B: Send + Sync + 'static> Middleware<B> for ProducingMiddleware {
+    async fn call(&self, _req: &mut Request<B>, res: &mut Response) -> MiddlewareResult {
+        let produced = std::mem::take(res)
+            .status_code(self.0)
+            .send_text("produced");
+        *res = produced;
+        next_res()
+    }
+}
+
+async fn status_of(app: &App<()>, method: &str, uri: &str) -> hyper::StatusCode {
+    app.handle(
+        hyper::Request::builder()
+            .uri(uri)
+            .method(method)
+            .body(())
+            .unwrap(),
+        Response::new(),
+    )
+    .await
+    .get_status()
+}
+
+#[tokio::test]
+async fn test_fallback_does_not_overwrite_middleware_response() {
+    let mut app = App::<()>::default();
+    app.use_global(ProducingMiddleware(418));
+    app.get("/exists", |_, res: Response| async move { res });
+
+    // No route at all: would have been a 404.
+    assert_eq!(status_of(&app, "GET", "/nowhere").await.as_u16(), 418);
+    // Route exists for another method: would have been a 405.
+    assert_eq!(status_of(&app, "POST", "/exists").await.as_u16(), 418);
+}
+
+#[tokio::test]
+async fn test_fallback_keeps_middleware_body_with_default_status() {
+    let mut app = App::<()>::default();
+    app.use_global(ProducingMiddleware(200));
+    app.not_found(|_, res: Response| async move { res.status_code(404).send_text("custom") });
+
+    let res = app
+        .handle(
+            hyper::Request::builder().uri("/nowhere").body(()).unwrap(),
+            Response::new(),
+        )
+        .await;
+    assert_eq!(res.get_status(), hyper::StatusCode::OK);
+    assert_eq!(res.body.content_length(), Some("produced".len() as u64));
+}
+
+#[tokio::test]
+async fn test_fallback_after_header_only_middleware() {
+    let mut app = App::<()>::default();
+    app.use_global(MarkerMiddleware);
+    app.get("/exists", |_, res: Response| async move { res });
+
+    assert_eq!(
+        status_of(&app, "GET", "/nowhere").await,
+        hyper::StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        status_of(&app, "POST", "/exists").await,
+        hyper::StatusCode::METHOD_NOT_ALLOWED
+    );
+    assert_eq!(
+        status_of(&app, "GET", "/exists").await,
+        hyper::StatusCode::OK
+    );
+}
+
+async fn rest_handler<B: Send + 'static>(req: Request<B>, res: Response) -> Response {
+    let rest = req.params().get("rest").map(str::to_owned);
+    match rest {
+        Some(rest) => res
+            .header(
+                "X-Rest",
+                hyper::header::HeaderValue::from_str(&rest).unwrap(),
+            )
+            .send_text("ok"),
+        None => res.status_code(500).send_text("missing remainder"),
+    }
+}
+
+async fn rest_of(app: &App<()>, uri: &str) -> (hyper::StatusCode, Option<String>) {
+    let res = app
+        .handle(
+            hyper::Request::builder().uri(uri).body(()).unwrap(),
+            Response::new(),
+        )
+        .await;
+    let rest = res
+        .headers
+        .get("X-Rest")
+        .map(|v| v.to_str().unwrap().to_owned());
+    (res.get_status(), rest)
+}
+
+#[tokio::test]
+async fn test_catch_all_remainder() {
+    let mut app = App::<()>::default();
+    // A global middleware must not shadow the route's own wildcard parameter.
+    app.use_global(MarkerMiddleware);
+    app.get("/browse/{*rest}", rest_handler);
+
+    for (uri, expected) in [
+        ("/browse/", ""),
+        ("/browse", ""),
+        ("/browse/a", "a"),
+        ("/browse/a/b/c", "a/b/c"),
+    ] {
+        let (status, rest) = rest_of(&app, uri).await;
+        assert_eq!(status, hyper::StatusCode::OK, "{uri}");
+        assert_eq!(rest.as_deref(), Some(expected), "{uri}");
+    }
+
+    let (status, _) = rest_of(&app, "/browsing").await;
+    assert_eq!(status, hyper::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_catch_all_remainder_in_nested_router() {
+    let mut app = App::<()>::default();
+    let mut files = Router::<()>::default();
+    files.get("/browse/{*rest}", rest_handler);
+    files.get("/{*rest}", rest_handler);
+    app.use_router("/files", files);
+
+    assert_eq!(rest_of(&app, "/files/browse/").await.1.as_deref(), Some(""));
+    assert_eq!(
+        rest_of(&app, "/files/browse/a/b/c").await.1.as_deref(),
+        Some("a/b/c")
+    );
+    assert_eq!(rest_of(&app, "/files/").await.1.as_deref(), Some(""));
+    assert_eq!(
+        rest_of(&app, "/files/other/x").await.1.as_deref(),
+        Some("other/x")
+    );
+}
+
+#[tokio::test]
+async fn test_explicit_route_overrides_catch_all_prefix() {
+    let mut app = App::<()>::default();
+    app.get("/browse/{*rest}", rest_handler);
+    app.get("/browse", |_, res: Response| async move {
+        res.header("X-Rest", hyper::header::HeaderValue::from_static("index"))
+            .send_text("index")
+    });
+
+    assert_eq!(rest_of(&app, "/browse").await.1.as_deref(), Some("index"));
+    assert_eq!(rest_of(&app, "/browse/a").await.1.as_deref(), Some("a"));
+}
+
+#[tokio::test]
+async fn test_body_blocking_does_not_stall_other_requests() {
+    use std::sync::{Arc, Mutex, mpsc};
+    use std::time::Duration;
+
+    let (tx, rx) = mpsc::channel::<()>();
+    let rx = Arc::new(Mutex::new(rx));
+
+    let mut app = App::<()>::default();
+    app.get("/slow", move |_, res: Response| {
+        let rx = Arc::clone(&rx);
+        async move {
+            res.body_blocking(move || {
+                // Only released once the concurrent request below has completed,
+                // which can't happen if this closure blocks the executor.
+                let released = rx.lock().unwrap().recv_timeout(Duration::from_secs(5));
+                if released.is_ok() {
+                    "released"
+                } else {
+                    "stalled"
+                }
+            })
+            .await
+        }
+    });
+    app.get(
+        "/fast",
+        |_, res: Response| async move { res.send_text("fast") },
+    );
+
+    let slow = app.handle(
+        hyper::Request::builder().uri("/slow").body(()).unwrap(),
+        Response::new(),
+    );
+    let fast = async {
+        let res = app
+            .handle(
+                hyper::Request::builder().uri("/fast").body(()).unwrap(),
+                Response::new(),
+            )
+            .await;
+        tx.send(()).unwrap();
+        res
+    };
+
+    let (slow, fast) = tokio::join!(slow, fast);
+    assert_eq!(fast.get_status(), hyper::StatusCode::OK);
+    assert_eq!(slow.get_status(), hyper::StatusCode::OK);
+    assert_eq!(slow.body.content_length(), Some("released".len() as u64));
+}
+
+#[tokio::test]
+async fn test_body_blocking_panic_becomes_500() {
+    let res = Response::new()
+        .body_blocking(|| -> &'static str { panic!("boom") })
+        .await;
+    assert_eq!(res.get_status(), hyper::StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(matches!(
+        res.error,
+        Some(ResponseError::BlockingTaskFailed(_))
+    ));
+}
+
+async fn read_file(req: Request<()>, res: Response) -> Result<Response, ResponseError> {
+    let name = req.params().get("name").unwrap_or("");
+    if name == "broken" {
+        Err(std::io::Error::other("disk failure"))?;
+    }
+    let contents = std::fs::read(format!("/definitely/missing/{name}"))?;
+    Ok(res.body(contents))
+}
+
+#[derive(Debug)]
+enum ApiError {
+    Forbidden,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (hyper::StatusCode::FORBIDDEN, "forbidden").into_response()
+    }
+}
+
+#[tokio::test]
+async fn test_handlers_returning_results() {
+    let mut app = App::<()>::default();
+    app.get("/files/{name}", read_file);
+    app.get("/admin", |_, _| async {
+        Err::<Response, _>(ApiError::Forbidden)
+    });
+    app.get("/ok", |_, _| async { Ok::<_, ApiError>("fine") });
+
+    assert_eq!(
+        status_of(&app, "GET", "/files/report.txt").await,
+        hyper::StatusCode::NOT_FOUND
+    );
+
+    let res = app
+        .handle(
+            hyper::Request::builder()
+                .uri("/files/broken")
+                .body(())
+                .unwrap(),
+            Response::new(),
+        )
+        .await;
+    assert_eq!(res.get_status(), hyper::StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(matches!(res.error, Some(ResponseError::FileOpenError(_))));
+
+    assert_eq!(
+        status_of(&app, "GET", "/admin").await,
+        hyper::StatusCode::FORBIDDEN
+    );
+    assert_eq!(status_of(&app, "GET", "/ok").await, hyper::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_error_format_modes() {
+    async fn error_of(app: &App<()>, uri: &str, accept: &str) -> (String, String) {
+        let req = hyper::Request::builder()
+            .uri(uri)
+            .header("Accept", accept)
+            .body(())
+            .unwrap();
+        let res = app.handle(req, Response::new()).await.into_hyper();
+        let content_type = res.headers()["content-type"].to_str().unwrap().to_owned();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        (content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    let routes = |app: &mut App<()>| {
+        app.get("/files/{name}", read_file);
+        app.get("/large", |_, _| async {
+            Err::<Response, _>(ResponseError::PayloadTooLarge(16))
+        });
+    };
+
+    // Plain text stays the default, whatever the client accepts.
+    let mut app = App::<()>::default();
+    routes(&mut app);
+    let (content_type, body) = error_of(&app, "/large", "application/json").await;
+    assert_eq!(content_type, "text/plain; charset=utf-8");
+    assert_eq!(body, "payload too large: limit is 16 bytes");
+
+    let mut app = App::<()>::default();
+    app.error_format(ErrorFormat::Json);
+    routes(&mut app);
+    let (content_type, body) = error_of(&app, "/large", "*/*").await;
+    assert_eq!(content_type, "application/json");
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({ "error": { "code": "payload_too_large", "message": "payload too large: limit is 16 bytes", "details": null } })
+    );
+    // Server errors still hide their details.
+    let (_, body) = error_of(&app, "/files/broken", "*/*").await;
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({ "error": { "code": "internal_server_error", "message": "Internal Server Error", "details": null } })
+    );
+
+    let mut app = App::<()>::default();
+    app.error_format(ErrorFormat::Negotiate);
+    routes(&mut app);
+    let (content_type, _) = error_of(&app, "/large", "application/json").await;
+    assert_eq!(content_type, "application/json");
+    let (content_type, _) = error_of(&app, "/large", "text/html").await;
+    assert_eq!(content_type, "text/plain; charset=utf-8");
+}
+
+#[tokio::test]
+async fn test_error_details_modes() {
+    async fn body_of(app: &App<()>, uri: &str) -> String {
+        let req = hyper::Request::builder().uri(uri).body(()).unwrap();
+        let res = app.handle(req, Response::new()).await.into_hyper();
+        assert_eq!(res.status(), hyper::StatusCode::INTERNAL_SERVER_ERROR);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    let routes = |app: &mut App<()>| {
+        app.get("/panic", |_, _| async {
+            let secrets: Vec<&str> = Vec::new();
+            Response::new().send_text(secrets[0])
+        });
+        app.get("/api", |_, _| async {
+            Err::<Response, _>(expressjs::prelude::ApiError::internal(
+                "db password rejected",
+            ))
+        });
+    };
+
+    // Production: the client only learns the status.
+    let mut app = App::<()>::default();
+    routes(&mut app);
+    assert_eq!(body_of(&app, "/panic").await, "Internal Server Error");
+    let body: serde_json::Value = serde_json::from_str(&body_of(&app, "/api").await).unwrap();
+    assert_eq!(
+        body,
+        json!({ "error": { "code": "internal_server_error", "message": "Internal Server Error", "details": null } })
+    );
+
+    // Development: the error chain is sent along.
+    let mut app = App::<()>::default();
+    app.error_details(true);
+    routes(&mut app);
+    let body = body_of(&app, "/panic").await;
+    assert!(
+        body.starts_with("handler panicked: index out of bounds"),
+        "{body}"
+    );
+    let body: serde_json::Value = serde_json::from_str(&body_of(&app, "/api").await).unwrap();
+    assert_eq!(
+        body,
+        json!({ "error": { "code": "internal_server_error", "message": "db password rejected", "details": null } })
+    );
+}
+
+#[tokio::test]
+async fn test_error_envelope_is_shared_by_router_and_middleware() {
+    async fn error_of(app: &App<()>, uri: &str, content_length: &str) -> serde_json::Value {
+        let req = hyper::Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("Accept", "application/json")
+            .header("Content-Length", content_length)
+            .body(())
+            .unwrap();
+        let res = app.handle(req, Response::new()).await.into_hyper();
+        assert_eq!(res.headers()["content-type"], "application/json");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    let build = |envelope: Option<ErrorEnvelope>| {
+        let mut app = App::<()>::default();
+        if let Some(envelope) = envelope {
+            app.error_envelope(envelope);
+        }
+        app.error_format(ErrorFormat::Json);
+        app.use_with(
+            "/limited",
+            RateLimitMiddleware::new(1, std::time::Duration::from_secs(60)),
+        );
+        app.use_with(
+            "/upload",
+            BodySizeLimitMiddleware {
+                max_size_bytes: 8,
+                strict: false,
+            },
+        );
+        app.use_global(
+            AuthMiddleware::builder()
+                .protect_route("/admin", AuthLevel::Admin)
+                .build_with_sessions(SessionTokenValidator::new()),
+        );
+        app.post(
+            "/limited",
+            |_, res: Response| async move { res.send_text("ok") },
+        );
+        app.post("/conflict", |_, mut res: Response| async move {
+            res.respond_enveloped_error(409, "conflict", serde_json::Value::Null, true);
+            res
+        });
+        app.post("/taken", |_, _| async {
+            Err::<Response, _>(expressjs::prelude::ApiError::conflict("taken"))
+        });
+        app.post("/large", |_, _| async {
+            Err::<Response, _>(ResponseError::PayloadTooLarge(16))
+        });
+        app
+    };
+
+    let app = build(None);
+    let errors = [
+        error_of(&app, "/missing", "0").await,
+        error_of(&app, "/upload", "64").await,
+        {
+            let first = hyper::Request::post("/limited").body(()).unwrap();
+            app.handle(first, Response::new()).await;
+            error_of(&app, "/limited", "0").await
+        },
+        error_of(&app, "/admin", "0").await,
+        error_of(&app, "/conflict", "0").await,
+        error_of(&app, "/taken", "0").await,
+        error_of(&app, "/large", "0").await,
+    ];
+    let codes: Vec<_> = errors.iter().map(|e| &e["error"]["code"]).collect();
+    assert_eq!(
+        codes,
+        [
+            "not_found",
+            "payload_too_large",
+            "too_many_requests",
+            "unauthorized",
+            "conflict",
+            "conflict",
+            "payload_too_large",
+        ]
+    );
+    for error in &errors {
+        let keys: Vec<_> = error["error"].as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["code", "details", "message"], "{error}");
+        assert_eq!(error.as_object().unwrap().len(), 1, "{error}");
+    }
+    assert_eq!(errors[2]["error"]["details"]["retry_after"], 60);
+
+    let app = build(Some(ErrorEnvelope::custom(
+        |error| json!({ "status": error.status.as_u16(), "title": error.message }),
+    )));
+    assert_eq!(
+        error_of(&app, "/missing", "0").await,
+        json!({ "status": 404, "title": "Not Found" })
+    );
+    assert_eq!(error_of(&app, "/admin", "0").await["status"], 401);
+    // Handlers' errors follow the same envelope.
+    for (uri, status, title) in [
+        ("/conflict", 409, "conflict"),
+        ("/taken", 409, "taken"),
+        ("/large", 413, "payload too large: limit is 16 bytes"),
+    ] {
+        assert_eq!(
+            error_of(&app, uri, "0").await,
+            json!({ "status": status, "title": title }),
+            "{uri}"
+        );
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    greeting: &'static str,
+}
+
+#[derive(Deserialize)]
+struct CreateUser {
+    name: String,
+}
+
+async fn create_user(
+    Path(id): Path<u32>,
+    Json(body): Json<CreateUser>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    (
+        hyper::StatusCode::CREATED,
+        Json(json!({ "id": id, "message": format!("{}, {}", state.greeting, body.name) })),
+    )
+}
+
+type FullBody = http_body_util::Full<bytes::Bytes>;
+
+async fn send(
+    app: &App<FullBody>,
+    uri: &str,
+    content_type: Option<&str>,
+    body: &'static str,
+) -> Response {
+    let mut req = hyper::Request::builder().method("POST").uri(uri);
+    if let Some(content_type) = content_type {
+        req = req.header("Content-Type", content_type);
+    }
+    let req = req.body(FullBody::from(body)).unwrap();
+    app.handle(req, Response::new()).await
+}
+
+#[tokio::test]
+async fn test_extractor_handler_end_to_end() {
+    let mut app = App::<FullBody>::default();
+    app.state(AppState { greeting: "Hello" });
+    app.post_x("/users/{id}", create_user);
+
+    let res = send(
+        &app,
+        "/users/42",
+        Some("application/json"),
+        r#"{"name":"Ada"}"#,
+    )
+    .await;
+    assert_eq!(res.get_status(), hyper::StatusCode::CREATED);
+    let body = res.into_hyper().into_body().collect().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body.to_bytes()).unwrap();
+    assert_eq!(body, json!({ "id": 42, "message": "Hello, Ada" }));
+
+    let status = |res: Response| res.get_status();
+    assert_eq!(
+        status(send(&app, "/users/abc", Some("application/json"), "{}").await),
+        hyper::StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        status(send(&app, "/users/1", Some("text/plain"), r#"{"name":"Ada"}"#).await),
+        hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE
+    );
+    assert_eq!(
+        status(send(&app, "/users/1", Some("application/json"), "{oops").await),
+        hyper::StatusCode::BAD_REQUEST
+    );
+
+    // Without registered state, the handler can't run.
+    let mut stateless = App::<FullBody>::default();
+    stateless.post_x("/users/{id}", |_: Path<u32>, _: State<AppState>| async {
+        "unreachable"
+    });
+    assert_eq!(
+        status(send(&stateless, "/users/1", None, "").await),
+        hyper::StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[tokio::test]
+async fn test_http10_streaming_response_is_buffered() {
+    // Files of 1 MiB or more are streamed by `send_file`.
+    let path = std::env::temp_dir().join(format!("expressjs-http10-{}.bin", std::process::id()));
+    std::fs::write(&path, vec![b'x'; 1536 * 1024]).unwrap();
+    let file = path.to_string_lossy().into_owned();
+
+    let mut app = App::<()>::default();
+    app.get("/download", move |_, res: Response| {
+        let file = file.clone();
+        async move {
+            res.header(
+                "Transfer-Encoding",
+                hyper::header::HeaderValue::from_static("chunked"),
+            )
+            .send_file(file)
+            .await
+        }
+    });
+
+    let request = |version| {
+        hyper::Request::builder()
+            .version(version)
+            .uri("/download")
+            .body(())
+            .unwrap()
+    };
+
+    let res = app
+        .handle(request(hyper::Version::HTTP_11), Response::new())
+        .await;
+    assert_eq!(res.body.content_length(), None, "HTTP/1.1 keeps streaming");
+
+    let res = app
+        .handle(request(hyper::Version::HTTP_10), Response::new())
+        .await;
+    assert_eq!(res.body.content_length(), Some(1536 * 1024));
+    let res = res.into_hyper();
+    assert!(res.headers().get("Transfer-Encoding").is_none());
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.len(), 1536 * 1024);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_callbacks_run_when_signal_resolves() {
+    use std::sync::{Arc, Mutex};
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+
+    let mut app = express();
+    app.shutdown_signal(async move {
+        let _ = stopped.await;
+    });
+    for name in ["flush", "close"] {
+        let calls = calls.clone();
+        app.on_shutdown(move || async move {
+            tokio::task::yield_now().await;
+            calls.lock().unwrap().push(name);
+        });
+    }
+
+    let server = tokio::spawn(app.listen(0, async |_| {}));
+    tokio::task::yield_now().await;
+    assert!(calls.lock().unwrap().is_empty());
+
+    stop.send(()).unwrap();
+    tokio::time::timeout(std::time::Duration::from_secs(5), server)
+        .await
+        .expect("listen should return after the shutdown signal")
+        .unwrap();
+    assert_eq!(*calls.lock().unwrap(), ["flush", "close"]);
+}
+
+#[tokio::test]
+async fn test_readiness_fails_while_draining_in_flight_requests() {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn send(port: u16, path: &str, connection: &str) -> tokio::net::TcpStream {
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let request =
+            format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: {connection}\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream
+    }
+    async fn read(mut stream: tokio::net::TcpStream) -> String {
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response))
+            .await
+            .expect("the server should close the connection")
+            .unwrap();
+        response
+    }
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let (started, mut started_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+    let release = Arc::new(tokio::sync::Notify::new());
+
+    let mut app = express();
+    app.shutdown_signal(async move {
+        let _ = stopped.await;
+    });
+    app.health_checks("/healthz", "/readyz")
+        .pre_stop_delay(Duration::from_secs(2));
+    let slow_release = release.clone();
+    app.get("/slow", move |_req, res: Response| {
+        let started = started.clone();
+        let release = slow_release.clone();
+        async move {
+            started.send(()).unwrap();
+            release.notified().await;
+            res.send_text("done")
+        }
+    });
+    let drain = app.drain_state();
+    let server = tokio::spawn(app.listen(port, async |_| {}));
+
+    let ready = loop {
+        match tokio::net::TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(_) => break read(send(port, "/readyz", "close").await).await,
+            Err(_) => tokio::task::yield_now().await,
+        }
+    };
+    assert!(ready.starts_with("HTTP/1.1 200"), "{ready}");
+
+    // Sent keep-alive: only draining makes the server close the connection.
+    let in_flight = send(port, "/slow", "keep-alive").await;
+    started_rx.recv().await.unwrap();
+    stop.send(()).unwrap();
+    while !drain.is_draining() {
+        tokio::task::yield_now().await;
+    }
+
+    // The request in flight is still waiting to be released.
+    let ready = read(send(port, "/readyz", "close").await).await;
+    assert!(ready.starts_with("HTTP/1.1 503"), "{ready}");
+    assert!(ready.ends_with(r#"{"status":"draining"}"#), "{ready}");
+    let live = read(send(port, "/healthz", "close").await).await;
+    assert!(live.starts_with("HTTP/1.1 200"), "{live}");
+
+    release.notify_one();
+    let slow = read(in_flight).await;
+    assert!(slow.starts_with("HTTP/1.1 200"), "{slow}");
+    assert!(slow.contains("connection: close"), "{slow}");
+    assert!(slow.ends_with("done"), "{slow}");
+
+    tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .expect("listen should return after the pre-stop delay")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_frozen_app_validates_and_serves() {
+    let mut app = App::<()>::default();
+    app.get("/a", async |_req, res: Response| res.send_text("a"));
+    app.all("/a", async |_req, res: Response| res.send_text("any"));
+    assert!(matches!(
+        app.freeze(),
+        Err(FreezeError::DuplicateRoute { method: MethodKind::Get, ref path, .. }) if &**path == "/a"
+    ));
+
+    async fn greet(Path(name): Path<String>, State(state): State<AppState>) -> String {
+        format!("{}, {name}", state.greeting)
+    }
+
+    // Settings such as app state carry over to the frozen app.
+    let mut app = App::<()>::default();
+    app.state(AppState { greeting: "Hello" });
+    app.get_x("/greet/{name}", greet);
+    let app = app.freeze().unwrap();
+
+    let req = hyper::Request::builder()
+        .uri("/greet/ada")
+        .body(())
+        .unwrap();
+    let res = app.handle(req, Response::new()).await.into_hyper();
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, "Hello, ada");
+}
+
+#[tokio::test]
+async fn test_extractor_handlers_keep_middleware_headers() {
+    let mut app = App::<()>::default();
+    app.use_global(CorsMiddleware::permissive());
+    app.use_global(|_req: &mut Request<()>, res: &mut Response| {
+        res.headers.insert("x-before", "mw".parse().unwrap());
+        res.headers.insert("x-replaced", "mw".parse().unwrap());
+        async { next_res() }
+    });
+    app.get_x("/greet/{name}", |Path(name): Path<String>| async move {
+        Response::new()
+            .header(
+                "x-replaced",
+                hyper::header::HeaderValue::from_static("handler"),
+            )
+            .send_text(format!("hi {name}"))
+    });
+    app.resource(
+        "/todos",
+        ResourceHandlers::new().show_x(|Path(id): Path<u32>| async move { Json(id) }),
+    );
+
+    for uri in ["/greet/ada", "/todos/7"] {
+        let req = hyper::Request::builder()
+            .uri(uri)
+            .header("origin", "https://app.example")
+            .body(())
+            .unwrap();
+        let res = app.handle(req, Response::new()).await;
+        assert_eq!(res.status, StatusCode::OK, "{uri}");
+        assert!(
+            res.headers.contains_key("access-control-allow-origin"),
+            "{uri}"
+        );
+        assert_eq!(res.headers["x-before"], "mw", "{uri}");
+    }
+
+    let req = hyper::Request::builder()
+        .uri("/greet/ada")
+        .body(())
+        .unwrap();
+    let res = app.handle(req, Response::new()).await;
+    assert_eq!(res.headers["x-replaced"], "handler");
+    assert_eq!(
+        res.into_hyper()
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes(),
+        "hi ada"
+    );
+}
+
+#[tokio::test]
+async fn test_handlers_returning_fresh_responses_keep_middleware_headers() {
+    let mut app = App::<()>::default();
+    app.use_global(|_req: &mut Request<()>, res: &mut Response| {
+        res.headers.insert("x-before", "mw".parse().unwrap());
+        res.headers.append("set-cookie", "mw=1".parse().unwrap());
+        async { next_res() }
+    });
+    app.get("/missing", |_, _| async {
+        Err::<Response, _>(expressjs::prelude::ApiError::not_found("no such item"))
+    });
+    app.get("/text", |_, _| async { "plain".to_owned() });
+    app.get("/kept", |_, res: Response| async move {
+        let mut res = res.send_text("kept");
+        res.headers.remove("x-before");
+        res
+    });
+
+    let call = |uri: &'static str| {
+        let req = hyper::Request::builder().uri(uri).body(()).unwrap();
+        app.handle(req, Response::new())
+    };
+    let res = call("/missing").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(res.headers["x-before"], "mw");
+    assert_eq!(res.headers["set-cookie"], "mw=1");
+
+    let res = call("/text").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.headers["x-before"], "mw");
+
+    // A handler that returns the response it was given keeps its own edits.
+    let res = call("/kept").await;
+    assert!(!res.headers.contains_key("x-before"));
+}
+
+#[tokio::test]
+async fn test_compression_decodes_request_and_encodes_response() {
+    use std::io::{Read, Write};
+
+    let mut app = App::<FullBody>::default();
+    app.use_global(CompressionMiddleware::new());
+    app.post_x("/echo", |Json(body): Json<serde_json::Value>| async move {
+        Json(body)
+    });
+
+    let payload = json!({ "text": "compressible ".repeat(200) });
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(payload.to_string().as_bytes()).unwrap();
+    let req = hyper::Request::builder()
+        .method("POST")
+        .uri("/echo")
+        .header("Content-Type", "application/json")
+        .header("Content-Encoding", "gzip")
+        .header("Accept-Encoding", "gzip;q=0.5, br")
+        .body(FullBody::from(gzip.finish().unwrap()))
+        .unwrap();
+    let res = app.handle(req, Response::new()).await.into_hyper();
+    assert_eq!(res.status(), hyper::StatusCode::OK);
+    assert_eq!(res.headers()["content-encoding"], "br");
+    assert_eq!(res.headers()["vary"], "Accept-Encoding");
+    let content_length = res.headers()["content-length"].clone();
+
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(content_length, body.len().to_string());
+    let mut decoded = Vec::new();
+    brotli::Decompressor::new(&body[..], 4096)
+        .read_to_end(&mut decoded)
+        .unwrap();
+    let echoed: serde_json::Value = serde_json::from_slice(&decoded).unwrap();
+    assert_eq!(echoed, payload);
+
+    // An encoding the server can't decode is refused up front.
+    let req = hyper::Request::builder()
+        .method("POST")
+        .uri("/echo")
+        .header("Content-Encoding", "zstd")
+        .body(FullBody::from("{}"))
+        .unwrap();
+    let res = app.handle(req, Response::new()).await.into_hyper();
+    assert_eq!(res.status(), hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(res.headers()["accept-encoding"], "br, gzip, deflate");
+}
+
+#[derive(Clone, Default)]
+struct Trace(Vec<&'static str>);
+
+fn trace_middleware(
+    name: &'static str,
+) -> impl Fn(&mut Request<()>, &mut Response) -> std::future::Ready<MiddlewareResult>
++ Send
++ Sync
++ 'static {
+    move |req: &mut Request<()>, _res: &mut Response| {
+        req.extensions_mut()
+            .get_or_insert_default::<Trace>()
+            .0
+            .push(name);
+        std::future::ready(next_res())
+    }
+}
+
+fn trace_of(req: &Request<()>) -> String {
+    req.extensions()
+        .get::<Trace>()
+        .map(|trace| trace.0.join(","))
+        .unwrap_or_default()
+}
+
+async fn body_of(app: &App<()>, uri: &str) -> String {
+    let req = hyper::Request::builder().uri(uri).body(()).unwrap();
+    let res = app.handle(req, Response::new()).await.into_hyper();
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn test_phases_order_layers_regardless_of_registration() {
+    let mut app = App::<()>::default();
+    app.use_with_phase("/", Phase::PostRouting, trace_middleware("post"));
+    app.use_with("/", trace_middleware("before"));
+    app.get("/page", async |req: Request<()>, res: Response| {
+        res.send_text(trace_of(&req))
+    });
+    app.use_with("/", trace_middleware("after"));
+    app.use_with_phase(
+        "/",
+        Phase::PostRouting,
+        |req: &mut Request<()>, res: &mut Response| {
+            res.body(trace_of(req));
+            std::future::ready(next_res())
+        },
+    );
+    app.use_with_phase("/", Phase::PreRouting, trace_middleware("pre"));
+
+    // Pre-routing middleware runs first although it was registered last, and
+    // the route answers before the routing-phase middleware added after it.
+    assert_eq!(body_of(&app, "/page").await, "pre,before");
+    // Without a matching route, post-routing middleware runs last.
+    assert_eq!(body_of(&app, "/other").await, "pre,before,after,post");
+}
+
+#[tokio::test]
+async fn test_route_default_content_type() {
+    let mut app = App::<()>::default();
+    app.route("/page")
+        .content_type("text/html")
+        .get(async |_req, res| res.body("<h1>Hello</h1>"))
+        .post(async |_req, res| res.send_json(&json!({ "ok": true })))
+        .delete(async |_req, res| res.status_code(204));
+
+    let call = async |method: &str| {
+        let req = hyper::Request::builder()
+            .method(method)
+            .uri("/page")
+            .body(())
+            .unwrap();
+        app.handle(req, Response::new()).await
+    };
+
+    let res = call("GET").await;
+    assert_eq!(res.headers["content-type"], "text/html; charset=utf-8");
+    // A content type set by the handler overrides the route default.
+    let res = call("POST").await;
+    assert_eq!(res.headers["content-type"], "application/json");
+    // Bodyless responses are left alone.
+    let res = call("DELETE").await;
+    assert!(!res.headers.contains_key("content-type"));
+}
+
+#[tokio::test]
+async fn test_handler_observes_client_disconnect() {
+    use tokio::io::AsyncWriteExt;
+    use tokio::sync::mpsc;
+
+    let (events, mut events_rx) = mpsc::unbounded_channel::<&'static str>();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let mut app = express();
+    app.shutdown_signal(async move {
+        let _ = stopped.await;
+    });
+    app.get("/slow", move |req, res: Response| {
+        let events = events.clone();
+        async move {
+            let disconnected = req.on_disconnect();
+            let worker_events = events.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = disconnected => worker_events.send("disconnected"),
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(10)) => {
+                        worker_events.send("timed out")
+                    }
+                }
+            });
+            events.send("started").unwrap();
+            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            res.send_text("too late")
+        }
+    });
+    let server = tokio::spawn(app.listen(port, async |_| {}));
+
+    let mut client = loop {
+        match tokio::net::TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::task::yield_now().await,
+        }
+    };
+    client
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    assert_eq!(events_rx.recv().await, Some("started"));
+    drop(client);
+
+    let event = tokio::time::timeout(std::time::Duration::from_secs(5), events_rx.recv())
+        .await
+        .expect("the disconnect should be observed");
+    assert_eq!(event, Some("disconnected"));
+
+    stop.send(()).unwrap();
+    server.await.unwrap();
+}
+
+/// An app configuration setting every field, as an operator would write it.
+const CONFIG_FIXTURE: &str = r#"
+[server]
+port = 8080
+trust_proxy = ["10.0.0.1", "10.0.0.2"]
+error_format = "json"
+json_max_bytes = 4096
+json_max_depth = 8
+
+[cors]
+origins = ["https://app.example"]
+methods = ["GET", "POST"]
+headers = ["X-Token"]
+allow_credentials = true
+max_age = 600
+
+[rate_limit]
+requests_per_window = 3
+window_secs = 120
+bytes_per_window = 1024
+retry_after_http_date = true
+
+[body_limit]
+max_bytes = 64
+strict = true
+
+[[static]]
+path = "/assets"
+root = '$STATIC_ROOT'
+max_age = 300
+"#;
+
+#[tokio::test]
+async fn test_apply_config_fixture() {
+    use expressjs::config::AppConfigFile;
+
+    let root = std::env::temp_dir().join(format!("expressjs-config-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("hello.txt"), "hello").unwrap();
+    let fixture = CONFIG_FIXTURE.replace("$STATIC_ROOT", &root.to_string_lossy());
+    let config: AppConfigFile = toml::from_str(&fixture).unwrap();
+    assert_eq!(config.server.port, 8080);
+
+    let mut app = App::<()>::default();
+    app.apply_config(&config).unwrap();
+    app.get("/settings", |req: Request<()>, res: Response| async move {
+        let trust = req.extensions().get::<TrustProxy>().cloned();
+        let limits = req.extensions().get::<JsonLimits>().copied();
+        res.send_text(format!("{trust:?} {limits:?}"))
+    });
+    app.get("/large", |_, _| async {
+        Err::<Response, _>(ResponseError::PayloadTooLarge(16))
+    });
+    app.get("/big", |_, res: Response| async move {
+        res.send_text("x".repeat(2048))
+    });
+    app.post("/upload", |_, res: Response| async move { res });
+
+    // Rate limiting keys on X-Real-IP here, as the test requests carry no
+    // socket address, so each check below runs as its own client.
+    let send = |client: &'static str, req: hyper::http::request::Builder| {
+        let req = req.header("X-Real-IP", client).body(()).unwrap();
+        async { app.handle(req, Response::new()).await.into_hyper() }
+    };
+    // Strict body limits want a Content-Length even on bodiless requests.
+    let get = |uri| {
+        hyper::Request::builder()
+            .uri(uri)
+            .header("Content-Length", "0")
+    };
+    async fn text<B: hyper::body::Body<Error: std::fmt::Debug>>(res: hyper::Response<B>) -> String {
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    // server
+    let res = send("server", get("/settings")).await;
+    assert_eq!(
+        text(res).await,
+        "Some(Ips([10.0.0.1, 10.0.0.2])) Some(JsonLimits { max_bytes: 4096, max_depth: 8 })"
+    );
+    let res = send("server", get("/large")).await;
+    assert_eq!(res.headers()["content-type"], "application/json");
+
+    // cors
+    let preflight = get("/upload")
+        .method("OPTIONS")
+        .header("Origin", "https://app.example");
+    let res = send("cors", preflight).await;
+    assert_eq!(res.status(), 204);
+    let headers = res.headers();
+    assert_eq!(
+        headers["access-control-allow-origin"],
+        "https://app.example"
+    );
+    assert_eq!(headers["access-control-allow-credentials"], "true");
+    assert_eq!(headers["access-control-allow-headers"], "X-Token");
+    assert_eq!(headers["access-control-max-age"], "600");
+    let mut methods: Vec<_> = headers["access-control-allow-methods"]
+        .to_str()
+        .unwrap()
+        .split(", ")
+        .collect();
+    methods.sort();
+    assert_eq!(methods, ["GET", "POST"]);
+    let res = send(
+        "cors",
+        get("/settings").header("Origin", "https://evil.example"),
+    )
+    .await;
+    assert!(!res.headers().contains_key("access-control-allow-origin"));
+
+    // body_limit
+    let post = || hyper::Request::builder().uri("/upload").method("POST");
+    let res = send("body", post().header("Content-Length", "65")).await;
+    assert_eq!(res.status(), 413);
+    let res = send("body", post()).await;
+    assert_eq!(res.status(), 411, "strict mode requires a Content-Length");
+    let res = send("body", post().header("Content-Length", "64")).await;
+    assert_eq!(res.status(), 200);
+
+    // static
+    let res = send("static", get("/assets/hello.txt")).await;
+    assert_eq!(res.headers()["cache-control"], "public, max-age=300");
+    assert_eq!(text(res).await, "hello");
+
+    // rate_limit: requests_per_window, window_secs and retry_after_http_date
+    for _ in 0..3 {
+        assert_eq!(send("rate", get("/settings")).await.status(), 200);
+    }
+    let res = send("rate", get("/settings")).await;
+    assert_eq!(res.status(), 429);
+    let retry_at = res.headers()["retry-after"].to_str().unwrap();
+    let retry_at = httpdate::parse_http_date(retry_at).unwrap();
+    let wait = retry_at
+        .duration_since(std::time::SystemTime::now())
+        .unwrap_or_default();
+    assert!(wait > std::time::Duration::from_secs(100), "{wait:?}");
+
+    // rate_limit: bytes_per_window
+    assert_eq!(send("bandwidth", get("/big")).await.status(), 200);
+    assert_eq!(send("bandwidth", get("/settings")).await.status(), 429);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_json_etag_and_if_match() {
+    #[derive(Serialize)]
+    struct Doc {
+        version: u32,
+    }
+    let current = Doc { version: 2 };
+    let etag = Response::json_etag(&current).unwrap();
+
+    let routes = |app: &mut App<()>| {
+        app.get("/doc", |_, res: Response| async move {
+            res.json_with_etag(&Doc { version: 2 })
+        });
+        app.put("/doc", |req: Request<()>, res: Response| async move {
+            req.check_if_match(&Response::json_etag(&Doc { version: 2 })?)?;
+            Ok::<_, ResponseError>(res.send_text("updated"))
+        });
+    };
+    let put = |if_match: Option<&str>| {
+        let mut req = hyper::Request::builder().method("PUT").uri("/doc");
+        if let Some(value) = if_match {
+            req = req.header("If-Match", value);
+        }
+        req.body(()).unwrap()
+    };
+
+    let mut app = App::<()>::default();
+    routes(&mut app);
+    let res = app
+        .handle(
+            hyper::Request::builder().uri("/doc").body(()).unwrap(),
+            Response::new(),
+        )
+        .await;
+    assert_eq!(res.headers["etag"], etag.as_str());
+    assert_eq!(res.headers["content-type"], "application/json");
+
+    let status = |res: Response| res.get_status().as_u16();
+    let tags = format!("\"stale\", {etag}");
+    assert_eq!(
+        status(app.handle(put(Some(&tags)), Response::new()).await),
+        200
+    );
+    assert_eq!(
+        status(app.handle(put(Some("*")), Response::new()).await),
+        200
+    );
+    assert_eq!(status(app.handle(put(None), Response::new()).await), 200);
+    let res = app.handle(put(Some("\"stale\"")), Response::new()).await;
+    assert_eq!(status(res), 412);
+    let weak = format!("W/{etag}");
+    assert_eq!(
+        status(app.handle(put(Some(&weak)), Response::new()).await),
+        412
+    );
+
+    let mut app = App::<()>::default();
+    app.if_match(IfMatch::Required);
+    routes(&mut app);
+    assert_eq!(status(app.handle(put(None), Response::new()).await), 428);
+    assert_eq!(
+        status(app.handle(put(Some(&etag)), Response::new()).await),
+        200
+    );
+}
+
+#[tokio::test]
+async fn test_responses_carry_only_handler_headers() {
+    // Header policy (caching, security headers) comes solely from middleware
+    // and handlers: the framework adds nothing but body framing.
+    let mut app = App::<()>::default();
+    app.get("/asset", |_, res: Response| async move {
+        res.header(
+            "Cache-Control",
+            hyper::header::HeaderValue::from_static("public, max-age=31536000"),
+        )
+        .body("body { }")
+    });
+
+    let req = hyper::Request::builder().uri("/asset").body(()).unwrap();
+    let res = app.handle(req, Response::new()).await.into_hyper();
+    let mut names: Vec<_> = res.headers().keys().map(|name| name.as_str()).collect();
+    names.sort();
+    assert_eq!(names, ["cache-control", "content-length"]);
+    assert_eq!(res.headers()["cache-control"], "public, max-age=31536000");
+}
+
+#[tokio::test]
+async fn test_security_headers_have_a_single_value() {
+    // SecurityHeadersMiddleware is the only source of these headers, and a
+    // handler setting one replaces the middleware's value rather than adding
+    // a contradictory second one.
+    let mut app = App::<()>::default();
+    app.use_with("/", SecurityHeadersMiddleware);
+    app.get("/", |_, res: Response| async move { res.body("home") });
+    app.get("/embeddable", |_, res: Response| async move {
+        res.header(
+            "X-Frame-Options",
+            hyper::header::HeaderValue::from_static("SAMEORIGIN"),
+        )
+        .body("widget")
+    });
+
+    let security_headers = [
+        "content-security-policy",
+        "x-xss-protection",
+        "x-content-type-options",
+        "x-frame-options",
+        "referrer-policy",
+        "strict-transport-security",
+    ];
+    for (uri, frame_options) in [("/", "DENY"), ("/embeddable", "SAMEORIGIN")] {
+        let req = hyper::Request::builder().uri(uri).body(()).unwrap();
+        let res = app.handle(req, Response::new()).await.into_hyper();
+        for name in security_headers {
+            assert_eq!(
+                res.headers().get_all(name).iter().count(),
+                1,
+                "{uri} {name}"
+            );
+        }
+        assert_eq!(res.headers()["x-frame-options"], frame_options);
+    }
+}
+
+#[tokio::test]
+async fn test_csp_nonce_matches_header() {
+    let mut app = App::<()>::default();
+    app.use_with("/", SecurityHeaders::new().script_src_nonce());
+    app.get("/", |req: Request<()>, res: Response| async move {
+        let nonce = req.csp_nonce().unwrap();
+        res.send_html(format!("<script nonce=\"{nonce}\">boot()</script>"))
+    });
+
+    let mut nonces = Vec::new();
+    for _ in 0..2 {
+        let req = hyper::Request::builder().uri("/").body(()).unwrap();
+        let res = app.handle(req, Response::new()).await.into_hyper();
+        let policy = res.headers()["content-security-policy"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let body = std::str::from_utf8(&body).unwrap();
+        let nonce = body
+            .strip_prefix("<script nonce=\"")
+            .and_then(|rest| rest.split_once('"'))
+            .unwrap()
+            .0
+            .to_owned();
+        assert!(
+            policy.contains(&format!("script-src 'self' 'nonce-{nonce}';")),
+            "{policy}"
+        );
+        assert!(!policy.contains("script-src 'self' 'unsafe-inline'"));
+        nonces.push(nonce);
+    }
+    assert_ne!(nonces[0], nonces[1]);
+}
+
+#[tokio::test]
+async fn test_debug_echo_reflects_the_request() {
+    let mut app = App::<FullBody>::default();
+    app.debug_echo("/debug/echo");
+
+    let req = hyper::Request::builder()
+        .method("PUT")
+        .uri("/debug/echo?tag=a&tag=b&page=2")
+        .header("Content-Type", "text/plain")
+        .header("X-Trace", "1")
+        .header("X-Trace", "2")
+        .body(FullBody::from("hello"))
+        .unwrap();
+    let res = app.handle(req, Response::new()).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::OK);
+    let body = res.into_hyper().into_body().collect().await.unwrap();
+    let echo: serde_json::Value = serde_json::from_slice(&body.to_bytes()).unwrap();
+    assert_eq!(
+        echo,
+        json!({
+            "method": "PUT",
+            "path": "/debug/echo",
+            "query": { "tag": ["a", "b"], "page": "2" },
+            "headers": { "content-type": "text/plain", "x-trace": ["1", "2"] },
+            "body": "hello",
+            "body_size": 5,
+        })
+    );
+
+    let req = hyper::Request::builder()
+        .method("POST")
+        .uri("/debug/echo")
+        .body(FullBody::from(vec![b'x'; 64 * 1024 + 1]))
+        .unwrap();
+    let res = app.handle(req, Response::new()).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn test_digest_verification() {
+    use base64::Engine;
+    use sha2::Digest;
+
+    let mut app = App::<FullBody>::default();
+    app.use_with("/", |_: &mut Request<FullBody>, res: &mut Response| {
+        res.headers.insert("x-before", "mw".parse().unwrap());
+        async { next_res() }
+    });
+    app.use_with("/", DigestVerificationMiddleware::new().response_digest());
+    app.post_x(
+        "/upload",
+        |Json(body): Json<serde_json::Value>| async move { Json(body) },
+    );
+    // Ignores the read error, so the middleware must catch the mismatch.
+    app.post(
+        "/lenient",
+        |req: Request<FullBody>, res: Response| async move {
+            let body = req.bytes().await.unwrap_or_default();
+            res.send_text(format!("{} bytes", body.len()))
+        },
+    );
+
+    let sha256 = |body: &[u8]| {
+        let digest = sha2::Sha256::digest(body);
+        format!(
+            "sha-256={}",
+            base64::engine::general_purpose::STANDARD.encode(digest)
+        )
+    };
+    let upload = |uri: &str, digest: &str, body: &'static str| {
+        hyper::Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("Content-Type", "application/json")
+            .header("Digest", digest)
+            .body(FullBody::from(body))
+            .unwrap()
+    };
+    let payload = r#"{"id":1}"#;
+
+    let res = app
+        .handle(
+            upload("/upload", &sha256(payload.as_bytes()), payload),
+            Response::new(),
+        )
+        .await
+        .into_hyper();
+    assert_eq!(res.status(), hyper::StatusCode::OK);
+    let sent = res.headers()["digest"].to_str().unwrap().to_owned();
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, payload);
+    assert_eq!(sent, sha256(&body));
+
+    // A corrupted body is rejected, whether the handler notices or not.
+    for uri in ["/upload", "/lenient"] {
+        let req = upload(uri, &sha256(payload.as_bytes()), r#"{"id":2}"#);
+        let res = app.handle(req, Response::new()).await;
+        assert_eq!(
+            res.get_status(),
+            hyper::StatusCode::UNPROCESSABLE_ENTITY,
+            "{uri}"
+        );
+        assert_eq!(res.headers["x-before"], "mw", "{uri}");
+    }
+
+    // Unsupported algorithms pass through unchecked.
+    let req = upload("/upload", "md5=HUXZLQLMuI/KZ5KDcJPcOA==", payload);
+    let res = app.handle(req, Response::new()).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::OK);
+
+    let req = upload("/upload", "sha-256=???", payload);
+    let res = app.handle(req, Response::new()).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_cache_policy_by_status() {
+    let mut app = App::<FullBody>::default();
+    app.cache_policy(
+        CachePolicy::new()
+            .server_errors("no-store")
+            .status(hyper::StatusCode::NOT_FOUND, "max-age=60"),
+    );
+    app.get("/ok", |_, res: Response| async move { res.text("ok") });
+    app.get("/fail", |_, res: Response| async move {
+        res.status_code(503).text("down")
+    });
+    app.get("/cached-fail", |_, res: Response| async move {
+        res.status_code(500).header(
+            "Cache-Control",
+            hyper::header::HeaderValue::from_static("max-age=5"),
+        )
+    });
+
+    let cache_control = |res: &Response| {
+        res.headers
+            .get("cache-control")
+            .map(|v| v.to_str().unwrap().to_owned())
+    };
+    for (uri, expected) in [
+        ("/ok", None),
+        ("/fail", Some("no-store")),
+        ("/missing", Some("max-age=60")),
+        ("/cached-fail", Some("max-age=5")),
+    ] {
+        let req = hyper::Request::builder()
+            .uri(uri)
+            .body(FullBody::default())
+            .unwrap();
+        let res = app.handle(req, Response::new()).await;
+        assert_eq!(cache_control(&res).as_deref(), expected, "{uri}");
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct SearchFilter {
+    status: String,
+    owners: Vec<u32>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Search {
+    tags: Vec<String>,
+    labels: Vec<String>,
+    filter: SearchFilter,
+    page: Option<u32>,
+}
+
+#[tokio::test]
+async fn test_query_as_with_arrays_and_nested_structs() {
+    let mut app = App::<FullBody>::default();
+    app.query_options(QueryOptions {
+        max_depth: 2,
+        ..QueryOptions::default()
+    });
+    app.get(
+        "/search",
+        |req: Request<FullBody>, res: Response| async move {
+            match req.query_as::<Search>() {
+                Ok(search) => res.text(format!("{search:?}")),
+                Err(e) => e.into_response(),
+            }
+        },
+    );
+
+    let search = |query: &str| {
+        let req = hyper::Request::builder()
+            .uri(format!("/search?{query}"))
+            .body(FullBody::default())
+            .unwrap();
+        app.handle(req, Response::new())
+    };
+
+    let res = search("tags[]=rust&tags[]=web&labels=one&filter[status]=open&filter[owners][]=7&filter[owners][]=9").await;
+    assert_eq!(res.get_status(), hyper::StatusCode::OK);
+    let body = res.into_hyper().into_body().collect().await.unwrap();
+    let expected = Search {
+        tags: vec!["rust".into(), "web".into()],
+        labels: vec!["one".into()],
+        filter: SearchFilter {
+            status: "open".into(),
+            owners: vec![7, 9],
+        },
+        page: None,
+    };
+    assert_eq!(body.to_bytes(), format!("{expected:?}"));
+
+    // Too deep, and values that don't convert.
+    for query in [
+        "tags[]=a&labels=b&filter[status]=open&filter[owners][]=1&x[a][b][c]=1",
+        "tags[]=a&labels=b&filter[status]=open&filter[owners][]=me",
+    ] {
+        let res = search(query).await;
+        assert_eq!(res.get_status(), hyper::StatusCode::BAD_REQUEST, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn test_single_flight_runs_the_handler_once_for_concurrent_requests() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let runs = Arc::new(AtomicUsize::new(0));
+    let mut app = App::<()>::default();
+    app.use_global(SingleFlightMiddleware::new());
+    app.get("/report", {
+        let runs = Arc::clone(&runs);
+        move |_, res: Response| {
+            let runs = Arc::clone(&runs);
+            async move {
+                let run = runs.fetch_add(1, Ordering::SeqCst) + 1;
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                res.send_text(format!("run {run}"))
+            }
+        }
+    });
+
+    let get = || {
+        let req = hyper::Request::builder().uri("/report").body(()).unwrap();
+        app.handle(req, Response::new())
+    };
+    let responses = futures_util::future::join_all((0..20).map(|_| get())).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    for res in responses {
+        assert_eq!(res.get_status(), hyper::StatusCode::OK);
+        let body = res.into_hyper().into_body().collect().await.unwrap();
+        assert_eq!(body.to_bytes(), "run 1");
+    }
+
+    // Once the flight is over, the next request runs the handler again.
+    let body = get()
+        .await
+        .into_hyper()
+        .into_body()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(body.to_bytes(), "run 2");
+}
+
+#[tokio::test]
+async fn test_header_policy_strips_requires_and_overrides_by_path() {
+    let mut app = App::<()>::default();
+    app.header_policy(
+        HeaderPolicy::new()
+            .require("X-Request-Id", "unset")
+            .require("Cache-Control", "no-store")
+            .forbid("X-Debug-Trace")
+            .path(
+                "/assets",
+                HeaderPolicy::new().require("Cache-Control", "public, max-age=3600"),
+            ),
+    );
+    let debug = |_, res: Response| async move {
+        res.header(
+            "X-Debug-Trace",
+            hyper::header::HeaderValue::from_static("db=12ms"),
+        )
+        .send_text("ok")
+    };
+    app.get("/api/users", debug);
+    app.get("/assets/app.js", debug);
+
+    let get = |uri: &'static str| {
+        let req = hyper::Request::builder().uri(uri).body(()).unwrap();
+        app.handle(req, Response::new())
+    };
+
+    let res = get("/api/users").await;
+    assert!(!res.headers.contains_key("x-debug-trace"));
+    assert_eq!(res.headers["x-request-id"], "unset");
+    assert_eq!(res.headers["cache-control"], "no-store");
+
+    let res = get("/assets/app.js").await;
+    assert!(!res.headers.contains_key("x-debug-trace"));
+    assert_eq!(res.headers["cache-control"], "public, max-age=3600");
+
+    // Responses the router produces itself are covered too.
+    let res = get("/missing").await;
+    assert_eq!(res.get_status(), hyper::StatusCode::NOT_FOUND);
+    assert_eq!(res.headers["cache-control"], "no-store");
+}
+
+#[tokio::test]
+async fn test_sub_app_prefixes_routes_and_scopes_middleware() {
+    let mut app = App::<()>::default();
+    app.get("/users", |_, res: Response| async move {
+        res.send_text("root users")
+    });
+    {
+        let mut api = app.sub("/api/");
+        api.use_with("/", |_: &mut Request<()>, res: &mut Response| {
+            res.headers
+                .insert("x-api", hyper::header::HeaderValue::from_static("1"));
+            std::future::ready(next_res())
+        });
+        api.get("/users", |_, res: Response| async move {
+            res.send_text("api users")
+        });
+        api.get(
+            "/",
+            |_, res: Response| async move { res.send_text("api root") },
+        );
+        api.sub("v1").post("items", |_, res: Response| async move {
+            res.send_text("v1 item")
+        });
+    }
+    // A prefix without a leading slash is still rooted.
+    app.sub("admin")
+        .get("/users", |_, res: Response| async move {
+            res.send_text("admin users")
+        });
+    app.get(
+        "/health",
+        |_, res: Response| async move { res.send_text("ok") },
+    );
+
+    let call = |method: &'static str, uri: &'static str| {
+        let req = hyper::Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(())
+            .unwrap();
+        app.handle(req, Response::new())
+    };
+    let text = |res: Response| async move {
+        let body = res.into_hyper().into_body().collect().await.unwrap();
+        String::from_utf8(body.to_bytes().to_vec()).unwrap()
+    };
+
+    for (method, uri, expected) in [
+        ("GET", "/api/users", "api users"),
+        ("GET", "/api", "api root"),
+        ("POST", "/api/v1/items", "v1 item"),
+    ] {
+        let res = call(method, uri).await;
+        assert_eq!(res.headers["x-api"], "1", "{uri}");
+        assert_eq!(text(res).await, expected);
+    }
+
+    // Siblings outside the prefix don't see its middleware.
+    for (uri, expected) in [
+        ("/users", "root users"),
+        ("/health", "ok"),
+        ("/admin/users", "admin users"),
+    ] {
+        let res = call("GET", uri).await;
+        assert!(!res.headers.contains_key("x-api"), "{uri}");
+        assert_eq!(text(res).await, expected);
+    }
+    let res = call("GET", "/v1/items").await;
+    assert_eq!(res.get_status(), hyper::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_time_marks_are_reported_in_server_timing() {
+    let mut app = App::<()>::default();
+    app.use_global(ServerTimingMiddleware::new());
+    app.get("/users", |req: Request<()>, res: Response| async move {
+        for _ in 0..2 {
+            let _db = req.time_mark("db");
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        req.time_mark("render").stop();
+        res.send_text("users")
+    });
+    app.get(
+        "/plain",
+        |_, res: Response| async move { res.send_text("ok") },
+    );
+
+    let get = |uri: &'static str| {
+        let req = hyper::Request::builder().uri(uri).body(()).unwrap();
+        app.handle(req, Response::new())
+    };
+
+    let res = get("/users").await;
+    let timing = res.headers["server-timing"].to_str().unwrap();
+    let (db, render) = timing.split_once(", ").unwrap();
+    let db: f64 = db.strip_prefix("db;dur=").unwrap().parse().unwrap();
+    assert!(db >= 10.0, "{timing}");
+    assert!(render.starts_with("render;dur="), "{timing}");
+
+    // No marks, no header.
+    let res = get("/plain").await;
+    assert!(!res.headers.contains_key("server-timing"));
+}
+
+type Todos = std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<u32, serde_json::Value>>>;
+
+async fn todo_index(State(todos): State<Todos>) -> Json<Vec<serde_json::Value>> {
+    Json(todos.lock().unwrap().values().cloned().collect())
+}
+
+async fn todo_create(
+    State(todos): State<Todos>,
+    Json(mut todo): Json<serde_json::Value>,
+) -> Json<serde_json::Value> {
+    let mut todos = todos.lock().unwrap();
+    let id = todos.keys().last().map_or(1, |last| last + 1);
+    todo["id"] = json!(id);
+    todos.insert(id, todo.clone());
+    Json(todo)
+}
+
+async fn todo_show(
+    Path(id): Path<u32>,
+    State(todos): State<Todos>,
+) -> Result<Json<serde_json::Value>, expressjs::prelude::ApiError> {
+    let todos = todos.lock().unwrap();
+    todos
+        .get(&id)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| expressjs::prelude::ApiError::not_found("todo"))
+}
+
+async fn todo_update(
+    Path(id): Path<u32>,
+    State(todos): State<Todos>,
+    Json(changes): Json<serde_json::Map<String, serde_json::Value>>,
+) -> Result<Json<serde_json::Value>, expressjs::prelude::ApiError> {
+    let mut todos = todos.lock().unwrap();
+    let todo = todos
+        .get_mut(&id)
+        .ok_or_else(|| expressjs::prelude::ApiError::not_found("todo"))?;
+    for (key, value) in changes {
+        todo[key] = value;
+    }
+    Ok(Json(todo.clone()))
+}
+
+async fn todo_destroy(Path(id): Path<u32>, State(todos): State<Todos>) -> hyper::StatusCode {
+    match todos.lock().unwrap().remove(&id) {
+        Some(_) => hyper::StatusCode::NO_CONTENT,
+        None => hyper::StatusCode::NOT_FOUND,
+    }
+}
+
+#[tokio::test]
+async fn test_resource_registers_crud_routes() {
+    let mut app = App::<FullBody>::default();
+    app.use_with_phase(
+        "/",
+        Phase::PreRouting,
+        PathRewriteMiddleware::strip_prefix("/api").pass_unmatched(true),
+    );
+    app.state(Todos::default());
+    app.resource(
+        "/todos",
+        ResourceHandlers::new()
+            .index_x(todo_index)
+            .create_x(todo_create)
+            .show_x(todo_show)
+            .update_x(todo_update)
+            .destroy_x(todo_destroy)
+            .middleware(|req: &mut Request<FullBody>, res: &mut Response| {
+                let blocked = req.headers().contains_key("x-blocked");
+                if blocked {
+                    res.status = hyper::StatusCode::FORBIDDEN;
+                }
+                std::future::ready(if blocked { stop_res() } else { next_res() })
+            }),
+    );
+    app.resource(
+        "/tags",
+        ResourceHandlers::new().index(|_, res: Response| async move { res.send_json(&json!([])) }),
+    );
+
+    let call = |method: &'static str, uri: &'static str, body: Option<serde_json::Value>| {
+        let mut req = hyper::Request::builder().method(method).uri(uri);
+        if body.is_some() {
+            req = req.header("Content-Type", "application/json");
+        }
+        let body = body.map_or_else(FullBody::default, |b| FullBody::from(b.to_string()));
+        app.handle(req.body(body).unwrap(), Response::new())
+    };
+    let json_of = |res: Response| async move {
+        let body = res.into_hyper().into_body().collect().await.unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body.to_bytes()).unwrap()
+    };
+
+    let res = call("POST", "/todos", Some(json!({ "title": "write docs" }))).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::CREATED);
+    assert_eq!(res.headers["location"], "/todos/1");
+    assert_eq!(
+        json_of(res).await,
+        json!({ "id": 1, "title": "write docs" })
+    );
+    call("POST", "/todos/", Some(json!({ "title": "ship" }))).await;
+    // Behind a rewrite, `Location` keeps the path the client used.
+    let res = call(
+        "POST",
+        "/api/todos?x=1",
+        Some(json!({ "title": "proxied" })),
+    )
+    .await;
+    assert_eq!(res.headers["location"], "/api/todos/3");
+
+    let res = call("GET", "/todos", None).await;
+    assert_eq!(json_of(res).await[1]["title"], "ship");
+
+    let res = call("PATCH", "/todos/1", Some(json!({ "done": true }))).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::OK);
+    let res = call(
+        "PUT",
+        "/todos/1",
+        Some(json!({ "title": "write more docs" })),
+    )
+    .await;
+    assert!(!res.headers.contains_key("location"));
+    let res = call("GET", "/todos/1", None).await;
+    assert_eq!(
+        json_of(res).await,
+        json!({ "id": 1, "title": "write more docs", "done": true })
+    );
+
+    let res = call("DELETE", "/todos/1", None).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::NO_CONTENT);
+    let res = call("GET", "/todos/1", None).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::NOT_FOUND);
+
+    // Actions left out are answered with 405 and the allowed methods.
+    let res = call("POST", "/tags", Some(json!({}))).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(res.headers["allow"], "GET");
+    let res = call("GET", "/tags/1", None).await;
+    assert_eq!(res.get_status(), hyper::StatusCode::NOT_FOUND);
+
+    // The resource's middleware runs for its routes only.
+    let blocked = |uri: &'static str| {
+        let req = hyper::Request::builder()
+            .uri(uri)
+            .header("x-blocked", "1")
+            .body(FullBody::default())
+            .unwrap();
+        app.handle(req, Response::new())
+    };
+    assert_eq!(
+        blocked("/todos").await.get_status(),
+        hyper::StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        blocked("/todos/2").await.get_status(),
+        hyper::StatusCode::FORBIDDEN
+    );
+    assert_eq!(blocked("/tags").await.get_status(), hyper::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_path_rewrite_routes_by_the_stripped_path() {
+    let mut app = App::<()>::default();
+    app.use_with_phase(
+        "/",
+        Phase::PreRouting,
+        PathRewriteMiddleware::strip_prefix("/app"),
+    );
+    // Mounted under the stripped path, so it only matches after the rewrite.
+    app.use_with("/users", |_: &mut Request<()>, res: &mut Response| {
+        res.headers
+            .insert("x-users", hyper::header::HeaderValue::from_static("1"));
+        async { next_res() }
+    });
+    app.get("/", |_, res: Response| async move { res.send_text("home") });
+    app.get(
+        "/users/{id}",
+        |req: Request<()>, res: Response| async move {
+            let body = format!(
+                "{} {} {}",
+                req.params().get("id").unwrap_or_default(),
+                req.matched_path().unwrap_or_default(),
+                req.original_url()
+            );
+            res.send_text(body)
+        },
+    );
+
+    let get = |uri: &'static str| {
+        let req = hyper::Request::builder().uri(uri).body(()).unwrap();
+        app.handle(req, Response::new())
+    };
+
+    let res = get("/app/users/7?tab=posts").await;
+    assert_eq!(res.get_status(), hyper::StatusCode::OK);
+    assert_eq!(res.headers["x-users"], "1");
+    let body = res.into_hyper().into_body().collect().await.unwrap();
+    assert_eq!(body.to_bytes(), "7 /users/{id} /app/users/7?tab=posts");
+
+    // The prefix alone is the root.
+    for root in ["/app", "/app/"] {
+        let res = get(root).await;
+        let body = res.into_hyper().into_body().collect().await.unwrap();
+        assert_eq!(body.to_bytes(), "home", "{root}");
+    }
+
+    // Paths without the prefix are not served, even if a route matches them.
+    for uri in ["/users/7", "/application/users/7"] {
+        let res = get(uri).await;
+        assert_eq!(res.get_status(), hyper::StatusCode::NOT_FOUND, "{uri}");
+        assert!(!res.headers.contains_key("x-users"), "{uri}");
+    }
+}
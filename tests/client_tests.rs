@@ -0,0 +1,186 @@
+#![cfg(feature = "client")]
+
+use expressjs::client::{ClientError, Http};
+use expressjs::prelude::*;
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use std::time::{Duration, Instant};
+
+/// Starts an upstream app on a free port; it stops when the sender is dropped.
+async fn upstream() -> (u16, tokio::sync::oneshot::Sender<()>) {
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let mut app = express();
+    app.shutdown_signal(async move {
+        let _ = stopped.await;
+    });
+    app.post("/echo", async |req: Request, res: Response| {
+        let header = |name| req.header(name).map(str::to_owned);
+        let echoed = json!({
+            "request_id": header("x-request-id"),
+            "traceparent": header("traceparent"),
+            "content_type": header("content-type"),
+            "authorization": header("authorization"),
+        });
+        let body: Value = req.json().await.unwrap_or_default();
+        res.send_json(&json!({ "headers": echoed, "body": body }))
+    });
+    app.get("/big", async |_req, res: Response| {
+        res.send_text("x".repeat(2048))
+    });
+    app.get("/slow", async |_req, res: Response| {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        res.send_text("late")
+    });
+    tokio::spawn(app.listen(port, async |_| {}));
+
+    while tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .is_err()
+    {
+        tokio::task::yield_now().await;
+    }
+    (port, stop)
+}
+
+async fn body_json(res: Response) -> Value {
+    let body = res.into_hyper().into_body().collect().await.unwrap();
+    serde_json::from_slice(&body.to_bytes()).unwrap()
+}
+
+#[tokio::test]
+async fn test_client_propagates_request_headers() {
+    let (port, _stop) = upstream().await;
+
+    let mut app = App::<()>::default();
+    app.state(Http::new().timeout(Duration::from_secs(5)));
+    app.get(
+        "/proxy",
+        move |req: Request<()>, res: Response| async move {
+            let upstream = req
+                .http_client()
+                .post(format!("http://127.0.0.1:{port}/echo"))
+                .json(&json!({ "id": 42 }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(upstream.status, StatusCode::OK);
+            res.send_json(&upstream.json::<Value>().unwrap())
+        },
+    );
+
+    let req = hyper::Request::builder()
+        .uri("/proxy")
+        .header("x-request-id", "req-7")
+        .header(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        )
+        .header("authorization", "Bearer secret")
+        .body(())
+        .unwrap();
+    let res = app.handle(req, Response::new()).await;
+    let echoed = body_json(res).await;
+
+    assert_eq!(echoed["headers"]["request_id"], "req-7");
+    assert_eq!(
+        echoed["headers"]["traceparent"],
+        "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+    );
+    assert_eq!(echoed["headers"]["content_type"], "application/json");
+    // Credentials are never forwarded implicitly.
+    assert_eq!(echoed["headers"]["authorization"], Value::Null);
+    assert_eq!(echoed["body"], json!({ "id": 42 }));
+}
+
+#[tokio::test]
+async fn test_client_calls_are_bounded_by_the_request_deadline() {
+    let (port, _stop) = upstream().await;
+
+    let mut app = App::<()>::default();
+    app.use_global(|req: &mut Request<()>, _res: &mut Response| {
+        let deadline = Instant::now() + Duration::from_millis(200);
+        req.extensions_mut().insert(Deadline(deadline));
+        async { next_res() }
+    });
+    app.get("/slow", move |req: Request<()>, res: Response| async move {
+        let started = Instant::now();
+        // The client's own timeout is longer; the deadline must win.
+        let err = req
+            .http_client()
+            .get(format!("http://127.0.0.1:{port}/slow"))
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err, ClientError::DeadlineExceeded);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // Once the deadline has passed, nothing is sent at all.
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let err = req
+            .http_client()
+            .get(format!("http://127.0.0.1:{port}/echo"))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err, ClientError::DeadlineExceeded);
+        res.send_text("ok")
+    });
+
+    let req = hyper::Request::builder().uri("/slow").body(()).unwrap();
+    let res = app.handle(req, Response::new()).await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_client_response_bodies_are_bounded_by_the_body_limit() {
+    let (port, _stop) = upstream().await;
+
+    let app = |client: Option<Http>| {
+        let mut app = App::<()>::default();
+        if let Some(client) = client {
+            app.state(client);
+        }
+        app.use_global(BodySizeLimitMiddleware {
+            max_size_bytes: 1024,
+            strict: false,
+        });
+        app.get(
+            "/proxy",
+            move |req: Request<()>, res: Response| async move {
+                match req
+                    .http_client()
+                    .get(format!("http://127.0.0.1:{port}/big"))
+                    .send()
+                    .await
+                {
+                    Ok(upstream) => res.send_text(upstream.body.len().to_string()),
+                    Err(e) => res.status_code(502).send_text(e.to_string()),
+                }
+            },
+        );
+        app
+    };
+    let call = async |app: App<()>| {
+        let req = hyper::Request::builder().uri("/proxy").body(()).unwrap();
+        let res = app.handle(req, Response::new()).await;
+        let status = res.status;
+        let body = res.into_hyper().into_body().collect().await.unwrap();
+        (status, String::from_utf8(body.to_bytes().to_vec()).unwrap())
+    };
+
+    let (status, body) = call(app(None)).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body, ClientError::BodyTooLarge(1024).to_string());
+
+    // A limit set on the registered client wins over the request's.
+    let (status, body) = call(app(Some(Http::new().max_body_size(4096)))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "2048");
+}